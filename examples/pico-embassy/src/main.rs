@@ -139,6 +139,7 @@ async fn main(spawner: Spawner) -> ! {
                     keepalive_interval: 120,
                     clean_session: false,
                     will_flag: false,
+                    will_options: None,
                     username: env!("EXAMPLE_MQTT_USER"),
                     password: env!("EXAMPLE_MQTT_PASSWORD"),
                 })
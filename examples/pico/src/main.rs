@@ -284,6 +284,7 @@ where
                     keepalive_interval: 0,
                     clean_session: false,
                     will_flag: false,
+                    will_options: None,
                     username: env!("EXAMPLE_MQTT_USER"),
                     password: env!("EXAMPLE_MQTT_PASSWORD"),
                 },
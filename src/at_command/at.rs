@@ -1,8 +1,8 @@
 //! Module to handle the basic AT commands
 
-use crate::at_command::AtRequest;
 #[allow(deprecated)]
 use crate::at_command::AtResponse;
+use crate::at_command::{AtRequest, SHORT_TIMEOUT_MS};
 use crate::AtError;
 #[cfg(feature = "defmt")]
 use defmt::{error, info};
@@ -56,6 +56,10 @@ impl AtRequest for At {
         info!("matready: {} | cfun: {}", _matready, _cfun);
         Ok(())
     }
+
+    fn timeout_ms(&self) -> u32 {
+        SHORT_TIMEOUT_MS
+    }
 }
 
 #[cfg(test)]
@@ -70,4 +74,9 @@ mod tests {
 
         assert_eq!(data, b"AT\r\n");
     }
+
+    #[test]
+    fn test_at_command_timeout() {
+        assert_eq!(At.timeout_ms(), SHORT_TIMEOUT_MS);
+    }
 }
@@ -1,5 +1,5 @@
 //! Module to handle the AT echo
-use crate::at_command::AtRequest;
+use crate::at_command::{AtRequest, SHORT_TIMEOUT_MS};
 use crate::AtError;
 
 /// Echo status
@@ -36,6 +36,10 @@ impl AtRequest for AtEchoState {
     fn parse_response_struct(&self, _data: &[u8]) -> Result<Self::Response, AtError> {
         Ok(())
     }
+
+    fn timeout_ms(&self) -> u32 {
+        SHORT_TIMEOUT_MS
+    }
 }
 
 /// Struct to set the echo state
@@ -59,6 +63,10 @@ impl AtRequest for AtEcho {
     fn parse_response_struct(&self, _data: &[u8]) -> Result<Self::Response, AtError> {
         Ok(())
     }
+
+    fn timeout_ms(&self) -> u32 {
+        SHORT_TIMEOUT_MS
+    }
 }
 
 #[cfg(test)]
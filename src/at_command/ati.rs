@@ -1,6 +1,6 @@
 //! Module for the product information
 
-use crate::at_command::AtRequest;
+use crate::at_command::{AtRequest, SHORT_TIMEOUT_MS};
 
 /// Request for the module information
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -36,6 +36,10 @@ impl AtRequest for AtI {
 
         Ok(ProductInformation { name })
     }
+
+    fn timeout_ms(&self) -> u32 {
+        SHORT_TIMEOUT_MS
+    }
 }
 
 #[cfg(test)]
@@ -8,6 +8,21 @@ use crate::AtError;
 #[cfg(feature = "defmt")]
 use defmt::info;
 
+/// Maximum size of the text kept for the verbose errors unknown by the driver
+pub const MAX_VERBOSE_ERROR_SIZE: usize = 64;
+
+/// Copies the text of an unknown verbose error, truncating it to the last
+/// character that fits
+fn truncated(text: &str) -> heapless::String<MAX_VERBOSE_ERROR_SIZE> {
+    let mut truncated = heapless::String::new();
+    for character in text.chars() {
+        if truncated.push(character).is_err() {
+            break;
+        }
+    }
+    truncated
+}
+
 /// Command to request the error report
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[derive(PartialEq, Clone)]
@@ -117,6 +132,628 @@ impl AtRequest for SetReportMobileEquipmentError {
     }
 }
 
+/// Errors reported by the module with `+CME ERROR: <err>`. Depending on the configured
+/// [ReportMobileEquipmentErrorSetting] the error is reported as numeric code or as verbose text
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(PartialEq, Clone, Debug)]
+pub enum CmeError {
+    /// 0: phone failure
+    PhoneFailure,
+    /// 1: no connection to phone
+    NoConnectionToPhone,
+    /// 2: phone-adaptor link reserved
+    PhoneAdaptorLinkReserved,
+    /// 3: operation not allowed
+    OperationNotAllowed,
+    /// 4: operation not supported
+    OperationNotSupported,
+    /// 5: PH-SIM PIN required
+    PhSimPinRequired,
+    /// 6: PH-FSIM PIN required
+    PhFSimPinRequired,
+    /// 7: PH-FSIM PUK required
+    PhFSimPukRequired,
+    /// 10: SIM not inserted
+    SimNotInserted,
+    /// 11: SIM PIN required
+    SimPinRequired,
+    /// 12: SIM PUK required
+    SimPukRequired,
+    /// 13: SIM failure
+    SimFailure,
+    /// 14: SIM busy
+    SimBusy,
+    /// 15: SIM wrong
+    SimWrong,
+    /// 16: incorrect password
+    IncorrectPassword,
+    /// 17: SIM PIN2 required
+    SimPin2Required,
+    /// 18: SIM PUK2 required
+    SimPuk2Required,
+    /// 20: memory full
+    MemoryFull,
+    /// 21: invalid index
+    InvalidIndex,
+    /// 22: not found
+    NotFound,
+    /// 23: memory failure
+    MemoryFailure,
+    /// 24: text string too long
+    TextStringTooLong,
+    /// 25: invalid characters in text string
+    InvalidCharactersInTextString,
+    /// 26: dial string too long
+    DialStringTooLong,
+    /// 27: invalid characters in dial string
+    InvalidCharactersInDialString,
+    /// 30: no network service
+    NoNetworkService,
+    /// 31: network timeout
+    NetworkTimeout,
+    /// 32: network not allowed - emergency calls only
+    NetworkNotAllowed,
+    /// 40: network personalization PIN required
+    NetworkPersonalizationPinRequired,
+    /// 41: network personalization PUK required
+    NetworkPersonalizationPukRequired,
+    /// 42: network subset personalization PIN required
+    NetworkSubsetPersonalizationPinRequired,
+    /// 43: network subset personalization PUK required
+    NetworkSubsetPersonalizationPukRequired,
+    /// 44: service provider personalization PIN required
+    ServiceProviderPersonalizationPinRequired,
+    /// 45: service provider personalization PUK required
+    ServiceProviderPersonalizationPukRequired,
+    /// 46: corporate personalization PIN required
+    CorporatePersonalizationPinRequired,
+    /// 47: corporate personalization PUK required
+    CorporatePersonalizationPukRequired,
+    /// 50: incorrect parameters
+    IncorrectParameters,
+    /// 51: command implemented but currently disabled
+    CommandDisabled,
+    /// 52: command aborted by user
+    CommandAborted,
+    /// 53: not attached to network due to MT functionality restrictions
+    NotAttachedRestricted,
+    /// 54: modem not allowed - MT restricted to emergency calls only
+    ModemRestrictedToEmergencyCalls,
+    /// 55: operation not allowed because of MT functionality restrictions
+    OperationNotAllowedRestricted,
+    /// 56: fixed dial number only allowed - called number is not a fixed dial number
+    FixedDialNumberOnly,
+    /// 57: temporarily out of service due to other MT usage
+    TemporarilyOutOfService,
+    /// 58: language/alphabet not supported
+    LanguageNotSupported,
+    /// 59: unexpected data value
+    UnexpectedDataValue,
+    /// 60: system failure
+    SystemFailure,
+    /// 61: data missing
+    DataMissing,
+    /// 62: call barred
+    CallBarred,
+    /// 63: message waiting indication subscription failure
+    MessageWaitingIndicationFailure,
+    /// 100: unknown
+    Unknown,
+    /// 103: illegal MS
+    IllegalMs,
+    /// 106: illegal ME
+    IllegalMe,
+    /// 107: GPRS services not allowed
+    GprsServicesNotAllowed,
+    /// 111: PLMN not allowed
+    PlmnNotAllowed,
+    /// 112: location area not allowed
+    LocationAreaNotAllowed,
+    /// 113: roaming not allowed in this location area
+    RoamingNotAllowed,
+    /// 132: service option not supported
+    ServiceOptionNotSupported,
+    /// 133: requested service option not subscribed
+    ServiceOptionNotSubscribed,
+    /// 134: service option temporarily out of order
+    ServiceOptionOutOfOrder,
+    /// 148: unspecified GPRS error
+    UnspecifiedGprsError,
+    /// 149: PDP authentication failure
+    PdpAuthenticationFailure,
+    /// 150: invalid mobile class
+    InvalidMobileClass,
+    /// 159: uplink busy/flow control, reported by the NB-IoT data services
+    UplinkBusy,
+    /// 171: last PDN disconnection not allowed
+    LastPdnDisconnectionNotAllowed,
+    /// Numeric error whose code is not known by the driver
+    Other(u16),
+    /// Verbose error whose text is not known by the driver, truncated to
+    /// [MAX_VERBOSE_ERROR_SIZE] bytes
+    OtherVerbose(heapless::String<MAX_VERBOSE_ERROR_SIZE>),
+}
+
+/// Numeric code and verbose text of each [CmeError]
+const CME_ERRORS: &[(CmeError, u16, &str)] = &[
+    (CmeError::PhoneFailure, 0, "phone failure"),
+    (CmeError::NoConnectionToPhone, 1, "no connection to phone"),
+    (
+        CmeError::PhoneAdaptorLinkReserved,
+        2,
+        "phone-adaptor link reserved",
+    ),
+    (CmeError::OperationNotAllowed, 3, "operation not allowed"),
+    (
+        CmeError::OperationNotSupported,
+        4,
+        "operation not supported",
+    ),
+    (CmeError::PhSimPinRequired, 5, "PH-SIM PIN required"),
+    (CmeError::PhFSimPinRequired, 6, "PH-FSIM PIN required"),
+    (CmeError::PhFSimPukRequired, 7, "PH-FSIM PUK required"),
+    (CmeError::SimNotInserted, 10, "SIM not inserted"),
+    (CmeError::SimPinRequired, 11, "SIM PIN required"),
+    (CmeError::SimPukRequired, 12, "SIM PUK required"),
+    (CmeError::SimFailure, 13, "SIM failure"),
+    (CmeError::SimBusy, 14, "SIM busy"),
+    (CmeError::SimWrong, 15, "SIM wrong"),
+    (CmeError::IncorrectPassword, 16, "incorrect password"),
+    (CmeError::SimPin2Required, 17, "SIM PIN2 required"),
+    (CmeError::SimPuk2Required, 18, "SIM PUK2 required"),
+    (CmeError::MemoryFull, 20, "memory full"),
+    (CmeError::InvalidIndex, 21, "invalid index"),
+    (CmeError::NotFound, 22, "not found"),
+    (CmeError::MemoryFailure, 23, "memory failure"),
+    (CmeError::TextStringTooLong, 24, "text string too long"),
+    (
+        CmeError::InvalidCharactersInTextString,
+        25,
+        "invalid characters in text string",
+    ),
+    (CmeError::DialStringTooLong, 26, "dial string too long"),
+    (
+        CmeError::InvalidCharactersInDialString,
+        27,
+        "invalid characters in dial string",
+    ),
+    (CmeError::NoNetworkService, 30, "no network service"),
+    (CmeError::NetworkTimeout, 31, "network timeout"),
+    (
+        CmeError::NetworkNotAllowed,
+        32,
+        "network not allowed - emergency calls only",
+    ),
+    (
+        CmeError::NetworkPersonalizationPinRequired,
+        40,
+        "network personalization PIN required",
+    ),
+    (
+        CmeError::NetworkPersonalizationPukRequired,
+        41,
+        "network personalization PUK required",
+    ),
+    (
+        CmeError::NetworkSubsetPersonalizationPinRequired,
+        42,
+        "network subset personalization PIN required",
+    ),
+    (
+        CmeError::NetworkSubsetPersonalizationPukRequired,
+        43,
+        "network subset personalization PUK required",
+    ),
+    (
+        CmeError::ServiceProviderPersonalizationPinRequired,
+        44,
+        "service provider personalization PIN required",
+    ),
+    (
+        CmeError::ServiceProviderPersonalizationPukRequired,
+        45,
+        "service provider personalization PUK required",
+    ),
+    (
+        CmeError::CorporatePersonalizationPinRequired,
+        46,
+        "corporate personalization PIN required",
+    ),
+    (
+        CmeError::CorporatePersonalizationPukRequired,
+        47,
+        "corporate personalization PUK required",
+    ),
+    (CmeError::IncorrectParameters, 50, "incorrect parameters"),
+    (
+        CmeError::CommandDisabled,
+        51,
+        "command implemented but currently disabled",
+    ),
+    (CmeError::CommandAborted, 52, "command aborted by user"),
+    (
+        CmeError::NotAttachedRestricted,
+        53,
+        "not attached to network due to MT functionality restrictions",
+    ),
+    (
+        CmeError::ModemRestrictedToEmergencyCalls,
+        54,
+        "modem not allowed - MT restricted to emergency calls only",
+    ),
+    (
+        CmeError::OperationNotAllowedRestricted,
+        55,
+        "operation not allowed because of MT functionality restrictions",
+    ),
+    (
+        CmeError::FixedDialNumberOnly,
+        56,
+        "fixed dial number only allowed - called number is not a fixed dial number",
+    ),
+    (
+        CmeError::TemporarilyOutOfService,
+        57,
+        "temporarily out of service due to other MT usage",
+    ),
+    (
+        CmeError::LanguageNotSupported,
+        58,
+        "language/alphabet not supported",
+    ),
+    (CmeError::UnexpectedDataValue, 59, "unexpected data value"),
+    (CmeError::SystemFailure, 60, "system failure"),
+    (CmeError::DataMissing, 61, "data missing"),
+    (CmeError::CallBarred, 62, "call barred"),
+    (
+        CmeError::MessageWaitingIndicationFailure,
+        63,
+        "message waiting indication subscription failure",
+    ),
+    (CmeError::Unknown, 100, "unknown"),
+    (CmeError::IllegalMs, 103, "illegal MS"),
+    (CmeError::IllegalMe, 106, "illegal ME"),
+    (
+        CmeError::GprsServicesNotAllowed,
+        107,
+        "GPRS services not allowed",
+    ),
+    (CmeError::PlmnNotAllowed, 111, "PLMN not allowed"),
+    (
+        CmeError::LocationAreaNotAllowed,
+        112,
+        "location area not allowed",
+    ),
+    (
+        CmeError::RoamingNotAllowed,
+        113,
+        "roaming not allowed in this location area",
+    ),
+    (
+        CmeError::ServiceOptionNotSupported,
+        132,
+        "service option not supported",
+    ),
+    (
+        CmeError::ServiceOptionNotSubscribed,
+        133,
+        "requested service option not subscribed",
+    ),
+    (
+        CmeError::ServiceOptionOutOfOrder,
+        134,
+        "service option temporarily out of order",
+    ),
+    (
+        CmeError::UnspecifiedGprsError,
+        148,
+        "unspecified GPRS error",
+    ),
+    (
+        CmeError::PdpAuthenticationFailure,
+        149,
+        "PDP authentication failure",
+    ),
+    (CmeError::InvalidMobileClass, 150, "invalid mobile class"),
+    (CmeError::UplinkBusy, 159, "uplink busy/flow control"),
+    (
+        CmeError::LastPdnDisconnectionNotAllowed,
+        171,
+        "last PDN disconnection not allowed",
+    ),
+];
+
+impl CmeError {
+    /// Gets the error matching the numeric code
+    pub fn from_code(code: u16) -> Self {
+        CME_ERRORS
+            .iter()
+            .find(|(_, error_code, _)| *error_code == code)
+            .map_or(CmeError::Other(code), |(error, _, _)| error.clone())
+    }
+
+    /// Gets the error matching the verbose text, ignoring the case
+    pub fn from_verbose(text: &str) -> Self {
+        CME_ERRORS
+            .iter()
+            .find(|(_, _, error_text)| error_text.eq_ignore_ascii_case(text))
+            .map_or_else(
+                || CmeError::OtherVerbose(truncated(text)),
+                |(error, _, _)| error.clone(),
+            )
+    }
+
+    /// Numeric code of the error, if known
+    pub fn code(&self) -> Option<u16> {
+        match self {
+            CmeError::Other(code) => Some(*code),
+            CmeError::OtherVerbose(_) => None,
+            error => CME_ERRORS
+                .iter()
+                .find(|(known, _, _)| known == error)
+                .map(|(_, code, _)| *code),
+        }
+    }
+
+    /// Verbose text of the error, if known
+    pub fn verbose(&self) -> Option<&str> {
+        match self {
+            CmeError::Other(_) => None,
+            CmeError::OtherVerbose(text) => Some(text),
+            error => CME_ERRORS
+                .iter()
+                .find(|(known, _, _)| known == error)
+                .map(|(_, _, text)| *text),
+        }
+    }
+
+    /// Parses the value of the error line, which can be numeric or verbose
+    fn parse(value: &[u8]) -> Self {
+        let value = core::str::from_utf8(value).unwrap_or_default().trim();
+        match value.parse::<u16>() {
+            Ok(code) => Self::from_code(code),
+            Err(_) => Self::from_verbose(value),
+        }
+    }
+}
+
+/// Errors related to the message service reported by the module with `+CMS ERROR: <err>`
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(PartialEq, Clone, Debug)]
+pub enum CmsError {
+    /// 300: ME failure
+    MeFailure,
+    /// 301: SMS service of ME reserved
+    SmsServiceReserved,
+    /// 302: operation not allowed
+    OperationNotAllowed,
+    /// 303: operation not supported
+    OperationNotSupported,
+    /// 304: invalid PDU mode parameter
+    InvalidPduModeParameter,
+    /// 305: invalid text mode parameter
+    InvalidTextModeParameter,
+    /// 310: SIM not inserted
+    SimNotInserted,
+    /// 311: SIM PIN required
+    SimPinRequired,
+    /// 312: PH-SIM PIN required
+    PhSimPinRequired,
+    /// 313: SIM failure
+    SimFailure,
+    /// 314: SIM busy
+    SimBusy,
+    /// 315: SIM wrong
+    SimWrong,
+    /// 316: SIM PUK required
+    SimPukRequired,
+    /// 320: memory failure
+    MemoryFailure,
+    /// 321: invalid memory index
+    InvalidMemoryIndex,
+    /// 322: memory full
+    MemoryFull,
+    /// 330: SMSC address unknown
+    SmscAddressUnknown,
+    /// 331: no network service
+    NoNetworkService,
+    /// 332: network timeout
+    NetworkTimeout,
+    /// 340: no +CNMA acknowledgement expected
+    NoAcknowledgementExpected,
+    /// 500: unknown error
+    UnknownError,
+    /// 512: user abort, this and the following codes are specific to the SIMCom modules
+    UserAbort,
+    /// 513: unable to store
+    UnableToStore,
+    /// 514: invalid status
+    InvalidStatus,
+    /// 515: device busy or invalid character in string
+    DeviceBusy,
+    /// 516: invalid length
+    InvalidLength,
+    /// 517: invalid character in PDU
+    InvalidCharacterInPdu,
+    /// 518: invalid parameter
+    InvalidParameter,
+    /// 519: invalid length or character
+    InvalidLengthOrCharacter,
+    /// 520: invalid character in text
+    InvalidCharacterInText,
+    /// 521: timer expired
+    TimerExpired,
+    /// 522: operation temporary not allowed
+    OperationTemporaryNotAllowed,
+    /// 532: SIM not ready
+    SimNotReady,
+    /// 534: cell broadcast error unknown
+    CellBroadcastErrorUnknown,
+    /// 535: protocol stack busy
+    ProtocolStackBusy,
+    /// Numeric error whose code is not known by the driver
+    Other(u16),
+    /// Verbose error whose text is not known by the driver, truncated to
+    /// [MAX_VERBOSE_ERROR_SIZE] bytes
+    OtherVerbose(heapless::String<MAX_VERBOSE_ERROR_SIZE>),
+}
+
+/// Numeric code and verbose text of each [CmsError]
+const CMS_ERRORS: &[(CmsError, u16, &str)] = &[
+    (CmsError::MeFailure, 300, "ME failure"),
+    (
+        CmsError::SmsServiceReserved,
+        301,
+        "SMS service of ME reserved",
+    ),
+    (CmsError::OperationNotAllowed, 302, "operation not allowed"),
+    (
+        CmsError::OperationNotSupported,
+        303,
+        "operation not supported",
+    ),
+    (
+        CmsError::InvalidPduModeParameter,
+        304,
+        "invalid PDU mode parameter",
+    ),
+    (
+        CmsError::InvalidTextModeParameter,
+        305,
+        "invalid text mode parameter",
+    ),
+    (CmsError::SimNotInserted, 310, "SIM not inserted"),
+    (CmsError::SimPinRequired, 311, "SIM PIN required"),
+    (CmsError::PhSimPinRequired, 312, "PH-SIM PIN required"),
+    (CmsError::SimFailure, 313, "SIM failure"),
+    (CmsError::SimBusy, 314, "SIM busy"),
+    (CmsError::SimWrong, 315, "SIM wrong"),
+    (CmsError::SimPukRequired, 316, "SIM PUK required"),
+    (CmsError::MemoryFailure, 320, "memory failure"),
+    (CmsError::InvalidMemoryIndex, 321, "invalid memory index"),
+    (CmsError::MemoryFull, 322, "memory full"),
+    (CmsError::SmscAddressUnknown, 330, "SMSC address unknown"),
+    (CmsError::NoNetworkService, 331, "no network service"),
+    (CmsError::NetworkTimeout, 332, "network timeout"),
+    (
+        CmsError::NoAcknowledgementExpected,
+        340,
+        "no +CNMA acknowledgement expected",
+    ),
+    (CmsError::UnknownError, 500, "unknown error"),
+    (CmsError::UserAbort, 512, "user abort"),
+    (CmsError::UnableToStore, 513, "unable to store"),
+    (CmsError::InvalidStatus, 514, "invalid status"),
+    (
+        CmsError::DeviceBusy,
+        515,
+        "device busy or invalid character in string",
+    ),
+    (CmsError::InvalidLength, 516, "invalid length"),
+    (
+        CmsError::InvalidCharacterInPdu,
+        517,
+        "invalid character in PDU",
+    ),
+    (CmsError::InvalidParameter, 518, "invalid parameter"),
+    (
+        CmsError::InvalidLengthOrCharacter,
+        519,
+        "invalid length or character",
+    ),
+    (
+        CmsError::InvalidCharacterInText,
+        520,
+        "invalid character in text",
+    ),
+    (CmsError::TimerExpired, 521, "timer expired"),
+    (
+        CmsError::OperationTemporaryNotAllowed,
+        522,
+        "operation temporary not allowed",
+    ),
+    (CmsError::SimNotReady, 532, "SIM not ready"),
+    (
+        CmsError::CellBroadcastErrorUnknown,
+        534,
+        "cell broadcast error unknown",
+    ),
+    (CmsError::ProtocolStackBusy, 535, "protocol stack busy"),
+];
+
+impl CmsError {
+    /// Gets the error matching the numeric code
+    pub fn from_code(code: u16) -> Self {
+        CMS_ERRORS
+            .iter()
+            .find(|(_, error_code, _)| *error_code == code)
+            .map_or(CmsError::Other(code), |(error, _, _)| error.clone())
+    }
+
+    /// Gets the error matching the verbose text, ignoring the case
+    pub fn from_verbose(text: &str) -> Self {
+        CMS_ERRORS
+            .iter()
+            .find(|(_, _, error_text)| error_text.eq_ignore_ascii_case(text))
+            .map_or_else(
+                || CmsError::OtherVerbose(truncated(text)),
+                |(error, _, _)| error.clone(),
+            )
+    }
+
+    /// Numeric code of the error, if known
+    pub fn code(&self) -> Option<u16> {
+        match self {
+            CmsError::Other(code) => Some(*code),
+            CmsError::OtherVerbose(_) => None,
+            error => CMS_ERRORS
+                .iter()
+                .find(|(known, _, _)| known == error)
+                .map(|(_, code, _)| *code),
+        }
+    }
+
+    /// Verbose text of the error, if known
+    pub fn verbose(&self) -> Option<&str> {
+        match self {
+            CmsError::Other(_) => None,
+            CmsError::OtherVerbose(text) => Some(text),
+            error => CMS_ERRORS
+                .iter()
+                .find(|(known, _, _)| known == error)
+                .map(|(_, _, text)| *text),
+        }
+    }
+
+    /// Parses the value of the error line, which can be numeric or verbose
+    fn parse(value: &[u8]) -> Self {
+        let value = core::str::from_utf8(value).unwrap_or_default().trim();
+        match value.parse::<u16>() {
+            Ok(code) => Self::from_code(code),
+            Err(_) => Self::from_verbose(value),
+        }
+    }
+}
+
+const CME_ERROR_PREFIX: &[u8] = b"+CME ERROR:";
+const CMS_ERROR_PREFIX: &[u8] = b"+CMS ERROR:";
+
+/// Parses an error line sent by the module when the report of mobile equipment errors is
+/// enabled. Returns [None] if the line is not an error line
+pub(crate) fn parse_error_line(line: &[u8]) -> Option<AtError> {
+    let line = crate::urc::trim_line(line);
+
+    if let Some(value) = line.strip_prefix(CME_ERROR_PREFIX) {
+        return Some(AtError::MobileEquipmentError(CmeError::parse(value)));
+    }
+
+    if let Some(value) = line.strip_prefix(CMS_ERROR_PREFIX) {
+        return Some(AtError::MessageServiceError(CmsError::parse(value)));
+    }
+
+    None
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -248,4 +885,96 @@ mod test {
 
         assert!(result.is_ok());
     }
+
+    #[test]
+    fn cme_error_from_numeric_line() {
+        let error = parse_error_line(b"\r\n+CME ERROR: 10\r\n");
+
+        assert!(matches!(
+            error,
+            Some(AtError::MobileEquipmentError(CmeError::SimNotInserted))
+        ));
+    }
+
+    #[test]
+    fn cme_error_from_verbose_line() {
+        let error = parse_error_line(b"+CME ERROR: operation not allowed\r\n");
+
+        assert!(matches!(
+            error,
+            Some(AtError::MobileEquipmentError(CmeError::OperationNotAllowed))
+        ));
+    }
+
+    #[test]
+    fn cme_error_unknown_values() {
+        assert_eq!(CmeError::from_code(999), CmeError::Other(999));
+        let error = CmeError::from_verbose("whatever");
+        assert_eq!(
+            error,
+            CmeError::OtherVerbose("whatever".try_into().unwrap())
+        );
+        assert_eq!(CmeError::Other(999).code(), Some(999));
+        assert_eq!(error.code(), None);
+    }
+
+    #[test]
+    fn verbose_error_text_is_truncated() {
+        let text = "é".repeat(MAX_VERBOSE_ERROR_SIZE);
+
+        let CmsError::OtherVerbose(kept) = CmsError::from_verbose(&text) else {
+            panic!("unexpected error");
+        };
+        assert_eq!(kept.as_str(), "é".repeat(MAX_VERBOSE_ERROR_SIZE / 2));
+    }
+
+    #[test]
+    fn cme_error_code_round_trip() {
+        assert_eq!(CmeError::NetworkTimeout.code(), Some(31));
+        assert_eq!(
+            CmeError::from_verbose("Incorrect Parameters"),
+            CmeError::IncorrectParameters
+        );
+    }
+
+    #[test]
+    fn cms_error_line() {
+        let error = parse_error_line(b"+CMS ERROR: 332");
+
+        assert!(matches!(
+            error,
+            Some(AtError::MessageServiceError(CmsError::NetworkTimeout))
+        ));
+    }
+
+    #[test]
+    fn cms_error_code_round_trip() {
+        let error = parse_error_line(b"+CMS ERROR: 535");
+
+        assert!(matches!(
+            error,
+            Some(AtError::MessageServiceError(CmsError::ProtocolStackBusy))
+        ));
+        assert_eq!(
+            CmsError::ProtocolStackBusy.verbose(),
+            Some("protocol stack busy")
+        );
+        assert_eq!(CmsError::from_verbose("SIM NOT READY").code(), Some(532));
+        assert_eq!(
+            CmsError::from_verbose("whatever").verbose(),
+            Some("whatever")
+        );
+        assert_eq!(CmsError::Other(999).verbose(), None);
+        assert_eq!(CmeError::from_code(159), CmeError::UplinkBusy);
+        assert_eq!(
+            CmeError::from_verbose("whatever").verbose(),
+            Some("whatever")
+        );
+    }
+
+    #[test]
+    fn plain_lines_are_not_errors() {
+        assert!(parse_error_line(b"ERROR\r\n").is_none());
+        assert!(parse_error_line(b"+CSQ: 20,99\r\n").is_none());
+    }
 }
@@ -0,0 +1,119 @@
+//! Module to store the certificates and private keys used by the TLS connections.
+//!
+//! The module keeps one credential of each [CredentialType], set with `AT+CSETCA` as described in
+//! the SIM7020 AT command manual. A credential, PEM or DER, is usually longer than a single
+//! command, so it is uploaded in chunks with [SetCredentialChunk]. The modems do the split with
+//! `upload_credential`. The TLS sessions, e.g. the ones created with `AT+CMQTTSNEW`, use the
+//! stored credentials
+use crate::at_command::{verify_ok, AtRequest};
+use crate::AtError;
+
+/// Bytes of the `AT+CSETCA` command that are not data, e.g. `AT+CSETCA=2,65535,1,1,` and `\r\n`
+const SET_CREDENTIAL_OVERHEAD: usize = 32;
+
+/// `<encode_type>` of `AT+CSETCA` for the data sent as hexadecimal
+const HEX_ENCODING: u8 = 1;
+
+/// Returns the number of bytes of a credential that can be sent with a single
+/// [SetCredentialChunk] when the command is built in a buffer of the given size
+pub const fn max_credential_chunk_size(buffer_size: usize) -> usize {
+    buffer_size.saturating_sub(SET_CREDENTIAL_OVERHEAD) / 2
+}
+
+/// Kind of the stored credential
+#[repr(u8)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum CredentialType {
+    /// Root certificate used to verify the server
+    CaCertificate = 0,
+    /// Certificate presented by the client
+    ClientCertificate = 1,
+    /// Private key of the client certificate
+    ClientPrivateKey = 2,
+}
+
+/// Command to store a chunk of a credential, `AT+CSETCA`. The module keeps the chunks until the
+/// last one is received, the credential replaces the one of the same type
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(PartialEq)]
+pub struct SetCredentialChunk<'a> {
+    pub kind: CredentialType,
+    /// Length of the whole credential in bytes
+    pub total_length: u16,
+    /// Indicates that this is the last chunk of the credential
+    pub last: bool,
+    pub data: &'a [u8],
+}
+
+impl AtRequest for SetCredentialChunk<'_> {
+    type Response = ();
+
+    fn get_command<'a>(&'a self, buffer: &'a mut [u8]) -> Result<&'a [u8], usize> {
+        at_commands::builder::CommandBuilder::create_set(buffer, true)
+            .named("+CSETCA")
+            .with_int_parameter(self.kind as u8)
+            .with_int_parameter(self.total_length as i32)
+            .with_int_parameter(self.last as u8)
+            .with_int_parameter(HEX_ENCODING)
+            .with_rax_hex_parameter(self.data)
+            .finish()
+    }
+
+    fn parse_response_struct(&self, data: &[u8]) -> Result<Self::Response, AtError> {
+        verify_ok(data)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::emulator::Sim7020Emulator;
+    use crate::testing::{NoopDelay, NoopPin};
+    use crate::Modem;
+
+    #[test]
+    fn test_set_credential_chunk_command() {
+        let mut buffer = [0; 64];
+        let command = SetCredentialChunk {
+            kind: CredentialType::ClientPrivateKey,
+            total_length: 300,
+            last: false,
+            data: b"-----",
+        };
+
+        assert_eq!(
+            command.get_command(&mut buffer).unwrap(),
+            b"AT+CSETCA=2,300,0,1,2d2d2d2d2d\r\n"
+        );
+        assert!(max_credential_chunk_size(64) >= command.data.len());
+    }
+
+    #[test]
+    fn test_upload_credentials() {
+        let emulator = Sim7020Emulator::new();
+        let (mut writer, mut reader) = emulator.split();
+        let mut modem = Modem::new(&mut writer, &mut reader, NoopPin, NoopPin, NoopDelay).unwrap();
+
+        // Needs several chunks with the default buffer of the modem
+        let data: std::vec::Vec<u8> = (0..1000).map(|index| index as u8).collect();
+        modem
+            .upload_credential(CredentialType::ClientPrivateKey, &data)
+            .unwrap();
+        assert_eq!(
+            emulator.credential(2).unwrap().data.as_slice(),
+            data.as_slice()
+        );
+        assert!(emulator.credential(0).is_none());
+        assert!(matches!(
+            modem.upload_credential(CredentialType::ClientPrivateKey, &[]),
+            Err(AtError::InvalidParameter)
+        ));
+
+        // Replaces the stored key
+        modem
+            .upload_credential(CredentialType::ClientPrivateKey, b"key")
+            .unwrap();
+        assert_eq!(emulator.credential(2).unwrap().data.as_slice(), b"key");
+    }
+}
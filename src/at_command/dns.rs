@@ -0,0 +1,48 @@
+//! Module to resolve host names with the DNS of the network
+
+use crate::at_command::{verify_ok, AtRequest};
+use crate::AtError;
+
+/// Command to resolve the IP address of a host. The module answers OK immediately and reports
+/// the result later with the `+CDNSGIP` unsolicited result code, see
+/// [Urc::DnsResolution](crate::urc::Urc::DnsResolution)
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(PartialEq, Clone)]
+pub struct GetHostByName<'a> {
+    /// Name of the host to be resolved
+    pub host: &'a str,
+}
+
+impl AtRequest for GetHostByName<'_> {
+    type Response = ();
+
+    fn get_command<'a>(&'a self, buffer: &'a mut [u8]) -> Result<&'a [u8], usize> {
+        at_commands::builder::CommandBuilder::create_set(buffer, true)
+            .named("+CDNSGIP")
+            .with_string_parameter(self.host)
+            .finish()
+    }
+
+    fn parse_response_struct(&self, data: &[u8]) -> Result<Self::Response, AtError> {
+        verify_ok(data)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_get_host_by_name_command() {
+        let mut buffer = [0; 64];
+        let command = GetHostByName {
+            host: "example.com",
+        };
+
+        assert_eq!(
+            command.get_command(&mut buffer).unwrap(),
+            b"AT+CDNSGIP=\"example.com\"\r\n"
+        );
+        command.parse_response_struct(b"\r\nOK\r").unwrap();
+    }
+}
@@ -1,10 +1,13 @@
 //! Module to handle the http requests
-use crate::at_command::AtRequest;
 #[allow(deprecated)]
 use crate::at_command::AtResponse;
+use crate::at_command::{AtRequest, NETWORK_TIMEOUT_MS};
+use crate::urc::{HttpResponseHeader, HTTP_HEADERS_SIZE};
 use crate::AtError;
 use at_commands::builder::CommandBuilder;
 use at_commands::parser::CommandParser;
+use core::fmt::Write as _;
+use embedded_io::{ErrorKind, ErrorType};
 
 #[cfg(feature = "defmt")]
 use defmt::debug;
@@ -168,12 +171,86 @@ impl<const HOST_MAX_SIZE: usize> AtRequest for GetHttpSessions<HOST_MAX_SIZE> {
 }
 
 /// create a HTTP or HTTPS session
+///
+/// With [CreateHttpSession::tls] the certificates are sent inline, as in
+/// `AT+CHTTPCREATE=<host>[,<user>,<pwd>[,<server_cert_len>,<server_cert>[,<client_cert_len>,
+/// <client_cert>,<client_pk_len>,<client_pk>]]]` of the SIM7020 AT command manual, so the buffer
+/// of the modem must fit them. The host must start with `https://`
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[derive(PartialEq, Clone, Debug)]
 pub struct CreateHttpSession<'a> {
     pub host: &'a str,
     pub user: Option<&'a str>,
     pub password: Option<&'a str>,
+    pub tls: Option<HttpsCertificates<'a>>,
+}
+
+/// Certificates of an HTTPS client in PEM format
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(PartialEq, Clone, Copy, Debug, Default)]
+pub struct HttpsCertificates<'a> {
+    /// CA used to verify the server, the server is not verified without it
+    pub server_certificate: Option<&'a str>,
+    /// Certificate presented by the client, it requires [HttpsCertificates::server_certificate]
+    /// and [HttpsCertificates::client_key]
+    pub client_certificate: Option<&'a str>,
+    pub client_key: Option<&'a str>,
+}
+
+impl<'a> HttpsCertificates<'a> {
+    /// Checks that the certificates are given in the order of the command and that they can be
+    /// sent as a string
+    pub fn validate(&self) -> Result<(), AtError> {
+        if (self.server_certificate.is_none() && self.client_certificate.is_some())
+            || self.client_certificate.is_some() != self.client_key.is_some()
+            || self
+                .certificates()
+                .any(|certificate| certificate.is_empty() || certificate.contains('"'))
+        {
+            return Err(AtError::InvalidParameter);
+        }
+
+        Ok(())
+    }
+
+    fn certificates(&self) -> impl Iterator<Item = &'a str> {
+        [
+            self.server_certificate,
+            self.client_certificate,
+            self.client_key,
+        ]
+        .into_iter()
+        .flatten()
+    }
+}
+
+/// Appends `,<length>,"<certificate>"` to the command of [size] bytes built in [buffer],
+/// escaping the line breaks, which would end the command. Returns the new size, also when it
+/// exceeds the buffer
+fn push_certificate(buffer: &mut [u8], size: usize, certificate: &str) -> usize {
+    let mut length: heapless::String<16> = heapless::String::new();
+    // A usize always fits
+    let _ = write!(length, ",{},\"", certificate.len());
+    let mut size = push_bytes(buffer, size, length.as_bytes());
+    for &byte in certificate.as_bytes() {
+        size = match byte {
+            b'\r' => push_bytes(buffer, size, b"\\r"),
+            b'\n' => push_bytes(buffer, size, b"\\n"),
+            _ => push_bytes(buffer, size, &[byte]),
+        };
+    }
+
+    push_bytes(buffer, size, b"\"")
+}
+
+/// Copies as much of [data] as fits at [offset] of [buffer]. Returns the offset after [data]
+fn push_bytes(buffer: &mut [u8], offset: usize, data: &[u8]) -> usize {
+    if let Some(space) = buffer.get_mut(offset..) {
+        let size = data.len().min(space.len());
+        space[..size].copy_from_slice(&data[..size]);
+    }
+
+    offset + data.len()
 }
 
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -183,7 +260,7 @@ pub struct CreateHttpSessionResponse {
 }
 
 impl CreateHttpSession<'_> {
-    fn get_client_id(data: &[u8]) -> Result<u8, AtError> {
+    fn get_client_id(&self, data: &[u8]) -> Result<u8, AtError> {
         let (client_id,) = at_commands::parser::CommandParser::parse(data)
             .trim_whitespace()
             .expect_identifier(b"+CHTTPCREATE: ")
@@ -200,23 +277,58 @@ impl AtRequest for CreateHttpSession<'_> {
     type Response = CreateHttpSessionResponse;
 
     fn get_command<'a>(&'a self, buffer: &'a mut [u8]) -> Result<&'a [u8], usize> {
-        at_commands::builder::CommandBuilder::create_set(buffer, true)
+        let Some(tls) = self.tls else {
+            let mut builder = at_commands::builder::CommandBuilder::create_set(buffer, true)
+                .named("+CHTTPCREATE")
+                .with_string_parameter(self.host);
+            if self.user.is_some() || self.password.is_some() {
+                builder = builder
+                    .with_optional_string_parameter(self.user)
+                    .with_optional_string_parameter(self.password);
+            }
+            return builder.finish();
+        };
+
+        // The builder can not escape the line breaks of the certificates, they are appended
+        // after the rest of the command
+        let mut size = at_commands::builder::CommandBuilder::create_set(&mut *buffer, true)
             .named("+CHTTPCREATE")
             .with_string_parameter(self.host)
-            // todo: optional parameters need to be fixed
-            // .with_optional_string_parameter(self.user)
-            // .with_optional_string_parameter(self.password)
-            .finish()
+            .with_string_parameter(self.user.unwrap_or_default())
+            .with_string_parameter(self.password.unwrap_or_default())
+            .finish_with(b"")?
+            .len();
+        for certificate in tls.certificates() {
+            size = push_certificate(buffer, size, certificate);
+        }
+        size = push_bytes(buffer, size, b"\r\n");
+        if size > buffer.len() {
+            return Err(size);
+        }
+
+        Ok(&buffer[..size])
+    }
+
+    fn validate(&self) -> Result<(), AtError> {
+        if let Some(tls) = self.tls {
+            let scheme = self.host.get(..8).unwrap_or_default();
+            if !scheme.eq_ignore_ascii_case("https://") {
+                return Err(AtError::InvalidParameter);
+            }
+            tls.validate()?;
+        }
+
+        Ok(())
     }
 
     #[allow(deprecated)]
     fn parse_response(&self, data: &[u8]) -> Result<AtResponse, AtError> {
-        let client_id = Self::get_client_id(data)?;
+        let client_id = self.get_client_id(data)?;
         Ok(AtResponse::HTTPSessionCreated(client_id))
     }
 
     fn parse_response_struct(&self, data: &[u8]) -> Result<Self::Response, AtError> {
-        let client_id = Self::get_client_id(data)?;
+        let client_id = self.get_client_id(data)?;
         Ok(CreateHttpSessionResponse { client_id })
     }
 }
@@ -241,6 +353,10 @@ impl AtRequest for HttpConnect {
     fn parse_response_struct(&self, _data: &[u8]) -> Result<Self::Response, AtError> {
         Ok(())
     }
+
+    fn timeout_ms(&self) -> u32 {
+        NETWORK_TIMEOUT_MS
+    }
 }
 
 /// Disconnect from a server
@@ -287,8 +403,18 @@ impl AtRequest for HttpDestroy {
     }
 }
 
+/// Maximum length of the path of a request
+pub const MAX_PATH_SIZE: usize = 255;
+/// Maximum length of the content type of a request
+pub const MAX_CONTENT_TYPE_SIZE: usize = 64;
+/// Maximum length of the custom headers of a request, before being converted to hex
+pub const MAX_HEADERS_SIZE: usize = 1024;
+/// Maximum length of the body of a request, before being converted to hex
+pub const MAX_BODY_SIZE: usize = 1024;
+
+/// Methods supported by `AT+CHTTPSEND`
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
-#[derive(PartialEq, Clone, Debug)]
+#[derive(PartialEq, Clone, Copy, Debug)]
 #[repr(u8)]
 pub enum HttpMethod {
     GET = 0,
@@ -297,42 +423,272 @@ pub enum HttpMethod {
     DELETE = 3,
 }
 
-/// customer_header: The string converted from customer header hex data
-/// content_type: A string indicate the content type of the content, if the method is not POST and PUT, it must be empty.
-/// content_string: The string converted from content hex data.
+impl HttpMethod {
+    /// Indicates if the request of this method can have a content type and a body
+    pub fn has_body(&self) -> bool {
+        matches!(self, HttpMethod::POST | HttpMethod::PUT)
+    }
+}
+
+/// Sends a request through a connected HTTP client. The headers and the body are sent as hex, so
+/// the command needs a buffer of about twice their size.
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[derive(PartialEq, Clone, Debug)]
 pub struct HttpSend<'a> {
     pub client_id: u8,
     pub method: HttpMethod,
+    /// Path of the request, it must start with `/`
     pub path: &'a str,
+    /// Custom header lines, each one ended with `\r\n`, e.g. `"Authorization: Bearer x\r\n"`
+    pub headers: Option<&'a str>,
+    /// Content type of the body, only allowed for POST and PUT
+    pub content_type: Option<&'a str>,
+    /// Body of the request, only allowed for POST and PUT
+    pub body: Option<&'a [u8]>,
 }
 
 impl AtRequest for HttpSend<'_> {
     type Response = ();
 
     fn get_command<'a>(&'a self, buffer: &'a mut [u8]) -> Result<&'a [u8], usize> {
-        let method: u8 = match self.method {
-            HttpMethod::GET => 0,
-            HttpMethod::POST => 1,
-            HttpMethod::PUT => 2,
-            HttpMethod::DELETE => 3,
-        };
-
-        at_commands::builder::CommandBuilder::create_set(buffer, true)
+        let mut builder = at_commands::builder::CommandBuilder::create_set(buffer, true)
             .named("+CHTTPSEND")
             .with_int_parameter(self.client_id)
-            .with_int_parameter(method)
-            .with_string_parameter(self.path)
-            // .with_optional_string_parameter(self.customer_header)
-            // .with_optional_string_parameter(self.content_type)
-            // .with_optional_string_parameter(self.content_string)
-            .finish()
+            .with_int_parameter(self.method as u8)
+            .with_string_parameter(self.path);
+
+        if self.headers.is_some() || self.content_type.is_some() || self.body.is_some() {
+            builder = builder.with_rax_hex_parameter(self.headers.unwrap_or_default().as_bytes());
+        }
+        if self.content_type.is_some() || self.body.is_some() {
+            builder = builder
+                .with_string_parameter(self.content_type.unwrap_or_default())
+                .with_rax_hex_parameter(self.body.unwrap_or_default());
+        }
+
+        builder.finish()
     }
 
     fn parse_response_struct(&self, _data: &[u8]) -> Result<Self::Response, AtError> {
         Ok(())
     }
+
+    fn timeout_ms(&self) -> u32 {
+        NETWORK_TIMEOUT_MS
+    }
+
+    fn validate(&self) -> Result<(), AtError> {
+        if !self.path.starts_with('/') {
+            return Err(AtError::InvalidParameter);
+        }
+        if !self.method.has_body() && (self.content_type.is_some() || self.body.is_some()) {
+            return Err(AtError::InvalidParameter);
+        }
+        if self.body.is_some() && self.content_type.is_none_or(str::is_empty) {
+            return Err(AtError::InvalidParameter);
+        }
+        if let Some(headers) = self.headers {
+            if !headers.ends_with("\r\n") || headers.contains("\r\n\r\n") {
+                return Err(AtError::InvalidParameter);
+            }
+        }
+
+        let too_long = |value: Option<usize>, max: usize| value.is_some_and(|len| len > max);
+        if self.path.len() > MAX_PATH_SIZE
+            || too_long(self.content_type.map(str::len), MAX_CONTENT_TYPE_SIZE)
+            || too_long(self.headers.map(str::len), MAX_HEADERS_SIZE)
+            || too_long(self.body.map(<[u8]>::len), MAX_BODY_SIZE)
+        {
+            return Err(AtError::CapacityError);
+        }
+
+        Ok(())
+    }
+}
+
+/// Request sent by the HTTP contexts, it is the same as [HttpSend] without the client id
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct HttpRequest<'a> {
+    pub method: HttpMethod,
+    pub path: &'a str,
+    pub headers: Option<&'a str>,
+    pub content_type: Option<&'a str>,
+    pub body: Option<&'a [u8]>,
+}
+
+impl<'a> HttpRequest<'a> {
+    fn new(method: HttpMethod, path: &'a str) -> Self {
+        Self {
+            method,
+            path,
+            headers: None,
+            content_type: None,
+            body: None,
+        }
+    }
+
+    pub fn get(path: &'a str) -> Self {
+        Self::new(HttpMethod::GET, path)
+    }
+
+    pub fn delete(path: &'a str) -> Self {
+        Self::new(HttpMethod::DELETE, path)
+    }
+
+    pub fn post(path: &'a str, content_type: &'a str, body: &'a [u8]) -> Self {
+        Self::new(HttpMethod::POST, path).with_body(content_type, body)
+    }
+
+    pub fn put(path: &'a str, content_type: &'a str, body: &'a [u8]) -> Self {
+        Self::new(HttpMethod::PUT, path).with_body(content_type, body)
+    }
+
+    /// Sets the custom header lines, each one ended with `\r\n`
+    pub fn with_headers(self, headers: &'a str) -> Self {
+        Self {
+            headers: Some(headers),
+            ..self
+        }
+    }
+
+    fn with_body(self, content_type: &'a str, body: &'a [u8]) -> Self {
+        Self {
+            content_type: Some(content_type),
+            body: Some(body),
+            ..self
+        }
+    }
+
+    /// Builds the command that sends the request through the given HTTP client
+    pub fn command(&self, client_id: u8) -> HttpSend<'a> {
+        HttpSend {
+            client_id,
+            method: self.method,
+            path: self.path,
+            headers: self.headers,
+            content_type: self.content_type,
+            body: self.body,
+        }
+    }
+}
+
+/// Default maximum size of the body of an [HttpResponse]
+pub const DEFAULT_BODY_SIZE: usize = 512;
+
+/// Response of a request sent with [HttpSend], received through the `+CHTTPNMIH` and
+/// `+CHTTPNMIC` unsolicited result codes
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(PartialEq, Clone, Debug)]
+pub struct HttpResponse<const BODY_SIZE: usize = DEFAULT_BODY_SIZE> {
+    pub status_code: u16,
+    /// Header lines, each one ended with `\r\n`
+    pub headers: heapless::Vec<u8, HTTP_HEADERS_SIZE>,
+    /// The body reassembled from all the fragments
+    pub body: heapless::Vec<u8, BODY_SIZE>,
+}
+
+impl<const BODY_SIZE: usize> HttpResponse<BODY_SIZE> {
+    pub(crate) fn new(header: HttpResponseHeader, body: heapless::Vec<u8, BODY_SIZE>) -> Self {
+        Self {
+            status_code: header.status_code,
+            headers: header.headers,
+            body,
+        }
+    }
+
+    /// Iterates over the name and the value of each header
+    pub fn headers(&self) -> HttpHeaders<'_> {
+        HttpHeaders::new(&self.headers)
+    }
+
+    /// Returns the value of the first header with the given name, ignoring the case
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers().find_header(name)
+    }
+}
+
+/// Iterator over the `name: value` lines of the headers of a response. The lines that are not
+/// valid UTF-8 or do not contain `:` are skipped
+pub struct HttpHeaders<'a> {
+    lines: core::slice::Split<'a, u8, fn(&u8) -> bool>,
+}
+
+impl<'a> HttpHeaders<'a> {
+    pub fn new(headers: &'a [u8]) -> Self {
+        Self {
+            lines: headers.split(|&byte| byte == b'\n'),
+        }
+    }
+
+    pub(crate) fn find_header(mut self, name: &str) -> Option<&'a str> {
+        self.find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value)
+    }
+}
+
+impl<'a> Iterator for HttpHeaders<'a> {
+    type Item = (&'a str, &'a str);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.lines.by_ref().find_map(|line| {
+            let line = core::str::from_utf8(line).ok()?;
+            let (name, value) = line.split_once(':')?;
+            Some((name.trim(), value.trim()))
+        })
+    }
+}
+
+/// Sink that stores the body of a response into a [heapless::Vec]
+pub(crate) struct BodyWriter<'a, const BODY_SIZE: usize>(
+    pub(crate) &'a mut heapless::Vec<u8, BODY_SIZE>,
+);
+
+impl<const BODY_SIZE: usize> BodyWriter<'_, BODY_SIZE> {
+    fn append(&mut self, buf: &[u8]) -> Result<usize, ErrorKind> {
+        let size = buf.len().min(BODY_SIZE - self.0.len());
+        if size == 0 && !buf.is_empty() {
+            return Err(ErrorKind::OutOfMemory);
+        }
+        // There is space for the bytes
+        let _ = self.0.extend_from_slice(&buf[..size]);
+
+        Ok(size)
+    }
+}
+
+impl<const BODY_SIZE: usize> ErrorType for BodyWriter<'_, BODY_SIZE> {
+    type Error = ErrorKind;
+}
+
+impl<const BODY_SIZE: usize> embedded_io::Write for BodyWriter<'_, BODY_SIZE> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.append(buf)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "nonblocking")]
+impl<const BODY_SIZE: usize> embedded_io_async::Write for BodyWriter<'_, BODY_SIZE> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.append(buf)
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Maps the error of the sink of a response body, a full sink is reported as
+/// [AtError::CapacityError]
+pub(crate) fn sink_error<E: embedded_io::Error>(error: E) -> AtError {
+    match error.kind() {
+        ErrorKind::OutOfMemory => AtError::CapacityError,
+        _ => AtError::IOError,
+    }
 }
 
 #[cfg(test)]
@@ -398,6 +754,7 @@ mod test {
             host: "example.com",
             user: None,
             password: None,
+            tls: None,
         };
         let mut buffer: [u8; 512] = [0; 512];
 
@@ -412,6 +769,7 @@ mod test {
             host: "example.com",
             user: None,
             password: None,
+            tls: None,
         };
 
         let data = b"\r\n+CHTTPCREATE: 2\r\n\r\nOK\r\n";
@@ -465,6 +823,9 @@ mod test {
             client_id: 1,
             method: HttpMethod::GET,
             path: "/index.html",
+            headers: None,
+            content_type: None,
+            body: None,
         };
         let mut buffer: [u8; 512] = [0; 512];
 
@@ -479,6 +840,9 @@ mod test {
             client_id: 2,
             method: HttpMethod::POST,
             path: "/api",
+            headers: None,
+            content_type: None,
+            body: None,
         };
         let mut buffer: [u8; 512] = [0; 512];
 
@@ -486,4 +850,218 @@ mod test {
 
         assert_eq!(bytes, b"AT+CHTTPSEND=2,1,\"/api\"\r\n");
     }
+
+    #[test]
+    fn http_send_with_headers_and_body() {
+        let cmd = HttpSend {
+            client_id: 0,
+            method: HttpMethod::PUT,
+            path: "/api",
+            headers: Some("A: b\r\n"),
+            content_type: Some("application/json"),
+            body: Some(b"{}"),
+        };
+        let mut buffer: [u8; 512] = [0; 512];
+
+        cmd.validate().unwrap();
+        let bytes = cmd.get_command(&mut buffer).unwrap();
+
+        assert_eq!(
+            bytes,
+            b"AT+CHTTPSEND=0,2,\"/api\",413a20620d0a,\"application/json\",7b7d\r\n"
+        );
+
+        let cmd = HttpSend {
+            method: HttpMethod::GET,
+            content_type: None,
+            body: None,
+            ..cmd
+        };
+        assert_eq!(
+            cmd.get_command(&mut buffer).unwrap(),
+            b"AT+CHTTPSEND=0,0,\"/api\",413a20620d0a\r\n"
+        );
+    }
+
+    #[test]
+    fn http_send_validation() {
+        let get = HttpSend {
+            client_id: 0,
+            method: HttpMethod::GET,
+            path: "/",
+            headers: None,
+            content_type: None,
+            body: None,
+        };
+        get.validate().unwrap();
+
+        let invalid = [
+            HttpSend {
+                path: "",
+                ..get.clone()
+            },
+            HttpSend {
+                body: Some(b"{}"),
+                content_type: Some("text/plain"),
+                ..get.clone()
+            },
+            HttpSend {
+                method: HttpMethod::POST,
+                body: Some(b"{}"),
+                ..get.clone()
+            },
+            HttpSend {
+                headers: Some("A: b"),
+                ..get.clone()
+            },
+            HttpSend {
+                headers: Some("A: b\r\n\r\nC: d\r\n"),
+                ..get.clone()
+            },
+        ];
+        for cmd in invalid {
+            assert!(matches!(cmd.validate(), Err(AtError::InvalidParameter)));
+        }
+
+        let body = [0; MAX_BODY_SIZE + 1];
+        let cmd = HttpSend {
+            method: HttpMethod::POST,
+            content_type: Some("application/octet-stream"),
+            body: Some(&body),
+            ..get
+        };
+        assert!(matches!(cmd.validate(), Err(AtError::CapacityError)));
+    }
+
+    #[test]
+    fn http_response_headers() {
+        let response: HttpResponse<8> = HttpResponse {
+            status_code: 200,
+            headers: heapless::Vec::from_slice(
+                b"Content-Type: application/json\r\ninvalid\r\ncontent-length:  2\r\n",
+            )
+            .unwrap(),
+            body: heapless::Vec::from_slice(b"{}").unwrap(),
+        };
+
+        let mut headers = response.headers();
+        assert_eq!(headers.next(), Some(("Content-Type", "application/json")));
+        assert_eq!(headers.next(), Some(("content-length", "2")));
+        assert_eq!(headers.next(), None);
+        assert_eq!(response.header("Content-Length"), Some("2"));
+        assert_eq!(response.header("Authorization"), None);
+    }
+
+    #[test]
+    fn body_writer_reports_full_body() {
+        use embedded_io::Write;
+
+        let mut body: heapless::Vec<u8, 4> = heapless::Vec::new();
+        let mut writer = BodyWriter(&mut body);
+        writer.write_all(b"abc").unwrap();
+        assert!(matches!(
+            writer.write_all(b"de").map_err(sink_error),
+            Err(AtError::CapacityError)
+        ));
+        assert_eq!(body.as_slice(), b"abcd");
+    }
+
+    #[test]
+    fn http_request_command() {
+        let request = HttpRequest::post("/items", "application/json", b"{}")
+            .with_headers("Authorization: Bearer x\r\n");
+
+        assert_eq!(
+            request.command(3),
+            HttpSend {
+                client_id: 3,
+                method: HttpMethod::POST,
+                path: "/items",
+                headers: Some("Authorization: Bearer x\r\n"),
+                content_type: Some("application/json"),
+                body: Some(b"{}"),
+            }
+        );
+        assert_eq!(HttpRequest::delete("/items/1").command(0).body, None);
+    }
+
+    #[test]
+    fn create_https_session_command() {
+        let mut buffer = [0; 128];
+        let mut cmd = CreateHttpSession {
+            host: "https://example.com",
+            user: Some("user"),
+            password: Some("secret"),
+            tls: None,
+        };
+        assert_eq!(
+            cmd.get_command(&mut buffer).unwrap(),
+            b"AT+CHTTPCREATE=\"https://example.com\",\"user\",\"secret\"\r\n"
+        );
+
+        // The line breaks are escaped
+        cmd.user = None;
+        cmd.password = None;
+        cmd.tls = Some(HttpsCertificates {
+            server_certificate: Some("-----BEGIN\r\nCA\n"),
+            ..Default::default()
+        });
+        cmd.validate().unwrap();
+        assert_eq!(
+            cmd.get_command(&mut buffer).unwrap(),
+            b"AT+CHTTPCREATE=\"https://example.com\",\"\",\"\",15,\"-----BEGIN\\r\\nCA\\n\"\r\n"
+        );
+        assert_eq!(
+            cmd.parse_response_struct(b"\r\n+CHTTPCREATE: 1\r\n\r\nOK\r")
+                .unwrap(),
+            CreateHttpSessionResponse { client_id: 1 }
+        );
+
+        cmd.tls = Some(HttpsCertificates {
+            server_certificate: Some("CA"),
+            client_certificate: Some("CERT"),
+            client_key: Some("KEY"),
+        });
+        cmd.validate().unwrap();
+        let expected: &[u8] =
+            b"AT+CHTTPCREATE=\"https://example.com\",\"\",\"\",2,\"CA\",4,\"CERT\",3,\"KEY\"\r\n";
+        assert_eq!(cmd.get_command(&mut buffer).unwrap(), expected);
+        // Reports the size required by the certificates
+        let mut small = [0; 48];
+        assert_eq!(cmd.get_command(&mut small), Err(expected.len()));
+
+        cmd.host = "http://example.com";
+        assert!(matches!(cmd.validate(), Err(AtError::InvalidParameter)));
+    }
+
+    #[test]
+    fn https_certificates_validation() {
+        let invalid = [
+            HttpsCertificates {
+                client_certificate: Some("CERT"),
+                client_key: Some("KEY"),
+                ..Default::default()
+            },
+            HttpsCertificates {
+                server_certificate: Some("CA"),
+                client_certificate: Some("CERT"),
+                ..Default::default()
+            },
+            HttpsCertificates {
+                server_certificate: Some("\"CA\""),
+                ..Default::default()
+            },
+            HttpsCertificates {
+                server_certificate: Some(""),
+                ..Default::default()
+            },
+        ];
+        for certificates in invalid {
+            assert!(matches!(
+                certificates.validate(),
+                Err(AtError::InvalidParameter)
+            ));
+        }
+        HttpsCertificates::default().validate().unwrap();
+    }
 }
@@ -31,7 +31,9 @@ pub mod ceer;
 pub mod cgcontrdp;
 pub mod clock;
 pub mod cmee;
+pub mod credentials;
 pub mod csclk;
+pub mod dns;
 pub(crate) mod flow_control;
 pub mod http;
 pub mod ip_address;
@@ -44,6 +46,7 @@ pub mod pdp_context;
 pub mod power_saving_mode;
 pub mod sleep_indication;
 pub mod socket;
+pub mod tls;
 pub mod wireless;
 
 // We have to do this workaround because the derive causes deprecation warnings.
@@ -85,6 +88,13 @@ mod deprecated {
 #[deprecated(since = "3.0.0", note = "Now each type has it's own response type.")]
 pub type AtResponse = deprecated::AtResponse;
 
+/// Default time to wait for the response of a command
+pub const DEFAULT_TIMEOUT_MS: u32 = 5_000;
+/// Time to wait for the commands that are answered immediately by the module
+pub const SHORT_TIMEOUT_MS: u32 = 1_000;
+/// Time to wait for the commands that need to reach the network, such as connecting to a server
+pub const NETWORK_TIMEOUT_MS: u32 = 75_000;
+
 /// Defines a generic trait for the defined AT commands
 pub trait AtRequest {
     /// Type response of the command
@@ -110,6 +120,17 @@ pub trait AtRequest {
 
     /// Parses the given data and returns a [Result] which contains the [Response] type defined
     fn parse_response_struct(&self, data: &[u8]) -> Result<Self::Response, AtError>;
+
+    /// Maximum time in milliseconds to wait for the response of the command. After this time
+    /// the modem gives up with [AtError::Timeout]
+    fn timeout_ms(&self) -> u32 {
+        DEFAULT_TIMEOUT_MS
+    }
+
+    /// Checks the parameters of the request before it is sent to the module
+    fn validate(&self) -> Result<(), AtError> {
+        Ok(())
+    }
 }
 
 /// Verifies if the data contains an OK ignoring any leading whitespaces
@@ -122,11 +143,52 @@ pub(crate) fn verify_ok(data: &[u8]) -> Result<(), AtError> {
     Ok(())
 }
 
+/// Decodes the given hex string into `out`. Both lower and upper case digits are accepted
+pub(crate) fn decode_hex<const N: usize>(
+    hex: &[u8],
+    out: &mut heapless::Vec<u8, N>,
+) -> Result<(), AtError> {
+    fn nibble(c: u8) -> Result<u8, AtError> {
+        match c {
+            b'0'..=b'9' => Ok(c - b'0'),
+            b'a'..=b'f' => Ok(c - b'a' + 10),
+            b'A'..=b'F' => Ok(c - b'A' + 10),
+            _ => Err(AtError::AtParseError),
+        }
+    }
+
+    if !hex.len().is_multiple_of(2) {
+        return Err(AtError::AtParseError);
+    }
+
+    for pair in hex.chunks(2) {
+        let byte = (nibble(pair[0])? << 4) | nibble(pair[1])?;
+        out.push(byte).map_err(|_| AtError::CapacityError)?;
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod test {
 
     use super::*;
 
+    #[test]
+    fn test_decode_hex() {
+        let mut out: heapless::Vec<u8, 8> = heapless::Vec::new();
+        decode_hex(b"DEADbeef", &mut out).unwrap();
+        assert_eq!(out.as_slice(), &[0xDE, 0xAD, 0xBE, 0xEF]);
+    }
+
+    #[test]
+    fn test_decode_hex_errors() {
+        let mut out: heapless::Vec<u8, 1> = heapless::Vec::new();
+        assert!(decode_hex(b"abc", &mut out).is_err());
+        assert!(decode_hex(b"zz", &mut out).is_err());
+        assert!(decode_hex(b"0102", &mut out).is_err());
+    }
+
     #[test]
     fn test_very_ok() {
         const OK_1: &[u8] = b"\r\nOK";
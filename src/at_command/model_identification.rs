@@ -1,7 +1,7 @@
 //! Module to get the model identification information
-use crate::at_command::AtRequest;
 #[allow(deprecated)]
 use crate::at_command::AtResponse;
+use crate::at_command::{AtRequest, SHORT_TIMEOUT_MS};
 use crate::AtError;
 #[cfg(feature = "defmt")]
 use defmt::error;
@@ -61,6 +61,10 @@ impl AtRequest for ModelIdentification {
         let id = Self::get_model(data)?;
         Ok(ModelIdentificationResponse { model: id })
     }
+
+    fn timeout_ms(&self) -> u32 {
+        SHORT_TIMEOUT_MS
+    }
 }
 
 #[cfg(test)]
@@ -1,10 +1,12 @@
 //! Model to handle the MQTT request
 use crate::at_command::mqtt::MQTTSessionWrapper::Disconnected;
-use crate::at_command::AtRequest;
 #[allow(deprecated)]
 use crate::at_command::AtResponse;
+use crate::at_command::{decode_hex, AtRequest, NETWORK_TIMEOUT_MS};
+use crate::urc::{MqttPublication, URC_PAYLOAD_SIZE, URC_TOPIC_SIZE};
 use crate::{AtError, Modem};
 use at_commands::builder::CommandBuilder;
+use core::fmt::Write as _;
 #[cfg(feature = "defmt")]
 use defmt::{error, info};
 use embedded_hal::delay::DelayNs;
@@ -13,14 +15,81 @@ use embedded_io::{Read, ReadReady, Write};
 
 /// Maximum server length
 const MAX_SERVER_LEN: usize = 50;
+/// Maximum number of MQTT sessions listed by [ListMQTTSessions]
+pub const MAX_MQTT_SESSIONS: usize = 4;
+/// Maximum number of subscriptions remembered by a [Mqtt] session
+pub const MAX_SUBSCRIPTIONS: usize = 8;
+/// Maximum length of a topic
+pub const MAX_TOPIC_SIZE: usize = 128;
+/// Maximum length of the client id sent in `AT+CMQCON`
+pub const MAX_CLIENT_ID_SIZE: usize = 120;
+/// Maximum length of the user name and the password sent in `AT+CMQCON`
+pub const MAX_USERNAME_SIZE: usize = 100;
+/// Maximum length of the will message
+pub const MAX_WILL_MESSAGE_SIZE: usize = 256;
+/// Maximum keepalive interval in seconds
+pub const MAX_KEEPALIVE_INTERVAL: u16 = 64800;
+/// Size of the will options once formatted: the topic, the message and the names and values of
+/// the options
+const WILL_OPTIONS_SIZE: usize = MAX_TOPIC_SIZE + MAX_WILL_MESSAGE_SIZE + 64;
 
 /// MQTT errors
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
-#[derive(PartialEq, Clone)]
+#[derive(PartialEq, Clone, Debug)]
 pub enum MQTTError {
     ConnectionFailed,
     Disconnected,
     Publish,
+    /// No message has been received in time
+    Timeout,
+    /// The received message could not be read
+    Receive,
+    /// The topic could not be subscribed or the subscription could not be stored
+    Subscribe,
+    /// The topic could not be unsubscribed
+    Unsubscribe,
+}
+
+impl From<AtError> for MQTTError {
+    fn from(error: AtError) -> Self {
+        match error {
+            AtError::Timeout => MQTTError::Timeout,
+            _ => MQTTError::Receive,
+        }
+    }
+}
+
+/// Failed publish, keeping the error of the module
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug)]
+enum PublishFailure {
+    /// The session is not connected to the broker
+    Disconnected,
+    /// The module did not publish the message
+    Module(AtError),
+}
+
+impl PublishFailure {
+    /// Indicates if the connection to the broker may have been lost. Otherwise the module
+    /// rejected the message, e.g. for its QoS, which it would also do after reconnecting
+    fn link_lost(&self) -> bool {
+        matches!(
+            self,
+            PublishFailure::Disconnected
+                | PublishFailure::Module(
+                    AtError::IOError | AtError::Timeout | AtError::MqttFailure
+                )
+        )
+    }
+}
+
+impl From<PublishFailure> for MQTTError {
+    fn from(failure: PublishFailure) -> Self {
+        match failure {
+            PublishFailure::Disconnected => MQTTError::Disconnected,
+            PublishFailure::Module(_) => MQTTError::Publish,
+        }
+    }
 }
 
 /// The mqtt session
@@ -29,6 +98,73 @@ pub enum MQTTError {
 pub struct Mqtt<'a> {
     session_settings: &'a MQTTSessionSettings<'a>,
     session_wrapper: MQTTSessionWrapper,
+    /// Format of the received payloads, see [Mqtt::set_data_format]
+    data_format: MQTTDataFormat,
+    /// Subscribed topics, subscribed again when the session connects
+    subscriptions: MQTTSubscriptions,
+    /// Indicates that the session has already been connected, so the next connection is a
+    /// reconnection that must restore the subscriptions
+    connected_before: bool,
+}
+
+/// Topic subscribed by a [Mqtt] session
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(PartialEq, Clone, Debug)]
+pub struct MQTTSubscription {
+    pub topic: heapless::String<MAX_TOPIC_SIZE>,
+    pub qos: u8,
+}
+
+/// Topics subscribed by a session, shared by the [Mqtt] session and the async MQTT context
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(PartialEq, Clone, Debug, Default)]
+pub(crate) struct MQTTSubscriptions(heapless::Vec<MQTTSubscription, MAX_SUBSCRIPTIONS>);
+
+impl MQTTSubscriptions {
+    pub(crate) const fn new() -> Self {
+        Self(heapless::Vec::new())
+    }
+
+    /// Entry of the topic, checked before subscribing to it and stored with
+    /// [MQTTSubscriptions::store] once subscribed. Fails with [AtError::CapacityError] if the
+    /// topic is too long or it is not known and there are already [MAX_SUBSCRIPTIONS] topics
+    pub(crate) fn entry(&self, topic: &str, qos: u8) -> Result<MQTTSubscription, AtError> {
+        let known = self
+            .0
+            .iter()
+            .any(|subscription| subscription.topic == topic);
+        if !known && self.0.is_full() {
+            return Err(AtError::CapacityError);
+        }
+
+        Ok(MQTTSubscription {
+            topic: topic.try_into().map_err(|_| AtError::CapacityError)?,
+            qos,
+        })
+    }
+
+    /// Stores the entry, updating the QoS of a known topic
+    pub(crate) fn store(&mut self, entry: MQTTSubscription) {
+        match self
+            .0
+            .iter_mut()
+            .find(|subscription| subscription.topic == entry.topic)
+        {
+            Some(subscription) => subscription.qos = entry.qos,
+            // There is space, it has been checked by entry
+            None => {
+                let _ = self.0.push(entry);
+            }
+        }
+    }
+
+    pub(crate) fn remove(&mut self, topic: &str) {
+        self.0.retain(|subscription| subscription.topic != topic);
+    }
+
+    pub(crate) fn as_slice(&self) -> &[MQTTSubscription] {
+        &self.0
+    }
 }
 
 impl<'a> Mqtt<'a> {
@@ -38,39 +174,56 @@ impl<'a> Mqtt<'a> {
         Self {
             session_settings,
             session_wrapper,
+            data_format: MQTTDataFormat::Bytes,
+            subscriptions: MQTTSubscriptions::new(),
+            connected_before: false,
         }
     }
     /// Creates the MQTT session
-    pub fn create_session<T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs>(
+    pub fn create_session<
+        T: Write,
+        U: Read + ReadReady,
+        P: OutputPin,
+        D: DelayNs,
+        const N: usize,
+    >(
         self,
-        modem: &mut Modem<'_, T, U, P, D>,
+        modem: &mut Modem<'_, T, U, P, D, N>,
     ) -> Result<Self, MQTTError> {
         let session_wrapper = self
             .session_wrapper
             .create_session(modem, self.session_settings)?;
         Ok(Self {
-            session_settings: self.session_settings,
             session_wrapper,
+            ..self
         })
     }
 
-    /// Connects the MQTT session
-    pub fn connect<T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs>(
+    /// Connects the MQTT session. When the session has already been connected before, e.g. it
+    /// has been disconnected and created again, the known topics are subscribed again
+    pub fn connect<T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs, const N: usize>(
         self,
         connection_settings: MQTTConnectionSettings,
-        modem: &mut Modem<'_, T, U, P, D>,
+        modem: &mut Modem<'_, T, U, P, D, N>,
     ) -> Result<Self, MQTTError> {
+        let reconnecting = self.connected_before;
         let session_wrapper = self.session_wrapper.connect(modem, connection_settings)?;
-        Ok(Self {
-            session_settings: self.session_settings,
+        let mqtt = Self {
             session_wrapper,
-        })
+            connected_before: true,
+            ..self
+        };
+        if reconnecting {
+            mqtt.resubscribe(modem)?;
+        }
+
+        Ok(mqtt)
     }
 
     /// Disconnects the MQTT session
-    pub fn disconnect<T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs>(
+    pub fn disconnect<T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs, const N: usize>(
         self,
-        modem: &mut Modem<'_, T, U, P, D>,
+        modem: &mut Modem<'_, T, U, P, D, N>,
     ) -> Result<Self, MQTTError> {
         match self.session_wrapper {
             Disconnected(_) => Err(MQTTError::Disconnected),
@@ -78,26 +231,26 @@ impl<'a> Mqtt<'a> {
                 let session = session.disconnect(modem).expect("connect failed");
                 let session_wrapper = Disconnected(session);
                 Ok(Self {
-                    session_settings: self.session_settings,
                     session_wrapper,
+                    ..self
                 })
             }
             MQTTSessionWrapper::ConnectedGood(session) => {
                 let session = session.disconnect(modem).expect("connect failed");
                 let session_wrapper = Disconnected(session);
                 Ok(Self {
-                    session_settings: self.session_settings,
                     session_wrapper,
+                    ..self
                 })
             }
         }
     }
 
     /// Publish on a MQTT session
-    pub fn publish<T, U, P, D>(
+    pub fn publish<T, U, P, D, const N: usize>(
         &self,
         message: &MQTTMessage,
-        p1: &mut Modem<T, U, P, D>,
+        p1: &mut Modem<T, U, P, D, N>,
     ) -> Result<(), MQTTError>
     where
         T: Write,
@@ -105,7 +258,427 @@ impl<'a> Mqtt<'a> {
         P: OutputPin,
         D: DelayNs,
     {
-        self.session_wrapper.publish(message, p1)
+        Ok(self.session_wrapper.publish(message, p1)?)
+    }
+
+    /// Subscribes to the topic, which may contain wildcards, and remembers it so it is
+    /// subscribed again when the session connects. Subscribing again to a known topic updates
+    /// its QoS
+    pub fn subscribe<T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs, const N: usize>(
+        &mut self,
+        topic: &str,
+        qos: u8,
+        modem: &mut Modem<'_, T, U, P, D, N>,
+    ) -> Result<(), MQTTError> {
+        let mqtt_id = self.session_wrapper.connected_mqtt_id()?;
+        let entry = self
+            .subscriptions
+            .entry(topic, qos)
+            .map_err(|_| MQTTError::Subscribe)?;
+
+        modem
+            .send_and_wait_response(&MQTTSubscribe {
+                mqtt_id,
+                topic,
+                qos,
+            })
+            .map_err(|_| MQTTError::Subscribe)?;
+        self.subscriptions.store(entry);
+
+        Ok(())
+    }
+
+    /// Unsubscribes from the topic and forgets it
+    pub fn unsubscribe<T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs, const N: usize>(
+        &mut self,
+        topic: &str,
+        modem: &mut Modem<'_, T, U, P, D, N>,
+    ) -> Result<(), MQTTError> {
+        let mqtt_id = self.session_wrapper.connected_mqtt_id()?;
+        modem
+            .send_and_wait_response(&MQTTUnsubscribe { mqtt_id, topic })
+            .map_err(|_| MQTTError::Unsubscribe)?;
+        self.subscriptions.remove(topic);
+
+        Ok(())
+    }
+
+    /// Topics currently subscribed
+    pub fn subscriptions(&self) -> &[MQTTSubscription] {
+        self.subscriptions.as_slice()
+    }
+
+    /// Closes the session, if any, and creates and connects a new one, subscribing again to the
+    /// known topics. The subscriptions and the data format are kept
+    pub(crate) fn reconnect<
+        T: Write,
+        U: Read + ReadReady,
+        P: OutputPin,
+        D: DelayNs,
+        const N: usize,
+    >(
+        &mut self,
+        connection_settings: MQTTConnectionSettings,
+        modem: &mut Modem<'_, T, U, P, D, N>,
+    ) -> Result<(), MQTTError> {
+        let previous =
+            core::mem::replace(&mut self.session_wrapper, Disconnected(MQTTSession::new()));
+        if let Ok(mqtt_id) = previous.mqtt_id() {
+            // The module may have already released the session
+            let _ = modem.send_and_wait_response(&CloseMQTTConnection { mqtt_id });
+            let _ = modem.take_mqtt_disconnection(mqtt_id);
+        }
+
+        let created =
+            Disconnected(MQTTSession::new()).create_session(modem, self.session_settings)?;
+        let mqtt_id = created.mqtt_id()?;
+        match created.connect(modem, connection_settings) {
+            Ok(connected) => {
+                self.session_wrapper = connected;
+                self.connected_before = true;
+            }
+            Err(e) => {
+                let _ = modem.send_and_wait_response(&CloseMQTTConnection { mqtt_id });
+                return Err(e);
+            }
+        }
+
+        self.resubscribe(modem)
+    }
+
+    /// Subscribes again to the known topics, e.g. after the session has been reconnected
+    pub fn resubscribe<T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs, const N: usize>(
+        &self,
+        modem: &mut Modem<'_, T, U, P, D, N>,
+    ) -> Result<(), MQTTError> {
+        let mqtt_id = self.session_wrapper.connected_mqtt_id()?;
+        for subscription in self.subscriptions.as_slice() {
+            #[cfg(feature = "defmt")]
+            info!("Subscribing again to {}", subscription.topic);
+            modem
+                .send_and_wait_response(&MQTTSubscribe {
+                    mqtt_id,
+                    topic: &subscription.topic,
+                    qos: subscription.qos,
+                })
+                .map_err(|_| MQTTError::Subscribe)?;
+        }
+
+        Ok(())
+    }
+
+    /// Configures the format in which the module sends and receives the payloads. The received
+    /// payloads are decoded according to it. Until it is called the payloads are returned as
+    /// sent by the module
+    pub fn set_data_format<
+        T: Write,
+        U: Read + ReadReady,
+        P: OutputPin,
+        D: DelayNs,
+        const N: usize,
+    >(
+        &mut self,
+        data_format: MQTTDataFormat,
+        modem: &mut Modem<'_, T, U, P, D, N>,
+    ) -> Result<(), MQTTError> {
+        modem
+            .send_and_wait_response(&MQTTRawData { data_format })
+            .map_err(|_| MQTTError::ConnectionFailed)?;
+        self.data_format = data_format;
+        Ok(())
+    }
+
+    /// Returns the oldest message received in the subscribed topics, without waiting for more
+    /// messages
+    pub fn try_receive<T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs, const N: usize>(
+        &self,
+        modem: &mut Modem<'_, T, U, P, D, N>,
+    ) -> Result<Option<MQTTReceivedMessage>, MQTTError> {
+        let mqtt_id = self.session_wrapper.mqtt_id()?;
+        Ok(modem.try_receive_mqtt_message(mqtt_id, self.data_format)?)
+    }
+
+    /// Waits at most [timeout_ms] for a message in the subscribed topics
+    pub fn receive<T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs, const N: usize>(
+        &self,
+        modem: &mut Modem<'_, T, U, P, D, N>,
+        timeout_ms: u32,
+    ) -> Result<MQTTReceivedMessage, MQTTError> {
+        let mqtt_id = self.session_wrapper.mqtt_id()?;
+        Ok(modem.receive_mqtt_message(mqtt_id, self.data_format, timeout_ms)?)
+    }
+
+    /// Calls [callback] with each message received in the subscribed topics that has not been
+    /// received yet. Returns the number of messages
+    pub fn poll<T, U, P, D, F, const N: usize>(
+        &self,
+        modem: &mut Modem<'_, T, U, P, D, N>,
+        mut callback: F,
+    ) -> Result<usize, MQTTError>
+    where
+        T: Write,
+        U: Read + ReadReady,
+        P: OutputPin,
+        D: DelayNs,
+        F: FnMut(MQTTReceivedMessage),
+    {
+        let mut count = 0;
+        while let Some(message) = self.try_receive(modem)? {
+            callback(message);
+            count += 1;
+        }
+
+        Ok(count)
+    }
+}
+
+/// State of the connection of a [SupervisedMqtt] session
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum MQTTConnectionState {
+    Connected,
+    /// The session is being created and connected, the attempts start at 1
+    Reconnecting {
+        attempt: u8,
+    },
+    /// All the attempts to connect have failed, it is tried again on the next call
+    Disconnected,
+}
+
+/// Function called each time the state of a [SupervisedMqtt] session changes
+pub type MQTTStateHandler = fn(MQTTConnectionState);
+
+/// Exponential backoff between the attempts to reconnect a [SupervisedMqtt] session
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct MQTTBackoff {
+    /// Wait after the first failed attempt, doubled after each one
+    pub initial_delay_ms: u32,
+    pub max_delay_ms: u32,
+    /// Attempts before reporting [MQTTConnectionState::Disconnected]
+    pub max_attempts: u8,
+}
+
+impl Default for MQTTBackoff {
+    fn default() -> Self {
+        Self {
+            initial_delay_ms: 1000,
+            max_delay_ms: 60_000,
+            max_attempts: 5,
+        }
+    }
+}
+
+impl MQTTBackoff {
+    /// Wait after the given failed attempt, starting at 1
+    pub fn delay_ms(&self, attempt: u8) -> u32 {
+        let shift = u32::from(attempt.saturating_sub(1)).min(31);
+        self.initial_delay_ms
+            .saturating_mul(1 << shift)
+            .min(self.max_delay_ms)
+    }
+}
+
+/// MQTT session that is created and connected again, with the same subscriptions, when the
+/// module reports with `+CMQDISCON` that the broker has closed the connection or when a publish
+/// fails. The state changes are reported to the [MQTTStateHandler]
+pub struct SupervisedMqtt<'a> {
+    mqtt: Mqtt<'a>,
+    connection_settings: MQTTConnectionSettings<'a>,
+    backoff: MQTTBackoff,
+    state: MQTTConnectionState,
+    state_handler: Option<MQTTStateHandler>,
+}
+
+impl<'a> SupervisedMqtt<'a> {
+    /// Creates the supervisor, the session is created and connected with
+    /// [SupervisedMqtt::connect]
+    pub fn new(
+        session_settings: &'a MQTTSessionSettings<'a>,
+        connection_settings: MQTTConnectionSettings<'a>,
+    ) -> Self {
+        Self {
+            mqtt: Mqtt::new(session_settings),
+            connection_settings,
+            backoff: MQTTBackoff::default(),
+            state: MQTTConnectionState::Disconnected,
+            state_handler: None,
+        }
+    }
+
+    pub fn with_backoff(mut self, backoff: MQTTBackoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    pub fn with_state_handler(mut self, handler: MQTTStateHandler) -> Self {
+        self.state_handler = Some(handler);
+        self
+    }
+
+    pub fn state(&self) -> MQTTConnectionState {
+        self.state
+    }
+
+    /// The supervised session, e.g. to list its subscriptions
+    pub fn mqtt(&self) -> &Mqtt<'a> {
+        &self.mqtt
+    }
+
+    fn set_state(&mut self, state: MQTTConnectionState) {
+        if self.state != state {
+            #[cfg(feature = "defmt")]
+            info!("MQTT connection state: {:?}", state);
+            self.state = state;
+            if let Some(handler) = self.state_handler {
+                handler(state);
+            }
+        }
+    }
+
+    /// Creates and connects the session, waiting between the failed attempts according to the
+    /// [MQTTBackoff]
+    pub fn connect<T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs, const N: usize>(
+        &mut self,
+        modem: &mut Modem<'_, T, U, P, D, N>,
+    ) -> Result<(), MQTTError> {
+        for attempt in 1..=self.backoff.max_attempts {
+            self.set_state(MQTTConnectionState::Reconnecting { attempt });
+            match self.mqtt.reconnect(self.connection_settings.clone(), modem) {
+                Ok(()) => {
+                    self.set_state(MQTTConnectionState::Connected);
+                    return Ok(());
+                }
+                Err(_e) => {
+                    #[cfg(feature = "defmt")]
+                    error!("MQTT connection attempt {} failed: {:?}", attempt, _e);
+                    if attempt < self.backoff.max_attempts {
+                        modem.delay.delay_ms(self.backoff.delay_ms(attempt));
+                    }
+                }
+            }
+        }
+
+        self.set_state(MQTTConnectionState::Disconnected);
+        Err(MQTTError::ConnectionFailed)
+    }
+
+    /// Checks if the broker has closed the connection, reconnecting if needed. It should be
+    /// called periodically, the other methods call it too
+    pub fn poll<T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs, const N: usize>(
+        &mut self,
+        modem: &mut Modem<'_, T, U, P, D, N>,
+    ) -> Result<(), MQTTError> {
+        if let Ok(mqtt_id) = self.mqtt.session_wrapper.mqtt_id() {
+            let disconnected = modem
+                .take_mqtt_disconnection(mqtt_id)
+                .map_err(|_| MQTTError::ConnectionFailed)?;
+            if disconnected {
+                #[cfg(feature = "defmt")]
+                info!("MQTT session {} disconnected by the broker", mqtt_id);
+                self.set_state(MQTTConnectionState::Disconnected);
+            }
+        }
+
+        match self.state {
+            MQTTConnectionState::Connected => Ok(()),
+            _ => self.connect(modem),
+        }
+    }
+
+    /// Publishes the message. If the publish fails because the connection has been lost, the
+    /// session is reconnected and the message is published again
+    pub fn publish<T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs, const N: usize>(
+        &mut self,
+        message: &MQTTMessage,
+        modem: &mut Modem<'_, T, U, P, D, N>,
+    ) -> Result<(), MQTTError> {
+        self.poll(modem)?;
+        let failure = match self.mqtt.session_wrapper.publish(message, modem) {
+            Ok(()) => return Ok(()),
+            Err(failure) => failure,
+        };
+        let disconnected = match self.mqtt.session_wrapper.mqtt_id() {
+            Ok(mqtt_id) => modem.take_mqtt_disconnection(mqtt_id).unwrap_or(false),
+            Err(_) => true,
+        };
+        if !disconnected && !failure.link_lost() {
+            #[cfg(feature = "defmt")]
+            error!("MQTT message rejected by the module: {:?}", failure);
+            return Err(failure.into());
+        }
+
+        self.set_state(MQTTConnectionState::Disconnected);
+        self.connect(modem)?;
+        self.mqtt.publish(message, modem)
+    }
+
+    /// See [Mqtt::subscribe], the topic is subscribed again after reconnecting
+    pub fn subscribe<T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs, const N: usize>(
+        &mut self,
+        topic: &str,
+        qos: u8,
+        modem: &mut Modem<'_, T, U, P, D, N>,
+    ) -> Result<(), MQTTError> {
+        self.poll(modem)?;
+        self.mqtt.subscribe(topic, qos, modem)
+    }
+
+    pub fn unsubscribe<T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs, const N: usize>(
+        &mut self,
+        topic: &str,
+        modem: &mut Modem<'_, T, U, P, D, N>,
+    ) -> Result<(), MQTTError> {
+        self.poll(modem)?;
+        self.mqtt.unsubscribe(topic, modem)
+    }
+
+    /// See [Mqtt::set_data_format]
+    pub fn set_data_format<
+        T: Write,
+        U: Read + ReadReady,
+        P: OutputPin,
+        D: DelayNs,
+        const N: usize,
+    >(
+        &mut self,
+        data_format: MQTTDataFormat,
+        modem: &mut Modem<'_, T, U, P, D, N>,
+    ) -> Result<(), MQTTError> {
+        self.mqtt.set_data_format(data_format, modem)
+    }
+
+    /// Returns the oldest received message, without waiting for more messages
+    pub fn try_receive<T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs, const N: usize>(
+        &mut self,
+        modem: &mut Modem<'_, T, U, P, D, N>,
+    ) -> Result<Option<MQTTReceivedMessage>, MQTTError> {
+        self.poll(modem)?;
+        self.mqtt.try_receive(modem)
+    }
+
+    /// Waits at most [timeout_ms] for a message in the subscribed topics
+    pub fn receive<T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs, const N: usize>(
+        &mut self,
+        modem: &mut Modem<'_, T, U, P, D, N>,
+        timeout_ms: u32,
+    ) -> Result<MQTTReceivedMessage, MQTTError> {
+        self.poll(modem)?;
+        self.mqtt.receive(modem, timeout_ms)
+    }
+
+    /// Disconnects the session from the broker and closes it
+    pub fn disconnect<T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs, const N: usize>(
+        self,
+        modem: &mut Modem<'_, T, U, P, D, N>,
+    ) -> Result<(), MQTTError> {
+        let mqtt_id = self.mqtt.session_wrapper.mqtt_id()?;
+        modem
+            .send_and_wait_response(&CloseMQTTConnection { mqtt_id })
+            .map_err(|_| MQTTError::Disconnected)?;
+        modem.clear_mqtt_messages(mqtt_id);
+
+        Ok(())
     }
 }
 
@@ -119,10 +692,27 @@ enum MQTTSessionWrapper {
 }
 
 impl MQTTSessionWrapper {
+    /// Id of the session in the module, once it has been created
+    fn mqtt_id(&self) -> Result<u8, MQTTError> {
+        match self {
+            Disconnected(_) => Err(MQTTError::Disconnected),
+            MQTTSessionWrapper::Connected(session) => Ok(session.state.mqtt_id),
+            MQTTSessionWrapper::ConnectedGood(session) => Ok(session.state.mqtt_id),
+        }
+    }
+
+    /// Id of the session in the module, once it is connected to the broker
+    fn connected_mqtt_id(&self) -> Result<u8, MQTTError> {
+        match self {
+            MQTTSessionWrapper::ConnectedGood(session) => Ok(session.state.mqtt_id),
+            _ => Err(MQTTError::Disconnected),
+        }
+    }
+
     /// Create a new MQTT session
-    fn create_session<T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs>(
+    fn create_session<T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs, const N: usize>(
         self,
-        modem: &mut Modem<'_, T, U, P, D>,
+        modem: &mut Modem<'_, T, U, P, D, N>,
         session_settings: &MQTTSessionSettings,
     ) -> Result<MQTTSessionWrapper, MQTTError> {
         match self {
@@ -143,9 +733,9 @@ impl MQTTSessionWrapper {
     }
 
     /// Connects the MQTT session
-    fn connect<T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs>(
+    fn connect<T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs, const N: usize>(
         self,
-        modem: &mut Modem<'_, T, U, P, D>,
+        modem: &mut Modem<'_, T, U, P, D, N>,
         connection_settings: MQTTConnectionSettings,
     ) -> Result<MQTTSessionWrapper, MQTTError> {
         match self {
@@ -169,17 +759,22 @@ impl MQTTSessionWrapper {
     }
 
     /// Publish on the MQTT session
-    pub(crate) fn publish<T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs>(
+    pub(crate) fn publish<
+        T: Write,
+        U: Read + ReadReady,
+        P: OutputPin,
+        D: DelayNs,
+        const N: usize,
+    >(
         &self,
         p0: &MQTTMessage,
-        p1: &mut Modem<'_, T, U, P, D>,
-    ) -> Result<(), MQTTError> {
+        p1: &mut Modem<'_, T, U, P, D, N>,
+    ) -> Result<(), PublishFailure> {
         match self {
-            Disconnected(_) => Err(MQTTError::Disconnected),
-            MQTTSessionWrapper::Connected(_) => Err(MQTTError::Disconnected), // should be state where session established but not connected
+            Disconnected(_) => Err(PublishFailure::Disconnected),
+            MQTTSessionWrapper::Connected(_) => Err(PublishFailure::Disconnected), // should be state where session established but not connected
             MQTTSessionWrapper::ConnectedGood(session) => {
-                session.publish(p0, p1)?;
-                Ok(())
+                session.publish(p0, p1).map_err(PublishFailure::Module)
             }
         }
     }
@@ -225,9 +820,15 @@ impl MQTTSession<StateDisconnected> {
     }
 
     /// Creates a new MQTT session
-    pub fn create_session<T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs>(
+    pub fn create_session<
+        T: Write,
+        U: Read + ReadReady,
+        P: OutputPin,
+        D: DelayNs,
+        const N: usize,
+    >(
         self,
-        modem: &mut Modem<'_, T, U, P, D>,
+        modem: &mut Modem<'_, T, U, P, D, N>,
         session_settings: &MQTTSessionSettings,
     ) -> Result<MQTTSession<StateConnected>, AtError> {
         #[cfg(feature = "defmt")]
@@ -241,9 +842,9 @@ impl MQTTSession<StateDisconnected> {
 
 impl MQTTSession<StateConnected> {
     /// Disconnects the MQTT session
-    pub fn disconnect<T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs>(
+    pub fn disconnect<T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs, const N: usize>(
         &self,
-        modem: &mut Modem<'_, T, U, P, D>,
+        modem: &mut Modem<'_, T, U, P, D, N>,
     ) -> Result<MQTTSession<StateDisconnected>, AtError> {
         modem.send_and_wait_response(&CloseMQTTConnection {
             mqtt_id: self.state.mqtt_id,
@@ -254,9 +855,9 @@ impl MQTTSession<StateConnected> {
     }
 
     /// Connects the MQTT session
-    pub fn connect<T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs>(
+    pub fn connect<T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs, const N: usize>(
         self,
-        modem: &mut Modem<'_, T, U, P, D>,
+        modem: &mut Modem<'_, T, U, P, D, N>,
         connection_settings: MQTTConnectionSettings,
     ) -> Result<MQTTSession<StateConnectedGood>, AtError> {
         let mqtt_id = self.state.mqtt_id;
@@ -266,13 +867,34 @@ impl MQTTSession<StateConnected> {
             state: StateConnectedGood { mqtt_id },
         })
     }
+
+    /// Returns the oldest message received in the subscribed topics, decoding its payload
+    /// according to [data_format], without waiting for more messages
+    pub fn try_receive<T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs, const N: usize>(
+        &self,
+        modem: &mut Modem<'_, T, U, P, D, N>,
+        data_format: MQTTDataFormat,
+    ) -> Result<Option<MQTTReceivedMessage>, AtError> {
+        modem.try_receive_mqtt_message(self.state.mqtt_id, data_format)
+    }
+
+    /// Waits at most [timeout_ms] for a message in the subscribed topics, decoding its payload
+    /// according to [data_format]
+    pub fn receive<T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs, const N: usize>(
+        &self,
+        modem: &mut Modem<'_, T, U, P, D, N>,
+        data_format: MQTTDataFormat,
+        timeout_ms: u32,
+    ) -> Result<MQTTReceivedMessage, AtError> {
+        modem.receive_mqtt_message(self.state.mqtt_id, data_format, timeout_ms)
+    }
 }
 
 impl MQTTSession<StateConnectedGood> {
     /// Disconnects the MQTT session
-    fn disconnect<T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs>(
+    fn disconnect<T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs, const N: usize>(
         &self,
-        modem: &mut Modem<'_, T, U, P, D>,
+        modem: &mut Modem<'_, T, U, P, D, N>,
     ) -> Result<MQTTSession<StateDisconnected>, AtError> {
         modem.send_and_wait_response(&CloseMQTTConnection {
             mqtt_id: self.state.mqtt_id,
@@ -283,22 +905,40 @@ impl MQTTSession<StateConnectedGood> {
     }
 
     /// Publish on the MQTT
-    fn publish<T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs>(
+    fn publish<T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs, const N: usize>(
         &self,
         message: &MQTTMessage,
-        modem: &mut Modem<'_, T, U, P, D>,
-    ) -> Result<(), MQTTError> {
-        modem
-            .send_and_wait_response(&MQTTPublish {
-                mqtt_id: self.state.mqtt_id,
-                topic: message.topic,
-                qos: message.qos,
-                retained: message.retained,
-                dup: message.dup,
-                message: message.message,
-            })
-            .map_err(|_| MQTTError::Publish)?;
-        Ok(())
+        modem: &mut Modem<'_, T, U, P, D, N>,
+    ) -> Result<(), AtError> {
+        modem.send_and_wait_response(&MQTTPublish {
+            mqtt_id: self.state.mqtt_id,
+            topic: message.topic,
+            qos: message.qos,
+            retained: message.retained,
+            dup: message.dup,
+            message: message.message,
+        })
+    }
+
+    /// Returns the oldest message received in the subscribed topics, decoding its payload
+    /// according to [data_format], without waiting for more messages
+    pub fn try_receive<T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs, const N: usize>(
+        &self,
+        modem: &mut Modem<'_, T, U, P, D, N>,
+        data_format: MQTTDataFormat,
+    ) -> Result<Option<MQTTReceivedMessage>, AtError> {
+        modem.try_receive_mqtt_message(self.state.mqtt_id, data_format)
+    }
+
+    /// Waits at most [timeout_ms] for a message in the subscribed topics, decoding its payload
+    /// according to [data_format]
+    pub fn receive<T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs, const N: usize>(
+        &self,
+        modem: &mut Modem<'_, T, U, P, D, N>,
+        data_format: MQTTDataFormat,
+        timeout_ms: u32,
+    ) -> Result<MQTTReceivedMessage, AtError> {
+        modem.receive_mqtt_message(self.state.mqtt_id, data_format, timeout_ms)
     }
 }
 
@@ -311,10 +951,10 @@ pub enum MQTTConnection {
 }
 
 impl MQTTConnection {
-    pub fn publish<T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs>(
+    pub fn publish<T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs, const N: usize>(
         &self,
         message: &MQTTMessage,
-        modem: &mut Modem<'_, T, U, P, D>,
+        modem: &mut Modem<'_, T, U, P, D, N>,
     ) -> Result<(), MQTTError> {
         match self {
             MQTTConnection::Disconnected => Err(MQTTError::Disconnected),
@@ -344,6 +984,8 @@ pub struct MQTTSessionSettings<'a> {
     pub timeout_ms: u16,         // 0 - 60.000
     pub buffer_size: u16,        // 20 - 1132
     pub context_id: Option<u16>, // PDP context, AT+CGAT response
+    /// Creates the session over TLS with `AT+CMQTTSNEW`, otherwise it uses plain TCP
+    pub tls: bool,
 }
 
 impl MQTTSessionSettings<'_> {
@@ -356,6 +998,7 @@ impl MQTTSessionSettings<'_> {
             timeout_ms,
             buffer_size,
             context_id: None,
+            tls: false,
         }
     }
 
@@ -376,6 +1019,14 @@ impl MQTTSessionSettings<'_> {
         self.context_id = context_id;
         self
     }
+
+    /// Creates a TLS session with `AT+CMQTTSNEW`. The module uses the certificates and the key
+    /// stored with `AT+CSETCA`, see
+    /// [SetCredentialChunk](crate::at_command::credentials::SetCredentialChunk)
+    pub fn with_tls(mut self) -> Self {
+        self.tls = true;
+        self
+    }
 }
 
 /// The MQTT session id
@@ -386,10 +1037,14 @@ pub struct MqttSessionId {
 }
 
 impl MQTTSessionSettings<'_> {
-    fn get_session_id(data: &[u8]) -> Result<u8, AtError> {
+    fn get_session_id(&self, data: &[u8]) -> Result<u8, AtError> {
+        let identifier: &[u8] = match self.tls {
+            true => b"+CMQTTSNEW: ",
+            false => b"+CMQNEW: ",
+        };
         let (mqtt_id,) = at_commands::parser::CommandParser::parse(data)
             .trim_whitespace()
-            .expect_identifier(b"+CMQNEW: ")
+            .expect_identifier(identifier)
             .expect_int_parameter()
             .trim_whitespace()
             .expect_identifier(b"OK")
@@ -403,8 +1058,13 @@ impl AtRequest for MQTTSessionSettings<'_> {
     type Response = MqttSessionId;
 
     fn get_command<'a>(&'a self, buffer: &'a mut [u8]) -> Result<&'a [u8], usize> {
+        // Both commands have the same parameters in the SIM7020 AT command manual
+        let command = match self.tls {
+            true => "+CMQTTSNEW",
+            false => "+CMQNEW",
+        };
         CommandBuilder::create_set(buffer, true)
-            .named("+CMQNEW")
+            .named(command)
             .with_string_parameter(self.server)
             .with_int_parameter(self.port)
             .with_int_parameter(self.timeout_ms)
@@ -415,14 +1075,18 @@ impl AtRequest for MQTTSessionSettings<'_> {
 
     #[allow(deprecated)]
     fn parse_response(&self, data: &[u8]) -> Result<AtResponse, AtError> {
-        let mqtt_id = Self::get_session_id(data)?;
+        let mqtt_id = self.get_session_id(data)?;
         Ok(AtResponse::MQTTSessionCreated(mqtt_id))
     }
 
     fn parse_response_struct(&self, data: &[u8]) -> Result<Self::Response, AtError> {
-        let mqtt_id = Self::get_session_id(data)?;
+        let mqtt_id = self.get_session_id(data)?;
         Ok(MqttSessionId { mqtt_id })
     }
+
+    fn timeout_ms(&self) -> u32 {
+        NETWORK_TIMEOUT_MS
+    }
 }
 
 /// The used state
@@ -529,6 +1193,68 @@ impl AtRequest for CloseMQTTConnection {
     }
 }
 
+/// MQTT session of the module, listed by [ListMQTTSessions]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(PartialEq, Clone, Debug)]
+pub struct MQTTSessionInfo {
+    pub mqtt_id: u8,
+    /// Indicates that the session is connected to the broker
+    pub connected: bool,
+    pub server: heapless::String<MAX_SERVER_LEN>,
+}
+
+/// Command to list all the MQTT sessions of the module with `AT+CMQCON?`, e.g. the sessions
+/// left by a previous boot of the MCU
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(PartialEq, Clone)]
+pub struct ListMQTTSessions;
+
+impl AtRequest for ListMQTTSessions {
+    type Response = heapless::Vec<MQTTSessionInfo, MAX_MQTT_SESSIONS>;
+
+    fn get_command<'a>(&'a self, buffer: &'a mut [u8]) -> Result<&'a [u8], usize> {
+        CommandBuilder::create_query(buffer, true)
+            .named("+CMQCON")
+            .finish()
+    }
+
+    fn parse_response_struct(&self, data: &[u8]) -> Result<Self::Response, AtError> {
+        let mut sessions = heapless::Vec::new();
+        let mut ok = false;
+
+        for line in data.split(|&byte| byte == b'\n') {
+            let line = crate::urc::trim_line(line);
+            if line.is_empty() {
+                continue;
+            }
+            if line == b"OK" {
+                ok = true;
+                continue;
+            }
+
+            let (mqtt_id, connected, server) = at_commands::parser::CommandParser::parse(line)
+                .expect_identifier(b"+CMQCON: ")
+                .expect_int_parameter()
+                .expect_int_parameter()
+                .expect_string_parameter()
+                .finish()?;
+            sessions
+                .push(MQTTSessionInfo {
+                    mqtt_id: mqtt_id as u8,
+                    connected: connected == 1,
+                    server: server.try_into()?,
+                })
+                .map_err(|_| AtError::CapacityError)?;
+        }
+
+        if !ok {
+            return Err(AtError::AtParseError);
+        }
+
+        Ok(sessions)
+    }
+}
+
 /// MQTT versions
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[derive(PartialEq, Clone)]
@@ -538,26 +1264,64 @@ pub enum MQTTVersion {
     MQTT311,
 }
 
-/// Options for MQTT will
+/// Options for MQTT will, the message published by the broker when the client disconnects
+/// ungracefully
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[derive(PartialEq, Clone)]
 pub struct WillOptions<'a> {
+    /// Topic without wildcards, at most [MAX_TOPIC_SIZE] bytes
     pub topic: &'a str,
     pub quality_of_service: u8,
     pub retained: bool,
+    /// At most [MAX_WILL_MESSAGE_SIZE] bytes, it can not contain quotes
+    pub message: &'a str,
+}
+
+impl WillOptions<'_> {
+    fn validate(&self) -> Result<(), AtError> {
+        if self.topic.len() > MAX_TOPIC_SIZE || self.message.len() > MAX_WILL_MESSAGE_SIZE {
+            return Err(AtError::CapacityError);
+        }
+        // The options are sent as a single string whose fields are separated by commas
+        if self.topic.is_empty()
+            || self.topic.contains(['+', '#', ',', '"'])
+            || self.message.contains('"')
+            || self.quality_of_service > 2
+        {
+            return Err(AtError::InvalidParameter);
+        }
+
+        Ok(())
+    }
+
+    /// Formats the options as expected by `AT+CMQCON`
+    fn format(&self) -> Result<heapless::String<WILL_OPTIONS_SIZE>, core::fmt::Error> {
+        let mut options = heapless::String::new();
+        write!(
+            options,
+            "topic={},QoS={},retained={},message_len={},message={}",
+            self.topic,
+            self.quality_of_service,
+            self.retained as u8,
+            self.message.len(),
+            self.message
+        )?;
+
+        Ok(options)
+    }
 }
 
 /// Command to connect to MQTT with different options
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[derive(PartialEq, Clone)]
-struct MQTTConnectionSettingsWithID<'a> {
+pub(crate) struct MQTTConnectionSettingsWithID<'a> {
     mqtt_id: u8,
     version: MQTTVersion,
     client_id: &'a str,
     keepalive_interval: u16, // 0 - 64800
     clean_session: bool,
     will_flag: bool,
-    // pub will_options: Option<WillOptions>,
+    will_options: Option<WillOptions<'a>>,
     username: &'a str,
     password: &'a str,
 }
@@ -570,14 +1334,15 @@ pub struct MQTTConnectionSettings<'a> {
     pub client_id: &'a str,
     pub keepalive_interval: u16, // 0 - 64800
     pub clean_session: bool,
+    /// Must be set if and only if [MQTTConnectionSettings::will_options] are given
     pub will_flag: bool,
-    // pub will_options: Option<WillOptions>,
+    pub will_options: Option<WillOptions<'a>>,
     pub username: &'a str,
     pub password: &'a str,
 }
 
 impl<'a> MQTTConnectionSettings<'a> {
-    fn with_mqtt_id(self, mqtt_id: u8) -> MQTTConnectionSettingsWithID<'a> {
+    pub(crate) fn with_mqtt_id(self, mqtt_id: u8) -> MQTTConnectionSettingsWithID<'a> {
         MQTTConnectionSettingsWithID {
             mqtt_id,
             version: self.version,
@@ -585,6 +1350,7 @@ impl<'a> MQTTConnectionSettings<'a> {
             keepalive_interval: self.keepalive_interval,
             clean_session: self.clean_session,
             will_flag: self.will_flag,
+            will_options: self.will_options,
             username: self.username,
             password: self.password,
         }
@@ -599,27 +1365,59 @@ impl AtRequest for MQTTConnectionSettingsWithID<'_> {
             MQTTVersion::MQTT31 => 3,
             MQTTVersion::MQTT311 => 4,
         };
-        CommandBuilder::create_set(buffer, true)
+        let builder = CommandBuilder::create_set(buffer, true)
             .named("+CMQCON")
             .with_int_parameter(self.mqtt_id)
             .with_int_parameter(version)
             .with_string_parameter(self.client_id)
             .with_int_parameter(self.keepalive_interval)
             .with_int_parameter(self.clean_session as u8)
-            .with_int_parameter(self.will_flag as u8)
+            .with_int_parameter(self.will_flag as u8);
+        let builder = match &self.will_options {
+            Some(will_options) => {
+                // The options have been validated, so they fit
+                let options = will_options.format().map_err(|_| 0usize)?;
+                builder.with_string_parameter(options)
+            }
+            None => builder,
+        };
+        builder
             .with_string_parameter(self.username)
             .with_string_parameter(self.password)
             .finish()
     }
 
+    fn validate(&self) -> Result<(), AtError> {
+        if self.client_id.len() > MAX_CLIENT_ID_SIZE
+            || self.username.len() > MAX_USERNAME_SIZE
+            || self.password.len() > MAX_USERNAME_SIZE
+        {
+            return Err(AtError::CapacityError);
+        }
+        if self.keepalive_interval > MAX_KEEPALIVE_INTERVAL
+            || self.will_flag != self.will_options.is_some()
+        {
+            return Err(AtError::InvalidParameter);
+        }
+
+        match &self.will_options {
+            Some(will_options) => will_options.validate(),
+            None => Ok(()),
+        }
+    }
+
     fn parse_response_struct(&self, _data: &[u8]) -> Result<Self::Response, AtError> {
         Ok(())
     }
+
+    fn timeout_ms(&self) -> u32 {
+        NETWORK_TIMEOUT_MS
+    }
 }
 
 /// MQTT format of the data
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
-#[derive(PartialEq, Clone)]
+#[derive(PartialEq, Clone, Copy, Debug)]
 pub enum MQTTDataFormat {
     Bytes,
     Hex,
@@ -652,6 +1450,45 @@ impl AtRequest for MQTTRawData {
     }
 }
 
+/// Message received in a subscribed topic
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(PartialEq, Clone, Debug)]
+pub struct MQTTReceivedMessage {
+    pub mqtt_id: u8,
+    pub topic: heapless::String<URC_TOPIC_SIZE>,
+    pub qos: u8,
+    pub retained: bool,
+    pub dup: bool,
+    /// The payload already decoded from hex when the data format is [MQTTDataFormat::Hex]
+    pub payload: heapless::Vec<u8, URC_PAYLOAD_SIZE>,
+}
+
+impl MQTTReceivedMessage {
+    /// Decodes the payload of the publication according to the configured data format
+    pub fn decode(
+        publication: MqttPublication,
+        data_format: MQTTDataFormat,
+    ) -> Result<Self, AtError> {
+        let payload = match data_format {
+            MQTTDataFormat::Bytes => publication.payload,
+            MQTTDataFormat::Hex => {
+                let mut payload = heapless::Vec::new();
+                decode_hex(&publication.payload, &mut payload)?;
+                payload
+            }
+        };
+
+        Ok(Self {
+            mqtt_id: publication.mqtt_id,
+            topic: publication.topic,
+            qos: publication.qos,
+            retained: publication.retained,
+            dup: publication.dup,
+            payload,
+        })
+    }
+}
+
 /// Publish a message via mqtt
 ///
 /// The message length has to be between 2 and 1000 byte.
@@ -698,6 +1535,10 @@ impl AtRequest for MQTTPublish<'_> {
     fn parse_response_struct(&self, _data: &[u8]) -> Result<Self::Response, AtError> {
         Ok(())
     }
+
+    fn timeout_ms(&self) -> u32 {
+        NETWORK_TIMEOUT_MS
+    }
 }
 
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -723,11 +1564,48 @@ impl AtRequest for MQTTSubscribe<'_> {
     fn parse_response_struct(&self, _data: &[u8]) -> Result<Self::Response, AtError> {
         Ok(())
     }
+
+    fn timeout_ms(&self) -> u32 {
+        NETWORK_TIMEOUT_MS
+    }
+}
+
+/// Unsubscribes the MQTT session from a topic
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(PartialEq, Clone)]
+pub struct MQTTUnsubscribe<'a> {
+    pub mqtt_id: u8,
+    pub topic: &'a str,
+}
+
+impl AtRequest for MQTTUnsubscribe<'_> {
+    type Response = ();
+
+    fn get_command<'a>(&'a self, buffer: &'a mut [u8]) -> Result<&'a [u8], usize> {
+        CommandBuilder::create_set(buffer, true)
+            .named("+CMQUNSUB")
+            .with_int_parameter(self.mqtt_id)
+            .with_string_parameter(self.topic)
+            .finish()
+    }
+
+    fn parse_response_struct(&self, _data: &[u8]) -> Result<Self::Response, AtError> {
+        Ok(())
+    }
+
+    fn timeout_ms(&self) -> u32 {
+        NETWORK_TIMEOUT_MS
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::at_command::credentials::CredentialType;
+    use crate::at_command::wireless::StartWirelessConnection;
+    use crate::emulator::Sim7020Emulator;
+    use crate::testing::{registered_modem, test_connection_settings, NoopDelay, NoopPin};
+    use crate::Modem;
 
     const TEST_SERVER: &str = "mqtt.example.com";
 
@@ -774,14 +1652,29 @@ mod test {
     #[test]
     fn mqtt_session_settings_parse_session_id_success() {
         let data = b"+CMQNEW: 3\r\nOK";
-        let id = MQTTSessionSettings::get_session_id(data).unwrap();
+        let id = MQTTSessionSettings::new(TEST_SERVER, 1883)
+            .get_session_id(data)
+            .unwrap();
         assert_eq!(id, 3);
     }
 
+    #[test]
+    fn mqtt_tls_session_settings() {
+        let settings = MQTTSessionSettings::new(TEST_SERVER, 8883).with_tls();
+        let mut buffer: [u8; 512] = [0; 512];
+        let cmd = settings.get_command(&mut buffer).unwrap();
+
+        assert_eq!(cmd, b"AT+CMQTTSNEW=\"mqtt.example.com\",8883,5000,600\r\n");
+        assert_eq!(settings.get_session_id(b"+CMQTTSNEW: 2\r\nOK").unwrap(), 2);
+        assert!(settings.get_session_id(b"+CMQNEW: 2\r\nOK").is_err());
+    }
+
     #[test]
     fn mqtt_session_settings_parse_session_id_failure() {
         let data = b"+CMQNEW: \r\nOK";
-        assert!(MQTTSessionSettings::get_session_id(data).is_err());
+        assert!(MQTTSessionSettings::new(TEST_SERVER, 1883)
+            .get_session_id(data)
+            .is_err());
     }
 
     #[test]
@@ -804,6 +1697,37 @@ mod test {
         assert!(cmd.starts_with(b"AT+CMQDISCON=5"));
     }
 
+    #[test]
+    fn list_mqtt_sessions() {
+        let mut buffer: [u8; 512] = [0; 512];
+        let cmd = ListMQTTSessions.get_command(&mut buffer).unwrap();
+        assert_eq!(cmd, b"AT+CMQCON?\r\n");
+
+        let data =
+            b"\r\n+CMQCON: 0,1,\"mqtt.example.com\"\r\n+CMQCON: 2,0,\"10.0.0.1\"\r\n\r\nOK\r\n";
+        let sessions = ListMQTTSessions.parse_response_struct(data).unwrap();
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(
+            sessions[0],
+            MQTTSessionInfo {
+                mqtt_id: 0,
+                connected: true,
+                server: "mqtt.example.com".try_into().unwrap(),
+            }
+        );
+        assert_eq!(sessions[1].mqtt_id, 2);
+        assert!(!sessions[1].connected);
+
+        let sessions = ListMQTTSessions
+            .parse_response_struct(b"\r\nOK\r\n")
+            .unwrap();
+        assert!(sessions.is_empty());
+        assert!(matches!(
+            ListMQTTSessions.parse_response_struct(b"\r\n+CMQCON: 0,1,\"a\"\r\n"),
+            Err(AtError::AtParseError)
+        ));
+    }
+
     #[test]
     fn mqtt_connection_settings_with_id_get_command() {
         let base = MQTTConnectionSettings {
@@ -812,6 +1736,7 @@ mod test {
             keepalive_interval: 60,
             clean_session: true,
             will_flag: false,
+            will_options: None,
             username: "user",
             password: "pass",
         };
@@ -824,6 +1749,137 @@ mod test {
         assert!(cmd.windows(b"user".len()).any(|w| w == b"user"));
     }
 
+    #[test]
+    fn mqtt_connection_settings_with_will() {
+        let settings = MQTTConnectionSettings {
+            version: MQTTVersion::MQTT311,
+            client_id: "device",
+            keepalive_interval: 60,
+            clean_session: true,
+            will_flag: true,
+            will_options: Some(WillOptions {
+                topic: "devices/device/status",
+                quality_of_service: 1,
+                retained: true,
+                message: "offline, unexpectedly",
+            }),
+            username: "",
+            password: "",
+        }
+        .with_mqtt_id(0);
+        let mut buffer: [u8; 512] = [0; 512];
+        let cmd = settings.get_command(&mut buffer).unwrap();
+
+        assert_eq!(
+            cmd,
+            b"AT+CMQCON=0,4,\"device\",60,1,1,\"topic=devices/device/status,QoS=1,retained=1,\
+            message_len=21,message=offline, unexpectedly\",\"\",\"\"\r\n"
+        );
+        assert!(settings.validate().is_ok());
+    }
+
+    #[test]
+    fn mqtt_connection_settings_validation() {
+        let will = WillOptions {
+            topic: "status",
+            quality_of_service: 0,
+            retained: false,
+            message: "offline",
+        };
+        let settings = MQTTConnectionSettings {
+            version: MQTTVersion::MQTT311,
+            client_id: "device",
+            keepalive_interval: 60,
+            clean_session: true,
+            will_flag: true,
+            will_options: Some(will.clone()),
+            username: "",
+            password: "",
+        };
+        let long = core::str::from_utf8(&[b'a'; MAX_WILL_MESSAGE_SIZE + 1]).unwrap();
+
+        let invalid = [
+            MQTTConnectionSettings {
+                will_flag: false,
+                ..settings.clone()
+            },
+            MQTTConnectionSettings {
+                will_options: None,
+                ..settings.clone()
+            },
+            MQTTConnectionSettings {
+                keepalive_interval: MAX_KEEPALIVE_INTERVAL + 1,
+                ..settings.clone()
+            },
+            MQTTConnectionSettings {
+                will_options: Some(WillOptions {
+                    topic: "status/+",
+                    ..will.clone()
+                }),
+                ..settings.clone()
+            },
+            MQTTConnectionSettings {
+                will_options: Some(WillOptions {
+                    quality_of_service: 3,
+                    ..will.clone()
+                }),
+                ..settings.clone()
+            },
+            MQTTConnectionSettings {
+                will_options: Some(WillOptions {
+                    message: "\"offline\"",
+                    ..will.clone()
+                }),
+                ..settings.clone()
+            },
+        ];
+        for settings in invalid {
+            assert!(matches!(
+                settings.with_mqtt_id(0).validate(),
+                Err(AtError::InvalidParameter)
+            ));
+        }
+
+        let too_long = [
+            MQTTConnectionSettings {
+                client_id: &long[..MAX_CLIENT_ID_SIZE + 1],
+                ..settings.clone()
+            },
+            MQTTConnectionSettings {
+                password: &long[..MAX_USERNAME_SIZE + 1],
+                ..settings.clone()
+            },
+            MQTTConnectionSettings {
+                will_options: Some(WillOptions {
+                    message: long,
+                    ..will.clone()
+                }),
+                ..settings.clone()
+            },
+        ];
+        for settings in too_long {
+            assert!(matches!(
+                settings.with_mqtt_id(0).validate(),
+                Err(AtError::CapacityError)
+            ));
+        }
+    }
+
+    #[test]
+    fn mqtt_backoff_delay() {
+        let backoff = MQTTBackoff {
+            initial_delay_ms: 500,
+            max_delay_ms: 3000,
+            max_attempts: 10,
+        };
+
+        assert_eq!(backoff.delay_ms(1), 500);
+        assert_eq!(backoff.delay_ms(2), 1000);
+        assert_eq!(backoff.delay_ms(3), 2000);
+        assert_eq!(backoff.delay_ms(4), 3000);
+        assert_eq!(backoff.delay_ms(u8::MAX), 3000);
+    }
+
     #[test]
     fn mqtt_raw_data_bytes() {
         let raw = MQTTRawData {
@@ -875,4 +1931,410 @@ mod test {
         assert!(cmd.windows(b"+CMQSUB".len()).any(|w| w == b"+CMQSUB"));
         assert!(cmd.windows(b"topic".len()).any(|w| w == b"topic"));
     }
+
+    #[test]
+    fn mqtt_unsubscribe_get_command() {
+        let unsub = MQTTUnsubscribe {
+            mqtt_id: 1,
+            topic: "sensors/+",
+        };
+        let mut buffer: [u8; 512] = [0; 512];
+        let cmd = unsub.get_command(&mut buffer).unwrap();
+
+        assert_eq!(cmd, b"AT+CMQUNSUB=1,\"sensors/+\"\r\n");
+    }
+
+    #[test]
+    fn mqtt_received_message_decode() {
+        let publication = MqttPublication {
+            mqtt_id: 1,
+            topic: heapless::String::try_from("sensors/temp").unwrap(),
+            qos: 1,
+            retained: true,
+            dup: false,
+            payload: heapless::Vec::from_slice(b"32312e35").unwrap(),
+        };
+
+        let message =
+            MQTTReceivedMessage::decode(publication.clone(), MQTTDataFormat::Hex).unwrap();
+        assert_eq!(message.mqtt_id, 1);
+        assert_eq!(message.topic.as_str(), "sensors/temp");
+        assert_eq!(message.qos, 1);
+        assert!(message.retained);
+        assert_eq!(message.payload.as_slice(), b"21.5");
+
+        let message =
+            MQTTReceivedMessage::decode(publication.clone(), MQTTDataFormat::Bytes).unwrap();
+        assert_eq!(message.payload.as_slice(), b"32312e35");
+
+        let publication = MqttPublication {
+            payload: heapless::Vec::from_slice(b"2x").unwrap(),
+            ..publication
+        };
+        assert!(matches!(
+            MQTTReceivedMessage::decode(publication, MQTTDataFormat::Hex),
+            Err(AtError::AtParseError)
+        ));
+    }
+
+    #[test]
+    fn test_mqtt_publish_is_delivered_to_subscribers() {
+        let emulator = Sim7020Emulator::new();
+        let (mut writer, mut reader) = emulator.split();
+        let mut modem = registered_modem(&mut writer, &mut reader);
+
+        let settings = MQTTSessionSettings::new("broker.example.com", 1883);
+        let mut mqtt = Mqtt::new(&settings)
+            .create_session(&mut modem)
+            .unwrap()
+            .connect(test_connection_settings(), &mut modem)
+            .unwrap();
+        let session = emulator.mqtt_session(0).unwrap();
+        assert_eq!(session.server.as_str(), "broker.example.com");
+        assert!(session.connected);
+
+        modem
+            .send_and_wait_response(&MQTTSubscribe {
+                mqtt_id: 0,
+                topic: "sensors/+",
+                qos: 0,
+            })
+            .unwrap();
+        mqtt.publish(
+            &MQTTMessage {
+                topic: "sensors/temp",
+                qos: 0,
+                retained: false,
+                dup: false,
+                message: b"2150",
+            },
+            &mut modem,
+        )
+        .unwrap();
+        let message = mqtt.try_receive(&mut modem).unwrap().unwrap();
+        assert_eq!(message.topic.as_str(), "sensors/temp");
+        assert_eq!(message.payload.as_slice(), b"2150");
+        assert!(modem.next_urc().is_none());
+        assert_eq!(mqtt.try_receive(&mut modem), Ok(None));
+
+        // The payloads are sent and received as hex
+        mqtt.set_data_format(MQTTDataFormat::Hex, &mut modem)
+            .unwrap();
+        for message in [b"32313530", b"32313630"] {
+            mqtt.publish(
+                &MQTTMessage {
+                    topic: "sensors/temp",
+                    qos: 0,
+                    retained: false,
+                    dup: false,
+                    message,
+                },
+                &mut modem,
+            )
+            .unwrap();
+        }
+        let message = mqtt.receive(&mut modem, 1000).unwrap();
+        assert_eq!(message.payload.as_slice(), b"2150");
+
+        let mut payloads: heapless::Vec<_, 2> = heapless::Vec::new();
+        let count = mqtt
+            .poll(&mut modem, |message| {
+                payloads.push(message.payload).unwrap();
+            })
+            .unwrap();
+        assert_eq!(count, 1);
+        assert_eq!(payloads[0].as_slice(), b"2160");
+        assert_eq!(mqtt.receive(&mut modem, 10), Err(MQTTError::Timeout));
+    }
+
+    #[test]
+    fn test_stale_mqtt_sessions_are_closed_on_startup() {
+        let emulator = Sim7020Emulator::new();
+        let settings = MQTTSessionSettings::new("broker.example.com", 1883);
+        {
+            let (mut writer, mut reader) = emulator.split();
+            let mut modem = registered_modem(&mut writer, &mut reader);
+            let _ = Mqtt::new(&settings)
+                .create_session(&mut modem)
+                .unwrap()
+                .connect(test_connection_settings(), &mut modem)
+                .unwrap();
+        }
+
+        // The MCU has been reset, the module still holds the session
+        let (mut writer, mut reader) = emulator.split();
+        let mut modem = Modem::new(&mut writer, &mut reader, NoopPin, NoopPin, NoopDelay).unwrap();
+        let sessions = modem.send_and_wait_response(&ListMQTTSessions).unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].mqtt_id, 0);
+        assert!(sessions[0].connected);
+        assert_eq!(sessions[0].server.as_str(), "broker.example.com");
+        assert!(Mqtt::new(&settings).create_session(&mut modem).is_err());
+
+        assert_eq!(modem.close_stale_mqtt_sessions().unwrap(), 1);
+        assert!(emulator.mqtt_session(0).is_none());
+        assert!(modem
+            .send_and_wait_response(&ListMQTTSessions)
+            .unwrap()
+            .is_empty());
+        assert_eq!(modem.close_stale_mqtt_sessions().unwrap(), 0);
+        assert!(Mqtt::new(&settings).create_session(&mut modem).is_ok());
+    }
+
+    #[test]
+    fn test_subscription_table() {
+        let mut subscriptions = MQTTSubscriptions::new();
+        for index in 0..MAX_SUBSCRIPTIONS {
+            let topic = std::format!("sensors/{index}");
+            let entry = subscriptions.entry(&topic, 0).unwrap();
+            subscriptions.store(entry);
+        }
+
+        // A known topic is updated even if the table is full
+        let entry = subscriptions.entry("sensors/1", 2).unwrap();
+        subscriptions.store(entry);
+        assert_eq!(subscriptions.as_slice()[1].qos, 2);
+        assert!(matches!(
+            subscriptions.entry("alarms", 0),
+            Err(AtError::CapacityError)
+        ));
+
+        subscriptions.remove("sensors/0");
+        assert_eq!(subscriptions.as_slice().len(), MAX_SUBSCRIPTIONS - 1);
+        assert!(matches!(
+            subscriptions.entry(&"a".repeat(MAX_TOPIC_SIZE + 1), 0),
+            Err(AtError::CapacityError)
+        ));
+        assert!(subscriptions.entry("alarms", 0).is_ok());
+    }
+
+    #[test]
+    fn test_mqtt_subscriptions_are_restored_on_reconnect() {
+        let emulator = Sim7020Emulator::new();
+        let (mut writer, mut reader) = emulator.split();
+        let mut modem = registered_modem(&mut writer, &mut reader);
+        let connection_settings = test_connection_settings();
+
+        let settings = MQTTSessionSettings::new("broker.example.com", 1883);
+        let mut mqtt = Mqtt::new(&settings);
+        assert_eq!(
+            mqtt.subscribe("sensors/+", 0, &mut modem),
+            Err(MQTTError::Disconnected)
+        );
+        let mut mqtt = mqtt
+            .create_session(&mut modem)
+            .unwrap()
+            .connect(connection_settings.clone(), &mut modem)
+            .unwrap();
+
+        mqtt.subscribe("sensors/+", 0, &mut modem).unwrap();
+        mqtt.subscribe("alarms/#", 1, &mut modem).unwrap();
+        mqtt.subscribe("sensors/+", 1, &mut modem).unwrap();
+        mqtt.subscribe("commands", 2, &mut modem).unwrap();
+        mqtt.unsubscribe("alarms/#", &mut modem).unwrap();
+        let subscriptions = mqtt.subscriptions();
+        assert_eq!(subscriptions.len(), 2);
+        assert_eq!(
+            (subscriptions[0].topic.as_str(), subscriptions[0].qos),
+            ("sensors/+", 1)
+        );
+        assert_eq!(
+            (subscriptions[1].topic.as_str(), subscriptions[1].qos),
+            ("commands", 2)
+        );
+        assert_eq!(
+            emulator.mqtt_session(0).unwrap().subscriptions.as_slice(),
+            ["sensors/+", "commands"]
+        );
+
+        let mqtt = mqtt
+            .disconnect(&mut modem)
+            .unwrap()
+            .create_session(&mut modem)
+            .unwrap();
+        assert!(emulator.mqtt_session(0).unwrap().subscriptions.is_empty());
+
+        let mqtt = mqtt.connect(connection_settings, &mut modem).unwrap();
+        assert_eq!(mqtt.subscriptions().len(), 2);
+        assert_eq!(
+            emulator.mqtt_session(0).unwrap().subscriptions.as_slice(),
+            ["sensors/+", "commands"]
+        );
+    }
+
+    #[test]
+    fn test_mqtt_tls_session() {
+        let emulator = Sim7020Emulator::new();
+        let (mut writer, mut reader) = emulator.split();
+        let mut modem = registered_modem(&mut writer, &mut reader);
+        // The CA certificate has not been stored yet
+        let settings = MQTTSessionSettings::new("broker.example.com", 8883).with_tls();
+        assert!(matches!(
+            Mqtt::new(&settings).create_session(&mut modem),
+            Err(MQTTError::Disconnected)
+        ));
+
+        modem
+            .upload_credential(
+                CredentialType::CaCertificate,
+                b"-----BEGIN CERTIFICATE-----",
+            )
+            .unwrap();
+        let mut mqtt = Mqtt::new(&settings)
+            .create_session(&mut modem)
+            .unwrap()
+            .connect(test_connection_settings(), &mut modem)
+            .unwrap();
+        let session = emulator.mqtt_session(0).unwrap();
+        assert!(session.tls);
+        assert_eq!(session.port, 8883);
+
+        // The rest of the API does not change
+        mqtt.subscribe("sensors/+", 0, &mut modem).unwrap();
+        mqtt.publish(
+            &MQTTMessage {
+                topic: "sensors/temp",
+                qos: 0,
+                retained: false,
+                dup: false,
+                message: b"2150",
+            },
+            &mut modem,
+        )
+        .unwrap();
+        assert_eq!(
+            mqtt.receive(&mut modem, 1000).unwrap().payload.as_slice(),
+            b"2150"
+        );
+    }
+
+    #[test]
+    fn test_supervised_mqtt_reconnects() {
+        use std::sync::Mutex;
+
+        static STATES: Mutex<std::vec::Vec<MQTTConnectionState>> = Mutex::new(std::vec::Vec::new());
+        fn record_state(state: MQTTConnectionState) {
+            STATES.lock().unwrap().push(state);
+        }
+        let take_states = || core::mem::take(&mut *STATES.lock().unwrap());
+
+        let emulator = Sim7020Emulator::new();
+        let (mut writer, mut reader) = emulator.split();
+        let mut modem = registered_modem(&mut writer, &mut reader);
+
+        let settings = MQTTSessionSettings::new("broker.example.com", 1883);
+        let mut mqtt = SupervisedMqtt::new(&settings, test_connection_settings())
+            .with_backoff(MQTTBackoff {
+                max_attempts: 3,
+                ..Default::default()
+            })
+            .with_state_handler(record_state);
+        mqtt.connect(&mut modem).unwrap();
+        mqtt.subscribe("sensors/+", 1, &mut modem).unwrap();
+        assert_eq!(
+            take_states(),
+            [
+                MQTTConnectionState::Reconnecting { attempt: 1 },
+                MQTTConnectionState::Connected
+            ]
+        );
+
+        // The broker closes the connection
+        emulator.disconnect_mqtt_session(0);
+        mqtt.poll(&mut modem).unwrap();
+        assert_eq!(mqtt.state(), MQTTConnectionState::Connected);
+        assert_eq!(
+            take_states(),
+            [
+                MQTTConnectionState::Disconnected,
+                MQTTConnectionState::Reconnecting { attempt: 1 },
+                MQTTConnectionState::Connected
+            ]
+        );
+        let session = emulator.mqtt_session(0).unwrap();
+        assert!(session.connected);
+        assert_eq!(session.subscriptions.as_slice(), ["sensors/+"]);
+
+        // A message rejected by the module does not reconnect
+        let message = MQTTMessage {
+            topic: "sensors/temp",
+            qos: 3,
+            retained: false,
+            dup: false,
+            message: b"2150",
+        };
+        assert_eq!(mqtt.publish(&message, &mut modem), Err(MQTTError::Publish));
+        assert!(take_states().is_empty());
+        assert_eq!(mqtt.state(), MQTTConnectionState::Connected);
+
+        // The network is lost, every attempt fails until it comes back
+        emulator.set_network_available(false);
+        assert_eq!(
+            mqtt.publish(&MQTTMessage { qos: 0, ..message }, &mut modem),
+            Err(MQTTError::ConnectionFailed)
+        );
+        assert_eq!(mqtt.state(), MQTTConnectionState::Disconnected);
+        assert_eq!(
+            take_states(),
+            [
+                MQTTConnectionState::Disconnected,
+                MQTTConnectionState::Reconnecting { attempt: 1 },
+                MQTTConnectionState::Reconnecting { attempt: 2 },
+                MQTTConnectionState::Reconnecting { attempt: 3 },
+                MQTTConnectionState::Disconnected
+            ]
+        );
+
+        emulator.set_network_available(true);
+        modem
+            .send_and_wait_response(&StartWirelessConnection)
+            .unwrap();
+        mqtt.poll(&mut modem).unwrap();
+        assert_eq!(mqtt.state(), MQTTConnectionState::Connected);
+        assert_eq!(mqtt.mqtt().subscriptions().len(), 1);
+        assert_eq!(
+            emulator.mqtt_session(0).unwrap().subscriptions.as_slice(),
+            ["sensors/+"]
+        );
+
+        mqtt.disconnect(&mut modem).unwrap();
+        assert!(emulator.mqtt_session(0).is_none());
+    }
+
+    #[test]
+    fn test_mqtt_will_is_sent_on_connect() {
+        let emulator = Sim7020Emulator::new();
+        let (mut writer, mut reader) = emulator.split();
+        let mut modem = registered_modem(&mut writer, &mut reader);
+
+        let settings = MQTTSessionSettings::new("broker.example.com", 1883);
+        Mqtt::new(&settings)
+            .create_session(&mut modem)
+            .unwrap()
+            .connect(
+                MQTTConnectionSettings {
+                    version: MQTTVersion::MQTT311,
+                    client_id: "device",
+                    keepalive_interval: 60,
+                    clean_session: true,
+                    will_flag: true,
+                    will_options: Some(WillOptions {
+                        topic: "devices/device/status",
+                        quality_of_service: 1,
+                        retained: true,
+                        message: "offline, unexpectedly",
+                    }),
+                    username: "",
+                    password: "",
+                },
+                &mut modem,
+            )
+            .unwrap();
+
+        let will = emulator.mqtt_session(0).unwrap().will.unwrap();
+        assert_eq!(will.topic.as_str(), "devices/device/status");
+        assert_eq!(will.qos, 1);
+        assert!(will.retained);
+        assert_eq!(will.message.as_str(), "offline, unexpectedly");
+    }
 }
@@ -1,5 +1,5 @@
 /// Commands for the NTP protocol
-use crate::at_command::AtRequest;
+use crate::at_command::{AtRequest, NETWORK_TIMEOUT_MS};
 use crate::AtError;
 
 /// Starts a NTP query
@@ -30,6 +30,10 @@ impl AtRequest for StartQueryNTP<'_> {
     fn parse_response_struct(&self, _data: &[u8]) -> Result<Self::Response, AtError> {
         Ok(())
     }
+
+    fn timeout_ms(&self) -> u32 {
+        NETWORK_TIMEOUT_MS
+    }
 }
 
 /// Stops the NTP query
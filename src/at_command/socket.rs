@@ -2,7 +2,7 @@
 #[allow(deprecated)]
 use crate::at_command::AtResponse;
 use crate::{
-    at_command::{verify_ok, AtRequest},
+    at_command::{verify_ok, AtRequest, NETWORK_TIMEOUT_MS},
     AtError,
 };
 
@@ -136,6 +136,27 @@ impl AtRequest for ConnectSocketToRemote<'_> {
         verify_ok(data)?;
         Ok(())
     }
+
+    fn timeout_ms(&self) -> u32 {
+        NETWORK_TIMEOUT_MS
+    }
+}
+
+/// Maximum number of bytes that the module accepts in a single [SendSocketMessage]
+pub const MAX_SEND_SIZE: usize = 512;
+
+/// Bytes of the `AT+CSOSEND` command that are not data, e.g. `AT+CSOSEND=0,1024,` and `\r\n`
+const SEND_COMMAND_OVERHEAD: usize = 24;
+
+/// Returns the number of bytes that can be sent with a single [SendSocketMessage] when the
+/// command is built in a buffer of the given size
+pub const fn max_send_size(buffer_size: usize) -> usize {
+    let size = buffer_size.saturating_sub(SEND_COMMAND_OVERHEAD) / 2;
+    if size < MAX_SEND_SIZE {
+        size
+    } else {
+        MAX_SEND_SIZE
+    }
 }
 
 /// Struct used to send data through the socket
@@ -240,6 +261,8 @@ impl AtRequest for CloseSocket {
 mod test {
     #![allow(deprecated)]
     use super::*;
+    use crate::emulator::Sim7020Emulator;
+    use crate::testing::registered_modem;
 
     #[test]
     fn test_create_socket_command() {
@@ -488,4 +511,86 @@ mod test {
             .parse_response_struct(data)
             .is_ok());
     }
+
+    #[test]
+    fn connect_socket_waits_for_the_network() {
+        let connect = ConnectSocketToRemote {
+            socket_id: 1,
+            port: 80,
+            remote_address: "127.0.0.1",
+        };
+
+        assert_eq!(connect.timeout_ms(), NETWORK_TIMEOUT_MS);
+        assert_eq!(
+            CloseSocket { socket_id: 1 }.timeout_ms(),
+            crate::at_command::DEFAULT_TIMEOUT_MS
+        );
+    }
+
+    #[test]
+    fn test_socket_echo() {
+        let emulator = Sim7020Emulator::new();
+        let (mut writer, mut reader) = emulator.split();
+        let mut modem = registered_modem(&mut writer, &mut reader);
+
+        let socket_id = modem
+            .send_and_wait_response(&CreateSocket {
+                domain: Domain::IPv4,
+                connection_type: Type::TCP,
+                protocol: Protocol::IP,
+                cid: None,
+            })
+            .unwrap()
+            .socket_id;
+        modem
+            .send_and_wait_response(&ConnectSocketToRemote {
+                socket_id,
+                port: 7,
+                remote_address: "127.0.0.1",
+            })
+            .unwrap();
+        modem
+            .send_and_wait_response(&SendSocketMessage {
+                socket_id,
+                data: b"HELLO",
+            })
+            .unwrap();
+        assert_eq!(emulator.socket(socket_id).unwrap().remote_port, Some(7));
+        modem
+            .send_and_wait_response(&CloseSocket { socket_id })
+            .unwrap();
+
+        let mut buf = [0; 16];
+        let size = modem.try_receive_socket_data(socket_id, &mut buf).unwrap();
+        assert_eq!(&buf[..size], b"HELLO");
+        assert!(emulator.socket(0).is_none());
+    }
+
+    #[test]
+    fn test_socket_data_larger_than_an_urc_is_reported() {
+        let emulator = Sim7020Emulator::new();
+        let (mut writer, mut reader) = emulator.split();
+        let mut modem = registered_modem(&mut writer, &mut reader);
+        let socket_id = modem
+            .send_and_wait_response(&CreateSocket {
+                domain: Domain::IPv4,
+                connection_type: Type::TCP,
+                protocol: Protocol::IP,
+                cid: None,
+            })
+            .unwrap()
+            .socket_id;
+
+        // Larger than the payload of an URC and than the buffer of the modem
+        emulator.receive_socket_data(socket_id, &[0x41; 300]);
+        emulator.receive_socket_data(socket_id, b"OK");
+
+        let mut buf = [0; 16];
+        assert!(matches!(
+            modem.try_receive_socket_data(socket_id, &mut buf),
+            Err(AtError::CapacityError)
+        ));
+        let size = modem.try_receive_socket_data(socket_id, &mut buf).unwrap();
+        assert_eq!(&buf[..size], b"OK");
+    }
 }
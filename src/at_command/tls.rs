@@ -0,0 +1,322 @@
+//! Module for the TLS connections of the module (`AT+CTLS*` commands).
+//!
+//! A TLS connection is identified by a TLS id chosen by the user. The connection is configured
+//! with [ConfigureTls], opened with [ConnectTls] and the data is sent with [SendTlsData]. The
+//! received data is reported with the `+CTLSRECV` unsolicited result code, see
+//! [Urc::TlsData](crate::urc::Urc::TlsData)
+use crate::{
+    at_command::socket::MAX_SEND_SIZE,
+    at_command::{verify_ok, AtRequest, NETWORK_TIMEOUT_MS},
+    AtError,
+};
+
+/// Transport used below TLS
+#[repr(u8)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum TlsSocketType {
+    TCP = 0,
+    /// DTLS
+    UDP = 1,
+}
+
+/// Verification of the certificates during the handshake
+#[repr(u8)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum VerifyMode {
+    /// The certificates are not verified
+    None = 0,
+    /// The certificate of the server is verified against the configured CA
+    Server = 1,
+    /// The certificate of the server is verified and the client presents its own certificate
+    ServerAndClient = 2,
+}
+
+/// TLS version used in the handshake
+#[repr(u8)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum TlsVersion {
+    /// The highest version supported by the server
+    Auto = 0,
+    Tls1_0 = 1,
+    Tls1_1 = 2,
+    Tls1_2 = 3,
+}
+
+/// Parameter of a TLS connection set with [ConfigureTls]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum TlsSetting<'a> {
+    /// Host name or IP address of the server
+    Host(&'a str),
+    Port(u16),
+    SocketType(TlsSocketType),
+    VerifyMode(VerifyMode),
+    Version(TlsVersion),
+    /// IANA number of the cipher suite, e.g. `0xC02F`. 0 lets the module choose
+    CipherSuite(u16),
+    /// Name sent in the server name indication extension
+    ServerName(&'a str),
+}
+
+impl TlsSetting<'_> {
+    fn code(&self) -> u8 {
+        match self {
+            TlsSetting::Host(_) => 1,
+            TlsSetting::Port(_) => 2,
+            TlsSetting::SocketType(_) => 3,
+            TlsSetting::VerifyMode(_) => 4,
+            TlsSetting::Version(_) => 5,
+            TlsSetting::CipherSuite(_) => 6,
+            TlsSetting::ServerName(_) => 7,
+        }
+    }
+}
+
+/// Command to set a parameter of the TLS connection, the connection is created by the module the
+/// first time it is configured
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(PartialEq, Clone)]
+pub struct ConfigureTls<'a> {
+    pub tls_id: u8,
+    pub setting: TlsSetting<'a>,
+}
+
+impl AtRequest for ConfigureTls<'_> {
+    type Response = ();
+
+    fn get_command<'a>(&'a self, buffer: &'a mut [u8]) -> Result<&'a [u8], usize> {
+        let builder = at_commands::builder::CommandBuilder::create_set(buffer, true)
+            .named("+CTLSCFG")
+            .with_int_parameter(self.tls_id)
+            .with_int_parameter(self.setting.code());
+
+        match self.setting {
+            TlsSetting::Host(value) | TlsSetting::ServerName(value) => {
+                builder.with_string_parameter(value).finish()
+            }
+            TlsSetting::Port(value) | TlsSetting::CipherSuite(value) => {
+                builder.with_int_parameter(value as i32).finish()
+            }
+            TlsSetting::SocketType(value) => builder.with_int_parameter(value as u8).finish(),
+            TlsSetting::VerifyMode(value) => builder.with_int_parameter(value as u8).finish(),
+            TlsSetting::Version(value) => builder.with_int_parameter(value as u8).finish(),
+        }
+    }
+
+    fn parse_response_struct(&self, data: &[u8]) -> Result<Self::Response, AtError> {
+        verify_ok(data)
+    }
+}
+
+/// Command to perform the handshake with the configured server
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(PartialEq, Clone)]
+pub struct ConnectTls {
+    pub tls_id: u8,
+}
+
+impl AtRequest for ConnectTls {
+    type Response = ();
+
+    fn get_command<'a>(&'a self, buffer: &'a mut [u8]) -> Result<&'a [u8], usize> {
+        at_commands::builder::CommandBuilder::create_set(buffer, true)
+            .named("+CTLSCONN")
+            .with_int_parameter(self.tls_id)
+            .finish()
+    }
+
+    fn parse_response_struct(&self, data: &[u8]) -> Result<Self::Response, AtError> {
+        let (_tls_id, result) = at_commands::parser::CommandParser::parse(data)
+            .trim_whitespace()
+            .expect_identifier(b"+CTLSCONN: ")
+            .expect_int_parameter()
+            .expect_int_parameter()
+            .trim_whitespace()
+            .expect_identifier(b"OK")
+            .finish()?;
+
+        match result {
+            1 => Ok(()),
+            error => Err(AtError::TlsConnectionFailed(error)),
+        }
+    }
+
+    fn timeout_ms(&self) -> u32 {
+        NETWORK_TIMEOUT_MS
+    }
+}
+
+/// Command to send data through the TLS connection. As with
+/// [SendSocketMessage](crate::at_command::socket::SendSocketMessage) the data is sent as hex,
+/// check [max_send_size](crate::at_command::socket::max_send_size). Larger data is rejected with
+/// [AtError::CapacityError]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(PartialEq)]
+pub struct SendTlsData<'a> {
+    pub tls_id: u8,
+    pub data: &'a [u8],
+}
+
+impl AtRequest for SendTlsData<'_> {
+    type Response = ();
+
+    fn get_command<'a>(&'a self, buffer: &'a mut [u8]) -> Result<&'a [u8], usize> {
+        // No buffer can hold a command whose hex length does not fit in its parameter
+        let hex_length = self
+            .data
+            .len()
+            .checked_mul(2)
+            .and_then(|length| i32::try_from(length).ok())
+            .ok_or(usize::MAX)?;
+        at_commands::builder::CommandBuilder::create_set(buffer, true)
+            .named("+CTLSSEND")
+            .with_int_parameter(self.tls_id)
+            .with_int_parameter(hex_length)
+            .with_rax_hex_parameter(self.data)
+            .finish()
+    }
+
+    fn validate(&self) -> Result<(), AtError> {
+        // The limit of the buffer is checked when the command is built
+        if self.data.len() > MAX_SEND_SIZE {
+            return Err(AtError::CapacityError);
+        }
+        Ok(())
+    }
+
+    fn parse_response_struct(&self, data: &[u8]) -> Result<Self::Response, AtError> {
+        verify_ok(data)
+    }
+}
+
+/// Command to close the TLS connection and release its configuration
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(PartialEq, Clone)]
+pub struct CloseTls {
+    pub tls_id: u8,
+}
+
+impl AtRequest for CloseTls {
+    type Response = ();
+
+    fn get_command<'a>(&'a self, buffer: &'a mut [u8]) -> Result<&'a [u8], usize> {
+        at_commands::builder::CommandBuilder::create_set(buffer, true)
+            .named("+CTLSCLOSE")
+            .with_int_parameter(self.tls_id)
+            .finish()
+    }
+
+    fn parse_response_struct(&self, data: &[u8]) -> Result<Self::Response, AtError> {
+        verify_ok(data)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_configure_tls_commands() {
+        let mut buffer = [0; 64];
+        let cases: [(TlsSetting, &[u8]); 7] = [
+            (
+                TlsSetting::Host("example.com"),
+                b"AT+CTLSCFG=1,1,\"example.com\"\r\n",
+            ),
+            (TlsSetting::Port(443), b"AT+CTLSCFG=1,2,443\r\n"),
+            (
+                TlsSetting::SocketType(TlsSocketType::UDP),
+                b"AT+CTLSCFG=1,3,1\r\n",
+            ),
+            (
+                TlsSetting::VerifyMode(VerifyMode::Server),
+                b"AT+CTLSCFG=1,4,1\r\n",
+            ),
+            (
+                TlsSetting::Version(TlsVersion::Tls1_2),
+                b"AT+CTLSCFG=1,5,3\r\n",
+            ),
+            (TlsSetting::CipherSuite(0xC02F), b"AT+CTLSCFG=1,6,49199\r\n"),
+            (
+                TlsSetting::ServerName("api.example.com"),
+                b"AT+CTLSCFG=1,7,\"api.example.com\"\r\n",
+            ),
+        ];
+
+        for (setting, expected) in cases {
+            let command = ConfigureTls { tls_id: 1, setting };
+            assert_eq!(command.get_command(&mut buffer).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_connect_tls() {
+        let mut buffer = [0; 32];
+        let command = ConnectTls { tls_id: 2 };
+
+        assert_eq!(
+            command.get_command(&mut buffer).unwrap(),
+            b"AT+CTLSCONN=2\r\n"
+        );
+        command
+            .parse_response_struct(b"\r\n+CTLSCONN: 2,1\r\n\r\nOK\r")
+            .unwrap();
+        assert!(matches!(
+            command.parse_response_struct(b"\r\n+CTLSCONN: 2,-3\r\n\r\nOK\r"),
+            Err(AtError::TlsConnectionFailed(-3))
+        ));
+    }
+
+    #[test]
+    fn test_send_and_close_tls() {
+        let mut buffer = [0; 32];
+
+        let send = SendTlsData {
+            tls_id: 1,
+            data: b"AB",
+        };
+        assert_eq!(
+            send.get_command(&mut buffer).unwrap(),
+            b"AT+CTLSSEND=1,4,4142\r\n"
+        );
+
+        let close = CloseTls { tls_id: 1 };
+        assert_eq!(
+            close.get_command(&mut buffer).unwrap(),
+            b"AT+CTLSCLOSE=1\r\n"
+        );
+    }
+
+    #[test]
+    fn test_send_tls_data_too_large() {
+        // Its hex length does not fit in the command
+        let data = [0; 40_000];
+
+        assert!(SendTlsData {
+            tls_id: 1,
+            data: &data[..MAX_SEND_SIZE],
+        }
+        .validate()
+        .is_ok());
+        assert!(matches!(
+            SendTlsData {
+                tls_id: 1,
+                data: &data,
+            }
+            .validate(),
+            Err(AtError::CapacityError)
+        ));
+
+        // Its length is not truncated when the command is built without validation
+        let mut buffer = [0; 128];
+        assert!(SendTlsData {
+            tls_id: 1,
+            data: &data,
+        }
+        .get_command(&mut buffer)
+        .is_err());
+    }
+}
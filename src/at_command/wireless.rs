@@ -1,5 +1,5 @@
 //! Module to handle the wireless commands
-use crate::at_command::AtRequest;
+use crate::at_command::{AtRequest, NETWORK_TIMEOUT_MS};
 
 /// Command to start the wireless connection
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -18,6 +18,10 @@ impl AtRequest for StartWirelessConnection {
     fn parse_response_struct(&self, _data: &[u8]) -> Result<Self::Response, crate::AtError> {
         Ok(())
     }
+
+    fn timeout_ms(&self) -> u32 {
+        NETWORK_TIMEOUT_MS
+    }
 }
 
 #[cfg(test)]
@@ -1,3 +1,5 @@
 //! Implementation of blocking contexts
 
+pub mod http_context;
 pub mod socket_context;
+pub mod tls_context;
@@ -9,25 +9,40 @@ use embedded_hal::digital::OutputPin;
 use embedded_io::{Read, ReadReady, Write};
 
 use crate::at_command::socket::*;
-use crate::contexts::common_socket_context::{Connected, PendingConnection};
-use crate::{AtError, Modem};
+use crate::contexts::common_socket_context::{Connected, PendingConnection, RECEIVE_TIMEOUT_MS};
+use crate::{AtError, Modem, BUFFER_SIZE};
 
 /// Defines a socket context, which is associated with one socket id.
 /// The socket context will be attached to a [Modem] thorugh a lifecycle
-pub struct SocketContext<'a, W: Write, R: Read + ReadReady, P: OutputPin, D: DelayNs, S> {
+pub struct SocketContext<
+    'a,
+    W: Write,
+    R: Read + ReadReady,
+    P: OutputPin,
+    D: DelayNs,
+    S,
+    const N: usize = BUFFER_SIZE,
+> {
     socket_id: u8,
-    modem: &'a mut Modem<'a, W, R, P, D>,
+    modem: &'a mut Modem<'a, W, R, P, D, N>,
     _state: PhantomData<S>,
 }
 
 /// Creates a new [SocketContext] using the given modem
-pub fn new_socket_context<'a, W: Write, R: Read + ReadReady, P: OutputPin, D: DelayNs>(
-    modem: &'a mut Modem<'a, W, R, P, D>,
+pub fn new_socket_context<
+    'a,
+    W: Write,
+    R: Read + ReadReady,
+    P: OutputPin,
+    D: DelayNs,
+    const N: usize,
+>(
+    modem: &'a mut Modem<'a, W, R, P, D, N>,
     domain: Domain,
     connection_type: Type,
     protocol: Protocol,
     cid: Option<i32>,
-) -> Result<SocketContext<'a, W, R, P, D, PendingConnection>, AtError> {
+) -> Result<SocketContext<'a, W, R, P, D, PendingConnection, N>, AtError> {
     #[cfg(feature = "defmt")]
     debug!("Creating a new HTTP Context");
 
@@ -45,25 +60,33 @@ pub fn new_socket_context<'a, W: Write, R: Read + ReadReady, P: OutputPin, D: De
     })
 }
 
-fn close_socket_context<W: Write, R: Read + ReadReady, P: OutputPin, D: DelayNs, S>(
-    context: SocketContext<W, R, P, D, S>,
+fn close_socket_context<
+    W: Write,
+    R: Read + ReadReady,
+    P: OutputPin,
+    D: DelayNs,
+    S,
+    const N: usize,
+>(
+    context: SocketContext<W, R, P, D, S, N>,
 ) -> Result<(), AtError> {
     context.modem.send_and_wait_response(&CloseSocket {
         socket_id: context.socket_id,
     })?;
+    context.modem.clear_socket_data(context.socket_id);
 
     Ok(())
 }
 
-impl<'a, W: Write, R: Read + ReadReady, P: OutputPin, D: DelayNs>
-    SocketContext<'a, W, R, P, D, PendingConnection>
+impl<'a, W: Write, R: Read + ReadReady, P: OutputPin, D: DelayNs, const N: usize>
+    SocketContext<'a, W, R, P, D, PendingConnection, N>
 {
     /// Connects the socket session to the remote server
     pub fn connect_to_remote(
         self,
         port: u16,
         address: &str,
-    ) -> Result<SocketContext<'a, W, R, P, D, Connected>, AtError> {
+    ) -> Result<SocketContext<'a, W, R, P, D, Connected, N>, AtError> {
         #[cfg(feature = "defmt")]
         debug!("Connecting socket to {}:{}", address, port);
 
@@ -88,8 +111,8 @@ impl<'a, W: Write, R: Read + ReadReady, P: OutputPin, D: DelayNs>
     }
 }
 
-impl<'a, W: Write, R: Read + ReadReady, P: OutputPin, D: DelayNs>
-    SocketContext<'a, W, R, P, D, Connected>
+impl<'a, W: Write, R: Read + ReadReady, P: OutputPin, D: DelayNs, const N: usize>
+    SocketContext<'a, W, R, P, D, Connected, N>
 {
     /// Sends the given string to the remote connection
     pub fn send_string(&mut self, data: &str) -> Result<(), AtError> {
@@ -111,6 +134,19 @@ impl<'a, W: Write, R: Read + ReadReady, P: OutputPin, D: DelayNs>
         Ok(())
     }
 
+    /// Copies into [buf] the data received from the remote connection, waiting up to
+    /// [RECEIVE_TIMEOUT_MS] for it to arrive. Returns the number of bytes copied
+    pub fn receive(&mut self, buf: &mut [u8]) -> Result<usize, AtError> {
+        self.modem
+            .receive_socket_data(self.socket_id, buf, RECEIVE_TIMEOUT_MS)
+    }
+
+    /// Copies into [buf] the data already received from the remote connection without waiting.
+    /// Returns the number of bytes copied, 0 if no data has been received
+    pub fn try_receive(&mut self, buf: &mut [u8]) -> Result<usize, AtError> {
+        self.modem.try_receive_socket_data(self.socket_id, buf)
+    }
+
     pub fn close(self) -> Result<(), AtError> {
         close_socket_context(self)
     }
@@ -250,6 +286,74 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_socket_context_scripted() -> Result<(), AtError> {
+        use crate::testing::{NoopDelay, NoopPin, ScriptedSerial, Step};
+
+        let script = [
+            Step::Expect(b"ATE0\r\n"),
+            Step::Reply(b"\r\nOK\r\n"),
+            Step::Expect(b"AT+CSOC=1,1,1,3\r\n"),
+            Step::Reply(b"\r\n+CSOC: 1\r\n\r\nOK\r\n"),
+            Step::Expect(b"AT+CSOCON=1,1111,\"127.0.0.1\"\r\n"),
+            Step::Reply(b"\r\nOK\r\n"),
+            Step::Expect(b"AT+CSOSEND=1,0,\"HELLO TEST\"\r\n"),
+            Step::Reply(b"\r\nOK\r\n"),
+            Step::Expect(b"AT+CSOCL=1\r\n"),
+            Step::Reply(b"\r\nOK\r\n"),
+        ];
+        let serial = ScriptedSerial::new(&script);
+        let (mut writer, mut reader) = serial.split();
+        let mut modem = Modem::new(&mut writer, &mut reader, NoopPin, NoopPin, NoopDelay)?;
+
+        let context = super::new_socket_context(
+            &mut modem,
+            crate::at_command::socket::Domain::IPv4,
+            crate::at_command::socket::Type::TCP,
+            crate::at_command::socket::Protocol::IP,
+            Some(3),
+        )?;
+        let mut connected_socket = context.connect_to_remote(1111, "127.0.0.1")?;
+        connected_socket.send_string("HELLO TEST")?;
+        connected_socket.close()?;
+
+        serial.done();
+        Ok(())
+    }
+
+    #[test]
+    fn test_socket_context_receive() -> Result<(), AtError> {
+        use crate::emulator::Sim7020Emulator;
+        use crate::testing::registered_modem;
+
+        let emulator = Sim7020Emulator::new();
+        let (mut writer, mut reader) = emulator.split();
+        let mut modem = registered_modem(&mut writer, &mut reader);
+
+        let context = super::new_socket_context(
+            &mut modem,
+            crate::at_command::socket::Domain::IPv4,
+            crate::at_command::socket::Type::TCP,
+            crate::at_command::socket::Protocol::IP,
+            None,
+        )?;
+        let mut socket = context.connect_to_remote(7, "127.0.0.1")?;
+        let mut buf = [0; 8];
+        assert_eq!(socket.try_receive(&mut buf)?, 0);
+
+        // The echo arrives while the next command is in flight
+        socket.send_data(b"HELLO ")?;
+        socket.send_data(b"WORLD")?;
+
+        let size = socket.receive(&mut buf)?;
+        assert_eq!(&buf[..size], b"HELLO WO");
+        let size = socket.try_receive(&mut buf)?;
+        assert_eq!(&buf[..size], b"RLD");
+        socket.close()?;
+
+        Ok(())
+    }
+
     struct TimesMatcher {
         matched: RefCell<i64>,
     }
@@ -0,0 +1,220 @@
+//! Contains the definitions for the TLS contexts
+
+use core::marker::PhantomData;
+
+#[cfg(feature = "defmt")]
+use defmt::debug;
+use embedded_hal::delay::DelayNs;
+use embedded_hal::digital::OutputPin;
+use embedded_io::{Read, ReadReady, Write};
+
+use crate::at_command::socket::max_send_size;
+use crate::at_command::tls::*;
+use crate::contexts::common_socket_context::{Connected, PendingConnection, RECEIVE_TIMEOUT_MS};
+use crate::urc::MAX_TLS_CONNECTIONS;
+use crate::{AtError, Modem, BUFFER_SIZE};
+
+/// Defines a TLS context, which is associated with one TLS id.
+/// The TLS context will be attached to a [Modem] through a lifecycle
+pub struct TlsContext<
+    'a,
+    W: Write,
+    R: Read + ReadReady,
+    P: OutputPin,
+    D: DelayNs,
+    S,
+    const N: usize = BUFFER_SIZE,
+> {
+    tls_id: u8,
+    modem: &'a mut Modem<'a, W, R, P, D, N>,
+    _state: PhantomData<S>,
+}
+
+/// Creates a new [TlsContext] to the given server using the given modem. The TLS id goes from 1
+/// to [MAX_TLS_CONNECTIONS]
+pub fn new_tls_context<
+    'a,
+    W: Write,
+    R: Read + ReadReady,
+    P: OutputPin,
+    D: DelayNs,
+    const N: usize,
+>(
+    modem: &'a mut Modem<'a, W, R, P, D, N>,
+    tls_id: u8,
+    host: &str,
+    port: u16,
+) -> Result<TlsContext<'a, W, R, P, D, PendingConnection, N>, AtError> {
+    #[cfg(feature = "defmt")]
+    debug!("Creating a new TLS Context to {}:{}", host, port);
+
+    if !(1..=MAX_TLS_CONNECTIONS).contains(&(tls_id as usize)) {
+        return Err(AtError::CapacityError);
+    }
+
+    let mut context = TlsContext {
+        tls_id,
+        modem,
+        _state: Default::default(),
+    };
+    context.configure(TlsSetting::Host(host))?;
+    context.configure(TlsSetting::Port(port))?;
+
+    Ok(context)
+}
+
+fn close_tls_context<W: Write, R: Read + ReadReady, P: OutputPin, D: DelayNs, S, const N: usize>(
+    context: TlsContext<W, R, P, D, S, N>,
+) -> Result<(), AtError> {
+    context.modem.send_and_wait_response(&CloseTls {
+        tls_id: context.tls_id,
+    })?;
+    context.modem.clear_tls_data(context.tls_id);
+
+    Ok(())
+}
+
+impl<'a, W: Write, R: Read + ReadReady, P: OutputPin, D: DelayNs, const N: usize>
+    TlsContext<'a, W, R, P, D, PendingConnection, N>
+{
+    /// Sets a parameter of the connection, e.g. the server name, the verify mode, the TLS version
+    /// or the cipher suite
+    pub fn configure(&mut self, setting: TlsSetting) -> Result<(), AtError> {
+        self.modem.send_and_wait_response(&ConfigureTls {
+            tls_id: self.tls_id,
+            setting,
+        })
+    }
+
+    /// Performs the handshake with the server
+    pub fn connect(self) -> Result<TlsContext<'a, W, R, P, D, Connected, N>, AtError> {
+        self.modem.send_and_wait_response(&ConnectTls {
+            tls_id: self.tls_id,
+        })?;
+
+        #[cfg(feature = "defmt")]
+        debug!("TLS connection {} established", self.tls_id);
+
+        Ok(TlsContext {
+            tls_id: self.tls_id,
+            modem: self.modem,
+            _state: Default::default(),
+        })
+    }
+
+    pub fn close(self) -> Result<(), AtError> {
+        close_tls_context(self)
+    }
+}
+
+impl<W: Write, R: Read + ReadReady, P: OutputPin, D: DelayNs, const N: usize>
+    TlsContext<'_, W, R, P, D, Connected, N>
+{
+    /// Sends the given data to the server, split in as many commands as needed
+    pub fn send_data(&mut self, data: &[u8]) -> Result<(), AtError> {
+        for chunk in data.chunks(max_send_size(N).max(1)) {
+            self.modem.send_and_wait_response(&SendTlsData {
+                tls_id: self.tls_id,
+                data: chunk,
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Copies into [buf] the data received from the server, waiting up to [RECEIVE_TIMEOUT_MS]
+    /// for it to arrive. Returns the number of bytes copied
+    pub fn receive(&mut self, buf: &mut [u8]) -> Result<usize, AtError> {
+        self.modem
+            .receive_tls_data(self.tls_id, buf, RECEIVE_TIMEOUT_MS)
+    }
+
+    /// Copies into [buf] the data already received from the server without waiting.
+    /// Returns the number of bytes copied, 0 if no data has been received
+    pub fn try_receive(&mut self, buf: &mut [u8]) -> Result<usize, AtError> {
+        self.modem.try_receive_tls_data(self.tls_id, buf)
+    }
+
+    pub fn close(self) -> Result<(), AtError> {
+        close_tls_context(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::at_command::credentials::CredentialType;
+    use crate::emulator::Sim7020Emulator;
+    use crate::testing::{registered_modem, NoopDelay, NoopPin};
+
+    #[test]
+    fn test_tls_context() -> Result<(), AtError> {
+        let emulator = Sim7020Emulator::new();
+        let (mut writer, mut reader) = emulator.split();
+        let mut modem = registered_modem(&mut writer, &mut reader);
+        modem.upload_credential(
+            CredentialType::CaCertificate,
+            b"-----BEGIN CERTIFICATE-----",
+        )?;
+
+        let mut context = new_tls_context(&mut modem, 1, "example.com", 443)?;
+        context.configure(TlsSetting::ServerName("api.example.com"))?;
+        context.configure(TlsSetting::VerifyMode(VerifyMode::Server))?;
+        context.configure(TlsSetting::Version(TlsVersion::Tls1_2))?;
+        context.configure(TlsSetting::CipherSuite(0xC02F))?;
+        let mut tls = context.connect()?;
+
+        let connection = emulator.tls_connection(1).unwrap();
+        assert_eq!(connection.host, "example.com");
+        assert_eq!(connection.port, Some(443));
+        assert_eq!(connection.server_name, "api.example.com");
+        assert_eq!(connection.verify_mode, VerifyMode::Server as u8);
+        assert_eq!(connection.version, TlsVersion::Tls1_2 as u8);
+        assert_eq!(connection.cipher_suite, 0xC02F);
+        assert!(connection.connected);
+
+        // Longer than a single command
+        let data = [b'A'; 300];
+        tls.send_data(&data)?;
+        let mut buf = [0; 512];
+        let mut received = 0;
+        while received < data.len() {
+            received += tls.receive(&mut buf[received..])?;
+        }
+        assert_eq!(&buf[..received], &data);
+        assert_eq!(tls.try_receive(&mut buf)?, 0);
+
+        tls.close()?;
+        assert!(emulator.tls_connection(1).is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_tls_handshake_without_ca() -> Result<(), AtError> {
+        let emulator = Sim7020Emulator::new();
+        let (mut writer, mut reader) = emulator.split();
+        let mut modem = registered_modem(&mut writer, &mut reader);
+
+        let mut context = new_tls_context(&mut modem, 2, "example.com", 443)?;
+        context.configure(TlsSetting::VerifyMode(VerifyMode::Server))?;
+        assert!(matches!(
+            context.connect(),
+            Err(AtError::TlsConnectionFailed(-1))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_tls_id_out_of_range() {
+        let emulator = Sim7020Emulator::new();
+        let (mut writer, mut reader) = emulator.split();
+        let mut modem = Modem::new(&mut writer, &mut reader, NoopPin, NoopPin, NoopDelay).unwrap();
+
+        assert!(matches!(
+            new_tls_context(&mut modem, 0, "example.com", 443),
+            Err(AtError::CapacityError)
+        ));
+    }
+}
@@ -0,0 +1,67 @@
+//! Contains the common definitions for the HTTP contexts
+
+use crate::at_command::http::{CreateHttpSession, HttpsCertificates};
+
+/// Settings of an HTTP context to an `https://` server. The certificates are sent in PEM format
+/// when the client is created, so the buffer of the modem must fit them. The context is created
+/// with [new_https_context](crate::contexts::blocking::http_context::new_https_context) or its
+/// async counterpart
+#[derive(PartialEq, Clone, Debug)]
+pub struct HttpsContextBuilder<'a> {
+    host: &'a str,
+    user: Option<&'a str>,
+    password: Option<&'a str>,
+    certificates: HttpsCertificates<'a>,
+}
+
+impl<'a> HttpsContextBuilder<'a> {
+    pub fn new(host: &'a str) -> Self {
+        Self {
+            host,
+            user: None,
+            password: None,
+            certificates: HttpsCertificates::default(),
+        }
+    }
+
+    /// Verifies the server with the given CA
+    pub fn with_ca_certificate(self, ca_certificate: &'a str) -> Self {
+        Self {
+            certificates: HttpsCertificates {
+                server_certificate: Some(ca_certificate),
+                ..self.certificates
+            },
+            ..self
+        }
+    }
+
+    /// Presents the given certificate to the server, it requires the CA of the server
+    pub fn with_client_certificate(self, certificate: &'a str, key: &'a str) -> Self {
+        Self {
+            certificates: HttpsCertificates {
+                client_certificate: Some(certificate),
+                client_key: Some(key),
+                ..self.certificates
+            },
+            ..self
+        }
+    }
+
+    /// Authenticates with the given user and password
+    pub fn with_user(self, user: &'a str, password: &'a str) -> Self {
+        Self {
+            user: Some(user),
+            password: Some(password),
+            ..self
+        }
+    }
+
+    pub(crate) fn session(&self) -> CreateHttpSession<'a> {
+        CreateHttpSession {
+            host: self.host,
+            user: self.user,
+            password: self.password,
+            tls: Some(self.certificates),
+        }
+    }
+}
@@ -4,6 +4,11 @@
 //!
 //!
 
+use crate::at_command::NETWORK_TIMEOUT_MS;
+
+/// Maximum time that the socket contexts wait for data in `receive`
+pub const RECEIVE_TIMEOUT_MS: u32 = NETWORK_TIMEOUT_MS;
+
 /// Marker interface to indicate the state of [HttpContext] and [AsyncHttpContext]
 pub struct PendingConnection;
 /// Marker interface to indicate the state of [HttpContext] and [AsyncHttpContext]
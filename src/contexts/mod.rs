@@ -8,4 +8,5 @@ pub mod nonblocking;
 
 pub mod blocking;
 
+pub mod common_http_context;
 pub mod common_socket_context;
@@ -0,0 +1,321 @@
+//! Contains the definitions for the async HTTP contexts
+
+use crate::at_command::http::*;
+use crate::contexts::common_http_context::HttpsContextBuilder;
+use crate::contexts::common_socket_context::{Connected, PendingConnection, RECEIVE_TIMEOUT_MS};
+use crate::nonblocking::AsyncModem;
+use crate::urc::HttpResponseHeader;
+use crate::{AtError, BUFFER_SIZE};
+use core::marker::PhantomData;
+#[cfg(feature = "defmt")]
+use defmt::debug;
+use embedded_hal::digital::OutputPin;
+use embedded_hal_async::delay::DelayNs;
+use embedded_io::ReadReady;
+use embedded_io_async::{Read, Write};
+
+/// Defines an async HTTP context, which is associated with one HTTP client of the module.
+/// The client is destroyed when the context is closed. As no command can be sent when the
+/// context is dropped, the client of a dropped context is destroyed when the next context is
+/// created, see [AsyncModem::destroy_released_http_clients]
+pub struct AsyncHttpContext<
+    'a,
+    W: Write,
+    R: Read + ReadReady,
+    P: OutputPin,
+    D: DelayNs,
+    S,
+    const N: usize = BUFFER_SIZE,
+> {
+    client_id: u8,
+    /// Only taken when the context changes its state
+    modem: Option<&'a mut AsyncModem<W, R, P, D, N>>,
+    _state: PhantomData<S>,
+}
+
+/// Creates a new [AsyncHttpContext] to the given server, e.g. `http://example.com`, using the
+/// given modem
+pub async fn new_async_http_context<
+    'a,
+    W: Write,
+    R: Read + ReadReady,
+    P: OutputPin,
+    D: DelayNs,
+    const N: usize,
+>(
+    modem: &'a mut AsyncModem<W, R, P, D, N>,
+    host: &str,
+) -> Result<AsyncHttpContext<'a, W, R, P, D, PendingConnection, N>, AtError> {
+    create_http_context(
+        modem,
+        CreateHttpSession {
+            host,
+            user: None,
+            password: None,
+            tls: None,
+        },
+    )
+    .await
+}
+
+/// Creates a new [AsyncHttpContext] to the `https://` server configured in the builder
+pub async fn new_async_https_context<
+    'a,
+    W: Write,
+    R: Read + ReadReady,
+    P: OutputPin,
+    D: DelayNs,
+    const N: usize,
+>(
+    modem: &'a mut AsyncModem<W, R, P, D, N>,
+    builder: &HttpsContextBuilder<'_>,
+) -> Result<AsyncHttpContext<'a, W, R, P, D, PendingConnection, N>, AtError> {
+    create_http_context(modem, builder.session()).await
+}
+
+async fn create_http_context<
+    'a,
+    W: Write,
+    R: Read + ReadReady,
+    P: OutputPin,
+    D: DelayNs,
+    const N: usize,
+>(
+    modem: &'a mut AsyncModem<W, R, P, D, N>,
+    session: CreateHttpSession<'_>,
+) -> Result<AsyncHttpContext<'a, W, R, P, D, PendingConnection, N>, AtError> {
+    #[cfg(feature = "defmt")]
+    debug!("Creating a new HTTP Context to {}", session.host);
+
+    modem.destroy_released_http_clients().await?;
+    let session = modem.send_and_wait_response(session).await?;
+    modem.clear_http_response(session.client_id);
+
+    Ok(AsyncHttpContext {
+        client_id: session.client_id,
+        modem: Some(modem),
+        _state: Default::default(),
+    })
+}
+
+impl<'a, W: Write, R: Read + ReadReady, P: OutputPin, D: DelayNs, S, const N: usize>
+    AsyncHttpContext<'a, W, R, P, D, S, N>
+{
+    pub fn client_id(&self) -> u8 {
+        self.client_id
+    }
+
+    fn modem(&mut self) -> &mut AsyncModem<W, R, P, D, N> {
+        self.modem
+            .as_deref_mut()
+            .expect("the modem is only taken when the context changes its state")
+    }
+
+    /// Moves the modem into a context with the new state
+    fn into_state<T>(mut self) -> AsyncHttpContext<'a, W, R, P, D, T, N> {
+        AsyncHttpContext {
+            client_id: self.client_id,
+            modem: self.modem.take(),
+            _state: Default::default(),
+        }
+    }
+
+    async fn destroy(mut self, connected: bool) -> Result<(), AtError> {
+        let client_id = self.client_id;
+        let Some(modem) = self.modem.take() else {
+            return Ok(());
+        };
+        if connected {
+            modem
+                .send_and_wait_response(HttpDisconnect { client_id })
+                .await?;
+        }
+        modem
+            .send_and_wait_response(HttpDestroy { client_id })
+            .await?;
+        modem.clear_http_response(client_id);
+
+        Ok(())
+    }
+}
+
+impl<W: Write, R: Read + ReadReady, P: OutputPin, D: DelayNs, S, const N: usize> Drop
+    for AsyncHttpContext<'_, W, R, P, D, S, N>
+{
+    fn drop(&mut self) {
+        if let Some(modem) = self.modem.take() {
+            modem.release_http_client(self.client_id);
+        }
+    }
+}
+
+impl<'a, W: Write, R: Read + ReadReady, P: OutputPin, D: DelayNs, const N: usize>
+    AsyncHttpContext<'a, W, R, P, D, PendingConnection, N>
+{
+    /// Connects the client to the server
+    pub async fn connect(
+        mut self,
+    ) -> Result<AsyncHttpContext<'a, W, R, P, D, Connected, N>, AtError> {
+        let client_id = self.client_id;
+        self.modem()
+            .send_and_wait_response(HttpConnect { client_id })
+            .await?;
+
+        #[cfg(feature = "defmt")]
+        debug!("HTTP client {} connected", client_id);
+
+        Ok(self.into_state())
+    }
+
+    /// Destroys the client in the module
+    pub async fn close(self) -> Result<(), AtError> {
+        self.destroy(false).await
+    }
+}
+
+impl<W: Write, R: Read + ReadReady, P: OutputPin, D: DelayNs, const N: usize>
+    AsyncHttpContext<'_, W, R, P, D, Connected, N>
+{
+    /// Sends the request and waits up to [RECEIVE_TIMEOUT_MS] for the whole response
+    pub async fn send<const BODY_SIZE: usize>(
+        &mut self,
+        request: &HttpRequest<'_>,
+    ) -> Result<HttpResponse<BODY_SIZE>, AtError> {
+        let client_id = self.send_request(request).await?;
+        self.modem()
+            .receive_http_response(client_id, RECEIVE_TIMEOUT_MS)
+            .await
+    }
+
+    /// Sends the request and waits up to [RECEIVE_TIMEOUT_MS] for the response, writing its body
+    /// into [sink] as it arrives. Returns the status code and the headers
+    pub async fn send_into<O: Write>(
+        &mut self,
+        request: &HttpRequest<'_>,
+        sink: &mut O,
+    ) -> Result<HttpResponseHeader, AtError> {
+        let client_id = self.send_request(request).await?;
+        self.modem()
+            .receive_http_response_into(client_id, sink, RECEIVE_TIMEOUT_MS)
+            .await
+    }
+
+    pub async fn get<const BODY_SIZE: usize>(
+        &mut self,
+        path: &str,
+    ) -> Result<HttpResponse<BODY_SIZE>, AtError> {
+        self.send(&HttpRequest::get(path)).await
+    }
+
+    pub async fn post<const BODY_SIZE: usize>(
+        &mut self,
+        path: &str,
+        content_type: &str,
+        body: &[u8],
+    ) -> Result<HttpResponse<BODY_SIZE>, AtError> {
+        self.send(&HttpRequest::post(path, content_type, body))
+            .await
+    }
+
+    pub async fn put<const BODY_SIZE: usize>(
+        &mut self,
+        path: &str,
+        content_type: &str,
+        body: &[u8],
+    ) -> Result<HttpResponse<BODY_SIZE>, AtError> {
+        self.send(&HttpRequest::put(path, content_type, body)).await
+    }
+
+    pub async fn delete<const BODY_SIZE: usize>(
+        &mut self,
+        path: &str,
+    ) -> Result<HttpResponse<BODY_SIZE>, AtError> {
+        self.send(&HttpRequest::delete(path)).await
+    }
+
+    /// Disconnects the client from the server and destroys it in the module
+    pub async fn close(self) -> Result<(), AtError> {
+        self.destroy(true).await
+    }
+
+    async fn send_request(&mut self, request: &HttpRequest<'_>) -> Result<u8, AtError> {
+        let client_id = self.client_id;
+        let modem = self.modem();
+        // Discard the rest of a previous response that was not read
+        modem.clear_http_response(client_id);
+        modem
+            .send_and_wait_response(request.command(client_id))
+            .await?;
+
+        Ok(client_id)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::emulator::Sim7020Emulator;
+    use crate::testing::{registered_async_modem, NoopDelay};
+    use embassy_futures::block_on;
+
+    #[test]
+    fn test_async_http_context() {
+        let emulator = Sim7020Emulator::new();
+
+        block_on(async {
+            let mut modem = registered_async_modem(&emulator, NoopDelay).await;
+
+            // Dropped without being closed
+            let context = new_async_http_context(&mut modem, "http://example.com")
+                .await
+                .unwrap();
+            assert_eq!(context.client_id(), 0);
+            drop(context);
+            assert!(emulator.http_client(0).is_some());
+
+            let mut http = new_async_http_context(&mut modem, "http://example.com")
+                .await
+                .unwrap()
+                .connect()
+                .await
+                .unwrap();
+            // The client of the dropped context has been destroyed first
+            assert_eq!(http.client_id(), 0);
+
+            let response: HttpResponse = http
+                .post("/items", "application/json", b"{}")
+                .await
+                .unwrap();
+            assert_eq!(response.status_code, 200);
+            assert_eq!(response.body, b"{}");
+
+            let mut body = [0; 8];
+            let header = http
+                .send_into(&HttpRequest::get("/"), &mut &mut body[..])
+                .await
+                .unwrap();
+            assert_eq!(header.header("Content-Length"), Some("1"));
+            assert_eq!(&body[..1], b"/");
+
+            http.close().await.unwrap();
+        });
+        assert!(emulator.http_client(0).is_none());
+    }
+
+    #[test]
+    fn test_http_header_larger_than_the_urc_payload() {
+        let emulator = Sim7020Emulator::new();
+        let header = format!("X-Long: {}\r\n", "a".repeat(290));
+        let hex: String = header.bytes().map(|byte| format!("{byte:02x}")).collect();
+
+        block_on(async {
+            let mut modem = registered_async_modem(&emulator, NoopDelay).await;
+            emulator.send_urc(&format!("+CHTTPNMIH: 0,200,{},{}", header.len(), hex));
+            emulator.send_urc("+CHTTPNMIC: 0,0,1,1,1,41");
+            assert!(matches!(
+                modem.receive_http_response::<16>(0, 1_000).await,
+                Err(AtError::CapacityError)
+            ));
+        });
+    }
+}
@@ -1,2 +1,5 @@
 //! Implementation of nonblocking contexts
+pub mod http_context;
+pub mod mqtt_context;
 pub mod socket_context;
+pub mod tls_context;
@@ -0,0 +1,378 @@
+//! Contains the definitions for the async MQTT contexts
+
+use crate::at_command::mqtt::*;
+use crate::contexts::common_socket_context::{Connected, PendingConnection, RECEIVE_TIMEOUT_MS};
+use crate::nonblocking::AsyncModem;
+use crate::{AtError, BUFFER_SIZE};
+use core::marker::PhantomData;
+#[cfg(feature = "defmt")]
+use defmt::debug;
+use embedded_hal::digital::OutputPin;
+use embedded_hal_async::delay::DelayNs;
+use embedded_io::ReadReady;
+use embedded_io_async::{Read, Write};
+
+/// Defines an async MQTT context, which is associated with one MQTT session of the module.
+/// The session is closed when the context is closed. As no command can be sent when the context
+/// is dropped, the session of a dropped context is closed when the next context is created, see
+/// [AsyncModem::close_released_mqtt_sessions]
+pub struct AsyncMqttContext<
+    'a,
+    W: Write,
+    R: Read + ReadReady,
+    P: OutputPin,
+    D: DelayNs,
+    S,
+    const N: usize = BUFFER_SIZE,
+> {
+    mqtt_id: u8,
+    /// Only taken when the context changes its state
+    modem: Option<&'a mut AsyncModem<W, R, P, D, N>>,
+    /// Format of the received payloads, see [AsyncMqttContext::set_data_format]
+    data_format: MQTTDataFormat,
+    subscriptions: MQTTSubscriptions,
+    _state: PhantomData<S>,
+}
+
+/// Creates a new MQTT session with the given settings, which may be a TLS session, and returns
+/// its [AsyncMqttContext]
+pub async fn new_async_mqtt_context<
+    'a,
+    W: Write,
+    R: Read + ReadReady,
+    P: OutputPin,
+    D: DelayNs,
+    const N: usize,
+>(
+    modem: &'a mut AsyncModem<W, R, P, D, N>,
+    settings: &MQTTSessionSettings<'_>,
+) -> Result<AsyncMqttContext<'a, W, R, P, D, PendingConnection, N>, AtError> {
+    #[cfg(feature = "defmt")]
+    debug!("Creating a new MQTT Context to {}", settings.server);
+
+    modem.close_released_mqtt_sessions().await?;
+    let session = modem.send_and_wait_response(settings.clone()).await?;
+    modem.clear_mqtt_messages(session.mqtt_id);
+
+    Ok(AsyncMqttContext {
+        mqtt_id: session.mqtt_id,
+        modem: Some(modem),
+        data_format: MQTTDataFormat::Bytes,
+        subscriptions: MQTTSubscriptions::new(),
+        _state: Default::default(),
+    })
+}
+
+impl<'a, W: Write, R: Read + ReadReady, P: OutputPin, D: DelayNs, S, const N: usize>
+    AsyncMqttContext<'a, W, R, P, D, S, N>
+{
+    pub fn mqtt_id(&self) -> u8 {
+        self.mqtt_id
+    }
+
+    fn modem(&mut self) -> &mut AsyncModem<W, R, P, D, N> {
+        self.modem
+            .as_deref_mut()
+            .expect("the modem is only taken when the context changes its state")
+    }
+
+    /// Moves the modem into a context with the new state
+    fn into_state<T>(mut self) -> AsyncMqttContext<'a, W, R, P, D, T, N> {
+        AsyncMqttContext {
+            mqtt_id: self.mqtt_id,
+            modem: self.modem.take(),
+            data_format: self.data_format,
+            subscriptions: core::mem::take(&mut self.subscriptions),
+            _state: Default::default(),
+        }
+    }
+
+    /// Configures the format in which the module sends and receives the payloads. The received
+    /// payloads are decoded according to it. Until it is called the payloads are returned as
+    /// sent by the module
+    pub async fn set_data_format(&mut self, data_format: MQTTDataFormat) -> Result<(), AtError> {
+        self.modem()
+            .send_and_wait_response(MQTTRawData { data_format })
+            .await?;
+        self.data_format = data_format;
+
+        Ok(())
+    }
+
+    /// Closes the session in the module, which disconnects it from the broker
+    pub async fn close(mut self) -> Result<(), AtError> {
+        let mqtt_id = self.mqtt_id;
+        let Some(modem) = self.modem.take() else {
+            return Ok(());
+        };
+        modem
+            .send_and_wait_response(CloseMQTTConnection { mqtt_id })
+            .await?;
+        modem.clear_mqtt_messages(mqtt_id);
+
+        Ok(())
+    }
+}
+
+impl<W: Write, R: Read + ReadReady, P: OutputPin, D: DelayNs, S, const N: usize> Drop
+    for AsyncMqttContext<'_, W, R, P, D, S, N>
+{
+    fn drop(&mut self) {
+        if let Some(modem) = self.modem.take() {
+            modem.release_mqtt_session(self.mqtt_id);
+        }
+    }
+}
+
+impl<'a, W: Write, R: Read + ReadReady, P: OutputPin, D: DelayNs, const N: usize>
+    AsyncMqttContext<'a, W, R, P, D, PendingConnection, N>
+{
+    /// Connects the session to the broker
+    pub async fn connect(
+        mut self,
+        settings: MQTTConnectionSettings<'_>,
+    ) -> Result<AsyncMqttContext<'a, W, R, P, D, Connected, N>, AtError> {
+        let mqtt_id = self.mqtt_id;
+        self.modem()
+            .send_and_wait_response(settings.with_mqtt_id(mqtt_id))
+            .await?;
+
+        #[cfg(feature = "defmt")]
+        debug!("MQTT session {} connected", mqtt_id);
+
+        Ok(self.into_state())
+    }
+}
+
+impl<'a, W: Write, R: Read + ReadReady, P: OutputPin, D: DelayNs, const N: usize>
+    AsyncMqttContext<'a, W, R, P, D, Connected, N>
+{
+    pub async fn publish(&mut self, message: &MQTTMessage<'_>) -> Result<(), AtError> {
+        let mqtt_id = self.mqtt_id;
+        self.modem()
+            .send_and_wait_response(MQTTPublish {
+                mqtt_id,
+                topic: message.topic,
+                qos: message.qos,
+                retained: message.retained,
+                dup: message.dup,
+                message: message.message,
+            })
+            .await
+    }
+
+    /// Subscribes to the topic, which may contain wildcards, and remembers it. Subscribing again
+    /// to a known topic updates its QoS. Fails with [AtError::CapacityError] if the topic is too
+    /// long or there are already [MAX_SUBSCRIPTIONS] topics
+    pub async fn subscribe(&mut self, topic: &str, qos: u8) -> Result<(), AtError> {
+        let entry = self.subscriptions.entry(topic, qos)?;
+
+        let mqtt_id = self.mqtt_id;
+        self.modem()
+            .send_and_wait_response(MQTTSubscribe {
+                mqtt_id,
+                topic,
+                qos,
+            })
+            .await?;
+        self.subscriptions.store(entry);
+
+        Ok(())
+    }
+
+    /// Unsubscribes from the topic and forgets it
+    pub async fn unsubscribe(&mut self, topic: &str) -> Result<(), AtError> {
+        let mqtt_id = self.mqtt_id;
+        self.modem()
+            .send_and_wait_response(MQTTUnsubscribe { mqtt_id, topic })
+            .await?;
+        self.subscriptions.remove(topic);
+
+        Ok(())
+    }
+
+    /// Topics currently subscribed
+    pub fn subscriptions(&self) -> &[MQTTSubscription] {
+        self.subscriptions.as_slice()
+    }
+
+    /// Returns the oldest message received in the subscribed topics, without waiting for more
+    /// messages
+    pub async fn try_receive(&mut self) -> Result<Option<MQTTReceivedMessage>, AtError> {
+        let (mqtt_id, data_format) = (self.mqtt_id, self.data_format);
+        self.modem()
+            .try_receive_mqtt_message(mqtt_id, data_format)
+            .await
+    }
+
+    /// Waits up to [RECEIVE_TIMEOUT_MS] for a message in the subscribed topics
+    pub async fn receive(&mut self) -> Result<MQTTReceivedMessage, AtError> {
+        let (mqtt_id, data_format) = (self.mqtt_id, self.data_format);
+        self.modem()
+            .receive_mqtt_message(mqtt_id, data_format, RECEIVE_TIMEOUT_MS)
+            .await
+    }
+
+    /// Returns the stream of the messages received in the subscribed topics
+    pub fn messages(&mut self) -> AsyncMqttMessages<'_, 'a, W, R, P, D, N> {
+        AsyncMqttMessages { context: self }
+    }
+}
+
+/// Stream of the messages received by an [AsyncMqttContext]
+pub struct AsyncMqttMessages<
+    'c,
+    'a,
+    W: Write,
+    R: Read + ReadReady,
+    P: OutputPin,
+    D: DelayNs,
+    const N: usize = BUFFER_SIZE,
+> {
+    context: &'c mut AsyncMqttContext<'a, W, R, P, D, Connected, N>,
+}
+
+impl<W: Write, R: Read + ReadReady, P: OutputPin, D: DelayNs, const N: usize>
+    AsyncMqttMessages<'_, '_, W, R, P, D, N>
+{
+    /// Waits until the next message is received. Returns None, ending the stream, once the
+    /// session has been disconnected from the broker. Only fails if the module can not be read or
+    /// the message can not be decoded
+    pub async fn next(&mut self) -> Result<Option<MQTTReceivedMessage>, AtError> {
+        let (mqtt_id, data_format) = (self.context.mqtt_id, self.context.data_format);
+        self.context
+            .modem()
+            .next_mqtt_message(mqtt_id, data_format)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::emulator::Sim7020Emulator;
+    use crate::testing::{registered_async_modem, test_connection_settings, NoopDelay};
+    use embassy_futures::block_on;
+
+    #[test]
+    fn test_async_mqtt_context() {
+        let emulator = Sim7020Emulator::new();
+        let settings = MQTTSessionSettings::new("broker.example.com", 1883);
+        let connection = test_connection_settings();
+
+        block_on(async {
+            let mut modem = registered_async_modem(&emulator, NoopDelay).await;
+
+            // Dropped without being closed
+            let context = new_async_mqtt_context(&mut modem, &settings).await.unwrap();
+            assert_eq!(context.mqtt_id(), 0);
+            drop(context);
+            assert!(emulator.mqtt_session(0).is_some());
+
+            let mut mqtt = new_async_mqtt_context(&mut modem, &settings)
+                .await
+                .unwrap()
+                .connect(connection)
+                .await
+                .unwrap();
+            // The session of the dropped context has been closed first
+            assert_eq!(mqtt.mqtt_id(), 0);
+            assert!(emulator.mqtt_session(0).unwrap().connected);
+
+            mqtt.set_data_format(MQTTDataFormat::Hex).await.unwrap();
+            mqtt.subscribe("sensors/+", 0).await.unwrap();
+            mqtt.subscribe("alarms", 1).await.unwrap();
+            mqtt.unsubscribe("alarms").await.unwrap();
+            assert_eq!(mqtt.subscriptions().len(), 1);
+            assert_eq!(
+                emulator.mqtt_session(0).unwrap().subscriptions.as_slice(),
+                ["sensors/+"]
+            );
+            assert_eq!(mqtt.try_receive().await.unwrap(), None);
+
+            for (topic, message) in [
+                ("sensors/temp", b"32313530"),
+                ("alarms", b"31313131"),
+                ("sensors/hum", b"34353030"),
+            ] {
+                mqtt.publish(&MQTTMessage {
+                    topic,
+                    qos: 0,
+                    retained: false,
+                    dup: false,
+                    message,
+                })
+                .await
+                .unwrap();
+            }
+
+            let mut messages = mqtt.messages();
+            let message = messages.next().await.unwrap().unwrap();
+            assert_eq!(message.topic.as_str(), "sensors/temp");
+            assert_eq!(message.payload.as_slice(), b"2150");
+            let message = messages.next().await.unwrap().unwrap();
+            assert_eq!(message.topic.as_str(), "sensors/hum");
+            assert_eq!(message.payload.as_slice(), b"4500");
+
+            mqtt.close().await.unwrap();
+        });
+        assert!(emulator.mqtt_session(0).is_none());
+    }
+
+    /// Lets the other futures run while the modem waits for data
+    struct YieldDelay;
+
+    impl DelayNs for YieldDelay {
+        async fn delay_ns(&mut self, _ns: u32) {
+            embassy_futures::yield_now().await;
+        }
+    }
+
+    #[test]
+    fn test_async_mqtt_messages_end_on_disconnection() {
+        let emulator = Sim7020Emulator::new();
+        let settings = MQTTSessionSettings::new("broker.example.com", 1883);
+        let connection = test_connection_settings();
+
+        block_on(async {
+            let mut modem = registered_async_modem(&emulator, YieldDelay).await;
+            let mut mqtt = new_async_mqtt_context(&mut modem, &settings)
+                .await
+                .unwrap()
+                .connect(connection)
+                .await
+                .unwrap();
+            mqtt.subscribe("sensors/+", 0).await.unwrap();
+            mqtt.publish(&MQTTMessage {
+                topic: "sensors/temp",
+                qos: 0,
+                retained: false,
+                dup: false,
+                message: b"21.5",
+            })
+            .await
+            .unwrap();
+
+            let mut messages = mqtt.messages();
+            let (received, _) = embassy_futures::join::join(
+                async {
+                    let mut received = std::vec::Vec::new();
+                    while let Some(message) = messages.next().await.unwrap() {
+                        received.push(message.payload.to_vec());
+                    }
+                    received
+                },
+                async {
+                    for _ in 0..10 {
+                        embassy_futures::yield_now().await;
+                    }
+                    emulator.disconnect_mqtt_session(0);
+                },
+            )
+            .await;
+
+            // The message received before the disconnection is still delivered
+            assert_eq!(received, [b"21.5".to_vec()]);
+        });
+    }
+}
@@ -2,9 +2,9 @@ use crate::at_command::socket::{
     CloseSocket, ConnectSocketToRemote, CreateSocket, Domain, Protocol, SendSocketMessage,
     SendSocketString, Type,
 };
-use crate::contexts::common_socket_context::{Connected, PendingConnection};
+use crate::contexts::common_socket_context::{Connected, PendingConnection, RECEIVE_TIMEOUT_MS};
 use crate::nonblocking::AsyncModem;
-use crate::AtError;
+use crate::{AtError, BUFFER_SIZE};
 use core::marker::PhantomData;
 #[cfg(feature = "defmt")]
 use defmt::debug;
@@ -13,19 +13,34 @@ use embedded_hal_async::delay::DelayNs;
 use embedded_io::ReadReady;
 use embedded_io_async::{Read, Write};
 
-pub struct AsyncSocketContext<'a, W: Write, R: Read + ReadReady, P: OutputPin, D: DelayNs, S> {
+pub struct AsyncSocketContext<
+    'a,
+    W: Write,
+    R: Read + ReadReady,
+    P: OutputPin,
+    D: DelayNs,
+    S,
+    const N: usize = BUFFER_SIZE,
+> {
     socket_id: u8,
-    modem: &'a mut AsyncModem<W, R, P, D>,
+    modem: &'a mut AsyncModem<W, R, P, D, N>,
     _state: PhantomData<S>,
 }
 
-pub async fn new_async_http_session<'a, W: Write, R: Read + ReadReady, P: OutputPin, D: DelayNs>(
-    modem: &'a mut AsyncModem<W, R, P, D>,
+pub async fn new_async_http_session<
+    'a,
+    W: Write,
+    R: Read + ReadReady,
+    P: OutputPin,
+    D: DelayNs,
+    const N: usize,
+>(
+    modem: &'a mut AsyncModem<W, R, P, D, N>,
     domain: Domain,
     connection_type: Type,
     protocol: Protocol,
     cid: Option<i32>,
-) -> Result<AsyncSocketContext<'a, W, R, P, D, PendingConnection>, AtError> {
+) -> Result<AsyncSocketContext<'a, W, R, P, D, PendingConnection, N>, AtError> {
     #[cfg(feature = "defmt")]
     debug!("Creating a new HTTP Context");
 
@@ -45,8 +60,16 @@ pub async fn new_async_http_session<'a, W: Write, R: Read + ReadReady, P: Output
     })
 }
 
-async fn close_socket_context<'a, W: Write, R: Read + ReadReady, P: OutputPin, D: DelayNs, S>(
-    context: AsyncSocketContext<'a, W, R, P, D, S>,
+async fn close_socket_context<
+    'a,
+    W: Write,
+    R: Read + ReadReady,
+    P: OutputPin,
+    D: DelayNs,
+    S,
+    const N: usize,
+>(
+    context: AsyncSocketContext<'a, W, R, P, D, S, N>,
 ) -> Result<(), AtError> {
     context
         .modem
@@ -54,19 +77,20 @@ async fn close_socket_context<'a, W: Write, R: Read + ReadReady, P: OutputPin, D
             socket_id: context.socket_id,
         })
         .await?;
+    context.modem.clear_socket_data(context.socket_id);
 
     Ok(())
 }
 
-impl<'a, W: Write, R: Read + ReadReady, P: OutputPin, D: DelayNs>
-    AsyncSocketContext<'a, W, R, P, D, PendingConnection>
+impl<'a, W: Write, R: Read + ReadReady, P: OutputPin, D: DelayNs, const N: usize>
+    AsyncSocketContext<'a, W, R, P, D, PendingConnection, N>
 {
     /// Connects the socket session to the remote server
     pub async fn connect_to_remote(
         self,
         port: u16,
         address: &str,
-    ) -> Result<AsyncSocketContext<'a, W, R, P, D, Connected>, AtError> {
+    ) -> Result<AsyncSocketContext<'a, W, R, P, D, Connected, N>, AtError> {
         #[cfg(feature = "defmt")]
         debug!("Connecting socket to {}:{}", address, port);
 
@@ -93,8 +117,8 @@ impl<'a, W: Write, R: Read + ReadReady, P: OutputPin, D: DelayNs>
     }
 }
 
-impl<'a, W: Write, R: Read + ReadReady, P: OutputPin, D: DelayNs>
-    AsyncSocketContext<'a, W, R, P, D, Connected>
+impl<'a, W: Write, R: Read + ReadReady, P: OutputPin, D: DelayNs, const N: usize>
+    AsyncSocketContext<'a, W, R, P, D, Connected, N>
 {
     /// Sends the given string to the remote connection
     pub async fn send_string(&mut self, data: &str) -> Result<(), AtError> {
@@ -120,7 +144,58 @@ impl<'a, W: Write, R: Read + ReadReady, P: OutputPin, D: DelayNs>
         Ok(())
     }
 
+    /// Copies into [buf] the data received from the remote connection, waiting up to
+    /// [RECEIVE_TIMEOUT_MS] for it to arrive. Returns the number of bytes copied
+    pub async fn receive(&mut self, buf: &mut [u8]) -> Result<usize, AtError> {
+        self.modem
+            .receive_socket_data(self.socket_id, buf, RECEIVE_TIMEOUT_MS)
+            .await
+    }
+
+    /// Copies into [buf] the data already received from the remote connection without waiting.
+    /// Returns the number of bytes copied, 0 if no data has been received
+    pub async fn try_receive(&mut self, buf: &mut [u8]) -> Result<usize, AtError> {
+        self.modem
+            .try_receive_socket_data(self.socket_id, buf)
+            .await
+    }
+
     pub async fn close(self) -> Result<(), AtError> {
         close_socket_context(self).await
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::at_command::socket::{Domain, Protocol, Type};
+    use crate::emulator::Sim7020Emulator;
+
+    use crate::testing::{registered_async_modem, NoopDelay};
+    use embassy_futures::block_on;
+
+    #[test]
+    fn test_async_socket_context_receive() {
+        let emulator = Sim7020Emulator::new();
+
+        block_on(async {
+            let mut modem = registered_async_modem(&emulator, NoopDelay).await;
+            let context = super::new_async_http_session(
+                &mut modem,
+                Domain::IPv4,
+                Type::TCP,
+                Protocol::IP,
+                None,
+            )
+            .await
+            .unwrap();
+            let mut socket = context.connect_to_remote(7, "127.0.0.1").await.unwrap();
+
+            socket.send_data(b"PING").await.unwrap();
+            let mut buf = [0; 8];
+            let size = socket.receive(&mut buf).await.unwrap();
+            assert_eq!(&buf[..size], b"PING");
+            assert_eq!(socket.try_receive(&mut buf).await.unwrap(), 0);
+            socket.close().await.unwrap();
+        });
+    }
+}
@@ -0,0 +1,188 @@
+use crate::at_command::socket::max_send_size;
+use crate::at_command::tls::{CloseTls, ConfigureTls, ConnectTls, SendTlsData, TlsSetting};
+use crate::contexts::common_socket_context::{Connected, PendingConnection, RECEIVE_TIMEOUT_MS};
+use crate::nonblocking::AsyncModem;
+use crate::urc::MAX_TLS_CONNECTIONS;
+use crate::{AtError, BUFFER_SIZE};
+use core::marker::PhantomData;
+#[cfg(feature = "defmt")]
+use defmt::debug;
+use embedded_hal::digital::OutputPin;
+use embedded_hal_async::delay::DelayNs;
+use embedded_io::ReadReady;
+use embedded_io_async::{Read, Write};
+
+pub struct AsyncTlsContext<
+    'a,
+    W: Write,
+    R: Read + ReadReady,
+    P: OutputPin,
+    D: DelayNs,
+    S,
+    const N: usize = BUFFER_SIZE,
+> {
+    tls_id: u8,
+    modem: &'a mut AsyncModem<W, R, P, D, N>,
+    _state: PhantomData<S>,
+}
+
+/// Creates a new [AsyncTlsContext] to the given server using the given modem. The TLS id goes
+/// from 1 to [MAX_TLS_CONNECTIONS]
+pub async fn new_async_tls_context<
+    'a,
+    W: Write,
+    R: Read + ReadReady,
+    P: OutputPin,
+    D: DelayNs,
+    const N: usize,
+>(
+    modem: &'a mut AsyncModem<W, R, P, D, N>,
+    tls_id: u8,
+    host: &str,
+    port: u16,
+) -> Result<AsyncTlsContext<'a, W, R, P, D, PendingConnection, N>, AtError> {
+    #[cfg(feature = "defmt")]
+    debug!("Creating a new TLS Context to {}:{}", host, port);
+
+    if !(1..=MAX_TLS_CONNECTIONS).contains(&(tls_id as usize)) {
+        return Err(AtError::CapacityError);
+    }
+
+    let mut context = AsyncTlsContext {
+        tls_id,
+        modem,
+        _state: Default::default(),
+    };
+    context.configure(TlsSetting::Host(host)).await?;
+    context.configure(TlsSetting::Port(port)).await?;
+
+    Ok(context)
+}
+
+async fn close_tls_context<
+    'a,
+    W: Write,
+    R: Read + ReadReady,
+    P: OutputPin,
+    D: DelayNs,
+    S,
+    const N: usize,
+>(
+    context: AsyncTlsContext<'a, W, R, P, D, S, N>,
+) -> Result<(), AtError> {
+    context
+        .modem
+        .send_and_wait_response(CloseTls {
+            tls_id: context.tls_id,
+        })
+        .await?;
+    context.modem.clear_tls_data(context.tls_id);
+
+    Ok(())
+}
+
+impl<'a, W: Write, R: Read + ReadReady, P: OutputPin, D: DelayNs, const N: usize>
+    AsyncTlsContext<'a, W, R, P, D, PendingConnection, N>
+{
+    /// Sets a parameter of the connection, e.g. the server name, the verify mode, the TLS version
+    /// or the cipher suite
+    pub async fn configure(&mut self, setting: TlsSetting<'_>) -> Result<(), AtError> {
+        self.modem
+            .send_and_wait_response(ConfigureTls {
+                tls_id: self.tls_id,
+                setting,
+            })
+            .await
+    }
+
+    /// Performs the handshake with the server
+    pub async fn connect(self) -> Result<AsyncTlsContext<'a, W, R, P, D, Connected, N>, AtError> {
+        self.modem
+            .send_and_wait_response(ConnectTls {
+                tls_id: self.tls_id,
+            })
+            .await?;
+
+        #[cfg(feature = "defmt")]
+        debug!("TLS connection {} established", self.tls_id);
+
+        Ok(AsyncTlsContext {
+            tls_id: self.tls_id,
+            modem: self.modem,
+            _state: Default::default(),
+        })
+    }
+
+    pub async fn close(self) -> Result<(), AtError> {
+        close_tls_context(self).await
+    }
+}
+
+impl<W: Write, R: Read + ReadReady, P: OutputPin, D: DelayNs, const N: usize>
+    AsyncTlsContext<'_, W, R, P, D, Connected, N>
+{
+    /// Sends the given data to the server, split in as many commands as needed
+    pub async fn send_data(&mut self, data: &[u8]) -> Result<(), AtError> {
+        for chunk in data.chunks(max_send_size(N).max(1)) {
+            self.modem
+                .send_and_wait_response(SendTlsData {
+                    tls_id: self.tls_id,
+                    data: chunk,
+                })
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Copies into [buf] the data received from the server, waiting up to [RECEIVE_TIMEOUT_MS]
+    /// for it to arrive. Returns the number of bytes copied
+    pub async fn receive(&mut self, buf: &mut [u8]) -> Result<usize, AtError> {
+        self.modem
+            .receive_tls_data(self.tls_id, buf, RECEIVE_TIMEOUT_MS)
+            .await
+    }
+
+    /// Copies into [buf] the data already received from the server without waiting.
+    /// Returns the number of bytes copied, 0 if no data has been received
+    pub async fn try_receive(&mut self, buf: &mut [u8]) -> Result<usize, AtError> {
+        self.modem.try_receive_tls_data(self.tls_id, buf).await
+    }
+
+    pub async fn close(self) -> Result<(), AtError> {
+        close_tls_context(self).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::at_command::tls::{TlsSetting, VerifyMode};
+    use crate::emulator::Sim7020Emulator;
+
+    use crate::testing::{registered_async_modem, NoopDelay};
+    use embassy_futures::block_on;
+
+    #[test]
+    fn test_async_tls_context() {
+        let emulator = Sim7020Emulator::new();
+
+        block_on(async {
+            let mut modem = registered_async_modem(&emulator, NoopDelay).await;
+            let mut context = super::new_async_tls_context(&mut modem, 2, "example.com", 443)
+                .await
+                .unwrap();
+            context
+                .configure(TlsSetting::VerifyMode(VerifyMode::None))
+                .await
+                .unwrap();
+            let mut tls = context.connect().await.unwrap();
+
+            tls.send_data(b"PING").await.unwrap();
+            let mut buf = [0; 8];
+            let size = tls.receive(&mut buf).await.unwrap();
+            assert_eq!(&buf[..size], b"PING");
+            tls.close().await.unwrap();
+        });
+        assert!(emulator.tls_connection(2).is_none());
+    }
+}
@@ -0,0 +1,1501 @@
+//! Behavioural emulator of the AT interface of the SIM7020 module.
+//!
+//! Unlike [crate::testing::ScriptedSerial], the [Sim7020Emulator] keeps the state of the module:
+//! SIM PIN, network registration, PDP context, sockets, TLS connections and their credentials,
+//! MQTT sessions and HTTP clients. The
+//! commands are answered depending on that state and the module emits the unsolicited result
+//! codes that would be received from the network, e.g. the data sent through a socket is echoed
+//! back with `+CSONMI`, the MQTT messages published on a subscribed topic are delivered with
+//! `+CMQPUB` and the HTTP requests are answered with `+CHTTPNMIH` and `+CHTTPNMIC`, echoing their
+//! body.
+//!
+//! ```
+//! use sim7020::emulator::Sim7020Emulator;
+//! use sim7020::testing::{NoopDelay, NoopPin};
+//! use sim7020::Modem;
+//!
+//! let emulator = Sim7020Emulator::new().with_pin(1234);
+//! let (mut writer, mut reader) = emulator.split();
+//!
+//! let mut modem = Modem::new(&mut writer, &mut reader, NoopPin, NoopPin, NoopDelay).unwrap();
+//! modem.try_to_unlock_sim(1234, None).unwrap();
+//! assert!(emulator.is_registered());
+//! ```
+//!
+//! This module is available with the `emulator` feature
+
+use crate::at_command::cmee::CmeError;
+use crate::at_command::http::{
+    MAX_BODY_SIZE, MAX_CONTENT_TYPE_SIZE, MAX_HEADERS_SIZE, MAX_PATH_SIZE,
+};
+use crate::at_command::mqtt::MAX_WILL_MESSAGE_SIZE;
+use crate::router::topic_matches;
+use core::cell::RefCell;
+use core::convert::Infallible;
+use core::fmt::{self, Write as _};
+use embedded_io::{ErrorType, Read, ReadReady, Write};
+use heapless::{Deque, String, Vec};
+
+/// Number of sockets that can be created at the same time
+pub const MAX_SOCKETS: usize = 5;
+/// Number of MQTT sessions that can be created at the same time
+pub const MAX_MQTT_SESSIONS: usize = 1;
+/// Number of subscriptions of each MQTT session
+pub const MAX_SUBSCRIPTIONS: usize = 8;
+/// Number of HTTP clients that can be created at the same time
+pub const MAX_HTTP_CLIENTS: usize = 4;
+/// Max size of the host names and MQTT servers
+pub const MAX_HOST_SIZE: usize = 64;
+/// Max size of the MQTT topics
+pub const MAX_TOPIC_SIZE: usize = 128;
+/// Number of hosts that can be resolved with `AT+CDNSGIP`
+pub const MAX_HOSTS: usize = 4;
+/// Number of TLS connections that can be configured at the same time, the TLS ids go from 1 to
+/// `MAX_TLS_CONNECTIONS`
+pub const MAX_TLS_CONNECTIONS: usize = 6;
+/// Number of credentials that can be stored with `AT+CSETCA` at the same time, one of each type
+pub const MAX_CREDENTIALS: usize = 3;
+/// Max size of each stored credential
+pub const MAX_CREDENTIAL_SIZE: usize = 2048;
+
+const MAX_COMMAND_SIZE: usize = 2048;
+const OUTPUT_SIZE: usize = 4096;
+const URC_SIZE: usize = 2200;
+const MAX_PENDING_URCS: usize = 8;
+/// Maximum size of each `+CHTTPNMIC` fragment of the body of an HTTP response
+const HTTP_FRAGMENT_SIZE: usize = 128;
+const MAX_ARGUMENTS: usize = 12;
+const MAX_COMMAND_NAME_SIZE: usize = 16;
+
+/// IP address given to the module once the PDP context is active
+const LOCAL_IP: &str = "10.0.0.2";
+
+/// Socket created with `AT+CSOC`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EmulatedSocket {
+    pub domain: u8,
+    pub connection_type: u8,
+    pub protocol: u8,
+    /// Port of the remote peer once the socket is connected
+    pub remote_port: Option<u16>,
+}
+
+/// TLS connection configured with `AT+CTLSCFG`
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct EmulatedTlsConnection {
+    pub host: String<MAX_HOST_SIZE>,
+    pub port: Option<u16>,
+    pub socket_type: u8,
+    pub verify_mode: u8,
+    pub version: u8,
+    pub cipher_suite: u16,
+    pub server_name: String<MAX_HOST_SIZE>,
+    /// Indicates that the handshake has been performed with `AT+CTLSCONN`
+    pub connected: bool,
+}
+
+/// Certificate or key stored with `AT+CSETCA`
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmulatedCredential {
+    pub kind: u8,
+    pub data: Vec<u8, MAX_CREDENTIAL_SIZE>,
+}
+
+/// MQTT session created with `AT+CMQNEW` or `AT+CMQTTSNEW`
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmulatedMqttSession {
+    pub server: String<MAX_HOST_SIZE>,
+    pub port: u16,
+    /// Indicates that `AT+CMQCON` has been received
+    pub connected: bool,
+    pub subscriptions: Vec<String<MAX_TOPIC_SIZE>, MAX_SUBSCRIPTIONS>,
+    /// Indicates that the session has been created with `AT+CMQTTSNEW`
+    pub tls: bool,
+    /// Will sent in `AT+CMQCON`
+    pub will: Option<EmulatedMqttWill>,
+}
+
+/// Will of an MQTT session
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmulatedMqttWill {
+    pub topic: String<MAX_TOPIC_SIZE>,
+    pub qos: u8,
+    pub retained: bool,
+    pub message: String<MAX_WILL_MESSAGE_SIZE>,
+}
+
+/// HTTP client created with `AT+CHTTPCREATE`
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmulatedHttpClient {
+    pub host: String<MAX_HOST_SIZE>,
+    /// Indicates that `AT+CHTTPCON` has been received
+    pub connected: bool,
+    /// Certificates of an HTTPS client, with the line breaks already unescaped
+    pub server_certificate: Option<String<MAX_CREDENTIAL_SIZE>>,
+    pub client_certificate: Option<String<MAX_CREDENTIAL_SIZE>>,
+    pub client_private_key: Option<String<MAX_CREDENTIAL_SIZE>>,
+    /// Last request received with `AT+CHTTPSEND`
+    pub last_request: Option<EmulatedHttpRequest>,
+}
+
+/// HTTP request received with `AT+CHTTPSEND`, the headers and the body are already decoded
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmulatedHttpRequest {
+    pub method: u8,
+    pub path: String<MAX_PATH_SIZE>,
+    pub headers: Vec<u8, MAX_HEADERS_SIZE>,
+    pub content_type: String<MAX_CONTENT_TYPE_SIZE>,
+    pub body: Vec<u8, MAX_BODY_SIZE>,
+}
+
+/// Kind of AT command received
+enum Kind<'a> {
+    /// `AT<name>`
+    Execute,
+    /// `AT<name>?`
+    Query,
+    /// `AT<name>=?`
+    Test,
+    /// `AT<name>=<arguments>`
+    Set(&'a [u8]),
+}
+
+/// Arguments of a set command
+struct Arguments<'a>(Vec<&'a [u8], MAX_ARGUMENTS>);
+
+impl<'a> Arguments<'a> {
+    /// Splits the arguments by the commas that are not quoted
+    fn parse(data: &'a [u8]) -> Result<Self, CmeError> {
+        let mut arguments = Vec::new();
+        let mut quoted = false;
+        let mut start = 0;
+        for (index, &byte) in data.iter().enumerate() {
+            match byte {
+                b'"' => quoted = !quoted,
+                b',' if !quoted => {
+                    arguments
+                        .push(&data[start..index])
+                        .map_err(|_| CmeError::IncorrectParameters)?;
+                    start = index + 1;
+                }
+                _ => {}
+            }
+        }
+        arguments
+            .push(&data[start..])
+            .map_err(|_| CmeError::IncorrectParameters)?;
+
+        Ok(Self(arguments))
+    }
+
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    fn raw(&self, index: usize) -> Result<&'a [u8], CmeError> {
+        self.0
+            .get(index)
+            .copied()
+            .ok_or(CmeError::IncorrectParameters)
+    }
+
+    fn int(&self, index: usize) -> Result<i32, CmeError> {
+        core::str::from_utf8(self.raw(index)?)
+            .ok()
+            .and_then(|value| value.trim().parse().ok())
+            .ok_or(CmeError::IncorrectParameters)
+    }
+
+    fn optional_int(&self, index: usize) -> Result<Option<i32>, CmeError> {
+        match self.0.get(index) {
+            None => Ok(None),
+            Some([]) => Ok(None),
+            Some(_) => self.int(index).map(Some),
+        }
+    }
+
+    fn string(&self, index: usize) -> Result<&'a str, CmeError> {
+        let value = self.raw(index)?;
+        let value = value
+            .strip_prefix(b"\"")
+            .and_then(|value| value.strip_suffix(b"\""))
+            .ok_or(CmeError::IncorrectParameters)?;
+
+        core::str::from_utf8(value).map_err(|_| CmeError::IncorrectParameters)
+    }
+}
+
+/// Bytes written by the module
+struct Output(Deque<u8, OUTPUT_SIZE>);
+
+impl fmt::Write for Output {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for &byte in s.as_bytes() {
+            self.0.push_back(byte).map_err(|_| fmt::Error)?;
+        }
+        Ok(())
+    }
+}
+
+struct State {
+    echo: bool,
+    /// Value configured with `AT+CMEE`
+    error_mode: i32,
+    /// Value configured with `AT+CEREG`
+    registration_urcs: i32,
+    pin: Option<u16>,
+    sim_unlocked: bool,
+    network_available: bool,
+    attached: bool,
+    pdp_active: bool,
+    sockets: [Option<EmulatedSocket>; MAX_SOCKETS],
+    tls_connections: [Option<EmulatedTlsConnection>; MAX_TLS_CONNECTIONS],
+    credentials: Vec<EmulatedCredential, MAX_CREDENTIALS>,
+    /// Credential whose chunks are being received
+    credential_upload: Option<EmulatedCredential>,
+    mqtt_sessions: [Option<EmulatedMqttSession>; MAX_MQTT_SESSIONS],
+    http_clients: [Option<EmulatedHttpClient>; MAX_HTTP_CLIENTS],
+    /// Names and addresses known by the DNS of the network
+    hosts: Vec<(String<MAX_HOST_SIZE>, String<MAX_HOST_SIZE>), MAX_HOSTS>,
+    /// Command being received
+    command: Vec<u8, MAX_COMMAND_SIZE>,
+    output: Output,
+    /// URCs caused by the current command, they are sent after its final result
+    pending_urcs: Vec<String<URC_SIZE>, MAX_PENDING_URCS>,
+    /// Name of the next command that fails, set with [Sim7020Emulator::fail_next_command]
+    failing_command: Option<String<MAX_COMMAND_NAME_SIZE>>,
+}
+
+/// Emulator of the SIM7020 module, see the [module documentation](self)
+pub struct Sim7020Emulator {
+    state: RefCell<State>,
+}
+
+impl Default for Sim7020Emulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sim7020Emulator {
+    /// Creates a module with echo enabled, a SIM without PIN and the network available
+    pub fn new() -> Self {
+        Self {
+            state: RefCell::new(State {
+                echo: true,
+                error_mode: 0,
+                registration_urcs: 0,
+                pin: None,
+                sim_unlocked: true,
+                network_available: true,
+                attached: false,
+                pdp_active: false,
+                sockets: Default::default(),
+                tls_connections: Default::default(),
+                credentials: Vec::new(),
+                credential_upload: None,
+                mqtt_sessions: Default::default(),
+                http_clients: Default::default(),
+                hosts: Vec::new(),
+                command: Vec::new(),
+                output: Output(Deque::new()),
+                pending_urcs: Vec::new(),
+                failing_command: None,
+            }),
+        }
+    }
+
+    /// The SIM requires the given PIN before registering to the network
+    pub fn with_pin(self, pin: u16) -> Self {
+        {
+            let mut state = self.state.borrow_mut();
+            state.pin = Some(pin);
+            state.sim_unlocked = false;
+        }
+        self
+    }
+
+    /// The DNS of the network resolves the host to the given address. Panics if more than
+    /// [MAX_HOSTS] hosts are added
+    pub fn with_host(self, host: &str, address: &str) -> Self {
+        {
+            let mut state = self.state.borrow_mut();
+            let entry = (
+                host.try_into().expect("host too long"),
+                address.try_into().expect("address too long"),
+            );
+            state.hosts.push(entry).expect("too many hosts");
+        }
+        self
+    }
+
+    /// The network is not available until [Sim7020Emulator::set_network_available] is called
+    pub fn without_network(self) -> Self {
+        self.state.borrow_mut().network_available = false;
+        self
+    }
+
+    /// Returns the writer and the reader handles to be used by the modem
+    pub fn split(&self) -> (EmulatorWriter<'_>, EmulatorReader<'_>) {
+        (
+            EmulatorWriter { emulator: self },
+            EmulatorReader { emulator: self },
+        )
+    }
+
+    /// Changes the availability of the network. The module reports the new registration status
+    /// with `+CEREG` if it has been enabled
+    pub fn set_network_available(&self, available: bool) {
+        let mut state = self.state.borrow_mut();
+        let was_registered = state.is_registered();
+        state.network_available = available;
+        if !available {
+            state.detach();
+        }
+        state.registration_changed(was_registered);
+        state.flush_urcs();
+    }
+
+    /// Sends the given unsolicited result code, e.g. `+CPSMSTATUS: "ENTER PSM"`
+    pub fn send_urc(&self, urc: &str) {
+        let mut state = self.state.borrow_mut();
+        state.line(format_args!("{}", urc));
+    }
+
+    /// Delivers data from the remote peer of the socket with `+CSONMI`
+    pub fn receive_socket_data(&self, socket_id: u8, data: &[u8]) {
+        let mut state = self.state.borrow_mut();
+        state.data_urc("+CSONMI", socket_id, data);
+        state.flush_urcs();
+    }
+
+    /// The broker closes the connection of the MQTT session, which is released by the module and
+    /// reported with `+CMQDISCON`
+    pub fn disconnect_mqtt_session(&self, mqtt_id: u8) {
+        let mut state = self.state.borrow_mut();
+        if let Some(session) = state.mqtt_sessions.get_mut(mqtt_id as usize) {
+            if session.take().is_some() {
+                state.urc(format_args!("+CMQDISCON: {}", mqtt_id));
+                state.flush_urcs();
+            }
+        }
+    }
+
+    /// The next command with the given name, e.g. `+CSOCL`, fails with
+    /// [CmeError::OperationNotAllowed] without being executed
+    pub fn fail_next_command(&self, name: &str) {
+        self.state.borrow_mut().failing_command = Some(name.try_into().expect("name too long"));
+    }
+
+    /// Indicates if the module is registered to the network
+    pub fn is_registered(&self) -> bool {
+        self.state.borrow().is_registered()
+    }
+
+    /// Indicates if the PDP context is active
+    pub fn pdp_active(&self) -> bool {
+        self.state.borrow().pdp_active
+    }
+
+    /// Indicates if the module echoes the commands
+    pub fn echo_enabled(&self) -> bool {
+        self.state.borrow().echo
+    }
+
+    /// Gets the socket with the given id
+    pub fn socket(&self, socket_id: u8) -> Option<EmulatedSocket> {
+        self.state
+            .borrow()
+            .sockets
+            .get(socket_id as usize)
+            .copied()
+            .flatten()
+    }
+
+    /// Gets the TLS connection with the given id
+    pub fn tls_connection(&self, tls_id: u8) -> Option<EmulatedTlsConnection> {
+        let index = (tls_id as usize).checked_sub(1)?;
+        self.state
+            .borrow()
+            .tls_connections
+            .get(index)
+            .cloned()
+            .flatten()
+    }
+
+    /// Gets the credential of the given type
+    pub fn credential(&self, kind: u8) -> Option<EmulatedCredential> {
+        self.state
+            .borrow()
+            .credentials
+            .iter()
+            .find(|credential| credential.kind == kind)
+            .cloned()
+    }
+
+    /// Gets the MQTT session with the given id
+    pub fn mqtt_session(&self, mqtt_id: u8) -> Option<EmulatedMqttSession> {
+        self.state
+            .borrow()
+            .mqtt_sessions
+            .get(mqtt_id as usize)
+            .cloned()
+            .flatten()
+    }
+
+    /// Gets the HTTP client with the given id
+    pub fn http_client(&self, client_id: u8) -> Option<EmulatedHttpClient> {
+        self.state
+            .borrow()
+            .http_clients
+            .get(client_id as usize)
+            .cloned()
+            .flatten()
+    }
+
+    fn write_bytes(&self, buf: &[u8]) -> usize {
+        let mut state = self.state.borrow_mut();
+        for &byte in buf {
+            match byte {
+                b'\r' => {
+                    let command = core::mem::take(&mut state.command);
+                    state.process(&command);
+                }
+                // The line feed after the carriage return is ignored
+                b'\n' => {}
+                // Commands that do not fit are discarded as the module would not understand them
+                byte => {
+                    let _ = state.command.push(byte);
+                }
+            }
+        }
+
+        buf.len()
+    }
+
+    fn read_bytes(&self, buf: &mut [u8]) -> usize {
+        let mut state = self.state.borrow_mut();
+        let mut size = 0;
+        while size < buf.len() {
+            let Some(byte) = state.output.0.pop_front() else {
+                break;
+            };
+            buf[size] = byte;
+            size += 1;
+        }
+
+        size
+    }
+
+    fn read_ready(&self) -> bool {
+        !self.state.borrow().output.0.is_empty()
+    }
+}
+
+impl State {
+    fn is_registered(&self) -> bool {
+        self.sim_unlocked && self.network_available
+    }
+
+    /// Registration status as reported by `+CEREG`, `+CREG` and `+CGREG`
+    fn registration_status(&self) -> u8 {
+        match (self.sim_unlocked, self.network_available) {
+            (true, true) => 1,
+            (true, false) => 2,
+            (false, _) => 0,
+        }
+    }
+
+    /// Drops the PDP context and the connections that depend on it
+    fn detach(&mut self) {
+        self.attached = false;
+        self.pdp_active = false;
+        self.sockets = Default::default();
+        for mqtt_id in 0..MAX_MQTT_SESSIONS {
+            if self.mqtt_sessions[mqtt_id].take().is_some() {
+                self.urc(format_args!("+CMQDISCON: {}", mqtt_id));
+            }
+        }
+        for client in self.http_clients.iter_mut().flatten() {
+            client.connected = false;
+        }
+    }
+
+    fn registration_changed(&mut self, was_registered: bool) {
+        if was_registered != self.is_registered() && self.registration_urcs > 0 {
+            let status = self.registration_status();
+            self.urc(format_args!("+CEREG: {}", status));
+        }
+    }
+
+    /// Writes a line of the response
+    fn line(&mut self, args: fmt::Arguments) {
+        let _ = write!(self.output, "\r\n{}\r\n", args);
+    }
+
+    /// Queues an unsolicited result code to be sent after the final result of the command
+    fn urc(&mut self, args: fmt::Arguments) {
+        let mut urc = String::new();
+        if urc.write_fmt(args).is_ok() {
+            let _ = self.pending_urcs.push(urc);
+        }
+    }
+
+    fn flush_urcs(&mut self) {
+        for urc in core::mem::take(&mut self.pending_urcs) {
+            self.line(format_args!("{}", urc));
+        }
+    }
+
+    /// Queues the URC that reports data received from the network, e.g. `+CSONMI`
+    fn data_urc(&mut self, prefix: &str, id: u8, data: &[u8]) {
+        self.hex_urc(format_args!("{}: {},{},", prefix, id, data.len() * 2), data);
+    }
+
+    /// Queues an URC made of the given arguments followed by the data in hex
+    fn hex_urc(&mut self, args: fmt::Arguments, data: &[u8]) {
+        let mut urc: String<URC_SIZE> = String::new();
+        if urc.write_fmt(args).is_err() {
+            return;
+        }
+        for byte in data {
+            if write!(urc, "{:02X}", byte).is_err() {
+                return;
+            }
+        }
+        let _ = self.pending_urcs.push(urc);
+    }
+
+    /// Sends the error according to the mode configured with `AT+CMEE`
+    fn error(&mut self, error: CmeError) {
+        match (self.error_mode, error.code(), error.verbose()) {
+            (2, _, Some(text)) => self.line(format_args!("+CME ERROR: {}", text)),
+            (1 | 2, Some(code), _) => self.line(format_args!("+CME ERROR: {}", code)),
+            _ => self.line(format_args!("ERROR")),
+        }
+    }
+
+    fn process(&mut self, line: &[u8]) {
+        let Some(command) = line
+            .strip_prefix(b"AT")
+            .or_else(|| line.strip_prefix(b"at"))
+        else {
+            // The module ignores anything that is not a command
+            return;
+        };
+
+        if self.echo {
+            for &byte in line.iter().chain(b"\r") {
+                let _ = self.output.0.push_back(byte);
+            }
+        }
+
+        let (name, kind) = if let Some(name) = command.strip_suffix(b"=?") {
+            (name, Kind::Test)
+        } else if let Some(name) = command.strip_suffix(b"?") {
+            (name, Kind::Query)
+        } else if let Some(position) = command.iter().position(|&byte| byte == b'=') {
+            (&command[..position], Kind::Set(&command[position + 1..]))
+        } else {
+            (command, Kind::Execute)
+        };
+
+        let result = match &self.failing_command {
+            Some(failing) if failing.as_bytes() == name => {
+                self.failing_command = None;
+                Err(CmeError::OperationNotAllowed)
+            }
+            _ => self.execute(name, kind),
+        };
+        match result {
+            Ok(()) => self.line(format_args!("OK")),
+            Err(error) => self.error(error),
+        }
+        self.flush_urcs();
+    }
+
+    fn execute(&mut self, name: &[u8], kind: Kind) -> Result<(), CmeError> {
+        let arguments = match kind {
+            Kind::Set(arguments) => Some(Arguments::parse(arguments)?),
+            _ => None,
+        };
+
+        match (name, kind, arguments) {
+            (b"", Kind::Execute, _) => Ok(()),
+            (b"E0", Kind::Execute, _) => {
+                self.echo = false;
+                Ok(())
+            }
+            (b"E1", Kind::Execute, _) => {
+                self.echo = true;
+                Ok(())
+            }
+            (b"E", Kind::Query, _) => {
+                let echo = self.echo as u8;
+                self.line(format_args!("{}", echo));
+                Ok(())
+            }
+            (b"I", Kind::Execute | Kind::Query, _) => {
+                self.line(format_args!("SIM7020E R2752.02"));
+                Ok(())
+            }
+            (b"+CGMM", Kind::Execute, _) => {
+                self.line(format_args!("SIM7020E"));
+                Ok(())
+            }
+            (b"+CMEE", Kind::Query, _) => {
+                let mode = self.error_mode;
+                self.line(format_args!("+CMEE: {}", mode));
+                Ok(())
+            }
+            (b"+CMEE", _, Some(arguments)) => {
+                let mode = arguments.int(0)?;
+                if !(0..=2).contains(&mode) {
+                    return Err(CmeError::IncorrectParameters);
+                }
+                self.error_mode = mode;
+                Ok(())
+            }
+            (b"+CPIN", Kind::Query, _) => {
+                if self.sim_unlocked {
+                    self.line(format_args!("+CPIN: READY"));
+                } else {
+                    self.line(format_args!("+CPIN: SIM PIN"));
+                }
+                Ok(())
+            }
+            (b"+CPIN", _, Some(arguments)) => self.enter_pin(arguments.int(0)?),
+            (b"+CEREG", Kind::Query, _) => {
+                let (mode, status) = (self.registration_urcs, self.registration_status());
+                self.line(format_args!("+CEREG: {},{}", mode, status));
+                Ok(())
+            }
+            (b"+CEREG", _, Some(arguments)) => {
+                self.registration_urcs = arguments.int(0)?;
+                Ok(())
+            }
+            (b"+CREG" | b"+CGREG", Kind::Query, _) => {
+                let status = self.registration_status();
+                let name = core::str::from_utf8(name).unwrap_or_default();
+                self.line(format_args!("{}: 0,{}", name, status));
+                Ok(())
+            }
+            (b"+CGATT", Kind::Query, _) => {
+                let attached = self.attached as u8;
+                self.line(format_args!("+CGATT: {}", attached));
+                Ok(())
+            }
+            (b"+CGATT", _, Some(arguments)) => {
+                if arguments.int(0)? == 0 {
+                    self.detach();
+                    return Ok(());
+                }
+                self.require_registration()?;
+                self.attached = true;
+                Ok(())
+            }
+            (b"+CGACT", Kind::Query, _) => {
+                let active = self.pdp_active as u8;
+                self.line(format_args!("+CGACT: 1,{}", active));
+                Ok(())
+            }
+            (b"+CIICR", Kind::Execute, _) => {
+                self.require_registration()?;
+                self.attached = true;
+                self.pdp_active = true;
+                Ok(())
+            }
+            (b"+CSQ", Kind::Execute, _) => {
+                if self.is_registered() {
+                    self.line(format_args!("+CSQ: 20,0"));
+                } else {
+                    self.line(format_args!("+CSQ: 99,99"));
+                }
+                Ok(())
+            }
+            (b"+CIFSR", Kind::Execute, _) => {
+                if !self.pdp_active {
+                    return Err(CmeError::OperationNotAllowed);
+                }
+                self.line(format_args!("+CIFSR: {}", LOCAL_IP));
+                Ok(())
+            }
+            (b"+CSOC", _, Some(arguments)) => self.create_socket(&arguments),
+            (b"+CSOCON", _, Some(arguments)) => {
+                let socket = self.socket(arguments.int(0)?)?;
+                let port = arguments.int(1)?;
+                arguments.string(2)?;
+                socket.remote_port =
+                    Some(u16::try_from(port).map_err(|_| CmeError::IncorrectParameters)?);
+                Ok(())
+            }
+            (b"+CSOSEND", _, Some(arguments)) => self.send_socket_data(&arguments),
+            (b"+CDNSGIP", _, Some(arguments)) => self.resolve_host(arguments.string(0)?),
+            (b"+CSOCL", _, Some(arguments)) => {
+                self.socket(arguments.int(0)?)?;
+                self.sockets[arguments.int(0)? as usize] = None;
+                Ok(())
+            }
+            (b"+CTLSCFG", _, Some(arguments)) => self.configure_tls(&arguments),
+            (b"+CTLSCONN", _, Some(arguments)) => {
+                self.require_pdp_context()?;
+                let tls_id = arguments.int(0)?;
+                let connection = self.tls_connection(tls_id)?.clone();
+                if connection.host.is_empty() || connection.port.is_none() {
+                    return Err(CmeError::OperationNotAllowed);
+                }
+                // The handshake fails without the credentials required by the verify mode
+                let stored = |kind: u8| {
+                    self.credentials
+                        .iter()
+                        .any(|credential| credential.kind == kind)
+                };
+                let verified = match connection.verify_mode {
+                    0 => true,
+                    1 => stored(0),
+                    _ => stored(0) && stored(1) && stored(2),
+                };
+                if !verified {
+                    self.line(format_args!("+CTLSCONN: {},-1", tls_id));
+                    return Ok(());
+                }
+
+                self.tls_connection(tls_id)?.connected = true;
+                self.line(format_args!("+CTLSCONN: {},1", tls_id));
+                Ok(())
+            }
+            (b"+CSETCA", _, Some(arguments)) => self.store_credential_chunk(&arguments),
+            (b"+CTLSSEND", _, Some(arguments)) => self.send_tls_data(&arguments),
+            (b"+CTLSCLOSE", _, Some(arguments)) => {
+                self.tls_connection(arguments.int(0)?)?;
+                self.tls_connections[arguments.int(0)? as usize - 1] = None;
+                Ok(())
+            }
+            (b"+CMQNEW", Kind::Query, _) => {
+                for index in 0..MAX_MQTT_SESSIONS {
+                    if let Some(session) = self.mqtt_sessions[index].clone() {
+                        self.line(format_args!("+CMQNEW: {},1,\"{}\"", index, session.server));
+                    }
+                }
+                Ok(())
+            }
+            (b"+CMQNEW", _, Some(arguments)) => {
+                let mqtt_id = self.create_mqtt_session(&arguments, false)?;
+                self.line(format_args!("+CMQNEW: {}", mqtt_id));
+                Ok(())
+            }
+            (b"+CMQTTSNEW", _, Some(arguments)) => {
+                // The broker is verified with the CA certificate stored with AT+CSETCA
+                if !self.credentials.iter().any(|stored| stored.kind == 0) {
+                    return Err(CmeError::OperationNotAllowed);
+                }
+                let mqtt_id = self.create_mqtt_session(&arguments, true)?;
+                self.line(format_args!("+CMQTTSNEW: {}", mqtt_id));
+                Ok(())
+            }
+            (b"+CMQCON", Kind::Query, _) => {
+                for index in 0..MAX_MQTT_SESSIONS {
+                    if let Some(session) = self.mqtt_sessions[index].clone() {
+                        self.line(format_args!(
+                            "+CMQCON: {},{},\"{}\"",
+                            index, session.connected as u8, session.server
+                        ));
+                    }
+                }
+                Ok(())
+            }
+            (b"+CMQCON", _, Some(arguments)) => self.connect_mqtt_session(&arguments),
+            (b"+CMQDISCON", _, Some(arguments)) => {
+                self.mqtt_session(arguments.int(0)?)?;
+                self.mqtt_sessions[arguments.int(0)? as usize] = None;
+                Ok(())
+            }
+            (b"+CMQSUB", _, Some(arguments)) => {
+                let topic = arguments.string(1)?;
+                let session = self.connected_mqtt_session(arguments.int(0)?)?;
+                if !session.subscriptions.iter().any(|known| known == topic) {
+                    let topic = topic.try_into().map_err(|_| CmeError::TextStringTooLong)?;
+                    session
+                        .subscriptions
+                        .push(topic)
+                        .map_err(|_| CmeError::MemoryFull)?;
+                }
+                Ok(())
+            }
+            (b"+CMQUNSUB", _, Some(arguments)) => {
+                let topic = arguments.string(1)?;
+                self.connected_mqtt_session(arguments.int(0)?)?
+                    .subscriptions
+                    .retain(|known| known != topic);
+                Ok(())
+            }
+            (b"+CMQPUB", _, Some(arguments)) => self.publish(&arguments),
+            (b"+CHTTPCREATE", Kind::Query, _) => {
+                for index in 0..MAX_HTTP_CLIENTS {
+                    match self.http_clients[index].clone() {
+                        Some(client) => {
+                            self.line(format_args!("+CHTTPCREATE: {},1,{}", index, client.host))
+                        }
+                        None => self.line(format_args!("+CHTTPCREATE: {},0,", index)),
+                    }
+                }
+                Ok(())
+            }
+            (b"+CHTTPCREATE", _, Some(arguments)) => {
+                let client_id = self.create_http_client(&arguments)?;
+                self.line(format_args!("+CHTTPCREATE: {}", client_id));
+                Ok(())
+            }
+            (b"+CHTTPCON", _, Some(arguments)) => {
+                self.require_pdp_context()?;
+                self.http_client(arguments.int(0)?)?.connected = true;
+                Ok(())
+            }
+            (b"+CHTTPSEND", _, Some(arguments)) => self.send_http_request(&arguments),
+            (b"+CHTTPDISCON", _, Some(arguments)) => {
+                self.http_client(arguments.int(0)?)?.connected = false;
+                Ok(())
+            }
+            (b"+CHTTPDESTROY", _, Some(arguments)) => {
+                self.http_client(arguments.int(0)?)?;
+                self.http_clients[arguments.int(0)? as usize] = None;
+                Ok(())
+            }
+            // Settings that do not change the behaviour of the emulator
+            (b"+CREVHEX" | b"+CSCLK" | b"+IFC" | b"+CSTT" | b"*MCGDEFCONT" | b"+CPSMS", _, _) => {
+                Ok(())
+            }
+            _ => Err(CmeError::OperationNotSupported),
+        }
+    }
+
+    fn require_registration(&self) -> Result<(), CmeError> {
+        match (self.sim_unlocked, self.network_available) {
+            (false, _) => Err(CmeError::SimPinRequired),
+            (true, false) => Err(CmeError::NoNetworkService),
+            (true, true) => Ok(()),
+        }
+    }
+
+    fn require_pdp_context(&self) -> Result<(), CmeError> {
+        self.require_registration()?;
+        if !self.attached {
+            return Err(CmeError::OperationNotAllowed);
+        }
+        Ok(())
+    }
+
+    fn enter_pin(&mut self, pin: i32) -> Result<(), CmeError> {
+        if self.sim_unlocked {
+            return Err(CmeError::OperationNotAllowed);
+        }
+        if self.pin.map(i32::from) != Some(pin) {
+            return Err(CmeError::IncorrectPassword);
+        }
+
+        let was_registered = self.is_registered();
+        self.sim_unlocked = true;
+        self.registration_changed(was_registered);
+        Ok(())
+    }
+
+    fn socket(&mut self, socket_id: i32) -> Result<&mut EmulatedSocket, CmeError> {
+        usize::try_from(socket_id)
+            .ok()
+            .and_then(|index| self.sockets.get_mut(index))
+            .and_then(Option::as_mut)
+            .ok_or(CmeError::OperationNotAllowed)
+    }
+
+    fn create_socket(&mut self, arguments: &Arguments) -> Result<(), CmeError> {
+        self.require_pdp_context()?;
+        let socket = EmulatedSocket {
+            domain: arguments.int(0)? as u8,
+            connection_type: arguments.int(1)? as u8,
+            protocol: arguments.int(2)? as u8,
+            remote_port: None,
+        };
+        arguments.optional_int(3)?;
+
+        let socket_id = self
+            .sockets
+            .iter()
+            .position(Option::is_none)
+            .ok_or(CmeError::MemoryFull)?;
+        self.sockets[socket_id] = Some(socket);
+        self.line(format_args!("+CSOC: {}", socket_id));
+        Ok(())
+    }
+
+    /// The result of the resolution is reported after the final result
+    fn resolve_host(&mut self, host: &str) -> Result<(), CmeError> {
+        self.require_pdp_context()?;
+
+        let address = self
+            .hosts
+            .iter()
+            .find(|(name, _)| name == host)
+            .map(|(_, address)| address.clone());
+        match address {
+            Some(address) => self.urc(format_args!("+CDNSGIP: 1,\"{}\",\"{}\"", host, address)),
+            // DNS resolution error
+            None => self.urc(format_args!("+CDNSGIP: 0,8")),
+        }
+        Ok(())
+    }
+
+    /// The remote peer of the sockets is an echo server
+    fn send_socket_data(&mut self, arguments: &Arguments) -> Result<(), CmeError> {
+        let socket_id = arguments.int(0)?;
+        if self.socket(socket_id)?.remote_port.is_none() {
+            return Err(CmeError::OperationNotAllowed);
+        }
+
+        let mut data: Vec<u8, MAX_COMMAND_SIZE> = Vec::new();
+        match arguments.int(1)? {
+            // The data is sent as a string
+            0 => data
+                .extend_from_slice(arguments.string(2)?.as_bytes())
+                .map_err(|_| CmeError::TextStringTooLong)?,
+            hex_length => {
+                let hex = arguments.raw(2)?;
+                if hex.len() != hex_length as usize {
+                    return Err(CmeError::IncorrectParameters);
+                }
+                crate::at_command::decode_hex(hex, &mut data)
+                    .map_err(|_| CmeError::IncorrectParameters)?;
+            }
+        }
+
+        self.data_urc("+CSONMI", socket_id as u8, &data);
+        Ok(())
+    }
+
+    fn tls_connection(&mut self, tls_id: i32) -> Result<&mut EmulatedTlsConnection, CmeError> {
+        usize::try_from(tls_id - 1)
+            .ok()
+            .and_then(|index| self.tls_connections.get_mut(index))
+            .and_then(Option::as_mut)
+            .ok_or(CmeError::OperationNotAllowed)
+    }
+
+    /// Sets the pairs of parameter and value, creating the connection if needed
+    fn configure_tls(&mut self, arguments: &Arguments) -> Result<(), CmeError> {
+        let tls_id = arguments.int(0)?;
+        let slot = usize::try_from(tls_id - 1)
+            .ok()
+            .and_then(|index| self.tls_connections.get_mut(index))
+            .ok_or(CmeError::IncorrectParameters)?;
+        let connection = slot.get_or_insert_with(Default::default);
+        if arguments.len() < 3 || arguments.len().is_multiple_of(2) {
+            return Err(CmeError::IncorrectParameters);
+        }
+
+        for index in (1..arguments.len()).step_by(2) {
+            let value = index + 1;
+            match arguments.int(index)? {
+                1 => {
+                    connection.host = arguments
+                        .string(value)?
+                        .try_into()
+                        .map_err(|_| CmeError::TextStringTooLong)?
+                }
+                2 => {
+                    let port = u16::try_from(arguments.int(value)?)
+                        .map_err(|_| CmeError::IncorrectParameters)?;
+                    connection.port = Some(port);
+                }
+                3 => connection.socket_type = arguments.int(value)? as u8,
+                4 => connection.verify_mode = arguments.int(value)? as u8,
+                5 => connection.version = arguments.int(value)? as u8,
+                6 => connection.cipher_suite = arguments.int(value)? as u16,
+                7 => {
+                    connection.server_name = arguments
+                        .string(value)?
+                        .try_into()
+                        .map_err(|_| CmeError::TextStringTooLong)?
+                }
+                _ => return Err(CmeError::IncorrectParameters),
+            }
+        }
+        Ok(())
+    }
+
+    /// Accumulates the chunks of the credential, storing it once the last one is received
+    fn store_credential_chunk(&mut self, arguments: &Arguments) -> Result<(), CmeError> {
+        let kind = arguments.int(0)?;
+        let total_length = arguments.int(1)? as usize;
+        let last = arguments.int(2)? != 0;
+        // Only the hexadecimal <encode_type> is emulated
+        if !(0..=2).contains(&kind) || arguments.int(3)? != 1 || arguments.len() != 5 {
+            return Err(CmeError::IncorrectParameters);
+        }
+        let kind = kind as u8;
+
+        let mut credential = match self.credential_upload.take() {
+            Some(upload) if upload.kind == kind => upload,
+            // A new upload discards the chunks of a previous one
+            _ => EmulatedCredential {
+                kind,
+                data: Vec::new(),
+            },
+        };
+        crate::at_command::decode_hex(arguments.raw(4)?, &mut credential.data)
+            .map_err(|_| CmeError::MemoryFull)?;
+
+        if !last {
+            self.credential_upload = Some(credential);
+            return Ok(());
+        }
+        if credential.data.len() != total_length {
+            return Err(CmeError::IncorrectParameters);
+        }
+
+        self.credentials.retain(|stored| stored.kind != kind);
+        self.credentials
+            .push(credential)
+            .map_err(|_| CmeError::MemoryFull)
+    }
+
+    /// The server of the TLS connections is an echo server
+    fn send_tls_data(&mut self, arguments: &Arguments) -> Result<(), CmeError> {
+        let tls_id = arguments.int(0)?;
+        if !self.tls_connection(tls_id)?.connected {
+            return Err(CmeError::OperationNotAllowed);
+        }
+
+        let hex = arguments.raw(2)?;
+        if hex.len() != arguments.int(1)? as usize {
+            return Err(CmeError::IncorrectParameters);
+        }
+        let mut data: Vec<u8, MAX_COMMAND_SIZE> = Vec::new();
+        crate::at_command::decode_hex(hex, &mut data).map_err(|_| CmeError::IncorrectParameters)?;
+
+        self.data_urc("+CTLSRECV", tls_id as u8, &data);
+        Ok(())
+    }
+
+    fn mqtt_session(&mut self, mqtt_id: i32) -> Result<&mut EmulatedMqttSession, CmeError> {
+        usize::try_from(mqtt_id)
+            .ok()
+            .and_then(|index| self.mqtt_sessions.get_mut(index))
+            .and_then(Option::as_mut)
+            .ok_or(CmeError::OperationNotAllowed)
+    }
+
+    fn connected_mqtt_session(
+        &mut self,
+        mqtt_id: i32,
+    ) -> Result<&mut EmulatedMqttSession, CmeError> {
+        let session = self.mqtt_session(mqtt_id)?;
+        if !session.connected {
+            return Err(CmeError::OperationNotAllowed);
+        }
+        Ok(session)
+    }
+
+    fn connect_mqtt_session(&mut self, arguments: &Arguments) -> Result<(), CmeError> {
+        let will = match arguments.int(5)? {
+            0 => None,
+            1 => Some(Self::parse_mqtt_will(arguments.string(6)?)?),
+            _ => return Err(CmeError::IncorrectParameters),
+        };
+
+        let session = self.mqtt_session(arguments.int(0)?)?;
+        session.connected = true;
+        session.will = will;
+        Ok(())
+    }
+
+    /// Parses `topic=<topic>,QoS=<qos>,retained=<retained>,message_len=<len>,message=<message>`
+    fn parse_mqtt_will(options: &str) -> Result<EmulatedMqttWill, CmeError> {
+        let mut fields = options.splitn(5, ',');
+        let mut field = |name: &str| {
+            fields
+                .next()
+                .and_then(|field| field.strip_prefix(name))
+                .and_then(|field| field.strip_prefix('='))
+                .ok_or(CmeError::IncorrectParameters)
+        };
+        let topic = field("topic")?;
+        let qos = field("QoS")?;
+        let retained = field("retained")?;
+        let message_len = field("message_len")?;
+        let message = field("message")?;
+        if message_len.parse() != Ok(message.len()) {
+            return Err(CmeError::IncorrectParameters);
+        }
+
+        Ok(EmulatedMqttWill {
+            topic: topic.try_into().map_err(|_| CmeError::TextStringTooLong)?,
+            qos: qos.parse().map_err(|_| CmeError::IncorrectParameters)?,
+            retained: retained == "1",
+            message: message
+                .try_into()
+                .map_err(|_| CmeError::TextStringTooLong)?,
+        })
+    }
+
+    /// Creates an MQTT session, over TLS for `AT+CMQTTSNEW`. Returns the id of the session
+    fn create_mqtt_session(&mut self, arguments: &Arguments, tls: bool) -> Result<usize, CmeError> {
+        self.require_pdp_context()?;
+        let server = arguments
+            .string(0)?
+            .try_into()
+            .map_err(|_| CmeError::TextStringTooLong)?;
+        let port = u16::try_from(arguments.int(1)?).map_err(|_| CmeError::IncorrectParameters)?;
+
+        let mqtt_id = self
+            .mqtt_sessions
+            .iter()
+            .position(Option::is_none)
+            .ok_or(CmeError::MemoryFull)?;
+        self.mqtt_sessions[mqtt_id] = Some(EmulatedMqttSession {
+            server,
+            port,
+            connected: false,
+            subscriptions: Vec::new(),
+            tls,
+            will: None,
+        });
+        Ok(mqtt_id)
+    }
+
+    /// The broker delivers the messages published on the topics the session is subscribed to
+    fn publish(&mut self, arguments: &Arguments) -> Result<(), CmeError> {
+        let mqtt_id = arguments.int(0)?;
+        let topic = arguments.string(1)?;
+        let qos = arguments.int(2)?;
+        let retained = arguments.int(3)?;
+        let dup = arguments.int(4)?;
+        let length = arguments.int(5)?;
+        let message = arguments.string(6)?;
+        if arguments.len() != 7 || !(0..=2).contains(&qos) {
+            return Err(CmeError::IncorrectParameters);
+        }
+
+        let session = self.connected_mqtt_session(mqtt_id)?;
+        if session
+            .subscriptions
+            .iter()
+            .any(|filter| topic_matches(filter, topic))
+        {
+            self.urc(format_args!(
+                "+CMQPUB: {},\"{}\",{},{},{},{},\"{}\"",
+                mqtt_id, topic, qos, retained, dup, length, message
+            ));
+        }
+        Ok(())
+    }
+
+    fn http_client(&mut self, client_id: i32) -> Result<&mut EmulatedHttpClient, CmeError> {
+        usize::try_from(client_id)
+            .ok()
+            .and_then(|index| self.http_clients.get_mut(index))
+            .and_then(Option::as_mut)
+            .ok_or(CmeError::OperationNotAllowed)
+    }
+
+    /// Creates an HTTP client, with the certificates that follow the user and the password for
+    /// an HTTPS client. Returns the id of the client
+    fn create_http_client(&mut self, arguments: &Arguments) -> Result<usize, CmeError> {
+        let host = arguments
+            .string(0)?
+            .try_into()
+            .map_err(|_| CmeError::TextStringTooLong)?;
+        if ![1, 3, 5, 9].contains(&arguments.len()) {
+            return Err(CmeError::IncorrectParameters);
+        }
+        let mut certificates = [const { None }; 3];
+        for (index, certificate) in certificates.iter_mut().enumerate() {
+            let first = 3 + 2 * index;
+            if arguments.len() > first {
+                *certificate = Some(Self::certificate(arguments, first)?);
+            }
+        }
+
+        let client_id = self
+            .http_clients
+            .iter()
+            .position(Option::is_none)
+            .ok_or(CmeError::MemoryFull)?;
+        let [server_certificate, client_certificate, client_private_key] = certificates;
+        self.http_clients[client_id] = Some(EmulatedHttpClient {
+            host,
+            connected: false,
+            server_certificate,
+            client_certificate,
+            client_private_key,
+            last_request: None,
+        });
+        Ok(client_id)
+    }
+
+    /// Reads the certificate sent as its length followed by the string with the escaped line
+    /// breaks, starting at the argument [first]
+    fn certificate(
+        arguments: &Arguments,
+        first: usize,
+    ) -> Result<String<MAX_CREDENTIAL_SIZE>, CmeError> {
+        let mut certificate = String::new();
+        let mut escaped = false;
+        for character in arguments.string(first + 1)?.chars() {
+            let character = match (escaped, character) {
+                (false, '\\') => {
+                    escaped = true;
+                    continue;
+                }
+                (true, 'r') => '\r',
+                (true, 'n') => '\n',
+                (true, _) => return Err(CmeError::IncorrectParameters),
+                (false, character) => character,
+            };
+            escaped = false;
+            certificate
+                .push(character)
+                .map_err(|_| CmeError::TextStringTooLong)?;
+        }
+        if escaped || certificate.len() != arguments.int(first)? as usize {
+            return Err(CmeError::IncorrectParameters);
+        }
+
+        Ok(certificate)
+    }
+
+    fn send_http_request(&mut self, arguments: &Arguments) -> Result<(), CmeError> {
+        let client = self.http_client(arguments.int(0)?)?;
+        if !client.connected {
+            return Err(CmeError::OperationNotAllowed);
+        }
+
+        let method = arguments.int(1)?;
+        if !(0..=3).contains(&method) {
+            return Err(CmeError::IncorrectParameters);
+        }
+        let mut request = EmulatedHttpRequest {
+            method: method as u8,
+            path: arguments
+                .string(2)?
+                .try_into()
+                .map_err(|_| CmeError::TextStringTooLong)?,
+            headers: Vec::new(),
+            content_type: String::new(),
+            body: Vec::new(),
+        };
+        if arguments.len() > 3 {
+            crate::at_command::decode_hex(arguments.raw(3)?, &mut request.headers)
+                .map_err(|_| CmeError::IncorrectParameters)?;
+        }
+        if arguments.len() > 4 {
+            // Only POST and PUT can have a body
+            if !matches!(method, 1 | 2) {
+                return Err(CmeError::IncorrectParameters);
+            }
+            request.content_type = arguments
+                .string(4)?
+                .try_into()
+                .map_err(|_| CmeError::TextStringTooLong)?;
+            crate::at_command::decode_hex(arguments.raw(5)?, &mut request.body)
+                .map_err(|_| CmeError::IncorrectParameters)?;
+        }
+
+        // Echo server: the body of the response is the body of the request, or the path if the
+        // request has no body
+        let content_type = if request.content_type.is_empty() {
+            "text/plain"
+        } else {
+            request.content_type.as_str()
+        };
+        let body: Vec<u8, MAX_BODY_SIZE> = if request.body.is_empty() {
+            Vec::from_slice(&request.path.as_bytes()[..request.path.len().min(MAX_BODY_SIZE)])
+                .unwrap_or_default()
+        } else {
+            request.body.clone()
+        };
+        let mut headers: String<URC_SIZE> = String::new();
+        write!(
+            headers,
+            "Content-Type: {}\r\nContent-Length: {}\r\n",
+            content_type,
+            body.len()
+        )
+        .map_err(|_| CmeError::TextStringTooLong)?;
+        client.last_request = Some(request);
+
+        let client_id = arguments.int(0)?;
+        self.hex_urc(
+            format_args!("+CHTTPNMIH: {},200,{},", client_id, headers.len()),
+            headers.as_bytes(),
+        );
+        let mut sum_length = 0;
+        for fragment in body.chunks(HTTP_FRAGMENT_SIZE) {
+            sum_length += fragment.len();
+            self.hex_urc(
+                format_args!(
+                    "+CHTTPNMIC: {},{},{},{},{},",
+                    client_id,
+                    (sum_length < body.len()) as u8,
+                    body.len(),
+                    sum_length,
+                    fragment.len()
+                ),
+                fragment,
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// Writer handle of a [Sim7020Emulator]
+pub struct EmulatorWriter<'a> {
+    emulator: &'a Sim7020Emulator,
+}
+
+/// Reader handle of a [Sim7020Emulator]
+pub struct EmulatorReader<'a> {
+    emulator: &'a Sim7020Emulator,
+}
+
+impl ErrorType for EmulatorWriter<'_> {
+    type Error = Infallible;
+}
+
+impl ErrorType for EmulatorReader<'_> {
+    type Error = Infallible;
+}
+
+impl Write for EmulatorWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        Ok(self.emulator.write_bytes(buf))
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl Read for EmulatorReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        Ok(self.emulator.read_bytes(buf))
+    }
+}
+
+impl ReadReady for EmulatorReader<'_> {
+    fn read_ready(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.emulator.read_ready())
+    }
+}
+
+#[cfg(feature = "nonblocking")]
+impl embedded_io_async::Write for EmulatorWriter<'_> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        Ok(self.emulator.write_bytes(buf))
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "nonblocking")]
+impl embedded_io_async::Read for EmulatorReader<'_> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        Ok(self.emulator.read_bytes(buf))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::at_command::at_cpin::{EnterPIN, PINRequired, PinStatus};
+    use crate::at_command::cmee::{
+        ReportMobileEquipmentErrorSetting, SetReportMobileEquipmentError,
+    };
+    use crate::at_command::wireless::StartWirelessConnection;
+    use crate::testing::{NoopDelay, NoopPin};
+    use crate::urc::Urc;
+    use crate::{AtError, Modem};
+
+    #[test]
+    fn test_echo_is_disabled_by_the_modem() {
+        let emulator = Sim7020Emulator::new();
+        let (mut writer, mut reader) = emulator.split();
+
+        Modem::new(&mut writer, &mut reader, NoopPin, NoopPin, NoopDelay).unwrap();
+
+        assert!(!emulator.echo_enabled());
+    }
+
+    #[test]
+    fn test_sim_pin() {
+        let emulator = Sim7020Emulator::new().with_pin(1234);
+        let (mut writer, mut reader) = emulator.split();
+        let mut modem = Modem::new(&mut writer, &mut reader, NoopPin, NoopPin, NoopDelay).unwrap();
+        modem
+            .send_and_wait_response(&SetReportMobileEquipmentError {
+                setting: ReportMobileEquipmentErrorSetting::EnabledVerbose,
+            })
+            .unwrap();
+
+        assert_eq!(
+            modem.send_and_wait_response(&PINRequired).unwrap(),
+            PinStatus::SimPin
+        );
+        assert!(matches!(
+            modem.send_and_wait_response(&EnterPIN { pin: 1111 }),
+            Err(AtError::MobileEquipmentError(CmeError::IncorrectPassword))
+        ));
+        assert!(matches!(
+            modem.send_and_wait_response(&StartWirelessConnection),
+            Err(AtError::MobileEquipmentError(CmeError::SimPinRequired))
+        ));
+
+        modem
+            .send_and_wait_response(&EnterPIN { pin: 1234 })
+            .unwrap();
+        assert_eq!(
+            modem.send_and_wait_response(&PINRequired).unwrap(),
+            PinStatus::Ready
+        );
+        assert!(emulator.is_registered());
+    }
+
+    #[test]
+    fn test_registration_urc() {
+        let emulator = Sim7020Emulator::new().without_network();
+        let (mut writer, mut reader) = emulator.split();
+        let mut modem = Modem::new(&mut writer, &mut reader, NoopPin, NoopPin, NoopDelay).unwrap();
+        writer_command(&mut modem, "AT+CEREG=1\r\n");
+
+        emulator.set_network_available(true);
+        modem.poll_urcs().unwrap();
+
+        assert!(matches!(
+            modem.next_urc(),
+            Some(Urc::NetworkRegistration(_))
+        ));
+    }
+
+    #[test]
+    fn test_unknown_command() {
+        let emulator = Sim7020Emulator::new();
+        let (mut writer, mut reader) = emulator.split();
+        let mut modem = Modem::new(&mut writer, &mut reader, NoopPin, NoopPin, NoopDelay).unwrap();
+
+        assert!(matches!(
+            modem.send_and_wait_response(&crate::at_command::ceer::ExtendedErrorReport),
+            Err(AtError::ErrorReply(_))
+        ));
+    }
+
+    /// Sends a raw command and waits for its response
+    fn writer_command<T: Write, U: Read + ReadReady>(
+        modem: &mut Modem<'_, T, U, NoopPin, NoopDelay>,
+        command: &str,
+    ) {
+        modem.writer.write_all(command.as_bytes()).unwrap();
+        let mut response = [0; crate::BUFFER_SIZE];
+        modem.read_response(&mut response).unwrap();
+    }
+}
@@ -7,19 +7,44 @@ pub mod at_command;
 pub mod nonblocking;
 
 pub mod contexts;
+#[cfg(any(test, feature = "emulator"))]
+pub mod emulator;
+#[cfg(feature = "nal")]
+pub mod nal;
+pub mod outbox;
+mod protocol;
+pub mod router;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod urc;
 use crate::at_command::at_cpin::{EnterPIN, PINRequired, PinStatus};
-use crate::at_command::csclk::CSCLKMode::HardwareControlled;
 use crate::at_command::csclk::{CSCLKMode, SetCSCLKMode};
 use crate::at_command::flow_control::ControlFlowStatus;
-use crate::at_command::http::HttpClient;
+use crate::at_command::http::{
+    sink_error, BodyWriter, HttpClient, HttpDestroy, HttpDisconnect, HttpResponse,
+};
+use crate::at_command::mqtt::{
+    CloseMQTTConnection, ListMQTTSessions, MQTTDataFormat, MQTTReceivedMessage,
+};
 #[allow(deprecated)]
 use crate::at_command::AtResponse;
 use crate::at_command::{
-    cmee::ReportMobileEquipmentErrorSetting, flow_control::GetFlowControlResponse,
+    cmee::{CmeError, CmsError, ReportMobileEquipmentErrorSetting},
+    flow_control::GetFlowControlResponse,
 };
-use at_command::AtRequest;
+use crate::protocol::{
+    check_can_sleep, credential_chunks, encode_command, forget_mqtt_session, next_unlock_step,
+    Datagram, Deadline, HostResolution, HttpBodyChunk, HttpHeader, MqttMessage, PendingLines,
+    ReadBuffer, ResponseFramer, SocketData, StreamFramer, StreamStep, TlsData, UnlockStep, UrcWait,
+    WakeUp, AT_COMMAND_TWICE, MAX_UNLOCK_TRIES,
+};
+use crate::urc::{HttpResponseHeader, Urc, UrcDispatcher, UrcHandler};
+use at_command::credentials::CredentialType;
+use at_command::dns::GetHostByName;
+use at_command::{AtRequest, DEFAULT_TIMEOUT_MS, NETWORK_TIMEOUT_MS};
 use at_commands::parser::ParseError;
 use core::cell::RefCell;
+use core::net::IpAddr;
 #[cfg(feature = "defmt")]
 use defmt::*;
 use embedded_hal::delay::DelayNs;
@@ -29,14 +54,26 @@ use embedded_io::Error;
 use embedded_io::ReadReady;
 pub use embedded_io::{Read, Write};
 
-const BUFFER_SIZE: usize = 512;
+/// Default size of the buffers used to send the commands and to receive the responses
+pub const BUFFER_SIZE: usize = 512;
+/// Interval used to poll the reader while waiting for the response of the module
+const READ_POLL_INTERVAL_US: u32 = 100;
 const LF: u8 = 10; // n
 const CR: u8 = 13; // r
 
 const OK_TERMINATOR: &[u8] = &[CR, LF, b'O', b'K', CR, LF];
 const ERROR_TERMINATOR: &[u8] = &[b'R', b'R', b'O', b'R', CR, LF];
 
-pub struct Modem<'a, T: Write, U: Read, P, D> {
+/// Size of the chunks read from the module. The bytes received after the final result code of a
+/// response, such as an unsolicited result code, are kept by the modem for the next read
+const READ_CHUNK_SIZE: usize = 64;
+
+/// Modem struct that will help controlling the SIM7020 module.
+///
+/// [N] is the size of the buffers used for the commands and the responses. Responses that do
+/// not fit fail with [AtError::ResponseTooLong] and can be read with
+/// [Modem::send_and_stream_response] instead
+pub struct Modem<'a, T: Write, U: Read, P, D, const N: usize = BUFFER_SIZE> {
     pub writer: &'a mut T,
     pub reader: &'a mut U,
     /// The pin that controls the power of the module
@@ -47,13 +84,24 @@ pub struct Modem<'a, T: Write, U: Read, P, D> {
     pub delay: D,
     /// Current sleep mode that has been configured for the module
     sleep_mode: RefCell<CSCLKMode>,
+    /// Stores the unsolicited result codes received from the module
+    urcs: UrcDispatcher,
+    /// Bitmask of the HTTP clients whose context could not destroy them
+    released_http_clients: u8,
+    /// Bytes read from the module that have not been processed yet
+    input: ReadBuffer,
 }
 
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[derive(Debug)]
 pub enum AtError {
     TooManyReturnedLines,
+    /// The module answered with a plain `ERROR`
     ErrorReply(usize),
+    /// The module answered with `+CME ERROR: <err>`
+    MobileEquipmentError(CmeError),
+    /// The module answered with `+CMS ERROR: <err>`
+    MessageServiceError(CmsError),
     CreateHTTPSessionFailed(HttpClient),
     MqttFailure,
     NotReady,
@@ -65,6 +113,20 @@ pub enum AtError {
     HALError,
     IllegalModuleState,
     IllegalPinStatus(PinStatus),
+    /// The module did not answer in the time defined by [AtRequest::timeout_ms]
+    Timeout,
+    /// The response of the module does not fit the buffer of the modem
+    ResponseTooLong,
+    /// The socket is not connected or has already been closed
+    SocketNotConnected,
+    /// The module could not resolve the host, with the DNS error code reported by the module
+    DnsResolutionFailed(i32),
+    /// The TLS handshake failed, with the result reported by the module
+    TlsConnectionFailed(i32),
+    /// A parameter of the request is not valid, e.g. it is empty or out of range
+    InvalidParameter,
+    /// The operation is not supported by the module, e.g. binding an UDP socket
+    NotSupported,
 }
 
 impl From<ParseError> for AtError {
@@ -86,10 +148,27 @@ impl From<chrono::format::ParseError> for AtError {
 }
 
 impl<'a, T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs> Modem<'a, T, U, P, D> {
+    /// Creates a new modem using buffers of [BUFFER_SIZE] bytes
+    pub fn new(
+        writer: &'a mut T,
+        reader: &'a mut U,
+        power_pin: P,
+        dtr_pin: P,
+        delay: D,
+    ) -> Result<Self, AtError> {
+        Self::new_with_buffer(writer, reader, power_pin, dtr_pin, delay)
+    }
+}
+
+impl<'a, T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs, const N: usize>
+    Modem<'a, T, U, P, D, N>
+{
     /// Time that we will await to ensure the system has turned ON
     const AWAIT_TIME_FOR_POWER_UP: u32 = 1000 * 10;
 
-    pub fn new(
+    /// Creates a new modem using buffers of [N] bytes, e.g.
+    /// `Modem::<_, _, _, _, 1024>::new_with_buffer(...)`
+    pub fn new_with_buffer(
         writer: &'a mut T,
         reader: &'a mut U,
         power_pin: P,
@@ -103,6 +182,9 @@ impl<'a, T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs> Modem<'a, T, U
             dtr_pin,
             delay,
             sleep_mode: RefCell::new(Default::default()),
+            urcs: UrcDispatcher::new(),
+            released_http_clients: 0,
+            input: ReadBuffer::new(),
         };
         #[cfg(feature = "defmt")]
         debug!("Ensuring the power pin is ON");
@@ -178,13 +260,7 @@ impl<'a, T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs> Modem<'a, T, U
         #[cfg(feature = "defmt")]
         info!("Starting sleeping");
 
-        if *self.sleep_mode.borrow() != HardwareControlled {
-            #[cfg(feature = "defmt")]
-            warn!("The sleep mode has not been enabled");
-
-            return Err(AtError::IllegalModuleState);
-        }
-
+        check_can_sleep(*self.sleep_mode.borrow())?;
         self.turn_on_dtr()
     }
 
@@ -195,32 +271,23 @@ impl<'a, T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs> Modem<'a, T, U
     pub fn wake_up(&mut self) -> Result<(), AtError> {
         #[cfg(feature = "defmt")]
         info!("Stopping sleeping");
-        let sleep_mode = *self.sleep_mode.borrow();
-        match sleep_mode {
-            CSCLKMode::Disabled => {
+        let wake_up = WakeUp::from(*self.sleep_mode.borrow());
+        match wake_up {
+            WakeUp::Nothing => {
                 #[cfg(feature = "defmt")]
                 debug!("The sleep mode is enabled, nothing to do");
                 Ok(())
             }
-            CSCLKMode::SoftwareControlled => {
+            WakeUp::SendCommands => {
                 #[cfg(feature = "defmt")]
                 debug!("Waking up from software");
-                // According to the manual we need to send twice any AT command to wake up the module
-                // when is configured in software mode
-                const AT_COMMAND_TWICE: &[u8] = b"AT\r\nAT\r\n";
-
                 self.writer
                     .write_all(AT_COMMAND_TWICE)
-                    .map_err(|_| AtError::IOError)?;
-
-                Ok(())
+                    .map_err(|_| AtError::IOError)
             }
-
-            CSCLKMode::HardwareControlled => {
+            WakeUp::ReleaseDtr => {
                 #[cfg(feature = "defmt")]
                 debug!("Waking up from hardware");
-
-                // Just pull off the DTR pin
                 self.turn_off_dtr()
             }
         }
@@ -236,6 +303,17 @@ impl<'a, T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs> Modem<'a, T, U
         Ok(())
     }
 
+    /// Sets how the module reports the errors
+    pub fn verbosity(
+        &mut self,
+        verbosity: ReportMobileEquipmentErrorSetting,
+    ) -> Result<(), AtError> {
+        self.send_and_wait_response(&at_command::cmee::SetReportMobileEquipmentError {
+            setting: verbosity,
+        })?;
+        Ok(())
+    }
+
     pub fn enable_numeric_errors(&mut self) -> Result<(), AtError> {
         self.send_and_wait_response(&at_command::cmee::SetReportMobileEquipmentError {
             setting: ReportMobileEquipmentErrorSetting::EnabledVerbose,
@@ -264,6 +342,295 @@ impl<'a, T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs> Modem<'a, T, U
         Ok(())
     }
 
+    /// Registers a handler that will be called for each received [Urc]. If the handler does
+    /// not consume the [Urc] it will be stored until it is retrieved with [Modem::next_urc]
+    pub fn set_urc_handler(&mut self, handler: Option<UrcHandler>) {
+        self.urcs.set_handler(handler);
+    }
+
+    /// Returns the oldest stored [Urc], if any
+    pub fn next_urc(&mut self) -> Option<Urc> {
+        self.urcs.pop()
+    }
+
+    /// Reads the bytes pending in the reader looking for unsolicited result codes. Any other
+    /// line is discarded
+    pub fn poll_urcs(&mut self) -> Result<(), AtError> {
+        let mut line = [0; N];
+        self.process_pending_lines(&mut line)
+    }
+
+    /// Copies into [buf] the data received in the socket that has not been read yet, without
+    /// waiting for more data. Returns the number of bytes copied, 0 if there is no data. Fails
+    /// once with [AtError::CapacityError] if received data has been dropped because it did not fit
+    pub fn try_receive_socket_data(
+        &mut self,
+        socket_id: u8,
+        buf: &mut [u8],
+    ) -> Result<usize, AtError> {
+        self.poll_urcs()?;
+        Ok(SocketData { socket_id, buf }
+            .poll(&mut self.urcs)?
+            .unwrap_or(0))
+    }
+
+    /// Copies into [buf] the data received in the socket, waiting at most [timeout_ms] for the
+    /// data to arrive. Returns the number of bytes copied
+    pub fn receive_socket_data(
+        &mut self,
+        socket_id: u8,
+        buf: &mut [u8],
+        timeout_ms: u32,
+    ) -> Result<usize, AtError> {
+        self.wait_for(
+            &mut SocketData { socket_id, buf },
+            &mut Deadline::new(timeout_ms),
+        )
+    }
+
+    /// Copies into [buf] the oldest datagram received in the UDP socket that has not been read,
+    /// discarding the bytes that do not fit, without waiting for more datagrams. Returns the
+    /// number of bytes copied, None if there is no datagram. Fails once with
+    /// [AtError::CapacityError] if received data has been dropped because it did not fit
+    pub fn try_receive_datagram(
+        &mut self,
+        socket_id: u8,
+        buf: &mut [u8],
+    ) -> Result<Option<usize>, AtError> {
+        self.poll_urcs()?;
+        Datagram { socket_id, buf }.poll(&mut self.urcs)
+    }
+
+    /// Copies into [buf] the oldest datagram received in the UDP socket, waiting at most
+    /// [timeout_ms] for it to arrive. The bytes that do not fit are discarded. Returns the number
+    /// of bytes copied
+    pub fn receive_datagram(
+        &mut self,
+        socket_id: u8,
+        buf: &mut [u8],
+        timeout_ms: u32,
+    ) -> Result<usize, AtError> {
+        self.wait_for(
+            &mut Datagram { socket_id, buf },
+            &mut Deadline::new(timeout_ms),
+        )
+    }
+
+    /// Discards the received data of the socket that has not been read
+    pub fn clear_socket_data(&mut self, socket_id: u8) {
+        self.urcs.clear_socket_data(socket_id);
+    }
+
+    /// Copies into [buf] the data received in the TLS connection that has not been read yet,
+    /// without waiting for more data. Returns the number of bytes copied, 0 if there is no data.
+    /// Fails once with [AtError::CapacityError] if received data has been dropped because it did
+    /// not fit
+    pub fn try_receive_tls_data(&mut self, tls_id: u8, buf: &mut [u8]) -> Result<usize, AtError> {
+        self.poll_urcs()?;
+        Ok(TlsData { tls_id, buf }.poll(&mut self.urcs)?.unwrap_or(0))
+    }
+
+    /// Copies into [buf] the data received in the TLS connection, waiting at most [timeout_ms]
+    /// for the data to arrive. Returns the number of bytes copied
+    pub fn receive_tls_data(
+        &mut self,
+        tls_id: u8,
+        buf: &mut [u8],
+        timeout_ms: u32,
+    ) -> Result<usize, AtError> {
+        self.wait_for(&mut TlsData { tls_id, buf }, &mut Deadline::new(timeout_ms))
+    }
+
+    /// Discards the received data of the TLS connection that has not been read
+    pub fn clear_tls_data(&mut self, tls_id: u8) {
+        self.urcs.clear_tls_data(tls_id);
+    }
+
+    /// Waits at most [timeout_ms] for the whole response of the request sent by the HTTP client
+    /// with [HttpSend](at_command::http::HttpSend). Fails with [AtError::CapacityError] if the
+    /// body does not fit in [BODY_SIZE] bytes
+    pub fn receive_http_response<const BODY_SIZE: usize>(
+        &mut self,
+        client_id: u8,
+        timeout_ms: u32,
+    ) -> Result<HttpResponse<BODY_SIZE>, AtError> {
+        let mut body = heapless::Vec::new();
+        let header =
+            self.receive_http_response_into(client_id, &mut BodyWriter(&mut body), timeout_ms)?;
+
+        Ok(HttpResponse::new(header, body))
+    }
+
+    /// Waits at most [timeout_ms] for the response of the request sent by the HTTP client with
+    /// [HttpSend](at_command::http::HttpSend), writing the body into [sink] as it arrives instead
+    /// of storing it. Returns the status code and the headers
+    pub fn receive_http_response_into<S: Write>(
+        &mut self,
+        client_id: u8,
+        sink: &mut S,
+        timeout_ms: u32,
+    ) -> Result<HttpResponseHeader, AtError> {
+        let mut deadline = Deadline::new(timeout_ms);
+        let header = self.wait_for(&mut HttpHeader { client_id }, &mut deadline)?;
+
+        let mut buf = [0; 128];
+        loop {
+            let (size, complete) = self.wait_for(
+                &mut HttpBodyChunk {
+                    client_id,
+                    buf: &mut buf,
+                },
+                &mut deadline,
+            )?;
+            sink.write_all(&buf[..size]).map_err(sink_error)?;
+            if complete {
+                return Ok(header);
+            }
+        }
+    }
+
+    /// Discards the response of the HTTP client that has not been read
+    pub fn clear_http_response(&mut self, client_id: u8) {
+        self.urcs.clear_http_response(client_id);
+    }
+
+    /// Marks the HTTP client to be destroyed by [Modem::destroy_released_http_clients], when its
+    /// context fails to destroy it
+    pub(crate) fn release_http_client(&mut self, client_id: u8) {
+        self.released_http_clients |= 1 << (client_id % 8);
+    }
+
+    /// Disconnects and destroys the HTTP clients whose context could not destroy them. It is
+    /// called when a new HTTP context is created
+    pub fn destroy_released_http_clients(&mut self) -> Result<(), AtError> {
+        while self.released_http_clients != 0 {
+            let client_id = self.released_http_clients.trailing_zeros() as u8;
+            // The client may not be connected, in which case the module rejects the command
+            let _ = self.send_and_wait_response(&HttpDisconnect { client_id });
+            self.send_and_wait_response(&HttpDestroy { client_id })?;
+            self.released_http_clients &= !(1 << client_id);
+            self.urcs.clear_http_response(client_id);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the oldest message received by the MQTT session in the subscribed topics,
+    /// decoding its payload according to [data_format], without waiting for more messages. Fails
+    /// once with [AtError::CapacityError] if a message has been dropped because it did not fit
+    pub fn try_receive_mqtt_message(
+        &mut self,
+        mqtt_id: u8,
+        data_format: MQTTDataFormat,
+    ) -> Result<Option<MQTTReceivedMessage>, AtError> {
+        self.poll_urcs()?;
+        MqttMessage {
+            mqtt_id,
+            data_format,
+        }
+        .poll(&mut self.urcs)
+    }
+
+    /// Waits at most [timeout_ms] for a message received by the MQTT session in the subscribed
+    /// topics, decoding its payload according to [data_format]
+    pub fn receive_mqtt_message(
+        &mut self,
+        mqtt_id: u8,
+        data_format: MQTTDataFormat,
+        timeout_ms: u32,
+    ) -> Result<MQTTReceivedMessage, AtError> {
+        self.wait_for(
+            &mut MqttMessage {
+                mqtt_id,
+                data_format,
+            },
+            &mut Deadline::new(timeout_ms),
+        )
+    }
+
+    /// Discards the messages received by the MQTT session that have not been read
+    pub fn clear_mqtt_messages(&mut self, mqtt_id: u8) {
+        self.urcs.clear_mqtt_publications(mqtt_id);
+    }
+
+    /// Returns true if the module has reported with `+CMQDISCON` that the MQTT session has been
+    /// disconnected from the broker since the last call
+    pub fn take_mqtt_disconnection(&mut self, mqtt_id: u8) -> Result<bool, AtError> {
+        self.poll_urcs()?;
+        Ok(self.urcs.take_mqtt_disconnection(mqtt_id))
+    }
+
+    /// Closes all the MQTT sessions of the module, e.g. the sessions left by a previous boot of
+    /// the MCU, which would otherwise use the slots of the new sessions. Must be called on startup,
+    /// before creating any session. Returns the number of closed sessions
+    pub fn close_stale_mqtt_sessions(&mut self) -> Result<usize, AtError> {
+        let sessions = self.send_and_wait_response(&ListMQTTSessions)?;
+        for session in &sessions {
+            #[cfg(feature = "defmt")]
+            warn!(
+                "Closing stale MQTT session {} to {}",
+                session.mqtt_id,
+                session.server.as_str()
+            );
+            self.send_and_wait_response(&CloseMQTTConnection {
+                mqtt_id: session.mqtt_id,
+            })?;
+            forget_mqtt_session(&mut self.urcs, session.mqtt_id);
+        }
+
+        Ok(sessions.len())
+    }
+
+    /// Stores the certificate or key in the module, split in as many commands as needed. The
+    /// credential already stored of the same type is replaced
+    pub fn upload_credential(&mut self, kind: CredentialType, data: &[u8]) -> Result<(), AtError> {
+        for chunk in credential_chunks(kind, data, N)? {
+            self.send_and_wait_response(&chunk)?;
+        }
+
+        Ok(())
+    }
+
+    /// Resolves the IP address of the host with the DNS of the network, waiting at most
+    /// [NETWORK_TIMEOUT_MS] for the answer of the module
+    pub fn resolve_host(&mut self, host: &str) -> Result<IpAddr, AtError> {
+        let mut resolution = HostResolution::start(&mut self.urcs);
+        self.send_and_wait_response(&GetHostByName { host })?;
+        self.wait_for(&mut resolution, &mut Deadline::new(NETWORK_TIMEOUT_MS))
+    }
+
+    /// Reads all the pending bytes from the reader, dispatching the URC and discarding any other
+    /// line. Once a line has started it is read until the end. [line] holds the current line
+    fn process_pending_lines(&mut self, line: &mut [u8]) -> Result<(), AtError> {
+        let mut pending = PendingLines::new(line);
+        let mut deadline = Deadline::new(DEFAULT_TIMEOUT_MS);
+
+        while pending.in_line() || self.read_ready()? {
+            self.fill_input(&mut deadline, AtError::IOError)?;
+            while let Some(byte) = self.input.next_byte() {
+                pending.push(byte, &mut self.urcs);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sends the command once the pending bytes of the reader have been processed. [line] is
+    /// used to hold the pending lines, the buffer of the response can be reused for it
+    fn send_command(&mut self, command: &[u8], line: &mut [u8]) -> Result<(), AtError> {
+        // Before we send the command we will ensure that the read buffer is empty, keeping
+        // the unsolicited result codes that could be pending
+        #[cfg(feature = "defmt")]
+        debug!("Checking if are pending bytes to read before performing the read operation");
+        self.process_pending_lines(line)?;
+
+        #[cfg(feature = "defmt")]
+        debug!("sending command: {=[u8]:a}", command);
+        self.writer
+            .write_all(command)
+            .map_err(|_e| AtError::IOError)
+    }
+
     pub fn send_and_wait_response<'b, V: AtRequest + 'b>(
         &'b mut self,
         payload: &V,
@@ -271,37 +638,46 @@ impl<'a, T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs> Modem<'a, T, U
         #[cfg(feature = "defmt")]
         info!("Sending command to the modem");
 
-        let mut buffer = [0; BUFFER_SIZE];
+        let mut buffer = [0; N];
+        let data = encode_command(payload, &mut buffer)?;
+        let mut response = [0; N];
+        self.send_command(data, &mut response)?;
 
-        // Before we try to read we will ensure that the read buffer is empty
-        #[cfg(feature = "defmt")]
-        debug!("Checking if are pending bytes to read before performing the read operation");
-        if self.reader.read_ready().map_err(|_e| AtError::IOError)? {
-            #[cfg(feature = "defmt")]
-            info!("There are some bytes pending to be read from the read, reading them before continuing");
-            let _flush_read_size = self
-                .reader
-                .read(&mut buffer)
-                .map_err(|_e| AtError::IOError)?;
+        let response_size =
+            self.read_command_response(data, payload.timeout_ms(), &mut response)?;
+        let response = payload.parse_response_struct(&response[..response_size])?;
 
-            #[cfg(feature = "defmt")]
-            debug!(
-                "The flush read has read {} bytes. The content was: {}",
-                _flush_read_size,
-                buffer[.._flush_read_size]
-            );
-        }
-        let data = payload.get_command_no_error(&mut buffer);
+        Ok(response)
+    }
 
-        #[cfg(feature = "defmt")]
-        debug!("sending command: {=[u8]:a}", data);
-        self.writer.write_all(data).map_err(|_e| AtError::IOError)?;
+    /// Sends the command and writes its response into [sink] instead of buffering it, so
+    /// responses longer than the buffer of the modem can be processed. The unsolicited result
+    /// codes and the final OK are not written. Returns the number of bytes written
+    pub fn send_and_stream_response<V: AtRequest, S: Write>(
+        &mut self,
+        payload: &V,
+        sink: &mut S,
+    ) -> Result<usize, AtError> {
+        let mut buffer = [0; N];
+        let data = encode_command(payload, &mut buffer)?;
+        let mut line = [0; N];
+        self.send_command(data, &mut line)?;
 
-        let mut read_buffer = [0; BUFFER_SIZE];
-        let response_size = self.read_response(&mut read_buffer)?;
-        let response = payload.parse_response_struct(&read_buffer[..response_size])?;
+        let mut framer = StreamFramer::new(&mut line);
+        let mut deadline = Deadline::new(payload.timeout_ms());
 
-        Ok(response)
+        loop {
+            self.fill_input(&mut deadline, AtError::IOError)?;
+            while let Some(byte) = self.input.next_byte() {
+                match framer.push(byte, &mut self.urcs, data)? {
+                    StreamStep::Continue => {}
+                    StreamStep::Write(bytes) => {
+                        sink.write_all(bytes).map_err(|_e| AtError::IOError)?
+                    }
+                    StreamStep::Finished(written) => return Ok(written),
+                }
+            }
+        }
     }
 
     #[deprecated(since = "3.0.0", note = "Use the send_and_wait_response")]
@@ -310,15 +686,16 @@ impl<'a, T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs> Modem<'a, T, U
         &'b mut self,
         payload: &V,
     ) -> Result<AtResponse, AtError> {
-        let mut buffer = [0; BUFFER_SIZE];
+        let mut buffer = [0; N];
         let data = payload.get_command_no_error(&mut buffer);
 
         #[cfg(feature = "defmt")]
         debug!("sending command: {=[u8]:a}", data);
         self.writer.write_all(data).map_err(|_e| AtError::IOError)?;
 
-        let mut read_buffer = [0; BUFFER_SIZE];
-        let response_size = self.read_response(&mut read_buffer)?;
+        let mut read_buffer = [0; N];
+        let response_size =
+            self.read_command_response(data, payload.timeout_ms(), &mut read_buffer)?;
         let response = payload.parse_response(&read_buffer[..response_size]);
         match response {
             Ok(response) => Ok(response),
@@ -335,69 +712,83 @@ impl<'a, T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs> Modem<'a, T, U
         }
     }
 
-    pub fn read_response(
+    /// Reads a response until the OK or ERROR terminators are found, waiting at most
+    /// [DEFAULT_TIMEOUT_MS]
+    pub fn read_response(&mut self, response_out: &mut [u8; N]) -> Result<usize, AtError> {
+        self.read_command_response(&[], DEFAULT_TIMEOUT_MS, response_out)
+    }
+
+    /// Polls the URC until the [waiter] gets its result, failing with [AtError::Timeout] once
+    /// the [deadline] is exhausted
+    fn wait_for<W: UrcWait>(
         &mut self,
-        response_out: &mut [u8; BUFFER_SIZE],
-    ) -> Result<usize, AtError> {
-        let mut offset = 0_usize;
-        let mut read_buffer: [u8; 100] = [0; 100];
+        waiter: &mut W,
+        deadline: &mut Deadline,
+    ) -> Result<W::Output, AtError> {
         loop {
-            match self.reader.read(&mut read_buffer) {
-                Ok(num_bytes) => {
-                    for i in 0..num_bytes {
-                        response_out[offset + i] = read_buffer[i];
-                        // info!("{=[u8]:a}, {}", *response_out, offset + i );
-
-                        // why is the index with + 1 and - 5?
-                        if offset + i < 5 {
-                            continue;
-                        }
-
-                        let start = offset + i - 5;
-                        let stop = offset + i + 1;
-
-                        match &response_out[start..stop] {
-                            OK_TERMINATOR => {
-                                return {
-                                    #[cfg(feature = "defmt")]
-                                    trace!(
-                                        "OK terminated: {=[u8]:a}",
-                                        response_out[..offset + i + 5]
-                                    );
-                                    Ok(offset + i)
-                                }
-                            }
-                            ERROR_TERMINATOR => {
-                                #[cfg(feature = "defmt")]
-                                error!(
-                                    "received ERROR response: {=[u8]:a}",
-                                    response_out[..offset + i + 5]
-                                );
-                                return Err(AtError::ErrorReply(offset + i));
-                            }
-                            _ =>
-                            {
-                                #[cfg(feature = "defmt")]
-                                continue
-                            }
-                        }
-                    }
+            self.poll_urcs()?;
+            if let Some(output) = waiter.poll(&mut self.urcs)? {
+                return Ok(output);
+            }
+            self.wait_until_read_ready(deadline)?;
+        }
+    }
 
-                    offset += num_bytes;
-                }
+    /// Waits until the reader has data available, failing with [AtError::Timeout] once the
+    /// [deadline] is exhausted. Every call consumes at least one poll interval, so the deadline
+    /// also expires while the module keeps sending data
+    fn wait_until_read_ready(&mut self, deadline: &mut Deadline) -> Result<(), AtError> {
+        loop {
+            let interval = deadline.next_wait()?;
+            if self.read_ready()? {
+                return Ok(());
+            }
+            self.delay.delay_us(interval);
+        }
+    }
 
-                Err(_e) => {
-                    #[cfg(feature = "defmt")]
-                    error!("uart error {}", _e.kind());
-                    return Err(AtError::NotReady);
+    /// Indicates if there are bytes to process, already read or pending in the reader
+    fn read_ready(&mut self) -> Result<bool, AtError> {
+        Ok(!self.input.is_empty() || self.reader.read_ready().map_err(|_e| AtError::IOError)?)
+    }
+
+    /// Reads the next chunk from the module once the previous one has been processed, waiting
+    /// at most until the [deadline] is exhausted. A failed read is reported as [error]
+    fn fill_input(&mut self, deadline: &mut Deadline, error: AtError) -> Result<(), AtError> {
+        if !self.input.is_empty() {
+            return Ok(());
+        }
+        self.wait_until_read_ready(deadline)?;
+        let size = self.reader.read(self.input.space()).map_err(|_e| {
+            #[cfg(feature = "defmt")]
+            error!("uart error {}", _e.kind());
+            error
+        })?;
+        self.input.filled(size);
+
+        Ok(())
+    }
+
+    /// Reads the response of the given command until the OK or ERROR terminators are found.
+    /// The unsolicited result codes received meanwhile are removed from the response
+    fn read_command_response(
+        &mut self,
+        command: &[u8],
+        timeout_ms: u32,
+        response_out: &mut [u8; N],
+    ) -> Result<usize, AtError> {
+        let mut framer = ResponseFramer::new(response_out);
+        let mut deadline = Deadline::new(timeout_ms);
+        loop {
+            self.fill_input(&mut deadline, AtError::NotReady)?;
+            while let Some(byte) = self.input.next_byte() {
+                if let Some(size) = framer.push(byte, &mut self.urcs, command)? {
+                    return Ok(size);
                 }
             }
         }
     }
 
-    /// Default max unlock tries
-    const MAX_UNLOCK_TRIES: usize = 1;
-
     /// Try to unlock the sim card. If the sim card is already unlocked nothing will happen.
     /// If the sim card needs a PIN the provided [pin] will be used to unlock.
     /// If the status of the SIM is nor unlocked nor required PIN an [AtError]::IllegalPinStatus
@@ -414,53 +805,356 @@ impl<'a, T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs> Modem<'a, T, U
         #[cfg(feature = "defmt")]
         info!("Trying to unlock SIM");
 
-        let max_unlock_tries = max_unlock_tries.unwrap_or(Self::MAX_UNLOCK_TRIES);
+        let max_unlock_tries = max_unlock_tries.unwrap_or(MAX_UNLOCK_TRIES);
 
         core::debug_assert!(max_unlock_tries > 0, "We need at least one try to unlock");
 
-        // First we need to check for the PIN status
-        let current_pin_status = self.send_and_wait_response(&PINRequired)?;
-
-        #[cfg(feature = "defmt")]
-        debug!("current pin status: {}", current_pin_status);
-
         let mut unlock_tries = 0;
 
         loop {
-            // First we need to check the status of the pin
-            match current_pin_status {
-                PinStatus::Ready => {
-                    #[cfg(feature = "defmt")]
-                    info!("SIM is already unlocked returning");
+            let current_pin_status = self.send_and_wait_response(&PINRequired)?;
 
-                    return Ok(());
-                }
-                PinStatus::SimPin => {
-                    #[cfg(feature = "defmt")]
-                    debug!("SIM pin is required")
-                }
-                _ => {
-                    // This is a state the method can not handle
+            #[cfg(feature = "defmt")]
+            debug!("current pin status: {}", current_pin_status);
+
+            match next_unlock_step(current_pin_status, unlock_tries, max_unlock_tries)? {
+                UnlockStep::Unlocked => return Ok(()),
+                UnlockStep::EnterPin => {
                     #[cfg(feature = "defmt")]
-                    warn!("SIM status can no be handled. {}", current_pin_status);
-                    return Err(AtError::IllegalPinStatus(current_pin_status));
+                    debug!("Trying to unlock SIM with the provided pin");
+
+                    self.send_and_wait_response(&EnterPIN { pin })?;
+                    unlock_tries += 1;
                 }
             }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::at_command::at::At;
+    use crate::at_command::model_identification::ModelIdentification;
+    use crate::emulator::Sim7020Emulator;
+    use crate::testing::{
+        registered_modem, NoopDelay, NoopPin, ScriptedSerial, SerialReader, SerialWriter, Step,
+    };
+
+    const ECHO_OFF: [Step; 2] = [Step::Expect(b"ATE0\r\n"), Step::Reply(b"\r\nOK\r\n")];
+
+    type TestModem<'a, 's, const N: usize> =
+        Modem<'a, SerialWriter<'s, 's>, SerialReader<'s, 's>, NoopPin, NoopDelay, N>;
+
+    fn modem<'a, 's, const N: usize>(
+        writer: &'a mut SerialWriter<'s, 's>,
+        reader: &'a mut SerialReader<'s, 's>,
+    ) -> TestModem<'a, 's, N> {
+        Modem::new_with_buffer(writer, reader, NoopPin, NoopPin, NoopDelay).unwrap()
+    }
+
+    #[test]
+    fn test_new_modem_disables_echo() {
+        let serial = ScriptedSerial::new(&ECHO_OFF);
+        let (mut writer, mut reader) = serial.split();
+
+        Modem::new(&mut writer, &mut reader, NoopPin, NoopPin, NoopDelay).unwrap();
+        serial.done();
+    }
+
+    #[test]
+    fn test_command_timeout() {
+        let script = [ECHO_OFF[0], ECHO_OFF[1], Step::Expect(b"AT\r\n")];
+        let serial = ScriptedSerial::new(&script);
+        let (mut writer, mut reader) = serial.split();
+        let mut modem = modem::<BUFFER_SIZE>(&mut writer, &mut reader);
+
+        assert!(matches!(
+            modem.send_and_wait_response(&At),
+            Err(AtError::Timeout)
+        ));
+        serial.done();
+    }
+
+    /// Module that answers the echo configuration and then keeps sending URCs instead of
+    /// answering the next command
+    struct ChattySerial<'c> {
+        commands: &'c core::cell::Cell<usize>,
+        sent: usize,
+    }
+
+    impl ChattySerial<'_> {
+        const OK: &'static [u8] = b"\r\nOK\r\n";
+        const URC: &'static [u8] = b"\r\n+CPSMSTATUS: \"EXIT PSM\"\r\n";
+    }
+
+    impl embedded_io::ErrorType for ChattySerial<'_> {
+        type Error = core::convert::Infallible;
+    }
+
+    impl Write for ChattySerial<'_> {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            let lines = buf.iter().filter(|&&byte| byte == b'\n').count();
+            self.commands.set(self.commands.get() + lines);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    impl Read for ChattySerial<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            buf[0] = match self.sent.checked_sub(Self::OK.len()) {
+                None => Self::OK[self.sent],
+                Some(index) => Self::URC[index % Self::URC.len()],
+            };
+            self.sent += 1;
+            Ok(1)
+        }
+    }
+
+    impl ReadReady for ChattySerial<'_> {
+        fn read_ready(&mut self) -> Result<bool, Self::Error> {
+            Ok(match self.commands.get() {
+                0 => false,
+                1 => self.sent < Self::OK.len(),
+                _ => true,
+            })
+        }
+    }
+
+    #[test]
+    fn test_command_timeout_while_receiving_urcs() {
+        let commands = core::cell::Cell::new(0);
+        let mut writer = ChattySerial {
+            commands: &commands,
+            sent: 0,
+        };
+        let mut reader = ChattySerial {
+            commands: &commands,
+            sent: 0,
+        };
+        let mut modem = Modem::new(&mut writer, &mut reader, NoopPin, NoopPin, NoopDelay).unwrap();
+
+        assert!(matches!(
+            modem.send_and_wait_response(&At),
+            Err(AtError::Timeout)
+        ));
+    }
+
+    #[test]
+    fn test_urc_received_before_the_command() {
+        let script = [
+            ECHO_OFF[0],
+            ECHO_OFF[1],
+            Step::Reply(b"\r\n+CPSMSTATUS: \"EXIT PSM\"\r\n"),
+            Step::Expect(b"AT+CGMM\r\n"),
+            Step::Reply(b"\r\nSIM7020E\r\n\r\nOK\r\n"),
+        ];
+        let serial = ScriptedSerial::new(&script);
+        let (mut writer, mut reader) = serial.split();
+        let mut modem = modem::<BUFFER_SIZE>(&mut writer, &mut reader);
+
+        let response = modem.send_and_wait_response(&ModelIdentification).unwrap();
+        assert_eq!(&response.model, b"SIM7020E");
+        assert!(matches!(
+            modem.next_urc(),
+            Some(Urc::PowerSavingMode(
+                crate::urc::PowerSavingModeStatus::Exited
+            ))
+        ));
+        serial.done();
+    }
+
+    #[test]
+    fn test_urc_received_before_the_same_query() {
+        use crate::at_command::network_registration_status::NetworkRegistrationStatus;
+
+        /// `AT+CEREG?`, whose response shares the prefix with the URC
+        struct RegistrationQuery;
+
+        impl AtRequest for RegistrationQuery {
+            type Response = ();
+
+            fn get_command<'a>(&'a self, buffer: &'a mut [u8]) -> Result<&'a [u8], usize> {
+                at_commands::builder::CommandBuilder::create_query(buffer, true)
+                    .named("+CEREG")
+                    .finish()
+            }
 
-            // If we already do the maximum unlock tries return an error with the current
-            // status
-            if unlock_tries >= Self::MAX_UNLOCK_TRIES {
-                #[cfg(feature = "defmt")]
-                return Err(AtError::IllegalPinStatus(current_pin_status));
+            fn parse_response_struct(&self, data: &[u8]) -> Result<Self::Response, AtError> {
+                assert_eq!(data, b"\r\n+CEREG: 1,1\r\n\r\nOK\r");
+                Ok(())
             }
+        }
 
-            // We have at least 1 try to unlock the pin
-            #[cfg(feature = "defmt")]
-            debug!("Trying to unlock SIM with the provided pin");
+        let script = [
+            ECHO_OFF[0],
+            ECHO_OFF[1],
+            Step::Reply(b"\r\n+CEREG: 5\r\n"),
+            Step::Expect(b"AT+CEREG?\r\n"),
+            Step::Reply(b"\r\n+CEREG: 1,1\r\n\r\nOK\r\n"),
+        ];
+        let serial = ScriptedSerial::new(&script);
+        let (mut writer, mut reader) = serial.split();
+        let mut modem = modem::<BUFFER_SIZE>(&mut writer, &mut reader);
+
+        modem.send_and_wait_response(&RegistrationQuery).unwrap();
+        assert!(matches!(
+            modem.next_urc(),
+            Some(Urc::NetworkRegistration(
+                NetworkRegistrationStatus::RegisteredRoaming
+            ))
+        ));
+        assert!(modem.next_urc().is_none());
+        serial.done();
+    }
 
-            self.send_and_wait_response(&EnterPIN { pin })?;
+    #[test]
+    fn test_urc_read_with_the_response_is_kept() {
+        let script = [
+            ECHO_OFF[0],
+            ECHO_OFF[1],
+            Step::Expect(b"AT+CGMM\r\n"),
+            // Read in the same chunk as the response
+            Step::Reply(b"\r\nSIM7020E\r\n\r\nOK\r\n\r\n+CPSMSTATUS: \"EXIT PSM\"\r\n"),
+        ];
+        let serial = ScriptedSerial::new(&script);
+        let (mut writer, mut reader) = serial.split();
+        let mut modem = modem::<BUFFER_SIZE>(&mut writer, &mut reader);
+
+        let response = modem.send_and_wait_response(&ModelIdentification).unwrap();
+        assert_eq!(&response.model, b"SIM7020E");
+        assert!(modem.next_urc().is_none());
+
+        modem.poll_urcs().unwrap();
+        assert!(matches!(
+            modem.next_urc(),
+            Some(Urc::PowerSavingMode(
+                crate::urc::PowerSavingModeStatus::Exited
+            ))
+        ));
+        serial.done();
+    }
 
-            unlock_tries += 1;
-        }
+    #[test]
+    fn test_urc_received_after_the_final_result() {
+        let script = [
+            ECHO_OFF[0],
+            ECHO_OFF[1],
+            Step::Expect(b"AT+CGMM\r\n"),
+            Step::Reply(b"\r\nSIM7020E\r\n\r\nOK\r\n\r\n+CPSMSTATUS: \"ENTER PSM\"\r\n"),
+        ];
+        let serial = ScriptedSerial::new(&script);
+        let (mut writer, mut reader) = serial.split();
+        let mut modem = modem::<BUFFER_SIZE>(&mut writer, &mut reader);
+
+        modem.send_and_wait_response(&ModelIdentification).unwrap();
+        modem.poll_urcs().unwrap();
+        assert!(matches!(
+            modem.next_urc(),
+            Some(Urc::PowerSavingMode(
+                crate::urc::PowerSavingModeStatus::Entered
+            ))
+        ));
+        serial.done();
+    }
+
+    #[test]
+    fn test_response_too_long() {
+        let script = [
+            ECHO_OFF[0],
+            ECHO_OFF[1],
+            Step::Expect(b"AT\r\n"),
+            Step::Reply(b"\r\nTHIS RESPONSE DOES NOT FIT\r\n\r\nOK\r\n"),
+        ];
+        let serial = ScriptedSerial::new(&script);
+        let (mut writer, mut reader) = serial.split();
+        let mut modem = modem::<16>(&mut writer, &mut reader);
+
+        assert!(matches!(
+            modem.send_and_wait_response(&At),
+            Err(AtError::ResponseTooLong)
+        ));
+        // The whole response has been consumed
+        serial.done();
+    }
+
+    #[test]
+    fn test_command_too_long() {
+        let serial = ScriptedSerial::new(&ECHO_OFF);
+        let (mut writer, mut reader) = serial.split();
+        let mut modem = modem::<8>(&mut writer, &mut reader);
+
+        assert!(matches!(
+            modem.send_and_wait_response(&ModelIdentification),
+            Err(AtError::CapacityError)
+        ));
+        serial.done();
+    }
+
+    #[test]
+    fn test_stream_response() {
+        let script = [
+            ECHO_OFF[0],
+            ECHO_OFF[1],
+            Step::Expect(b"AT\r\n"),
+            Step::Reply(b"\r\n+CPSMSTATUS: \"EXIT PSM\"\r\n"),
+            Step::Reply(b"A LONG LINE OF THE RESPONSE THAT DOES NOT FIT\r\n\r\nOK\r\n"),
+        ];
+        let serial = ScriptedSerial::new(&script);
+        let (mut writer, mut reader) = serial.split();
+        let mut modem = modem::<32>(&mut writer, &mut reader);
+        let mut out = [0; 128];
+        let mut sink = &mut out[..];
+
+        let written = modem.send_and_stream_response(&At, &mut sink).unwrap();
+
+        assert!(modem.next_urc().is_some());
+        assert_eq!(
+            &out[..written],
+            b"\r\nA LONG LINE OF THE RESPONSE THAT DOES NOT FIT\r\n\r\n"
+        );
+        serial.done();
+    }
+
+    #[test]
+    fn test_stream_response_error() {
+        let script = [
+            ECHO_OFF[0],
+            ECHO_OFF[1],
+            Step::Expect(b"AT\r\n"),
+            Step::Reply(b"\r\n+CME ERROR: 3\r\n"),
+        ];
+        let serial = ScriptedSerial::new(&script);
+        let (mut writer, mut reader) = serial.split();
+        let mut modem = modem::<16>(&mut writer, &mut reader);
+        let mut out = [0; 64];
+        let mut sink = &mut out[..];
+
+        assert!(matches!(
+            modem.send_and_stream_response(&At, &mut sink),
+            Err(AtError::MobileEquipmentError(
+                at_command::cmee::CmeError::OperationNotAllowed
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_resolve_host() {
+        let emulator = Sim7020Emulator::new().with_host("example.com", "93.184.216.34");
+        let (mut writer, mut reader) = emulator.split();
+        let mut modem = registered_modem(&mut writer, &mut reader);
+
+        assert_eq!(
+            modem.resolve_host("example.com").unwrap(),
+            core::net::IpAddr::from([93, 184, 216, 34])
+        );
+        assert!(matches!(
+            modem.resolve_host("unknown.com"),
+            Err(AtError::DnsResolutionFailed(8))
+        ));
     }
 }
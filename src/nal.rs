@@ -0,0 +1,468 @@
+//! Implementation of the [embedded_nal] traits on top of the IP stack of the module, so crates
+//! built on [TcpClientStack] or [UdpClientStack] can be used with the [Modem].
+//!
+//! ```ignore
+//! let mut stack = NetworkStack::new(&mut modem);
+//! let mut socket = stack.socket()?;
+//! nb::block!(stack.connect(&mut socket, "93.184.216.34:80".parse().unwrap()))?;
+//! ```
+//!
+//! The module is not asked for a socket until the socket is connected, because the domain of
+//! the socket depends on the remote address. Up to [MAX_SOCKETS] sockets can be open at once.
+//!
+//! This module is available with the `nal` feature
+
+use crate::at_command::socket::{
+    max_send_size, CloseSocket, ConnectSocketToRemote, CreateSocket, Domain, Protocol,
+    SendSocketMessage, Type,
+};
+use crate::urc::MAX_SOCKETS;
+use crate::{AtError, Modem, BUFFER_SIZE};
+use core::fmt::Write as _;
+use core::net::SocketAddr;
+#[cfg(feature = "defmt")]
+use defmt::debug;
+use embedded_hal::delay::DelayNs;
+use embedded_hal::digital::OutputPin;
+use embedded_io::{Read, ReadReady, Write};
+use embedded_nal::{nb, TcpClientStack, TcpError, TcpErrorKind, UdpClientStack};
+
+/// Maximum length of an IP address formatted as text
+const ADDRESS_SIZE: usize = 48;
+
+impl TcpError for AtError {
+    fn kind(&self) -> TcpErrorKind {
+        match self {
+            AtError::SocketNotConnected => TcpErrorKind::PipeClosed,
+            // embedded-nal has no kind for the unsupported operations
+            AtError::NotSupported => TcpErrorKind::Other,
+            _ => TcpErrorKind::Other,
+        }
+    }
+}
+
+/// Handle of a TCP socket opened with [NetworkStack]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, PartialEq)]
+pub struct TcpSocket {
+    handle: usize,
+}
+
+/// Handle of an UDP socket opened with [NetworkStack]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, PartialEq)]
+pub struct UdpSocket {
+    handle: usize,
+}
+
+/// Entry of the handle table
+#[derive(Clone, Copy)]
+struct SocketEntry {
+    /// Id assigned by the module once the socket is connected
+    socket_id: Option<u8>,
+    remote: Option<SocketAddr>,
+    /// The socket has been released but the module failed to close it
+    closing: bool,
+}
+
+/// Network stack that uses the sockets of the module through the [Modem]
+pub struct NetworkStack<
+    'm,
+    'a,
+    T: Write,
+    U: Read + ReadReady,
+    P: OutputPin,
+    D: DelayNs,
+    const N: usize = BUFFER_SIZE,
+> {
+    modem: &'m mut Modem<'a, T, U, P, D, N>,
+    /// PDP context used to create the sockets
+    cid: Option<i32>,
+    sockets: [Option<SocketEntry>; MAX_SOCKETS],
+}
+
+impl<'m, 'a, T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs, const N: usize>
+    NetworkStack<'m, 'a, T, U, P, D, N>
+{
+    /// Creates a stack that uses the default PDP context
+    pub fn new(modem: &'m mut Modem<'a, T, U, P, D, N>) -> Self {
+        Self::with_pdp_context(modem, None)
+    }
+
+    /// Creates a stack that opens the sockets in the given PDP context, check
+    /// [PDPContext](crate::at_command::pdp_context::PDPContext)
+    pub fn with_pdp_context(modem: &'m mut Modem<'a, T, U, P, D, N>, cid: Option<i32>) -> Self {
+        Self {
+            modem,
+            cid,
+            sockets: [None; MAX_SOCKETS],
+        }
+    }
+
+    /// Returns the modem used by the stack
+    pub fn modem(&mut self) -> &mut Modem<'a, T, U, P, D, N> {
+        self.modem
+    }
+
+    fn allocate(&mut self) -> Result<usize, AtError> {
+        let handle = self
+            .sockets
+            .iter()
+            .position(Option::is_none)
+            .or_else(|| self.retry_close())
+            .ok_or(AtError::CapacityError)?;
+        self.sockets[handle] = Some(SocketEntry {
+            socket_id: None,
+            remote: None,
+            closing: false,
+        });
+
+        Ok(handle)
+    }
+
+    /// Closes again the released sockets that the module failed to close. Returns the first
+    /// handle that becomes free
+    fn retry_close(&mut self) -> Option<usize> {
+        (0..MAX_SOCKETS).find(|&handle| {
+            self.sockets[handle].is_some_and(|entry| entry.closing) && self.release(handle).is_ok()
+        })
+    }
+
+    fn entry(&self, handle: usize) -> Result<SocketEntry, AtError> {
+        self.sockets
+            .get(handle)
+            .copied()
+            .flatten()
+            .ok_or(AtError::SocketNotConnected)
+    }
+
+    /// Returns the id of the socket in the module, failing if it is not connected
+    fn connected_id(&self, handle: usize) -> Result<u8, AtError> {
+        self.entry(handle)?
+            .socket_id
+            .ok_or(AtError::SocketNotConnected)
+    }
+
+    /// Creates the socket in the module and connects it to the remote address
+    fn open(
+        &mut self,
+        handle: usize,
+        connection_type: Type,
+        remote: SocketAddr,
+    ) -> Result<(), AtError> {
+        let entry = self.entry(handle)?;
+        if entry.socket_id.is_some() {
+            return Err(AtError::IllegalModuleState);
+        }
+
+        let domain = match remote {
+            SocketAddr::V4(_) => Domain::IPv4,
+            SocketAddr::V6(_) => Domain::IPv6,
+        };
+        let mut address: heapless::String<ADDRESS_SIZE> = heapless::String::new();
+        write!(address, "{}", remote.ip()).map_err(|_| AtError::CapacityError)?;
+
+        #[cfg(feature = "defmt")]
+        debug!("Opening socket to {}:{}", address.as_str(), remote.port());
+
+        let socket_id = self
+            .modem
+            .send_and_wait_response(&CreateSocket {
+                domain,
+                connection_type,
+                protocol: Protocol::IP,
+                cid: self.cid,
+            })?
+            .socket_id;
+
+        let connected = self.modem.send_and_wait_response(&ConnectSocketToRemote {
+            socket_id,
+            port: remote.port(),
+            remote_address: &address,
+        });
+        if let Err(error) = connected {
+            // Do not leave the socket open in the module
+            let _ = self
+                .modem
+                .send_and_wait_response(&CloseSocket { socket_id });
+            return Err(error);
+        }
+
+        self.sockets[handle] = Some(SocketEntry {
+            socket_id: Some(socket_id),
+            remote: Some(remote),
+            closing: false,
+        });
+
+        Ok(())
+    }
+
+    /// Sends as much data as fits in a single command. Returns the number of bytes sent
+    fn send_chunk(&mut self, handle: usize, buffer: &[u8]) -> Result<usize, AtError> {
+        let socket_id = self.connected_id(handle)?;
+        let size = buffer.len().min(max_send_size(N));
+        if size == 0 && !buffer.is_empty() {
+            return Err(AtError::CapacityError);
+        }
+
+        self.modem.send_and_wait_response(&SendSocketMessage {
+            socket_id,
+            data: &buffer[..size],
+        })?;
+
+        Ok(size)
+    }
+
+    fn receive_data(&mut self, handle: usize, buffer: &mut [u8]) -> nb::Result<usize, AtError> {
+        let socket_id = self.connected_id(handle)?;
+        match self.modem.try_receive_socket_data(socket_id, buffer)? {
+            0 if !buffer.is_empty() => Err(nb::Error::WouldBlock),
+            size => Ok(size),
+        }
+    }
+
+    fn release(&mut self, handle: usize) -> Result<(), AtError> {
+        let entry = self.entry(handle)?;
+        if let Some(socket_id) = entry.socket_id {
+            self.modem.clear_socket_data(socket_id);
+            let closed = self
+                .modem
+                .send_and_wait_response(&CloseSocket { socket_id });
+            if let Err(error) = closed {
+                // The handle is kept until the socket is closed when a handle is needed
+                self.sockets[handle] = Some(SocketEntry {
+                    closing: true,
+                    ..entry
+                });
+                return Err(error);
+            }
+        }
+        self.sockets[handle] = None;
+
+        Ok(())
+    }
+}
+
+impl<T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs, const N: usize> TcpClientStack
+    for NetworkStack<'_, '_, T, U, P, D, N>
+{
+    type TcpSocket = TcpSocket;
+    type Error = AtError;
+
+    fn socket(&mut self) -> Result<Self::TcpSocket, Self::Error> {
+        Ok(TcpSocket {
+            handle: self.allocate()?,
+        })
+    }
+
+    fn connect(
+        &mut self,
+        socket: &mut Self::TcpSocket,
+        remote: SocketAddr,
+    ) -> nb::Result<(), Self::Error> {
+        Ok(self.open(socket.handle, Type::TCP, remote)?)
+    }
+
+    fn send(
+        &mut self,
+        socket: &mut Self::TcpSocket,
+        buffer: &[u8],
+    ) -> nb::Result<usize, Self::Error> {
+        Ok(self.send_chunk(socket.handle, buffer)?)
+    }
+
+    fn receive(
+        &mut self,
+        socket: &mut Self::TcpSocket,
+        buffer: &mut [u8],
+    ) -> nb::Result<usize, Self::Error> {
+        self.receive_data(socket.handle, buffer)
+    }
+
+    fn close(&mut self, socket: Self::TcpSocket) -> Result<(), Self::Error> {
+        self.release(socket.handle)
+    }
+}
+
+impl<T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs, const N: usize> UdpClientStack
+    for NetworkStack<'_, '_, T, U, P, D, N>
+{
+    type UdpSocket = UdpSocket;
+    type Error = AtError;
+
+    fn socket(&mut self) -> Result<Self::UdpSocket, Self::Error> {
+        Ok(UdpSocket {
+            handle: self.allocate()?,
+        })
+    }
+
+    fn connect(
+        &mut self,
+        socket: &mut Self::UdpSocket,
+        remote: SocketAddr,
+    ) -> Result<(), Self::Error> {
+        self.open(socket.handle, Type::UPD, remote)
+    }
+
+    fn send(&mut self, socket: &mut Self::UdpSocket, buffer: &[u8]) -> nb::Result<(), Self::Error> {
+        // A datagram can not be split
+        if buffer.len() > max_send_size(N) {
+            return Err(nb::Error::Other(AtError::CapacityError));
+        }
+        self.send_chunk(socket.handle, buffer)?;
+
+        Ok(())
+    }
+
+    fn receive(
+        &mut self,
+        socket: &mut Self::UdpSocket,
+        buffer: &mut [u8],
+    ) -> nb::Result<(usize, SocketAddr), Self::Error> {
+        let socket_id = self.connected_id(socket.handle)?;
+        let size = self
+            .modem
+            .try_receive_datagram(socket_id, buffer)?
+            .ok_or(nb::Error::WouldBlock)?;
+        // The module only reports the data, it comes from the connected peer
+        let remote = self
+            .entry(socket.handle)?
+            .remote
+            .ok_or(AtError::SocketNotConnected)?;
+
+        Ok((size, remote))
+    }
+
+    fn close(&mut self, socket: Self::UdpSocket) -> Result<(), Self::Error> {
+        self.release(socket.handle)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::emulator::Sim7020Emulator;
+    use crate::testing::{registered_modem, NoopDelay, NoopPin};
+
+    #[test]
+    fn test_tcp_sockets() {
+        let emulator = Sim7020Emulator::new();
+        let (mut writer, mut reader) = emulator.split();
+        let mut modem = registered_modem(&mut writer, &mut reader);
+        let mut stack = NetworkStack::new(&mut modem);
+        let remote: SocketAddr = "127.0.0.1:7".parse().unwrap();
+
+        let mut first = TcpClientStack::socket(&mut stack).unwrap();
+        let mut second = TcpClientStack::socket(&mut stack).unwrap();
+        assert!(matches!(
+            TcpClientStack::send(&mut stack, &mut first, b"HELLO"),
+            Err(nb::Error::Other(AtError::SocketNotConnected))
+        ));
+
+        TcpClientStack::connect(&mut stack, &mut first, remote).unwrap();
+        TcpClientStack::connect(&mut stack, &mut second, remote).unwrap();
+        assert!(emulator.socket(0).is_some());
+        assert!(emulator.socket(1).is_some());
+
+        let mut buffer = [0; 16];
+        assert!(matches!(
+            TcpClientStack::receive(&mut stack, &mut first, &mut buffer),
+            Err(nb::Error::WouldBlock)
+        ));
+        assert_eq!(
+            TcpClientStack::send(&mut stack, &mut first, b"FIRST").unwrap(),
+            5
+        );
+        assert_eq!(
+            TcpClientStack::send(&mut stack, &mut second, b"SECOND").unwrap(),
+            6
+        );
+
+        let size = TcpClientStack::receive(&mut stack, &mut second, &mut buffer).unwrap();
+        assert_eq!(&buffer[..size], b"SECOND");
+        let size = TcpClientStack::receive(&mut stack, &mut first, &mut buffer).unwrap();
+        assert_eq!(&buffer[..size], b"FIRST");
+
+        TcpClientStack::close(&mut stack, first).unwrap();
+        assert!(emulator.socket(0).is_none());
+        TcpClientStack::close(&mut stack, second).unwrap();
+        assert!(emulator.socket(1).is_none());
+    }
+
+    #[test]
+    fn test_udp_socket() {
+        let emulator = Sim7020Emulator::new();
+        let (mut writer, mut reader) = emulator.split();
+        let mut modem = registered_modem(&mut writer, &mut reader);
+        let mut stack = NetworkStack::new(&mut modem);
+        let remote: SocketAddr = "10.0.0.1:5683".parse().unwrap();
+
+        let mut socket = UdpClientStack::socket(&mut stack).unwrap();
+        UdpClientStack::connect(&mut stack, &mut socket, remote).unwrap();
+        assert_eq!(emulator.socket(0).unwrap().connection_type, Type::UPD as u8);
+
+        UdpClientStack::send(&mut stack, &mut socket, b"PING").unwrap();
+        let mut buffer = [0; 8];
+        let (size, from) = UdpClientStack::receive(&mut stack, &mut socket, &mut buffer).unwrap();
+        assert_eq!(&buffer[..size], b"PING");
+        assert_eq!(from, remote);
+        assert!(matches!(
+            UdpClientStack::receive(&mut stack, &mut socket, &mut buffer),
+            Err(nb::Error::WouldBlock)
+        ));
+
+        // Each datagram is received on its own, truncated to the buffer
+        UdpClientStack::send(&mut stack, &mut socket, b"FIRST DATAGRAM").unwrap();
+        UdpClientStack::send(&mut stack, &mut socket, b"SECOND").unwrap();
+        let (size, _) = UdpClientStack::receive(&mut stack, &mut socket, &mut buffer).unwrap();
+        assert_eq!(&buffer[..size], b"FIRST DA");
+        let (size, _) = UdpClientStack::receive(&mut stack, &mut socket, &mut buffer).unwrap();
+        assert_eq!(&buffer[..size], b"SECOND");
+
+        // Does not fit the default buffer of the modem
+        let datagram = [0; 300];
+        assert!(matches!(
+            UdpClientStack::send(&mut stack, &mut socket, &datagram),
+            Err(nb::Error::Other(AtError::CapacityError))
+        ));
+        UdpClientStack::close(&mut stack, socket).unwrap();
+    }
+
+    #[test]
+    fn test_failed_close_is_retried() {
+        let emulator = Sim7020Emulator::new();
+        let (mut writer, mut reader) = emulator.split();
+        let mut modem = registered_modem(&mut writer, &mut reader);
+        let mut stack = NetworkStack::new(&mut modem);
+
+        let mut socket = TcpClientStack::socket(&mut stack).unwrap();
+        TcpClientStack::connect(&mut stack, &mut socket, "127.0.0.1:7".parse().unwrap()).unwrap();
+        emulator.fail_next_command("+CSOCL");
+        assert!(TcpClientStack::close(&mut stack, socket).is_err());
+        assert!(emulator.socket(0).is_some());
+
+        // The socket is closed once its handle is needed
+        for _ in 1..MAX_SOCKETS {
+            TcpClientStack::socket(&mut stack).unwrap();
+        }
+        assert!(emulator.socket(0).is_some());
+        TcpClientStack::socket(&mut stack).unwrap();
+        assert!(emulator.socket(0).is_none());
+    }
+
+    #[test]
+    fn test_handle_table_is_full() {
+        let emulator = Sim7020Emulator::new();
+        let (mut writer, mut reader) = emulator.split();
+        let mut modem = Modem::new(&mut writer, &mut reader, NoopPin, NoopPin, NoopDelay).unwrap();
+        let mut stack = NetworkStack::new(&mut modem);
+
+        for _ in 0..MAX_SOCKETS {
+            TcpClientStack::socket(&mut stack).unwrap();
+        }
+        assert!(matches!(
+            UdpClientStack::socket(&mut stack),
+            Err(AtError::CapacityError)
+        ));
+    }
+}
@@ -3,17 +3,35 @@
 //!
 //! To use this module the feature nonblocking must be enabled
 
+#[cfg(feature = "nal-async")]
+pub mod nal;
+
+use crate::at_command::credentials::CredentialType;
+use crate::at_command::dns::GetHostByName;
+use crate::at_command::http::{sink_error, BodyWriter, HttpDestroy, HttpDisconnect, HttpResponse};
+use crate::at_command::mqtt::{
+    CloseMQTTConnection, ListMQTTSessions, MQTTDataFormat, MQTTReceivedMessage,
+};
 use crate::at_command::AtRequest;
 #[allow(deprecated)]
 use crate::at_command::AtResponse;
-use crate::{at_command, AtError, BUFFER_SIZE, ERROR_TERMINATOR, OK_TERMINATOR};
+use crate::at_command::{DEFAULT_TIMEOUT_MS, NETWORK_TIMEOUT_MS};
+use crate::protocol::{
+    check_can_sleep, credential_chunks, encode_command, forget_mqtt_session, next_unlock_step,
+    Datagram, Deadline, HostResolution, HttpBodyChunk, HttpHeader, MqttMessage, NextMqttMessage,
+    PendingLines, ReadBuffer, ResponseFramer, SocketData, StreamFramer, StreamStep, TlsData,
+    UnlockStep, UrcWait, WakeUp, AT_COMMAND_TWICE, MAX_UNLOCK_TRIES,
+};
+use crate::urc::{HttpResponseHeader, Urc, UrcDispatcher, UrcHandler};
+use crate::{at_command, AtError, BUFFER_SIZE};
 use core::cell::RefCell;
+use core::net::IpAddr;
 use embedded_io_async::{Read, Write};
 
-use crate::at_command::at_cpin::{EnterPIN, PINRequired, PinStatus};
+use crate::at_command::at_cpin::{EnterPIN, PINRequired};
 use crate::at_command::cmee::ReportMobileEquipmentErrorSetting;
-use crate::at_command::csclk::CSCLKMode::HardwareControlled;
 use crate::at_command::csclk::{CSCLKMode, SetCSCLKMode};
+use crate::at_command::flow_control::{ControlFlowStatus, GetFlowControlResponse};
 #[cfg(feature = "defmt")]
 use defmt::*;
 use embedded_hal::digital::OutputPin;
@@ -28,7 +46,15 @@ use core::debug_assert;
 const AWAIT_TIME_FOR_POWER_UP: u32 = 1000 * 10;
 
 /// Modem struct that will help controlling the SIM7020 module with async methods
-pub struct AsyncModem<T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs> {
+///
+/// [N] is the size of the buffers used for the commands and the responses, see [crate::Modem]
+pub struct AsyncModem<
+    T: Write,
+    U: Read + ReadReady,
+    P: OutputPin,
+    D: DelayNs,
+    const N: usize = BUFFER_SIZE,
+> {
     /// The writer where the AT Commands will be sent
     pub writer: T,
     /// The reader where the AT Commands will be received
@@ -41,15 +67,39 @@ pub struct AsyncModem<T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs> {
     pub delay: D,
     /// Current sleep mode that has been configured for the module
     sleep_mode: RefCell<CSCLKMode>,
+    /// Stores the unsolicited result codes received from the module
+    urcs: UrcDispatcher,
+    /// Bitmask of the HTTP clients whose context has been dropped without being closed
+    released_http_clients: u8,
+    /// Bitmask of the MQTT sessions whose context has been dropped without being closed
+    released_mqtt_sessions: u8,
+    /// Bytes read from the module that have not been processed yet
+    input: ReadBuffer,
 }
 
-impl<'a, T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs> AsyncModem<T, U, P, D> {
+impl<T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs> AsyncModem<T, U, P, D> {
+    /// Creates a new modem using buffers of [BUFFER_SIZE] bytes
     pub async fn new(
         writer: T,
         reader: U,
         power_pin: P,
         dtr_pin: P,
         delay: D,
+    ) -> Result<Self, AtError> {
+        Self::new_with_buffer(writer, reader, power_pin, dtr_pin, delay).await
+    }
+}
+
+impl<T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs, const N: usize>
+    AsyncModem<T, U, P, D, N>
+{
+    /// Creates a new modem using buffers of [N] bytes
+    pub async fn new_with_buffer(
+        writer: T,
+        reader: U,
+        power_pin: P,
+        dtr_pin: P,
+        delay: D,
     ) -> Result<Self, AtError> {
         let mut modem = Self {
             writer,
@@ -58,6 +108,10 @@ impl<'a, T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs> AsyncModem<T,
             dtr_pin,
             delay,
             sleep_mode: RefCell::new(Default::default()),
+            urcs: UrcDispatcher::new(),
+            released_http_clients: 0,
+            released_mqtt_sessions: 0,
+            input: ReadBuffer::new(),
         };
         #[cfg(feature = "defmt")]
         debug!("Ensuring the power pin is ON");
@@ -133,13 +187,7 @@ impl<'a, T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs> AsyncModem<T,
         #[cfg(feature = "defmt")]
         info!("Starting sleeping");
 
-        if *self.sleep_mode.borrow() != HardwareControlled {
-            #[cfg(feature = "defmt")]
-            warn!("The sleep mode has not been enabled");
-
-            return Err(AtError::IllegalModuleState);
-        }
-
+        check_can_sleep(*self.sleep_mode.borrow())?;
         self.turn_on_dtr()
     }
 
@@ -150,39 +198,33 @@ impl<'a, T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs> AsyncModem<T,
     pub async fn wake_up(&mut self) -> Result<(), AtError> {
         #[cfg(feature = "defmt")]
         info!("Stopping sleeping");
-        let sleep_mode = *self.sleep_mode.borrow();
-        match sleep_mode {
-            CSCLKMode::Disabled => {
+        let wake_up = WakeUp::from(*self.sleep_mode.borrow());
+        match wake_up {
+            WakeUp::Nothing => {
                 #[cfg(feature = "defmt")]
                 debug!("The sleep mode is enabled, nothing to do");
                 Ok(())
             }
-            CSCLKMode::SoftwareControlled => {
+            WakeUp::SendCommands => {
                 #[cfg(feature = "defmt")]
                 debug!("Waking up from software");
-                // According to the manual we need to send twice any AT command to wake up the module
-                // when is configured in software mode
-                const AT_COMMAND_TWICE: &[u8] = b"AT\r\nAT\r\n";
-
                 self.writer
                     .write_all(AT_COMMAND_TWICE)
                     .await
-                    .map_err(|_| AtError::IOError)?;
-
-                Ok(())
+                    .map_err(|_| AtError::IOError)
             }
-
-            CSCLKMode::HardwareControlled => {
+            WakeUp::ReleaseDtr => {
                 #[cfg(feature = "defmt")]
                 debug!("Waking up from hardware");
-
-                // Just pull off the DTR pin
                 self.turn_off_dtr()
             }
         }
     }
 
-    async fn disable_echo(&mut self) -> Result<(), AtError> {
+    /// disable echo if echo is enabled
+    pub async fn disable_echo(&mut self) -> Result<(), AtError> {
+        #[cfg(feature = "defmt")]
+        info!("Disable echo");
         self.send_and_wait_response(at_command::ate::AtEcho {
             status: at_command::ate::Echo::Disable,
         })
@@ -190,6 +232,7 @@ impl<'a, T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs> AsyncModem<T,
         Ok(())
     }
 
+    /// Sets how the module reports the errors
     pub async fn verbosity(
         &mut self,
         verbosity: ReportMobileEquipmentErrorSetting,
@@ -201,77 +244,474 @@ impl<'a, T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs> AsyncModem<T,
         Ok(())
     }
 
-    pub async fn send_and_wait_response<V: AtRequest + 'a>(
-        &'a mut self,
-        payload: V,
-    ) -> Result<V::Response, crate::AtError> {
-        let mut buffer = [0; BUFFER_SIZE];
+    pub async fn enable_numeric_errors(&mut self) -> Result<(), AtError> {
+        self.verbosity(ReportMobileEquipmentErrorSetting::EnabledVerbose)
+            .await
+    }
+
+    pub async fn get_flow_control(&mut self) -> Result<GetFlowControlResponse, AtError> {
+        self.send_and_wait_response(at_command::flow_control::GetFlowControl {})
+            .await
+    }
 
-        // Before we try to read we will ensure that the read buffer is empty
+    pub async fn set_flow_control(&mut self) -> Result<(), AtError> {
+        self.send_and_wait_response(at_command::flow_control::SetFlowControl {
+            ta_to_te: ControlFlowStatus::Software,
+            te_to_ta: ControlFlowStatus::Software,
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// probe the modem's readiness by sending 'AT'. Errors if not ready.
+    pub async fn ready(&mut self) -> Result<(), AtError> {
         #[cfg(feature = "defmt")]
-        debug!("Checking if are pending bytes to read before performing the read operation");
-        if self.reader.read_ready().map_err(|_e| AtError::IOError)? {
-            #[cfg(feature = "defmt")]
-            info!("There are some bytes pending to be read from the read, reading them before continuing");
-            let _flush_read_size = self
-                .reader
-                .read(&mut buffer)
+        info!("probing modem readiness");
+        self.send_and_wait_response(at_command::at::At {}).await?;
+        Ok(())
+    }
+
+    /// Registers a handler that will be called for each received [Urc]. If the handler does
+    /// not consume the [Urc] it will be stored until it is retrieved with [AsyncModem::next_urc]
+    pub fn set_urc_handler(&mut self, handler: Option<UrcHandler>) {
+        self.urcs.set_handler(handler);
+    }
+
+    /// Returns the oldest stored [Urc], if any
+    pub fn next_urc(&mut self) -> Option<Urc> {
+        self.urcs.pop()
+    }
+
+    /// Reads the bytes pending in the reader looking for unsolicited result codes. Any other
+    /// line is discarded
+    pub async fn poll_urcs(&mut self) -> Result<(), AtError> {
+        let mut line = [0; N];
+        self.process_pending_lines(&mut line).await
+    }
+
+    /// Copies into [buf] the data received in the socket that has not been read yet, without
+    /// waiting for more data. Returns the number of bytes copied, 0 if there is no data. Fails
+    /// once with [AtError::CapacityError] if received data has been dropped because it did not fit
+    pub async fn try_receive_socket_data(
+        &mut self,
+        socket_id: u8,
+        buf: &mut [u8],
+    ) -> Result<usize, AtError> {
+        self.poll_urcs().await?;
+        Ok(SocketData { socket_id, buf }
+            .poll(&mut self.urcs)?
+            .unwrap_or(0))
+    }
+
+    /// Copies into [buf] the data received in the socket, waiting at most [timeout_ms] for the
+    /// data to arrive. Returns the number of bytes copied
+    pub async fn receive_socket_data(
+        &mut self,
+        socket_id: u8,
+        buf: &mut [u8],
+        timeout_ms: u32,
+    ) -> Result<usize, AtError> {
+        self.wait_for(
+            &mut SocketData { socket_id, buf },
+            &mut Deadline::new(timeout_ms),
+        )
+        .await
+    }
+
+    /// Copies into [buf] the oldest datagram received in the UDP socket that has not been read,
+    /// discarding the bytes that do not fit, without waiting for more datagrams. Returns the
+    /// number of bytes copied, None if there is no datagram. Fails once with
+    /// [AtError::CapacityError] if received data has been dropped because it did not fit
+    pub async fn try_receive_datagram(
+        &mut self,
+        socket_id: u8,
+        buf: &mut [u8],
+    ) -> Result<Option<usize>, AtError> {
+        self.poll_urcs().await?;
+        Datagram { socket_id, buf }.poll(&mut self.urcs)
+    }
+
+    /// Copies into [buf] the oldest datagram received in the UDP socket, waiting at most
+    /// [timeout_ms] for it to arrive. The bytes that do not fit are discarded. Returns the number
+    /// of bytes copied
+    pub async fn receive_datagram(
+        &mut self,
+        socket_id: u8,
+        buf: &mut [u8],
+        timeout_ms: u32,
+    ) -> Result<usize, AtError> {
+        self.wait_for(
+            &mut Datagram { socket_id, buf },
+            &mut Deadline::new(timeout_ms),
+        )
+        .await
+    }
+
+    /// Discards the received data of the socket that has not been read
+    pub fn clear_socket_data(&mut self, socket_id: u8) {
+        self.urcs.clear_socket_data(socket_id);
+    }
+
+    /// Copies into [buf] the data received in the TLS connection that has not been read yet,
+    /// without waiting for more data. Returns the number of bytes copied, 0 if there is no data.
+    /// Fails once with [AtError::CapacityError] if received data has been dropped because it did
+    /// not fit
+    pub async fn try_receive_tls_data(
+        &mut self,
+        tls_id: u8,
+        buf: &mut [u8],
+    ) -> Result<usize, AtError> {
+        self.poll_urcs().await?;
+        Ok(TlsData { tls_id, buf }.poll(&mut self.urcs)?.unwrap_or(0))
+    }
+
+    /// Copies into [buf] the data received in the TLS connection, waiting at most [timeout_ms]
+    /// for the data to arrive. Returns the number of bytes copied
+    pub async fn receive_tls_data(
+        &mut self,
+        tls_id: u8,
+        buf: &mut [u8],
+        timeout_ms: u32,
+    ) -> Result<usize, AtError> {
+        self.wait_for(&mut TlsData { tls_id, buf }, &mut Deadline::new(timeout_ms))
+            .await
+    }
+
+    /// Discards the received data of the TLS connection that has not been read
+    pub fn clear_tls_data(&mut self, tls_id: u8) {
+        self.urcs.clear_tls_data(tls_id);
+    }
+
+    /// Waits at most [timeout_ms] for the whole response of the request sent by the HTTP client
+    /// with [HttpSend](at_command::http::HttpSend). Fails with [AtError::CapacityError] if the
+    /// body does not fit in [BODY_SIZE] bytes
+    pub async fn receive_http_response<const BODY_SIZE: usize>(
+        &mut self,
+        client_id: u8,
+        timeout_ms: u32,
+    ) -> Result<HttpResponse<BODY_SIZE>, AtError> {
+        let mut body = heapless::Vec::new();
+        let header = self
+            .receive_http_response_into(client_id, &mut BodyWriter(&mut body), timeout_ms)
+            .await?;
+
+        Ok(HttpResponse::new(header, body))
+    }
+
+    /// Waits at most [timeout_ms] for the response of the request sent by the HTTP client with
+    /// [HttpSend](at_command::http::HttpSend), writing the body into [sink] as it arrives instead
+    /// of storing it. Returns the status code and the headers
+    pub async fn receive_http_response_into<S: Write>(
+        &mut self,
+        client_id: u8,
+        sink: &mut S,
+        timeout_ms: u32,
+    ) -> Result<HttpResponseHeader, AtError> {
+        let mut deadline = Deadline::new(timeout_ms);
+        let header = self
+            .wait_for(&mut HttpHeader { client_id }, &mut deadline)
+            .await?;
+
+        let mut buf = [0; 128];
+        loop {
+            let (size, complete) = self
+                .wait_for(
+                    &mut HttpBodyChunk {
+                        client_id,
+                        buf: &mut buf,
+                    },
+                    &mut deadline,
+                )
+                .await?;
+            sink.write_all(&buf[..size]).await.map_err(sink_error)?;
+            if complete {
+                return Ok(header);
+            }
+        }
+    }
+
+    /// Discards the response of the HTTP client that has not been read
+    pub fn clear_http_response(&mut self, client_id: u8) {
+        self.urcs.clear_http_response(client_id);
+    }
+
+    /// Returns the oldest message received by the MQTT session in the subscribed topics,
+    /// decoding its payload according to [data_format], without waiting for more messages. Fails
+    /// once with [AtError::CapacityError] if a message has been dropped because it did not fit
+    pub async fn try_receive_mqtt_message(
+        &mut self,
+        mqtt_id: u8,
+        data_format: MQTTDataFormat,
+    ) -> Result<Option<MQTTReceivedMessage>, AtError> {
+        self.poll_urcs().await?;
+        MqttMessage {
+            mqtt_id,
+            data_format,
+        }
+        .poll(&mut self.urcs)
+    }
+
+    /// Waits at most [timeout_ms] for a message received by the MQTT session in the subscribed
+    /// topics, decoding its payload according to [data_format]
+    pub async fn receive_mqtt_message(
+        &mut self,
+        mqtt_id: u8,
+        data_format: MQTTDataFormat,
+        timeout_ms: u32,
+    ) -> Result<MQTTReceivedMessage, AtError> {
+        self.wait_for(
+            &mut MqttMessage {
+                mqtt_id,
+                data_format,
+            },
+            &mut Deadline::new(timeout_ms),
+        )
+        .await
+    }
+
+    /// Waits for the next message received by the MQTT session in the subscribed topics. Returns
+    /// None once the module has reported with `+CMQDISCON` that the session has been disconnected
+    /// and all its received messages have been read
+    pub(crate) async fn next_mqtt_message(
+        &mut self,
+        mqtt_id: u8,
+        data_format: MQTTDataFormat,
+    ) -> Result<Option<MQTTReceivedMessage>, AtError> {
+        let mut waiter = NextMqttMessage(MqttMessage {
+            mqtt_id,
+            data_format,
+        });
+        loop {
+            match self
+                .wait_for(&mut waiter, &mut Deadline::new(DEFAULT_TIMEOUT_MS))
                 .await
-                .map_err(|_e| AtError::IOError)?;
+            {
+                Err(AtError::Timeout) => continue,
+                result => return result,
+            }
+        }
+    }
+
+    /// Discards the messages received by the MQTT session that have not been read
+    pub fn clear_mqtt_messages(&mut self, mqtt_id: u8) {
+        self.urcs.clear_mqtt_publications(mqtt_id);
+    }
+
+    /// Returns true if the module has reported with `+CMQDISCON` that the MQTT session has been
+    /// disconnected from the broker since the last call
+    pub async fn take_mqtt_disconnection(&mut self, mqtt_id: u8) -> Result<bool, AtError> {
+        self.poll_urcs().await?;
+        Ok(self.urcs.take_mqtt_disconnection(mqtt_id))
+    }
+
+    /// Marks the HTTP client to be destroyed by [AsyncModem::destroy_released_http_clients]. The
+    /// commands can not be sent when an async context is dropped, so it is done later
+    pub(crate) fn release_http_client(&mut self, client_id: u8) {
+        self.released_http_clients |= 1 << (client_id % 8);
+    }
+
+    /// Disconnects and destroys the HTTP clients whose context has been dropped without being
+    /// closed. It is called when a new HTTP context is created
+    pub async fn destroy_released_http_clients(&mut self) -> Result<(), AtError> {
+        while self.released_http_clients != 0 {
+            let client_id = self.released_http_clients.trailing_zeros() as u8;
+            // The client may not be connected, in which case the module rejects the command
+            let _ = self
+                .send_and_wait_response(HttpDisconnect { client_id })
+                .await;
+            self.send_and_wait_response(HttpDestroy { client_id })
+                .await?;
+            self.released_http_clients &= !(1 << client_id);
+            self.urcs.clear_http_response(client_id);
+        }
+
+        Ok(())
+    }
+
+    /// Marks the MQTT session to be closed by [AsyncModem::close_released_mqtt_sessions]. The
+    /// commands can not be sent when an async context is dropped, so it is done later
+    pub(crate) fn release_mqtt_session(&mut self, mqtt_id: u8) {
+        self.released_mqtt_sessions |= 1 << (mqtt_id % 8);
+    }
+
+    /// Closes the MQTT sessions whose context has been dropped without being closed. It is called
+    /// when a new MQTT context is created
+    pub async fn close_released_mqtt_sessions(&mut self) -> Result<(), AtError> {
+        while self.released_mqtt_sessions != 0 {
+            let mqtt_id = self.released_mqtt_sessions.trailing_zeros() as u8;
+            self.send_and_wait_response(CloseMQTTConnection { mqtt_id })
+                .await?;
+            self.released_mqtt_sessions &= !(1 << mqtt_id);
+            self.urcs.clear_mqtt_publications(mqtt_id);
+        }
+
+        Ok(())
+    }
 
+    /// Closes all the MQTT sessions of the module, e.g. the sessions left by a previous boot of
+    /// the MCU, which would otherwise use the slots of the new sessions. Must be called on startup,
+    /// before creating any session. Returns the number of closed sessions
+    pub async fn close_stale_mqtt_sessions(&mut self) -> Result<usize, AtError> {
+        let sessions = self.send_and_wait_response(ListMQTTSessions).await?;
+        for session in &sessions {
             #[cfg(feature = "defmt")]
-            debug!(
-                "The flush read has read {} bytes. The content was: {}",
-                _flush_read_size,
-                buffer[.._flush_read_size]
+            warn!(
+                "Closing stale MQTT session {} to {}",
+                session.mqtt_id,
+                session.server.as_str()
             );
+            self.send_and_wait_response(CloseMQTTConnection {
+                mqtt_id: session.mqtt_id,
+            })
+            .await?;
+            forget_mqtt_session(&mut self.urcs, session.mqtt_id);
+            self.released_mqtt_sessions &= !(1 << (session.mqtt_id % 8));
         }
 
-        let data = payload.get_command_no_error(&mut buffer);
+        Ok(sessions.len())
+    }
+
+    /// Stores the certificate or key in the module, split in as many commands as needed. The
+    /// credential already stored of the same type is replaced
+    pub async fn upload_credential(
+        &mut self,
+        kind: CredentialType,
+        data: &[u8],
+    ) -> Result<(), AtError> {
+        for chunk in credential_chunks(kind, data, N)? {
+            self.send_and_wait_response(chunk).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Resolves the IP address of the host with the DNS of the network, waiting at most
+    /// [NETWORK_TIMEOUT_MS] for the answer of the module
+    pub async fn resolve_host(&mut self, host: &str) -> Result<IpAddr, AtError> {
+        let mut resolution = HostResolution::start(&mut self.urcs);
+        self.send_and_wait_response(GetHostByName { host }).await?;
+        self.wait_for(&mut resolution, &mut Deadline::new(NETWORK_TIMEOUT_MS))
+            .await
+    }
+
+    /// Reads all the pending bytes from the reader, dispatching the URC and discarding any other
+    /// line. Once a line has started it is read until the end. [line] holds the current line
+    async fn process_pending_lines(&mut self, line: &mut [u8]) -> Result<(), AtError> {
+        let mut pending = PendingLines::new(line);
+        let mut deadline = Deadline::new(DEFAULT_TIMEOUT_MS);
+
+        while pending.in_line() || self.read_ready()? {
+            self.fill_input(&mut deadline, AtError::IOError).await?;
+            while let Some(byte) = self.input.next_byte() {
+                pending.push(byte, &mut self.urcs);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sends the command once the pending bytes of the reader have been processed. [line] is
+    /// used to hold the pending lines, the buffer of the response can be reused for it
+    async fn send_command(&mut self, command: &[u8], line: &mut [u8]) -> Result<(), AtError> {
+        // Before we send the command we will ensure that the read buffer is empty, keeping
+        // the unsolicited result codes that could be pending
         #[cfg(feature = "defmt")]
-        debug!("payload: {=[u8]:a}", &data);
+        debug!("Checking if are pending bytes to read before performing the read operation");
+        self.process_pending_lines(line).await?;
+
+        #[cfg(feature = "defmt")]
+        debug!("payload: {=[u8]:a}", command);
         self.writer
-            .write_all(data)
+            .write_all(command)
             .await
-            .map_err(|_| AtError::IOError)?;
-        let response_size = self.read_response(&mut buffer).await?;
+            .map_err(|_| AtError::IOError)
+    }
+
+    pub async fn send_and_wait_response<V: AtRequest>(
+        &mut self,
+        payload: V,
+    ) -> Result<V::Response, crate::AtError> {
+        let mut buffer = [0; N];
+        let data = encode_command(&payload, &mut buffer)?;
+        let mut response = [0; N];
+        self.send_command(data, &mut response).await?;
+
+        let response_size = self
+            .read_command_response(data, payload.timeout_ms(), &mut response)
+            .await?;
 
         #[cfg(feature = "defmt")]
-        debug!("received response: {=[u8]:a}", buffer[..response_size]);
-        let response = payload.parse_response_struct(&buffer);
+        debug!("received response: {=[u8]:a}", response[..response_size]);
+        let response = payload.parse_response_struct(&response[..response_size]);
         #[cfg(feature = "defmt")]
         debug!("parsed response: {}", response);
         response
     }
 
+    /// Sends the command and writes its response into [sink] instead of buffering it, so
+    /// responses longer than the buffer of the modem can be processed. The unsolicited result
+    /// codes and the final OK are not written. Returns the number of bytes written
+    pub async fn send_and_stream_response<V: AtRequest, S: Write>(
+        &mut self,
+        payload: &V,
+        sink: &mut S,
+    ) -> Result<usize, AtError> {
+        let mut buffer = [0; N];
+        let data = encode_command(payload, &mut buffer)?;
+        let mut line = [0; N];
+        self.send_command(data, &mut line).await?;
+
+        let mut framer = StreamFramer::new(&mut line);
+        let mut deadline = Deadline::new(payload.timeout_ms());
+
+        loop {
+            self.fill_input(&mut deadline, AtError::IOError).await?;
+            while let Some(byte) = self.input.next_byte() {
+                match framer.push(byte, &mut self.urcs, data)? {
+                    StreamStep::Continue => {}
+                    StreamStep::Write(bytes) => {
+                        sink.write_all(bytes).await.map_err(|_e| AtError::IOError)?
+                    }
+                    StreamStep::Finished(written) => return Ok(written),
+                }
+            }
+        }
+    }
+
     #[deprecated(since = "3.0.0", note = "Use the send_and_wait_response")]
     #[allow(deprecated)]
-    pub async fn send_and_wait_reply<V: AtRequest + 'a>(
-        &'a mut self,
+    pub async fn send_and_wait_reply<V: AtRequest>(
+        &mut self,
         payload: V,
     ) -> Result<AtResponse, crate::AtError> {
-        let mut buffer = [0; BUFFER_SIZE];
+        let mut buffer = [0; N];
         let data = payload.get_command_no_error(&mut buffer);
         #[cfg(feature = "defmt")]
         debug!("payload: {=[u8]:a}", &data);
-        self.writer.write_all(data).await.unwrap();
-        match self.read_response(&mut buffer).await {
+        self.writer
+            .write_all(data)
+            .await
+            .map_err(|_| AtError::IOError)?;
+        let mut read_buffer = [0; N];
+        match self
+            .read_command_response(data, payload.timeout_ms(), &mut read_buffer)
+            .await
+        {
             Ok(response_size) => {
                 #[cfg(feature = "defmt")]
-                debug!("received response: {=[u8]:a}", buffer[..response_size]);
-                let response = payload.parse_response(&buffer);
+                debug!("received response: {=[u8]:a}", read_buffer[..response_size]);
+                let response = payload.parse_response(&read_buffer[..response_size]);
                 #[cfg(feature = "defmt")]
                 debug!("parsed response: {}", response);
                 response
             }
             Err(at_error) => {
+                #[cfg(feature = "defmt")]
                 match at_error {
                     AtError::ErrorReply(response_size) => {
-                        #[cfg(feature = "defmt")]
-                        debug!("received response: {=[u8]:a}", buffer[..response_size]);
+                        debug!("received response: {=[u8]:a}", read_buffer[..response_size]);
                     }
                     _ => {
-                        #[cfg(feature = "defmt")]
                         debug!("error: {:?}", at_error);
                     }
                 }
@@ -281,55 +721,92 @@ impl<'a, T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs> AsyncModem<T,
         }
     }
 
+    /// Reads a response until the OK or ERROR terminators are found, waiting at most
+    /// [DEFAULT_TIMEOUT_MS]
+    pub async fn read_response(&mut self, response_out: &mut [u8; N]) -> Result<usize, AtError> {
+        self.read_command_response(&[], DEFAULT_TIMEOUT_MS, response_out)
+            .await
+    }
+
+    /// Reads and discards the next response
     pub async fn read_next_response(&mut self) -> Result<(), crate::AtError> {
-        let mut buffer = [0; BUFFER_SIZE];
-        #[cfg(feature = "defmt")]
-        let response_size = self.read_response(&mut buffer).await?;
+        let mut buffer = [0; N];
+        let _response_size = self.read_response(&mut buffer).await?;
         #[cfg(feature = "defmt")]
-        debug!("received response: {=[u8]:a}", buffer[..response_size]);
+        debug!("received response: {=[u8]:a}", buffer[.._response_size]);
         Ok(())
     }
 
-    async fn read_response(
+    /// Polls the URC until the [waiter] gets its result, failing with [AtError::Timeout] once
+    /// the [deadline] is exhausted
+    async fn wait_for<W: UrcWait>(
         &mut self,
-        response_out: &mut [u8; BUFFER_SIZE],
-    ) -> Result<usize, crate::AtError> {
-        let mut offset = 0_usize;
-        let mut read_buffer: [u8; 10] = [0; 10];
+        waiter: &mut W,
+        deadline: &mut Deadline,
+    ) -> Result<W::Output, AtError> {
         loop {
-            match self.reader.read(&mut read_buffer).await {
-                Ok(num_bytes) => {
-                    for i in 0..num_bytes {
-                        response_out[offset + i] = read_buffer[i];
-                        // debug!("{=[u8]:a}, {}", *response_out, offset + i );
-
-                        // why is the index with + 1 and - 5?
-                        if offset + i < 5 {
-                            continue;
-                        }
-
-                        let start = offset + i - 5;
-                        let stop = offset + i + 1;
-
-                        match &response_out[start..stop] {
-                            OK_TERMINATOR => return Ok(offset + i),
-                            ERROR_TERMINATOR => return Err(AtError::ErrorReply(offset + i)),
-                            _ => continue,
-                        }
-                    }
-                    offset += num_bytes;
-                }
+            self.poll_urcs().await?;
+            if let Some(output) = waiter.poll(&mut self.urcs)? {
+                return Ok(output);
+            }
+            self.wait_until_read_ready(deadline).await?;
+        }
+    }
 
-                Err(e) => {
-                    #[cfg(feature = "defmt")]
-                    error!("no data: {:?}", e.kind());
-                }
+    /// Waits until the reader has data available, failing with [AtError::Timeout] once the
+    /// [deadline] is exhausted. Every call consumes at least one poll interval, so the deadline
+    /// also expires while the module keeps sending data
+    async fn wait_until_read_ready(&mut self, deadline: &mut Deadline) -> Result<(), AtError> {
+        loop {
+            let interval = deadline.next_wait()?;
+            if self.read_ready()? {
+                return Ok(());
             }
+            self.delay.delay_us(interval).await;
+        }
+    }
+
+    /// Indicates if there are bytes to process, already read or pending in the reader
+    fn read_ready(&mut self) -> Result<bool, AtError> {
+        Ok(!self.input.is_empty() || self.reader.read_ready().map_err(|_e| AtError::IOError)?)
+    }
+
+    /// Reads the next chunk from the module once the previous one has been processed, waiting
+    /// at most until the [deadline] is exhausted. A failed read is reported as [error]
+    async fn fill_input(&mut self, deadline: &mut Deadline, error: AtError) -> Result<(), AtError> {
+        if !self.input.is_empty() {
+            return Ok(());
         }
+        self.wait_until_read_ready(deadline).await?;
+        let size = self.reader.read(self.input.space()).await.map_err(|_e| {
+            #[cfg(feature = "defmt")]
+            error!("uart error {}", _e.kind());
+            error
+        })?;
+        self.input.filled(size);
+
+        Ok(())
     }
 
-    /// Default max unlock tries
-    const MAX_UNLOCK_TRIES: usize = 1;
+    /// Reads the response of the given command until the OK or ERROR terminators are found.
+    /// The unsolicited result codes received meanwhile are removed from the response
+    async fn read_command_response(
+        &mut self,
+        command: &[u8],
+        timeout_ms: u32,
+        response_out: &mut [u8; N],
+    ) -> Result<usize, crate::AtError> {
+        let mut framer = ResponseFramer::new(response_out);
+        let mut deadline = Deadline::new(timeout_ms);
+        loop {
+            self.fill_input(&mut deadline, AtError::NotReady).await?;
+            while let Some(byte) = self.input.next_byte() {
+                if let Some(size) = framer.push(byte, &mut self.urcs, command)? {
+                    return Ok(size);
+                }
+            }
+        }
+    }
 
     /// Try to unlock the sim card. If the sim card is already unlocked nothing will happen.
     /// If the sim card needs a PIN the provided [pin] will be used to unlock.
@@ -347,53 +824,120 @@ impl<'a, T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs> AsyncModem<T,
         #[cfg(feature = "defmt")]
         info!("Trying to unlock SIM");
 
-        let max_unlock_tries = max_unlock_tries.unwrap_or(Self::MAX_UNLOCK_TRIES);
+        let max_unlock_tries = max_unlock_tries.unwrap_or(MAX_UNLOCK_TRIES);
 
         debug_assert!(max_unlock_tries > 0, "We need at least one try to unlock");
 
-        // First we need to check for the PIN status
-        let current_pin_status = self.send_and_wait_response(PINRequired).await?;
-
-        #[cfg(feature = "defmt")]
-        debug!("current pin status: {}", current_pin_status);
-
         let mut unlock_tries = 0;
 
         loop {
-            // First we need to check the status of the pin
-            match current_pin_status {
-                PinStatus::Ready => {
-                    #[cfg(feature = "defmt")]
-                    info!("SIM is already unlocked returning");
+            let current_pin_status = self.send_and_wait_response(PINRequired).await?;
 
-                    return Ok(());
-                }
-                PinStatus::SimPin => {
-                    #[cfg(feature = "defmt")]
-                    debug!("SIM pin is required")
-                }
-                _ => {
-                    // This is a state the method can not handle
+            #[cfg(feature = "defmt")]
+            debug!("current pin status: {}", current_pin_status);
+
+            match next_unlock_step(current_pin_status, unlock_tries, max_unlock_tries)? {
+                UnlockStep::Unlocked => return Ok(()),
+                UnlockStep::EnterPin => {
                     #[cfg(feature = "defmt")]
-                    warn!("SIM status can no be handled. {}", current_pin_status);
-                    return Err(AtError::IllegalPinStatus(current_pin_status));
+                    debug!("Trying to unlock SIM with the provided pin");
+
+                    self.send_and_wait_response(EnterPIN { pin }).await?;
+                    unlock_tries += 1;
                 }
             }
+        }
+    }
+}
 
-            // If we already do the maximum unlock tries return an error with the current
-            // status
-            if unlock_tries >= Self::MAX_UNLOCK_TRIES {
-                #[cfg(feature = "defmt")]
-                return Err(AtError::IllegalPinStatus(current_pin_status));
-            }
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::at_command::model_identification::ModelIdentification;
+    use crate::testing::{NoopDelay, NoopPin, ScriptedSerial, Step};
+    use embassy_futures::block_on;
+
+    #[test]
+    fn test_send_and_wait_response() {
+        let script = [
+            Step::Expect(b"ATE0\r\n"),
+            Step::Reply(b"\r\nOK\r\n"),
+            Step::Reply(b"\r\n+CPSMSTATUS: \"ENTER PSM\"\r\n"),
+            Step::Expect(b"AT+CGMM\r\n"),
+            Step::Reply(b"\r\nSIM7020E\r\n\r\nOK\r\n"),
+        ];
+        let serial = ScriptedSerial::new(&script);
+        let (writer, reader) = serial.split();
+
+        block_on(async {
+            let mut modem = AsyncModem::new(writer, reader, NoopPin, NoopPin, NoopDelay)
+                .await
+                .unwrap();
+            let response = modem
+                .send_and_wait_response(ModelIdentification)
+                .await
+                .unwrap();
+            assert_eq!(&response.model, b"SIM7020E");
+        });
 
-            // We have at least 1 try to unlock the pin
-            #[cfg(feature = "defmt")]
-            debug!("Trying to unlock SIM with the provided pin");
+        serial.done();
+    }
 
-            self.send_and_wait_response(EnterPIN { pin }).await?;
+    #[test]
+    fn test_command_timeout() {
+        let script = [
+            Step::Expect(b"ATE0\r\n"),
+            Step::Reply(b"\r\nOK\r\n"),
+            Step::Expect(b"AT+CGMM\r\n"),
+        ];
+        let serial = ScriptedSerial::new(&script);
+        let (writer, reader) = serial.split();
+
+        block_on(async {
+            let mut modem = AsyncModem::new(writer, reader, NoopPin, NoopPin, NoopDelay)
+                .await
+                .unwrap();
+            assert!(matches!(
+                modem.send_and_wait_response(ModelIdentification).await,
+                Err(AtError::Timeout)
+            ));
+        });
+    }
 
-            unlock_tries += 1;
-        }
+    #[test]
+    fn test_unlock_sim_with_the_emulator() {
+        let emulator = crate::emulator::Sim7020Emulator::new().with_pin(1234);
+        let (writer, reader) = emulator.split();
+
+        block_on(async {
+            let mut modem = AsyncModem::new(writer, reader, NoopPin, NoopPin, NoopDelay)
+                .await
+                .unwrap();
+            assert!(matches!(
+                modem.try_to_unlock_sim(1111, None).await,
+                Err(AtError::ErrorReply(_))
+            ));
+            modem.try_to_unlock_sim(1234, None).await.unwrap();
+        });
+
+        assert!(emulator.is_registered());
+    }
+
+    #[test]
+    fn test_same_commands_as_the_blocking_modem() {
+        let emulator = crate::emulator::Sim7020Emulator::new();
+        let (writer, reader) = emulator.split();
+
+        block_on(async {
+            let mut modem = AsyncModem::new(writer, reader, NoopPin, NoopPin, NoopDelay)
+                .await
+                .unwrap();
+            modem.enable_numeric_errors().await.unwrap();
+            modem.set_flow_control().await.unwrap();
+            assert!(matches!(
+                modem.start_sleeping().await,
+                Err(AtError::IllegalModuleState)
+            ));
+        });
     }
 }
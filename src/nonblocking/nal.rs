@@ -0,0 +1,533 @@
+//! Implementation of the [embedded_nal_async] traits on top of the IP stack of the module, so
+//! crates built on [TcpConnect], [Dns] or [UdpStack] can be used with the [AsyncModem].
+//!
+//! ```ignore
+//! let stack: AsyncNetworkStack<NoopRawMutex, _, _, _, _> = AsyncNetworkStack::new(&mut modem);
+//! let address = stack.get_host_by_name("example.com", AddrType::IPv4).await?;
+//! let mut connection = stack.connect(SocketAddr::new(address, 80)).await?;
+//! connection.write_all(b"GET / HTTP/1.0\r\n\r\n").await?;
+//! ```
+//!
+//! The stack shares the modem between the open connections with a mutex, so it can be used from
+//! several tasks. A connection that is dropped without [AsyncSocketConnection::close] is closed in
+//! the module the next time the stack uses the modem.
+//!
+//! This module is available with the `nal-async` feature
+
+use crate::at_command::cmee::CmeError;
+use crate::at_command::socket::{
+    max_send_size, CloseSocket, ConnectSocketToRemote, CreateSocket, Domain, Protocol,
+    SendSocketMessage, Type,
+};
+use crate::nonblocking::AsyncModem;
+use crate::urc::{IP_ADDRESS_SIZE, MAX_SOCKETS};
+use crate::{AtError, BUFFER_SIZE};
+use core::cell::Cell;
+use core::fmt::Write as _;
+use core::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+#[cfg(feature = "defmt")]
+use defmt::debug;
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::blocking_mutex::Mutex as BlockingMutex;
+use embassy_sync::mutex::{Mutex, MutexGuard};
+use embedded_hal::digital::OutputPin;
+use embedded_hal_async::delay::DelayNs;
+use embedded_io::ReadReady;
+use embedded_io_async::{Read, Write};
+use embedded_io_async_06::ErrorKind;
+use embedded_nal_async::{AddrType, ConnectedUdp, Dns, TcpConnect, UdpStack, UnconnectedUdp};
+
+/// Time that a read holds the modem waiting for data before letting other connections use it
+const RECEIVE_POLL_MS: u32 = 100;
+
+impl embedded_io_async_06::Error for AtError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            AtError::SocketNotConnected => ErrorKind::NotConnected,
+            AtError::Timeout => ErrorKind::TimedOut,
+            AtError::CapacityError => ErrorKind::OutOfMemory,
+            AtError::NotSupported
+            | AtError::MobileEquipmentError(CmeError::OperationNotSupported) => {
+                ErrorKind::Unsupported
+            }
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+/// Network stack that uses the sockets of the module through the [AsyncModem]
+pub struct AsyncNetworkStack<
+    'm,
+    M: RawMutex,
+    T: Write,
+    U: Read + ReadReady,
+    P: OutputPin,
+    D: DelayNs,
+    const N: usize = BUFFER_SIZE,
+> {
+    modem: Mutex<M, &'m mut AsyncModem<T, U, P, D, N>>,
+    /// PDP context used to create the sockets
+    cid: Option<i32>,
+    /// Bit mask of the sockets whose connection was dropped without closing it
+    dropped: BlockingMutex<M, Cell<u8>>,
+}
+
+impl<'m, M: RawMutex, T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs, const N: usize>
+    AsyncNetworkStack<'m, M, T, U, P, D, N>
+{
+    /// Creates a stack that uses the default PDP context
+    pub fn new(modem: &'m mut AsyncModem<T, U, P, D, N>) -> Self {
+        Self::with_pdp_context(modem, None)
+    }
+
+    /// Creates a stack that opens the sockets in the given PDP context, check
+    /// [PDPContext](crate::at_command::pdp_context::PDPContext)
+    pub fn with_pdp_context(modem: &'m mut AsyncModem<T, U, P, D, N>, cid: Option<i32>) -> Self {
+        Self {
+            modem: Mutex::new(modem),
+            cid,
+            dropped: BlockingMutex::new(Cell::new(0)),
+        }
+    }
+
+    /// Locks the modem, closing first the sockets of the dropped connections
+    pub async fn modem(&self) -> MutexGuard<'_, M, &'m mut AsyncModem<T, U, P, D, N>> {
+        let mut modem = self.modem.lock().await;
+        let dropped = self.dropped.lock(Cell::take);
+        for socket_id in (0..MAX_SOCKETS as u8).filter(|id| dropped & (1 << id) != 0) {
+            #[cfg(feature = "defmt")]
+            debug!("Closing the dropped socket {}", socket_id);
+            modem.clear_socket_data(socket_id);
+            let _ = modem
+                .send_and_wait_response(CloseSocket { socket_id })
+                .await;
+        }
+
+        modem
+    }
+
+    /// Creates the socket in the module and connects it to the remote address
+    async fn open(
+        &self,
+        connection_type: Type,
+        remote: SocketAddr,
+    ) -> Result<AsyncSocketConnection<'_, 'm, M, T, U, P, D, N>, AtError> {
+        let domain = match remote {
+            SocketAddr::V4(_) => Domain::IPv4,
+            SocketAddr::V6(_) => Domain::IPv6,
+        };
+        let mut address: heapless::String<IP_ADDRESS_SIZE> = heapless::String::new();
+        write!(address, "{}", remote.ip()).map_err(|_| AtError::CapacityError)?;
+
+        #[cfg(feature = "defmt")]
+        debug!("Opening socket to {}:{}", address.as_str(), remote.port());
+
+        let mut modem = self.modem().await;
+        let socket_id = modem
+            .send_and_wait_response(CreateSocket {
+                domain,
+                connection_type,
+                protocol: Protocol::IP,
+                cid: self.cid,
+            })
+            .await?
+            .socket_id;
+
+        let connected = modem
+            .send_and_wait_response(ConnectSocketToRemote {
+                socket_id,
+                port: remote.port(),
+                remote_address: &address,
+            })
+            .await;
+        if let Err(error) = connected {
+            // Do not leave the socket open in the module
+            let _ = modem
+                .send_and_wait_response(CloseSocket { socket_id })
+                .await;
+            return Err(error);
+        }
+
+        Ok(AsyncSocketConnection {
+            stack: self,
+            socket_id,
+            remote,
+            open: true,
+        })
+    }
+}
+
+/// Socket of the module connected to a remote address, opened with [AsyncNetworkStack]. It is
+/// used both for TCP connections and connected UDP sockets
+pub struct AsyncSocketConnection<
+    's,
+    'm,
+    M: RawMutex,
+    T: Write,
+    U: Read + ReadReady,
+    P: OutputPin,
+    D: DelayNs,
+    const N: usize = BUFFER_SIZE,
+> {
+    stack: &'s AsyncNetworkStack<'m, M, T, U, P, D, N>,
+    socket_id: u8,
+    remote: SocketAddr,
+    /// Whether the socket has to be closed when the connection is dropped
+    open: bool,
+}
+
+impl<M: RawMutex, T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs, const N: usize>
+    AsyncSocketConnection<'_, '_, M, T, U, P, D, N>
+{
+    /// Id of the socket in the module
+    pub fn socket_id(&self) -> u8 {
+        self.socket_id
+    }
+
+    /// Address the socket is connected to
+    pub fn remote(&self) -> SocketAddr {
+        self.remote
+    }
+
+    /// Closes the socket in the module
+    pub async fn close(mut self) -> Result<(), AtError> {
+        self.open = false;
+        let mut modem = self.stack.modem().await;
+        modem.clear_socket_data(self.socket_id);
+        modem
+            .send_and_wait_response(CloseSocket {
+                socket_id: self.socket_id,
+            })
+            .await
+    }
+
+    /// Sends as much data as fits in a single command. Returns the number of bytes sent
+    async fn send_chunk(&mut self, buffer: &[u8]) -> Result<usize, AtError> {
+        let size = buffer.len().min(max_send_size(N));
+        if size == 0 && !buffer.is_empty() {
+            return Err(AtError::CapacityError);
+        }
+
+        self.stack
+            .modem()
+            .await
+            .send_and_wait_response(SendSocketMessage {
+                socket_id: self.socket_id,
+                data: &buffer[..size],
+            })
+            .await?;
+
+        Ok(size)
+    }
+
+    /// Waits until data is received in the socket, releasing the modem between polls so other
+    /// connections can use it
+    async fn receive_data(&mut self, buffer: &mut [u8]) -> Result<usize, AtError> {
+        if buffer.is_empty() {
+            return Ok(0);
+        }
+
+        loop {
+            let received = self
+                .stack
+                .modem()
+                .await
+                .receive_socket_data(self.socket_id, buffer, RECEIVE_POLL_MS)
+                .await;
+            match received {
+                Err(AtError::Timeout) => embassy_futures::yield_now().await,
+                received => return received,
+            }
+        }
+    }
+
+    /// Waits until a datagram is received in the socket, releasing the modem between polls so
+    /// other connections can use it. The bytes that do not fit in [buffer] are discarded
+    async fn receive_datagram(&mut self, buffer: &mut [u8]) -> Result<usize, AtError> {
+        loop {
+            let received = self
+                .stack
+                .modem()
+                .await
+                .receive_datagram(self.socket_id, buffer, RECEIVE_POLL_MS)
+                .await;
+            match received {
+                Err(AtError::Timeout) => embassy_futures::yield_now().await,
+                received => return received,
+            }
+        }
+    }
+}
+
+impl<M: RawMutex, T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs, const N: usize> Drop
+    for AsyncSocketConnection<'_, '_, M, T, U, P, D, N>
+{
+    fn drop(&mut self) {
+        if self.open {
+            let socket_id = self.socket_id;
+            self.stack
+                .dropped
+                .lock(|dropped| dropped.set(dropped.get() | (1 << socket_id)));
+        }
+    }
+}
+
+impl<M: RawMutex, T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs, const N: usize>
+    embedded_io_async_06::ErrorType for AsyncSocketConnection<'_, '_, M, T, U, P, D, N>
+{
+    type Error = AtError;
+}
+
+impl<M: RawMutex, T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs, const N: usize>
+    embedded_io_async_06::Read for AsyncSocketConnection<'_, '_, M, T, U, P, D, N>
+{
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        self.receive_data(buf).await
+    }
+}
+
+impl<M: RawMutex, T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs, const N: usize>
+    embedded_io_async_06::Write for AsyncSocketConnection<'_, '_, M, T, U, P, D, N>
+{
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.send_chunk(buf).await
+    }
+}
+
+impl<M: RawMutex, T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs, const N: usize>
+    ConnectedUdp for AsyncSocketConnection<'_, '_, M, T, U, P, D, N>
+{
+    type Error = AtError;
+
+    async fn send(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        // A datagram can not be split
+        if data.len() > max_send_size(N) {
+            return Err(AtError::CapacityError);
+        }
+        self.send_chunk(data).await?;
+
+        Ok(())
+    }
+
+    async fn receive_into(&mut self, buffer: &mut [u8]) -> Result<usize, Self::Error> {
+        self.receive_datagram(buffer).await
+    }
+}
+
+impl<'m, M: RawMutex, T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs, const N: usize>
+    TcpConnect for AsyncNetworkStack<'m, M, T, U, P, D, N>
+{
+    type Error = AtError;
+    type Connection<'a>
+        = AsyncSocketConnection<'a, 'm, M, T, U, P, D, N>
+    where
+        Self: 'a;
+
+    async fn connect<'a>(
+        &'a self,
+        remote: SocketAddr,
+    ) -> Result<Self::Connection<'a>, Self::Error> {
+        self.open(Type::TCP, remote).await
+    }
+}
+
+impl<M: RawMutex, T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs, const N: usize> Dns
+    for AsyncNetworkStack<'_, M, T, U, P, D, N>
+{
+    type Error = AtError;
+
+    async fn get_host_by_name(
+        &self,
+        host: &str,
+        addr_type: AddrType,
+    ) -> Result<IpAddr, Self::Error> {
+        let address = self.modem().await.resolve_host(host).await?;
+        match (addr_type, address) {
+            (AddrType::Either, _)
+            | (AddrType::IPv4, IpAddr::V4(_))
+            | (AddrType::IPv6, IpAddr::V6(_)) => Ok(address),
+            // The module does not let us choose the type of address to resolve
+            _ => Err(AtError::NotSupported),
+        }
+    }
+
+    async fn get_host_by_address(
+        &self,
+        _addr: IpAddr,
+        _result: &mut [u8],
+    ) -> Result<usize, Self::Error> {
+        Err(AtError::NotSupported)
+    }
+}
+
+/// Unconnected UDP socket. The module can only send datagrams through connected sockets, so
+/// [UdpStack::bind_single] and [UdpStack::bind_multiple] always fail and this type has no values
+pub enum UnboundUdpSocket {}
+
+impl UnconnectedUdp for UnboundUdpSocket {
+    type Error = AtError;
+
+    async fn send(
+        &mut self,
+        _local: SocketAddr,
+        _remote: SocketAddr,
+        _data: &[u8],
+    ) -> Result<(), Self::Error> {
+        match *self {}
+    }
+
+    async fn receive_into(
+        &mut self,
+        _buffer: &mut [u8],
+    ) -> Result<(usize, SocketAddr, SocketAddr), Self::Error> {
+        match *self {}
+    }
+}
+
+impl<
+        's,
+        'm,
+        M: RawMutex,
+        T: Write,
+        U: Read + ReadReady,
+        P: OutputPin,
+        D: DelayNs,
+        const N: usize,
+    > UdpStack for &'s AsyncNetworkStack<'m, M, T, U, P, D, N>
+{
+    type Error = AtError;
+    type Connected = AsyncSocketConnection<'s, 'm, M, T, U, P, D, N>;
+    type UniquelyBound = UnboundUdpSocket;
+    type MultiplyBound = UnboundUdpSocket;
+
+    /// The module chooses the local address, which is not reported, so `local` must be
+    /// unspecified and the returned address is unspecified too
+    async fn connect_from(
+        &self,
+        local: SocketAddr,
+        remote: SocketAddr,
+    ) -> Result<(SocketAddr, Self::Connected), Self::Error> {
+        if !local.ip().is_unspecified() || local.port() != 0 {
+            return Err(AtError::NotSupported);
+        }
+        let connection = self.open(Type::UPD, remote).await?;
+        let unspecified = match remote {
+            SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        };
+
+        Ok((SocketAddr::new(unspecified, 0), connection))
+    }
+
+    async fn bind_single(
+        &self,
+        _local: SocketAddr,
+    ) -> Result<(SocketAddr, Self::UniquelyBound), Self::Error> {
+        Err(AtError::NotSupported)
+    }
+
+    async fn bind_multiple(&self, _local: SocketAddr) -> Result<Self::MultiplyBound, Self::Error> {
+        Err(AtError::NotSupported)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::emulator::Sim7020Emulator;
+    use crate::testing::{registered_async_modem, NoopDelay};
+    use embassy_futures::block_on;
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+    use embedded_io_async_06::{Read as _, Write as _};
+
+    #[test]
+    fn test_tcp_connections() {
+        let emulator = Sim7020Emulator::new().with_host("echo.example.com", "127.0.0.1");
+
+        block_on(async {
+            let mut modem = registered_async_modem(&emulator, NoopDelay).await;
+            let stack: AsyncNetworkStack<NoopRawMutex, _, _, _, _> =
+                AsyncNetworkStack::new(&mut modem);
+
+            let address = stack
+                .get_host_by_name("echo.example.com", AddrType::Either)
+                .await
+                .unwrap();
+            assert!(matches!(
+                stack
+                    .get_host_by_name("echo.example.com", AddrType::IPv6)
+                    .await,
+                Err(AtError::NotSupported)
+            ));
+            let remote = SocketAddr::new(address, 7);
+
+            let mut first = stack.connect(remote).await.unwrap();
+            let mut second = stack.connect(remote).await.unwrap();
+            first.write_all(b"FIRST").await.unwrap();
+            second.write_all(b"SECOND").await.unwrap();
+
+            let mut buffer = [0; 16];
+            let size = second.read(&mut buffer).await.unwrap();
+            assert_eq!(&buffer[..size], b"SECOND");
+            let size = first.read(&mut buffer).await.unwrap();
+            assert_eq!(&buffer[..size], b"FIRST");
+
+            first.close().await.unwrap();
+            assert!(emulator.socket(0).is_none());
+
+            // Dropping the connection closes the socket the next time the modem is used
+            drop(second);
+            assert!(emulator.socket(1).is_some());
+            let _ = stack.modem().await;
+            assert!(emulator.socket(1).is_none());
+        });
+    }
+
+    #[test]
+    fn test_udp_connection() {
+        let emulator = Sim7020Emulator::new();
+
+        block_on(async {
+            let mut modem = registered_async_modem(&emulator, NoopDelay).await;
+            let stack: AsyncNetworkStack<NoopRawMutex, _, _, _, _> =
+                AsyncNetworkStack::new(&mut modem);
+            let remote: SocketAddr = "10.0.0.1:5683".parse().unwrap();
+
+            assert!(matches!(
+                UdpStack::connect_from(&&stack, "10.0.0.2:5683".parse().unwrap(), remote).await,
+                Err(AtError::NotSupported)
+            ));
+            let (local, mut socket) = UdpStack::connect(&&stack, remote).await.unwrap();
+            assert_eq!(local, "0.0.0.0:0".parse().unwrap());
+            assert_eq!(emulator.socket(0).unwrap().connection_type, Type::UPD as u8);
+
+            ConnectedUdp::send(&mut socket, b"PING").await.unwrap();
+            let mut buffer = [0; 8];
+            let size = socket.receive_into(&mut buffer).await.unwrap();
+            assert_eq!(&buffer[..size], b"PING");
+
+            // Each datagram is received on its own, truncated to the buffer
+            ConnectedUdp::send(&mut socket, b"FIRST DATAGRAM")
+                .await
+                .unwrap();
+            ConnectedUdp::send(&mut socket, b"SECOND").await.unwrap();
+            let size = socket.receive_into(&mut buffer).await.unwrap();
+            assert_eq!(&buffer[..size], b"FIRST DA");
+            let size = socket.receive_into(&mut buffer).await.unwrap();
+            assert_eq!(&buffer[..size], b"SECOND");
+
+            // Does not fit the default buffer of the modem
+            assert!(matches!(
+                ConnectedUdp::send(&mut socket, &[0; 300]).await,
+                Err(AtError::CapacityError)
+            ));
+            let Err(error) = (&stack).bind_multiple(remote).await;
+            assert!(matches!(error, AtError::NotSupported));
+            assert_eq!(
+                embedded_io_async_06::Error::kind(&error),
+                ErrorKind::Unsupported
+            );
+            socket.close().await.unwrap();
+        });
+    }
+}
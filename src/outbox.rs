@@ -0,0 +1,795 @@
+//! Store-and-forward outbox for the MQTT publications.
+//!
+//! The [MqttOutbox] queues the messages while the session is offline and publishes them in order
+//! once it is connected. A message is only removed from the outbox after the module has accepted
+//! its `AT+CMQPUB`, so a failed publish is retried on the next flush. The messages can be kept
+//! in an [OutboxStore], e.g. in flash with the [StorageOutboxStore] of the `storage` feature, so
+//! they survive a reset.
+use crate::at_command::mqtt::{MQTTError, MQTTMessage, Mqtt, MAX_TOPIC_SIZE};
+use crate::Modem;
+use core::convert::Infallible;
+#[cfg(feature = "defmt")]
+use defmt::warn;
+use embedded_hal::delay::DelayNs;
+use embedded_hal::digital::OutputPin;
+use embedded_io::{Read, ReadReady, Write};
+
+/// Default number of messages kept by the [MqttOutbox]
+pub const OUTBOX_SIZE: usize = 8;
+/// Maximum payload of a message kept by the [MqttOutbox]
+pub const OUTBOX_PAYLOAD_SIZE: usize = 256;
+
+/// Message waiting in the [MqttOutbox]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(PartialEq, Clone, Debug)]
+pub struct OutboxMessage {
+    pub topic: heapless::String<MAX_TOPIC_SIZE>,
+    pub qos: u8,
+    pub retained: bool,
+    pub payload: heapless::Vec<u8, OUTBOX_PAYLOAD_SIZE>,
+}
+
+impl OutboxMessage {
+    /// Copies the message, failing with [OutboxError::TooLarge] if it does not fit
+    pub fn new<E>(message: &MQTTMessage) -> Result<Self, OutboxError<E>> {
+        if message.qos > 2 {
+            return Err(OutboxError::InvalidQoS);
+        }
+
+        Ok(Self {
+            topic: message
+                .topic
+                .try_into()
+                .map_err(|_| OutboxError::TooLarge)?,
+            qos: message.qos,
+            retained: message.retained,
+            payload: heapless::Vec::from_slice(message.message)
+                .map_err(|_| OutboxError::TooLarge)?,
+        })
+    }
+
+    /// The message to publish
+    pub fn message(&self) -> MQTTMessage<'_> {
+        MQTTMessage {
+            topic: &self.topic,
+            qos: self.qos,
+            retained: self.retained,
+            dup: false,
+            message: &self.payload,
+        }
+    }
+}
+
+/// Errors of the [MqttOutbox], `E` is the error of its [OutboxStore]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(PartialEq, Clone, Debug)]
+pub enum OutboxError<E> {
+    /// The topic or the payload do not fit in an [OutboxMessage]
+    TooLarge,
+    InvalidQoS,
+    /// The outbox already holds `N` messages waiting to be published
+    Full,
+    /// The message has not been published, it stays in the outbox
+    Publish(MQTTError),
+    Store(E),
+}
+
+/// Persistence of the messages of the [MqttOutbox]
+pub trait OutboxStore {
+    type Error;
+
+    /// Replaces the stored messages with the given ones, oldest first
+    fn save<'m>(
+        &mut self,
+        messages: impl Iterator<Item = &'m OutboxMessage>,
+    ) -> Result<(), Self::Error>;
+
+    /// Calls `push` with each stored message, oldest first
+    fn load(&mut self, push: impl FnMut(OutboxMessage)) -> Result<(), Self::Error>;
+}
+
+/// [OutboxStore] that keeps the messages only in memory
+#[derive(Default)]
+pub struct NoPersistence;
+
+impl OutboxStore for NoPersistence {
+    type Error = Infallible;
+
+    fn save<'m>(
+        &mut self,
+        _messages: impl Iterator<Item = &'m OutboxMessage>,
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn load(&mut self, _push: impl FnMut(OutboxMessage)) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Bounded ring of the messages waiting to be published. The messages are only removed once
+/// published, so no message can be queued while it is full
+pub struct MqttOutbox<S: OutboxStore = NoPersistence, const N: usize = OUTBOX_SIZE> {
+    messages: heapless::Deque<OutboxMessage, N>,
+    store: S,
+}
+
+impl<const N: usize> MqttOutbox<NoPersistence, N> {
+    pub const fn new() -> Self {
+        Self {
+            messages: heapless::Deque::new(),
+            store: NoPersistence,
+        }
+    }
+}
+
+impl<const N: usize> Default for MqttOutbox<NoPersistence, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S: OutboxStore, const N: usize> MqttOutbox<S, N> {
+    /// Creates an outbox with the messages kept in the store. Fails with [OutboxError::Full] if
+    /// the store holds more than `N` messages
+    pub fn with_store(mut store: S) -> Result<Self, OutboxError<S::Error>> {
+        let mut messages = heapless::Deque::new();
+        let mut full = false;
+        store
+            .load(|message| full |= messages.push_back(message).is_err())
+            .map_err(OutboxError::Store)?;
+        if full {
+            return Err(OutboxError::Full);
+        }
+
+        Ok(Self { messages, store })
+    }
+
+    fn save(&mut self) -> Result<(), OutboxError<S::Error>> {
+        self.store
+            .save(self.messages.iter())
+            .map_err(OutboxError::Store)
+    }
+
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    /// Messages waiting to be published, oldest first
+    pub fn iter(&self) -> impl Iterator<Item = &OutboxMessage> {
+        self.messages.iter()
+    }
+
+    /// Queues the message, which is published by the next flush. Fails with [OutboxError::Full]
+    /// if the outbox already holds `N` messages. The message is not queued if it can not be saved
+    pub fn enqueue(&mut self, message: &MQTTMessage) -> Result<(), OutboxError<S::Error>> {
+        let message = OutboxMessage::new(message)?;
+        if self.messages.push_back(message).is_err() {
+            #[cfg(feature = "defmt")]
+            warn!("MQTT outbox is full");
+            return Err(OutboxError::Full);
+        }
+        if let Err(error) = self.save() {
+            self.messages.pop_back();
+            return Err(error);
+        }
+
+        Ok(())
+    }
+
+    /// Oldest message, which must be published next. Once published it is removed with
+    /// [MqttOutbox::confirm], e.g. when publishing with an async context
+    pub fn front(&self) -> Option<&OutboxMessage> {
+        self.messages.front()
+    }
+
+    /// Removes the oldest message after it has been published
+    pub fn confirm(&mut self) -> Result<(), OutboxError<S::Error>> {
+        if self.messages.pop_front().is_some() {
+            self.save()?;
+        }
+
+        Ok(())
+    }
+
+    /// Publishes the messages in order with `publish` until one fails. Returns the number of
+    /// published messages
+    pub fn flush_with(
+        &mut self,
+        mut publish: impl FnMut(&MQTTMessage) -> Result<(), MQTTError>,
+    ) -> Result<usize, OutboxError<S::Error>> {
+        let mut published = 0;
+        let mut result = Ok(());
+        while let Some(message) = self.messages.front() {
+            if let Err(e) = publish(&message.message()) {
+                result = Err(OutboxError::Publish(e));
+                break;
+            }
+            self.messages.pop_front();
+            published += 1;
+        }
+
+        if published > 0 {
+            self.save()?;
+        }
+        result.map(|_| published)
+    }
+
+    /// Publishes the messages in order on the session until one fails. Returns the number of
+    /// published messages
+    pub fn flush<T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs, const M: usize>(
+        &mut self,
+        mqtt: &Mqtt,
+        modem: &mut Modem<'_, T, U, P, D, M>,
+    ) -> Result<usize, OutboxError<S::Error>> {
+        self.flush_with(|message| mqtt.publish(message, modem))
+    }
+
+    /// Queues the message and publishes all the queued messages in order. The message stays in
+    /// the outbox if it can not be published yet
+    pub fn publish<T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs, const M: usize>(
+        &mut self,
+        message: &MQTTMessage,
+        mqtt: &Mqtt,
+        modem: &mut Modem<'_, T, U, P, D, M>,
+    ) -> Result<usize, OutboxError<S::Error>> {
+        self.enqueue(message)?;
+        self.flush(mqtt, modem)
+    }
+}
+
+/// [OutboxStore] that keeps the messages in a region of a flash, or any other
+/// [Storage](embedded_storage::Storage), starting at `offset`. The region is split in two slots
+/// that are written alternately, and the slot with the latest valid generation is loaded, so the
+/// previous messages are kept if a save is interrupted, e.g. by a power loss. A slot is rewritten
+/// after each change, so the flash wear must be taken into account
+#[cfg(feature = "storage")]
+pub struct StorageOutboxStore<S> {
+    storage: S,
+    offset: u32,
+    size: u32,
+    /// Slot and generation of the latest save, once the slots have been read
+    latest: Option<Option<(u32, u32)>>,
+}
+
+/// Errors of the [StorageOutboxStore]
+#[cfg(feature = "storage")]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(PartialEq, Clone, Debug)]
+pub enum StorageOutboxError<E> {
+    Storage(E),
+    /// The messages do not fit in a slot
+    Full,
+    /// The stored data is not valid
+    Corrupted,
+    /// The region is outside the storage, or its slots are too small or too large
+    InvalidRegion,
+}
+
+#[cfg(feature = "storage")]
+const STORE_MAGIC: &[u8; 4] = b"MQOB";
+/// Magic, generation, number of messages, length of the messages and checksum
+#[cfg(feature = "storage")]
+const STORE_HEADER_SIZE: u32 = 13;
+/// Flags, topic length and payload length
+#[cfg(feature = "storage")]
+const ENTRY_HEADER_SIZE: u32 = 4;
+/// The length of the messages of a slot is stored in 16 bits
+#[cfg(feature = "storage")]
+const MAX_SLOT_SIZE: u32 = STORE_HEADER_SIZE + u16::MAX as u32;
+
+/// CRC-16/CCITT-FALSE
+#[cfg(feature = "storage")]
+fn crc16(mut crc: u16, data: &[u8]) -> u16 {
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+
+    crc
+}
+
+/// Header of a slot of the [StorageOutboxStore]
+#[cfg(feature = "storage")]
+struct SlotHeader {
+    generation: u32,
+    count: u8,
+    length: u16,
+    checksum: u16,
+}
+
+#[cfg(feature = "storage")]
+impl SlotHeader {
+    /// Fields covered by the checksum
+    fn fields(&self) -> [u8; 7] {
+        let mut fields = [0; 7];
+        fields[..4].copy_from_slice(&self.generation.to_le_bytes());
+        fields[4] = self.count;
+        fields[5..].copy_from_slice(&self.length.to_le_bytes());
+        fields
+    }
+
+    fn encode(&self) -> [u8; STORE_HEADER_SIZE as usize] {
+        let mut header = [0; STORE_HEADER_SIZE as usize];
+        header[..4].copy_from_slice(STORE_MAGIC);
+        header[4..11].copy_from_slice(&self.fields());
+        header[11..].copy_from_slice(&self.checksum.to_le_bytes());
+        header
+    }
+
+    /// Returns None if the slot has never been written, e.g. an erased flash
+    fn decode(header: &[u8; STORE_HEADER_SIZE as usize]) -> Option<Self> {
+        if &header[..4] != STORE_MAGIC {
+            return None;
+        }
+
+        Some(Self {
+            generation: u32::from_le_bytes([header[4], header[5], header[6], header[7]]),
+            count: header[8],
+            length: u16::from_le_bytes([header[9], header[10]]),
+            checksum: u16::from_le_bytes([header[11], header[12]]),
+        })
+    }
+}
+
+#[cfg(feature = "storage")]
+impl<S: embedded_storage::Storage> StorageOutboxStore<S> {
+    /// Uses `size` bytes of the storage from `offset`, split in two slots. Fails with
+    /// [StorageOutboxError::InvalidRegion] if the region does not fit in the storage or a slot
+    /// can not hold its header or is larger than the length it can store
+    pub fn new(storage: S, offset: u32, size: u32) -> Result<Self, StorageOutboxError<S::Error>> {
+        let slot_size = size / 2;
+        let end = offset as u64 + size as u64;
+        if !(STORE_HEADER_SIZE..=MAX_SLOT_SIZE).contains(&slot_size)
+            || end > storage.capacity() as u64
+        {
+            return Err(StorageOutboxError::InvalidRegion);
+        }
+
+        Ok(Self {
+            storage,
+            offset,
+            size,
+            latest: None,
+        })
+    }
+
+    /// Returns the storage
+    pub fn release(self) -> S {
+        self.storage
+    }
+
+    fn slot_size(&self) -> u32 {
+        self.size / 2
+    }
+
+    fn write(
+        &mut self,
+        slot: u32,
+        position: &mut u32,
+        data: &[u8],
+    ) -> Result<(), StorageOutboxError<S::Error>> {
+        let end = *position + data.len() as u32;
+        if end > self.slot_size() {
+            return Err(StorageOutboxError::Full);
+        }
+        self.storage
+            .write(self.offset + slot * self.slot_size() + *position, data)
+            .map_err(StorageOutboxError::Storage)?;
+        *position = end;
+
+        Ok(())
+    }
+
+    fn read(
+        &mut self,
+        slot: u32,
+        position: &mut u32,
+        data: &mut [u8],
+    ) -> Result<(), StorageOutboxError<S::Error>> {
+        let end = *position + data.len() as u32;
+        if end > self.slot_size() {
+            return Err(StorageOutboxError::Corrupted);
+        }
+        self.storage
+            .read(self.offset + slot * self.slot_size() + *position, data)
+            .map_err(StorageOutboxError::Storage)?;
+        *position = end;
+
+        Ok(())
+    }
+
+    /// Reads the header of the slot, returning None if it has not been completely written
+    fn read_valid_header(
+        &mut self,
+        slot: u32,
+    ) -> Result<Option<SlotHeader>, StorageOutboxError<S::Error>> {
+        let mut header = [0; STORE_HEADER_SIZE as usize];
+        self.read(slot, &mut 0, &mut header)?;
+        let Some(header) = SlotHeader::decode(&header) else {
+            return Ok(None);
+        };
+        if header.length as u32 > self.slot_size().saturating_sub(STORE_HEADER_SIZE) {
+            return Ok(None);
+        }
+
+        // The checksum covers the messages and then the header fields
+        let mut checksum = 0xffff;
+        let mut position = STORE_HEADER_SIZE;
+        let end = STORE_HEADER_SIZE + header.length as u32;
+        let mut chunk = [0; 32];
+        while position < end {
+            let chunk = &mut chunk[..(end - position).min(32) as usize];
+            self.read(slot, &mut position, chunk)?;
+            checksum = crc16(checksum, chunk);
+        }
+        checksum = crc16(checksum, &header.fields());
+
+        Ok((checksum == header.checksum).then_some(header))
+    }
+
+    /// Returns the slot and the header of the latest complete save
+    fn latest(&mut self) -> Result<Option<(u32, SlotHeader)>, StorageOutboxError<S::Error>> {
+        let mut latest: Option<(u32, SlotHeader)> = None;
+        for slot in 0..2 {
+            if let Some(header) = self.read_valid_header(slot)? {
+                if latest
+                    .as_ref()
+                    .is_none_or(|(_, latest)| header.generation > latest.generation)
+                {
+                    latest = Some((slot, header));
+                }
+            }
+        }
+        self.latest = Some(
+            latest
+                .as_ref()
+                .map(|(slot, header)| (*slot, header.generation)),
+        );
+
+        Ok(latest)
+    }
+}
+
+#[cfg(feature = "storage")]
+impl<S: embedded_storage::Storage> OutboxStore for StorageOutboxStore<S> {
+    type Error = StorageOutboxError<S::Error>;
+
+    fn save<'m>(
+        &mut self,
+        messages: impl Iterator<Item = &'m OutboxMessage>,
+    ) -> Result<(), Self::Error> {
+        let latest = match self.latest {
+            Some(latest) => latest,
+            None => self
+                .latest()?
+                .map(|(slot, header)| (slot, header.generation)),
+        };
+        // The slot of the latest save is kept until the new one is complete
+        let (slot, generation) = match latest {
+            Some((slot, generation)) => (1 - slot, generation.wrapping_add(1)),
+            None => (0, 0),
+        };
+
+        // The header is written last and its checksum covers the messages, so the slot is only
+        // loaded once it has been completely written
+        let mut position = STORE_HEADER_SIZE;
+        let mut count: u8 = 0;
+        let mut checksum = 0xffff;
+        for message in messages {
+            let flags = message.qos | (message.retained as u8) << 2;
+            let payload_length = (message.payload.len() as u16).to_le_bytes();
+            let entry = [
+                flags,
+                message.topic.len() as u8,
+                payload_length[0],
+                payload_length[1],
+            ];
+            for data in [&entry, message.topic.as_bytes(), &message.payload] {
+                self.write(slot, &mut position, data)?;
+                checksum = crc16(checksum, data);
+            }
+            count = count.checked_add(1).ok_or(StorageOutboxError::Full)?;
+        }
+
+        let mut header = SlotHeader {
+            generation,
+            count,
+            length: (position - STORE_HEADER_SIZE) as u16,
+            checksum: 0,
+        };
+        header.checksum = crc16(checksum, &header.fields());
+        self.write(slot, &mut 0, &header.encode())?;
+        self.latest = Some(Some((slot, generation)));
+
+        Ok(())
+    }
+
+    fn load(&mut self, mut push: impl FnMut(OutboxMessage)) -> Result<(), Self::Error> {
+        let Some((slot, header)) = self.latest()? else {
+            return Ok(());
+        };
+
+        let mut position = STORE_HEADER_SIZE;
+        for _ in 0..header.count {
+            let mut entry = [0; ENTRY_HEADER_SIZE as usize];
+            self.read(slot, &mut position, &mut entry)?;
+            let payload_length = u16::from_le_bytes([entry[2], entry[3]]) as usize;
+
+            let mut topic = [0; MAX_TOPIC_SIZE];
+            let topic = topic
+                .get_mut(..entry[1] as usize)
+                .ok_or(StorageOutboxError::Corrupted)?;
+            self.read(slot, &mut position, topic)?;
+            let mut payload = [0; OUTBOX_PAYLOAD_SIZE];
+            let payload = payload
+                .get_mut(..payload_length)
+                .ok_or(StorageOutboxError::Corrupted)?;
+            self.read(slot, &mut position, payload)?;
+
+            push(OutboxMessage {
+                topic: core::str::from_utf8(topic)
+                    .ok()
+                    .and_then(|topic| topic.try_into().ok())
+                    .ok_or(StorageOutboxError::Corrupted)?,
+                qos: entry[0] & 0b11,
+                retained: entry[0] & 0b100 != 0,
+                // It fits, the length has been checked
+                payload: heapless::Vec::from_slice(payload)
+                    .map_err(|_| StorageOutboxError::Corrupted)?,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::at_command::mqtt::MQTTSessionSettings;
+    use crate::emulator::Sim7020Emulator;
+    use crate::testing::{registered_modem, test_connection_settings};
+
+    fn message(payload: &[u8]) -> MQTTMessage<'_> {
+        MQTTMessage {
+            topic: "sensors/temp",
+            qos: 1,
+            retained: false,
+            dup: false,
+            message: payload,
+        }
+    }
+
+    #[test]
+    fn test_outbox_keeps_the_queued_messages_when_full() {
+        let mut outbox: MqttOutbox<NoPersistence, 2> = MqttOutbox::new();
+        for payload in [b"1", b"2"] {
+            outbox.enqueue(&message(payload)).unwrap();
+        }
+        assert_eq!(outbox.enqueue(&message(b"3")), Err(OutboxError::Full));
+
+        assert!(outbox
+            .iter()
+            .map(|message| message.payload.as_slice())
+            .eq([b"1", b"2"]));
+        assert_eq!(
+            outbox.enqueue(&MQTTMessage {
+                qos: 3,
+                ..message(b"4")
+            }),
+            Err(OutboxError::InvalidQoS)
+        );
+
+        assert_eq!(outbox.front().unwrap().payload.as_slice(), b"1");
+        outbox.confirm().unwrap();
+        assert_eq!(outbox.len(), 1);
+        outbox.enqueue(&message(b"3")).unwrap();
+        assert!(outbox
+            .iter()
+            .map(|message| message.payload.as_slice())
+            .eq([b"2", b"3"]));
+    }
+
+    #[test]
+    fn test_outbox_keeps_the_messages_until_they_are_published() {
+        let emulator = Sim7020Emulator::new();
+        let (mut writer, mut reader) = emulator.split();
+        let mut modem = registered_modem(&mut writer, &mut reader);
+        let settings = MQTTSessionSettings::new("broker.example.com", 1883);
+        let mut outbox: MqttOutbox = MqttOutbox::new();
+
+        // Not connected yet
+        let mqtt = Mqtt::new(&settings);
+        assert_eq!(
+            outbox.publish(&message(b"1"), &mqtt, &mut modem),
+            Err(OutboxError::Publish(MQTTError::Disconnected))
+        );
+        assert_eq!(
+            outbox.publish(&message(b"2"), &mqtt, &mut modem),
+            Err(OutboxError::Publish(MQTTError::Disconnected))
+        );
+        assert_eq!(outbox.len(), 2);
+
+        let mut mqtt = mqtt
+            .create_session(&mut modem)
+            .unwrap()
+            .connect(test_connection_settings(), &mut modem)
+            .unwrap();
+        mqtt.subscribe("sensors/+", 1, &mut modem).unwrap();
+
+        assert_eq!(outbox.publish(&message(b"3"), &mqtt, &mut modem), Ok(3));
+        assert!(outbox.is_empty());
+        for payload in [b"1", b"2", b"3"] {
+            let received = mqtt.try_receive(&mut modem).unwrap().unwrap();
+            assert_eq!(received.payload.as_slice(), payload);
+        }
+
+        // The module rejects the message, which stays in the outbox
+        emulator.set_network_available(false);
+        assert!(outbox.flush(&mqtt, &mut modem).is_ok());
+        assert_eq!(
+            outbox.publish(&message(b"4"), &mqtt, &mut modem),
+            Err(OutboxError::Publish(MQTTError::Publish))
+        );
+        assert_eq!(outbox.len(), 1);
+    }
+
+    #[cfg(feature = "storage")]
+    mod storage {
+        use super::*;
+        use embedded_storage::{ReadStorage, Storage};
+
+        /// Storage in RAM whose writes can be made to fail after a number of writes, writing only
+        /// half of the data of the failed write as on a power loss
+        #[derive(Clone)]
+        struct RamStorage {
+            data: [u8; 1024],
+            writes_before_failure: Option<usize>,
+        }
+
+        impl RamStorage {
+            fn new() -> Self {
+                Self {
+                    data: [0xff; 1024],
+                    writes_before_failure: None,
+                }
+            }
+        }
+
+        impl ReadStorage for RamStorage {
+            type Error = ();
+
+            fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+                let offset = offset as usize;
+                bytes.copy_from_slice(&self.data[offset..offset + bytes.len()]);
+                Ok(())
+            }
+
+            fn capacity(&self) -> usize {
+                self.data.len()
+            }
+        }
+
+        impl Storage for RamStorage {
+            fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+                let offset = offset as usize;
+                match self.writes_before_failure {
+                    Some(0) => {
+                        let half = bytes.len() / 2;
+                        self.data[offset..offset + half].copy_from_slice(&bytes[..half]);
+                        Err(())
+                    }
+                    writes => {
+                        self.writes_before_failure = writes.map(|writes| writes - 1);
+                        self.data[offset..offset + bytes.len()].copy_from_slice(bytes);
+                        Ok(())
+                    }
+                }
+            }
+        }
+
+        #[test]
+        fn test_outbox_is_restored_from_the_storage() {
+            let store = StorageOutboxStore::new(RamStorage::new(), 16, 512).unwrap();
+            let mut outbox: MqttOutbox<_> = MqttOutbox::with_store(store).unwrap();
+            assert!(outbox.is_empty());
+
+            outbox.enqueue(&message(b"21.5")).unwrap();
+            outbox
+                .enqueue(&MQTTMessage {
+                    topic: "alarms",
+                    qos: 2,
+                    retained: true,
+                    dup: false,
+                    message: b"overheat",
+                })
+                .unwrap();
+            outbox.enqueue(&message(b"22.0")).unwrap();
+            outbox.confirm().unwrap();
+
+            let expected: heapless::Vec<OutboxMessage, 2> = outbox.iter().cloned().collect();
+            let storage = outbox.store.release();
+            let outbox: MqttOutbox<_> =
+                MqttOutbox::with_store(StorageOutboxStore::new(storage, 16, 512).unwrap()).unwrap();
+            let restored: heapless::Vec<OutboxMessage, 2> = outbox.iter().cloned().collect();
+            assert_eq!(restored, expected);
+            assert_eq!(restored[0].topic.as_str(), "alarms");
+            assert!(restored[0].retained);
+            assert_eq!(restored[0].qos, 2);
+
+            // The region is too small for the messages
+            let mut store = StorageOutboxStore::new(RamStorage::new(), 0, 32).unwrap();
+            assert_eq!(store.save(restored.iter()), Err(StorageOutboxError::Full));
+        }
+
+        #[test]
+        fn test_invalid_region() {
+            for (offset, size) in [(0, 1026), (1000, 32), (u32::MAX, 32), (0, 24)] {
+                assert!(
+                    matches!(
+                        StorageOutboxStore::new(RamStorage::new(), offset, size),
+                        Err(StorageOutboxError::InvalidRegion)
+                    ),
+                    "region at {offset} of {size} bytes"
+                );
+            }
+            assert!(StorageOutboxStore::new(RamStorage::new(), 0, 1024).is_ok());
+        }
+
+        #[test]
+        fn test_interrupted_save_keeps_the_previous_messages() {
+            let store = StorageOutboxStore::new(RamStorage::new(), 0, 512).unwrap();
+            let mut outbox: MqttOutbox<_> = MqttOutbox::with_store(store).unwrap();
+            outbox.enqueue(&message(b"21.5")).unwrap();
+            outbox.enqueue(&message(b"22.0")).unwrap();
+            let expected: heapless::Vec<OutboxMessage, 2> = outbox.iter().cloned().collect();
+            let storage = outbox.store.release();
+
+            // Interrupts the next save at each of its writes: 3 per message and the header
+            for writes in 0..10 {
+                let mut storage = storage.clone();
+                storage.writes_before_failure = Some(writes);
+                let mut outbox: MqttOutbox<_> =
+                    MqttOutbox::with_store(StorageOutboxStore::new(storage, 0, 512).unwrap())
+                        .unwrap();
+                assert_eq!(
+                    outbox.enqueue(&message(b"22.5")),
+                    Err(OutboxError::Store(StorageOutboxError::Storage(())))
+                );
+                assert_eq!(outbox.len(), 2);
+
+                let mut storage = outbox.store.release();
+                storage.writes_before_failure = None;
+                let outbox: MqttOutbox<_> =
+                    MqttOutbox::with_store(StorageOutboxStore::new(storage, 0, 512).unwrap())
+                        .unwrap();
+                let restored: heapless::Vec<OutboxMessage, 2> = outbox.iter().cloned().collect();
+                assert_eq!(restored, expected, "interrupted at write {writes}");
+            }
+
+            // Once complete, the new save replaces the previous one
+            let mut outbox: MqttOutbox<_> =
+                MqttOutbox::with_store(StorageOutboxStore::new(storage, 0, 512).unwrap()).unwrap();
+            outbox.enqueue(&message(b"22.5")).unwrap();
+            outbox.confirm().unwrap();
+            let storage = outbox.store.release();
+            let outbox: MqttOutbox<_> =
+                MqttOutbox::with_store(StorageOutboxStore::new(storage, 0, 512).unwrap()).unwrap();
+            assert!(outbox
+                .iter()
+                .map(|message| message.payload.as_slice())
+                .eq([b"22.0", b"22.5"]));
+        }
+    }
+}
@@ -0,0 +1,832 @@
+//! Sans-IO core shared by [crate::Modem] and [crate::nonblocking::AsyncModem].
+//!
+//! The framing of the responses, the detection of the terminators, the results awaited from the
+//! URC and the state kept between commands live here. The modems only move the bytes between
+//! these state machines and the serial port, so both front-ends behave the same way.
+
+use crate::at_command::at_cpin::PinStatus;
+use crate::at_command::cmee::parse_error_line;
+use crate::at_command::credentials::{
+    max_credential_chunk_size, CredentialType, SetCredentialChunk,
+};
+use crate::at_command::csclk::CSCLKMode;
+use crate::at_command::mqtt::{MQTTDataFormat, MQTTReceivedMessage};
+use crate::at_command::AtRequest;
+use crate::urc::{trim_line, HttpResponseHeader, UrcDispatcher};
+use crate::{AtError, ERROR_TERMINATOR, LF, OK_TERMINATOR, READ_CHUNK_SIZE, READ_POLL_INTERVAL_US};
+use core::net::IpAddr;
+#[cfg(feature = "defmt")]
+use defmt::*;
+
+/// According to the manual we need to send twice any AT command to wake up the module when it
+/// is configured in software mode
+pub(crate) const AT_COMMAND_TWICE: &[u8] = b"AT\r\nAT\r\n";
+
+/// Validates the request and builds its command into the buffer
+pub(crate) fn encode_command<'b, V: AtRequest>(
+    payload: &'b V,
+    buffer: &'b mut [u8],
+) -> Result<&'b [u8], AtError> {
+    payload.validate()?;
+    payload
+        .get_command(buffer)
+        .map_err(|_| AtError::CapacityError)
+}
+
+/// Time left to receive the response of a command
+pub(crate) struct Deadline {
+    remaining_us: u32,
+}
+
+impl Deadline {
+    pub(crate) fn new(timeout_ms: u32) -> Self {
+        Self {
+            remaining_us: timeout_ms.saturating_mul(1000),
+        }
+    }
+
+    /// Returns the time in microseconds to wait before polling the reader again, failing with
+    /// [AtError::Timeout] once the time is exhausted
+    pub(crate) fn next_wait(&mut self) -> Result<u32, AtError> {
+        if self.remaining_us == 0 {
+            #[cfg(feature = "defmt")]
+            warn!("Timeout waiting for the response of the module");
+            return Err(AtError::Timeout);
+        }
+        let interval = READ_POLL_INTERVAL_US.min(self.remaining_us);
+        self.remaining_us -= interval;
+
+        Ok(interval)
+    }
+}
+
+/// Chunk of bytes read from the module that has not been processed yet. The bytes received
+/// after the final result code of a response are kept here for the next read
+pub(crate) struct ReadBuffer {
+    data: [u8; READ_CHUNK_SIZE],
+    start: usize,
+    end: usize,
+}
+
+impl ReadBuffer {
+    pub(crate) const fn new() -> Self {
+        Self {
+            data: [0; READ_CHUNK_SIZE],
+            start: 0,
+            end: 0,
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// Returns the space where the next chunk is read, discarding what is left of the current one
+    pub(crate) fn space(&mut self) -> &mut [u8] {
+        core::debug_assert!(self.is_empty());
+        &mut self.data
+    }
+
+    /// Sets the number of bytes read into the [ReadBuffer::space]
+    pub(crate) fn filled(&mut self, size: usize) {
+        self.start = 0;
+        self.end = size.min(READ_CHUNK_SIZE);
+    }
+
+    /// Takes the next byte that has not been processed
+    pub(crate) fn next_byte(&mut self) -> Option<u8> {
+        if self.is_empty() {
+            return None;
+        }
+        let byte = self.data[self.start];
+        self.start += 1;
+        Some(byte)
+    }
+}
+
+/// Collects the lines received while no response is expected, dispatching the unsolicited
+/// result codes and discarding any other line
+pub(crate) struct PendingLines<'b> {
+    line: &'b mut [u8],
+    len: usize,
+    /// Indicates that the rest of the current line, an URC too long for the buffer, is discarded
+    skipping: bool,
+}
+
+impl<'b> PendingLines<'b> {
+    pub(crate) fn new(line: &'b mut [u8]) -> Self {
+        Self {
+            line,
+            len: 0,
+            skipping: false,
+        }
+    }
+
+    /// Indicates if a line has started and must be read until the end
+    pub(crate) fn in_line(&self) -> bool {
+        self.len > 0 || self.skipping
+    }
+
+    /// No command is waiting for a response, so the lines are classified without one. Otherwise
+    /// an URC that is also the response of the next command, e.g. `+CEREG`, would be discarded
+    pub(crate) fn push(&mut self, byte: u8, urcs: &mut UrcDispatcher) {
+        const NO_COMMAND: &[u8] = &[];
+
+        if self.skipping {
+            self.skipping = byte != LF;
+            return;
+        }
+        if self.len == self.line.len() {
+            #[cfg(feature = "defmt")]
+            warn!("Discarding pending line that does not fit the buffer");
+            self.skipping = urcs.process_long_line(self.line, NO_COMMAND);
+            self.len = 0;
+            if self.skipping {
+                self.skipping = byte != LF;
+                return;
+            }
+        }
+        self.line[self.len] = byte;
+        self.len += 1;
+
+        if byte == LF {
+            if !urcs.process_line(&self.line[..self.len], NO_COMMAND) {
+                #[cfg(feature = "defmt")]
+                debug!("Discarding pending line: {=[u8]:a}", self.line[..self.len]);
+            }
+            self.len = 0;
+        }
+    }
+}
+
+/// Frames the response of a command into a buffer until the OK or ERROR terminators are found.
+/// The unsolicited result codes received meanwhile are removed from the response
+pub(crate) struct ResponseFramer<'b> {
+    response: &'b mut [u8],
+    offset: usize,
+    line_start: usize,
+    overflowed: bool,
+    /// Indicates that the rest of the current line, an URC too long for the buffer, is discarded
+    skipping: bool,
+}
+
+impl<'b> ResponseFramer<'b> {
+    pub(crate) fn new(response: &'b mut [u8]) -> Self {
+        Self {
+            response,
+            offset: 0,
+            line_start: 0,
+            overflowed: false,
+            skipping: false,
+        }
+    }
+
+    /// Processes the next received byte. Returns the size of the response once it is complete
+    pub(crate) fn push(
+        &mut self,
+        byte: u8,
+        urcs: &mut UrcDispatcher,
+        command: &[u8],
+    ) -> Result<Option<usize>, AtError> {
+        if self.skipping {
+            self.skipping = byte != LF;
+            return Ok(None);
+        }
+        if self.offset == self.response.len() {
+            let line = &self.response[self.line_start..self.offset];
+            if urcs.process_long_line(line, command) {
+                // The URC is not part of the response
+                self.offset = self.line_start;
+                self.skipping = byte != LF;
+                return Ok(None);
+            }
+            #[cfg(feature = "defmt")]
+            warn!("The response does not fit the buffer, discarding it");
+            self.overflowed = true;
+            (self.offset, self.line_start) =
+                discard_overflowed_response(self.response, self.offset, self.line_start);
+        }
+        self.response[self.offset] = byte;
+        self.offset += 1;
+
+        if byte == LF {
+            let line = &self.response[self.line_start..self.offset];
+            if urcs.process_line(line, command) {
+                // The URC is not part of the response
+                self.offset = self.line_start;
+                return Ok(None);
+            }
+            if let Some(error) = parse_error_line(line) {
+                #[cfg(feature = "defmt")]
+                error!("received error response: {}", error);
+                return Err(error);
+            }
+            self.line_start = self.offset;
+        }
+
+        let received = &self.response[..self.offset];
+        if received.ends_with(OK_TERMINATOR) {
+            if self.overflowed {
+                return Err(AtError::ResponseTooLong);
+            }
+            #[cfg(feature = "defmt")]
+            trace!("OK terminated: {=[u8]:a}", received);
+            return Ok(Some(self.offset - 1));
+        }
+        if received.ends_with(ERROR_TERMINATOR) {
+            #[cfg(feature = "defmt")]
+            error!("received ERROR response: {=[u8]:a}", received);
+            return Err(AtError::ErrorReply(self.offset - 1));
+        }
+
+        Ok(None)
+    }
+}
+
+/// Frees space in a full response buffer keeping the current line, or at least the bytes
+/// needed to detect the terminators. Returns the new offset and line start
+fn discard_overflowed_response(
+    response: &mut [u8],
+    offset: usize,
+    line_start: usize,
+) -> (usize, usize) {
+    // At least one byte is discarded so there is space for the next one
+    let tail_start = offset.saturating_sub(OK_TERMINATOR.len() - 1).max(1);
+    let keep_from = if line_start > 0 {
+        line_start.min(tail_start)
+    } else {
+        tail_start
+    };
+    response.copy_within(keep_from..offset, 0);
+
+    (offset - keep_from, line_start.saturating_sub(keep_from))
+}
+
+/// Action requested by the [StreamFramer]
+pub(crate) enum StreamStep<'a> {
+    /// Nothing to do, keep reading
+    Continue,
+    /// The bytes are part of the response and must be written to the sink
+    Write(&'a [u8]),
+    /// The response is complete, containing the given number of bytes
+    Finished(usize),
+}
+
+/// Frames the response of a command line by line so it can be written to a sink. The lines
+/// that fit the buffer are checked for unsolicited result codes and terminators, longer lines
+/// can only be part of the response
+pub(crate) struct StreamFramer<'b> {
+    line: &'b mut [u8],
+    len: usize,
+    /// Indicates that the beginning of the current line was already written to the sink
+    streamed: bool,
+    /// Indicates that the rest of the current line, an URC too long for the buffer, is discarded
+    skipping: bool,
+    written: usize,
+}
+
+impl<'b> StreamFramer<'b> {
+    pub(crate) fn new(line: &'b mut [u8]) -> Self {
+        Self {
+            line,
+            len: 0,
+            streamed: false,
+            skipping: false,
+            written: 0,
+        }
+    }
+
+    pub(crate) fn push(
+        &mut self,
+        byte: u8,
+        urcs: &mut UrcDispatcher,
+        command: &[u8],
+    ) -> Result<StreamStep<'_>, AtError> {
+        if self.skipping {
+            self.skipping = byte != LF;
+            return Ok(StreamStep::Continue);
+        }
+        self.line[self.len] = byte;
+        self.len += 1;
+
+        if byte != LF {
+            if self.len < self.line.len() {
+                return Ok(StreamStep::Continue);
+            }
+            let len = core::mem::take(&mut self.len);
+            if !self.streamed && urcs.process_long_line(&self.line[..len], command) {
+                self.skipping = true;
+                return Ok(StreamStep::Continue);
+            }
+            // Otherwise a line longer than the buffer can only be part of the response
+            self.written += len;
+            self.streamed = true;
+            return Ok(StreamStep::Write(&self.line[..len]));
+        }
+
+        let len = core::mem::take(&mut self.len);
+        if !core::mem::take(&mut self.streamed) {
+            let line = &self.line[..len];
+            if urcs.process_line(line, command) {
+                return Ok(StreamStep::Continue);
+            }
+            if let Some(error) = parse_error_line(line) {
+                return Err(error);
+            }
+            match trim_line(line) {
+                b"OK" => return Ok(StreamStep::Finished(self.written)),
+                b"ERROR" => return Err(AtError::ErrorReply(self.written)),
+                _ => {}
+            }
+        }
+
+        self.written += len;
+        Ok(StreamStep::Write(&self.line[..len]))
+    }
+}
+
+/// A result that arrives with the unsolicited result codes. The modems poll the URC and call
+/// [UrcWait::poll] until it returns the result or the deadline is exhausted
+pub(crate) trait UrcWait {
+    type Output;
+
+    /// Takes the result from the URC received so far, None if it has not arrived yet
+    fn poll(&mut self, urcs: &mut UrcDispatcher) -> Result<Option<Self::Output>, AtError>;
+}
+
+/// Data received in a socket, copied into [SocketData::buf]. An empty buffer is filled at once
+pub(crate) struct SocketData<'b> {
+    pub(crate) socket_id: u8,
+    pub(crate) buf: &'b mut [u8],
+}
+
+impl UrcWait for SocketData<'_> {
+    type Output = usize;
+
+    fn poll(&mut self, urcs: &mut UrcDispatcher) -> Result<Option<usize>, AtError> {
+        let size = urcs.read_socket_data(self.socket_id, self.buf)?;
+        Ok((size > 0 || self.buf.is_empty()).then_some(size))
+    }
+}
+
+/// Oldest datagram received in a UDP socket, copied into [Datagram::buf]. The bytes that do not
+/// fit are discarded
+pub(crate) struct Datagram<'b> {
+    pub(crate) socket_id: u8,
+    pub(crate) buf: &'b mut [u8],
+}
+
+impl UrcWait for Datagram<'_> {
+    type Output = usize;
+
+    fn poll(&mut self, urcs: &mut UrcDispatcher) -> Result<Option<usize>, AtError> {
+        urcs.read_socket_datagram(self.socket_id, self.buf)
+    }
+}
+
+/// Data received in a TLS connection, copied into [TlsData::buf]. An empty buffer is filled at
+/// once
+pub(crate) struct TlsData<'b> {
+    pub(crate) tls_id: u8,
+    pub(crate) buf: &'b mut [u8],
+}
+
+impl UrcWait for TlsData<'_> {
+    type Output = usize;
+
+    fn poll(&mut self, urcs: &mut UrcDispatcher) -> Result<Option<usize>, AtError> {
+        let size = urcs.read_tls_data(self.tls_id, self.buf)?;
+        Ok((size > 0 || self.buf.is_empty()).then_some(size))
+    }
+}
+
+/// Status code and headers of the response received by an HTTP client. Fails with
+/// [AtError::CapacityError] if they have been dropped because they did not fit
+pub(crate) struct HttpHeader {
+    pub(crate) client_id: u8,
+}
+
+impl UrcWait for HttpHeader {
+    type Output = HttpResponseHeader;
+
+    fn poll(&mut self, urcs: &mut UrcDispatcher) -> Result<Option<HttpResponseHeader>, AtError> {
+        urcs.take_http_header(self.client_id)
+    }
+}
+
+/// Next part of the body of the response received by an HTTP client, copied into
+/// [HttpBodyChunk::buf]. Returns the copied bytes and whether the body is complete
+pub(crate) struct HttpBodyChunk<'b> {
+    pub(crate) client_id: u8,
+    pub(crate) buf: &'b mut [u8],
+}
+
+impl UrcWait for HttpBodyChunk<'_> {
+    type Output = (usize, bool);
+
+    fn poll(&mut self, urcs: &mut UrcDispatcher) -> Result<Option<(usize, bool)>, AtError> {
+        let (size, complete) = urcs.read_http_body(self.client_id, self.buf)?;
+        Ok((size > 0 || complete).then_some((size, complete)))
+    }
+}
+
+/// Message received by an MQTT session in the subscribed topics
+pub(crate) struct MqttMessage {
+    pub(crate) mqtt_id: u8,
+    pub(crate) data_format: MQTTDataFormat,
+}
+
+impl UrcWait for MqttMessage {
+    type Output = MQTTReceivedMessage;
+
+    fn poll(&mut self, urcs: &mut UrcDispatcher) -> Result<Option<MQTTReceivedMessage>, AtError> {
+        urcs.take_mqtt_publication(self.mqtt_id)?
+            .map(|publication| MQTTReceivedMessage::decode(publication, self.data_format))
+            .transpose()
+    }
+}
+
+/// Message received by an MQTT session, or None once the session has been disconnected and all
+/// its received messages have been read
+#[cfg(feature = "nonblocking")]
+pub(crate) struct NextMqttMessage(pub(crate) MqttMessage);
+
+#[cfg(feature = "nonblocking")]
+impl UrcWait for NextMqttMessage {
+    type Output = Option<MQTTReceivedMessage>;
+
+    fn poll(
+        &mut self,
+        urcs: &mut UrcDispatcher,
+    ) -> Result<Option<Option<MQTTReceivedMessage>>, AtError> {
+        if let Some(message) = self.0.poll(urcs)? {
+            return Ok(Some(Some(message)));
+        }
+        Ok(urcs.take_mqtt_disconnection(self.0.mqtt_id).then_some(None))
+    }
+}
+
+/// Address resolved by the module after a [GetHostByName](crate::at_command::dns::GetHostByName)
+pub(crate) struct HostResolution;
+
+impl HostResolution {
+    /// Discards the result of a previous resolution that was not consumed. Must be called
+    /// before sending the request
+    pub(crate) fn start(urcs: &mut UrcDispatcher) -> Self {
+        urcs.take_dns_resolution();
+        HostResolution
+    }
+}
+
+impl UrcWait for HostResolution {
+    type Output = IpAddr;
+
+    fn poll(&mut self, urcs: &mut UrcDispatcher) -> Result<Option<IpAddr>, AtError> {
+        urcs.take_dns_resolution()
+            .map(|resolution| resolution.address())
+            .transpose()
+    }
+}
+
+/// Discards the state kept for an MQTT session that has been closed
+pub(crate) fn forget_mqtt_session(urcs: &mut UrcDispatcher, mqtt_id: u8) {
+    urcs.clear_mqtt_publications(mqtt_id);
+    urcs.take_mqtt_disconnection(mqtt_id);
+}
+
+/// Splits the credential in the chunks that fit in a command built in a buffer of
+/// [buffer_size] bytes
+pub(crate) fn credential_chunks(
+    kind: CredentialType,
+    data: &[u8],
+    buffer_size: usize,
+) -> Result<impl Iterator<Item = SetCredentialChunk<'_>>, AtError> {
+    let total_length = u16::try_from(data.len()).map_err(|_| AtError::CapacityError)?;
+    let chunk_size = max_credential_chunk_size(buffer_size);
+    if data.is_empty() || chunk_size == 0 {
+        return Err(AtError::InvalidParameter);
+    }
+
+    let chunks = data.len().div_ceil(chunk_size);
+    Ok(data
+        .chunks(chunk_size)
+        .enumerate()
+        .map(move |(index, chunk)| SetCredentialChunk {
+            kind,
+            total_length,
+            last: index + 1 == chunks,
+            data: chunk,
+        }))
+}
+
+/// Checks that the module can be put to sleep with the DTR pin
+pub(crate) fn check_can_sleep(mode: CSCLKMode) -> Result<(), AtError> {
+    if mode != CSCLKMode::HardwareControlled {
+        #[cfg(feature = "defmt")]
+        warn!("The sleep mode has not been enabled");
+
+        return Err(AtError::IllegalModuleState);
+    }
+
+    Ok(())
+}
+
+/// How to wake up the module
+pub(crate) enum WakeUp {
+    /// The sleep mode is not enabled
+    Nothing,
+    /// Send [AT_COMMAND_TWICE]
+    SendCommands,
+    /// Pull off the DTR pin
+    ReleaseDtr,
+}
+
+impl From<CSCLKMode> for WakeUp {
+    fn from(mode: CSCLKMode) -> Self {
+        match mode {
+            CSCLKMode::Disabled => WakeUp::Nothing,
+            CSCLKMode::SoftwareControlled => WakeUp::SendCommands,
+            CSCLKMode::HardwareControlled => WakeUp::ReleaseDtr,
+        }
+    }
+}
+
+/// Default max unlock tries
+pub(crate) const MAX_UNLOCK_TRIES: usize = 1;
+
+/// Next step to unlock the SIM
+#[derive(Debug, PartialEq)]
+pub(crate) enum UnlockStep {
+    /// The SIM is ready to be used
+    Unlocked,
+    /// The PIN must be entered
+    EnterPin,
+}
+
+/// Decides the next step to unlock the SIM given its current status and the tries done
+pub(crate) fn next_unlock_step(
+    status: PinStatus,
+    tries: usize,
+    max_tries: usize,
+) -> Result<UnlockStep, AtError> {
+    match status {
+        PinStatus::Ready => {
+            #[cfg(feature = "defmt")]
+            info!("SIM is already unlocked returning");
+            Ok(UnlockStep::Unlocked)
+        }
+        // If we already do the maximum unlock tries return an error with the current status
+        PinStatus::SimPin if tries >= max_tries => Err(AtError::IllegalPinStatus(status)),
+        PinStatus::SimPin => {
+            #[cfg(feature = "defmt")]
+            debug!("SIM pin is required");
+            Ok(UnlockStep::EnterPin)
+        }
+        // This is a state the method can not handle
+        status => {
+            #[cfg(feature = "defmt")]
+            warn!("SIM status can no be handled. {}", status);
+            Err(AtError::IllegalPinStatus(status))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_deadline() {
+        let mut deadline = Deadline::new(0);
+        assert!(matches!(deadline.next_wait(), Err(AtError::Timeout)));
+
+        let mut deadline = Deadline::new(1);
+        let mut waited = 0;
+        while let Ok(interval) = deadline.next_wait() {
+            waited += interval;
+        }
+        assert_eq!(waited, 1000);
+    }
+
+    #[test]
+    fn test_read_buffer_keeps_the_unprocessed_bytes() {
+        let mut input = ReadBuffer::new();
+        assert!(input.is_empty());
+        assert_eq!(input.next_byte(), None);
+
+        input.space()[..3].copy_from_slice(b"OK\r");
+        input.filled(3);
+        assert_eq!(input.next_byte(), Some(b'O'));
+        assert!(!input.is_empty());
+        assert_eq!(input.next_byte(), Some(b'K'));
+        assert_eq!(input.next_byte(), Some(b'\r'));
+        assert!(input.is_empty());
+        assert_eq!(input.next_byte(), None);
+    }
+
+    #[test]
+    fn test_discard_overflowed_response() {
+        let mut response = *b"\r\n0123456789";
+        let (offset, line_start) = discard_overflowed_response(&mut response, 12, 2);
+        assert_eq!((offset, line_start), (10, 0));
+        assert_eq!(&response[..offset], b"0123456789");
+
+        let mut response = *b"0123456789";
+        let (offset, line_start) = discard_overflowed_response(&mut response, 10, 0);
+        assert_eq!((offset, line_start), (5, 0));
+        assert_eq!(&response[..offset], b"56789");
+    }
+
+    fn frame(framer: &mut ResponseFramer, data: &[u8]) -> Result<Option<usize>, AtError> {
+        let mut urcs = UrcDispatcher::new();
+        for &byte in data {
+            if let Some(size) = framer.push(byte, &mut urcs, b"AT+CSQ\r\n")? {
+                return Ok(Some(size));
+            }
+        }
+        Ok(None)
+    }
+
+    #[test]
+    fn test_response_framer() {
+        let mut buffer = [0; 64];
+        let mut framer = ResponseFramer::new(&mut buffer);
+        let size = frame(
+            &mut framer,
+            b"\r\n+CSQ: 20,0\r\n\r\n+CPSMSTATUS: \"EXIT PSM\"\r\n\r\nOK\r\n",
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(&buffer[..size], b"\r\n+CSQ: 20,0\r\n\r\n\r\nOK\r");
+    }
+
+    #[test]
+    fn test_response_framer_errors() {
+        let mut buffer = [0; 64];
+        let mut framer = ResponseFramer::new(&mut buffer);
+        assert!(matches!(
+            frame(&mut framer, b"\r\nERROR\r\n"),
+            Err(AtError::ErrorReply(_))
+        ));
+
+        let mut framer = ResponseFramer::new(&mut buffer);
+        assert!(matches!(
+            frame(&mut framer, b"\r\n+CME ERROR: 3\r\n"),
+            Err(AtError::MobileEquipmentError(_))
+        ));
+
+        let mut buffer = [0; 8];
+        let mut framer = ResponseFramer::new(&mut buffer);
+        assert!(matches!(
+            frame(&mut framer, b"\r\n+CSQ: 20,0\r\n\r\nOK\r\n"),
+            Err(AtError::ResponseTooLong)
+        ));
+    }
+
+    #[test]
+    fn test_framers_report_urc_longer_than_the_buffer() {
+        let mut urcs = UrcDispatcher::new();
+        let mut buffer = [0; 32];
+        let mut framer = ResponseFramer::new(&mut buffer);
+        let mut size = None;
+        for &byte in b"\r\n+CSQ: 20,0\r\n+CSONMI: 1,64,41414141414141414141414141414141\
+            41414141414141414141414141414141\r\n\r\nOK\r\n"
+        {
+            size = size.or(framer.push(byte, &mut urcs, b"AT+CSQ\r\n").unwrap());
+        }
+        assert_eq!(&buffer[..size.unwrap()], b"\r\n+CSQ: 20,0\r\n\r\nOK\r");
+        let mut data = [0; 8];
+        assert!(matches!(
+            urcs.read_socket_data(1, &mut data),
+            Err(AtError::CapacityError)
+        ));
+
+        let mut framer = PendingLines::new(&mut buffer);
+        for &byte in b"+CTLSRECV: 2,64,41414141414141414141414141414141\
+            41414141414141414141414141414141\r\n+CSONMI: 1,2,5A\r\n"
+        {
+            framer.push(byte, &mut urcs);
+        }
+        assert!(!framer.in_line());
+        assert!(matches!(
+            urcs.read_tls_data(2, &mut data),
+            Err(AtError::CapacityError)
+        ));
+        assert_eq!(urcs.read_socket_data(1, &mut data).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_stream_framer() {
+        let mut urcs = UrcDispatcher::new();
+        let mut buffer = [0; 8];
+        let mut framer = StreamFramer::new(&mut buffer);
+        let mut output = std::vec::Vec::new();
+        let mut finished = None;
+
+        for &byte in b"\r\nLONG RESPONSE\r\n\r\nOK\r\n" {
+            match framer.push(byte, &mut urcs, b"AT\r\n").unwrap() {
+                StreamStep::Continue => {}
+                StreamStep::Write(data) => output.extend_from_slice(data),
+                StreamStep::Finished(written) => finished = Some(written),
+            }
+        }
+
+        assert_eq!(output, b"\r\nLONG RESPONSE\r\n\r\n");
+        assert_eq!(finished, Some(output.len()));
+    }
+
+    #[test]
+    fn test_waiters_return_once_the_result_has_arrived() {
+        let mut urcs = UrcDispatcher::new();
+        let mut buf = [0; 4];
+        let mut waiter = SocketData {
+            socket_id: 1,
+            buf: &mut buf,
+        };
+        assert_eq!(waiter.poll(&mut urcs).unwrap(), None);
+        urcs.process_line(b"+CSONMI: 1,2,5A\r\n", b"");
+        assert_eq!(waiter.poll(&mut urcs).unwrap(), Some(1));
+        let mut empty = SocketData {
+            socket_id: 1,
+            buf: &mut [],
+        };
+        assert_eq!(empty.poll(&mut urcs).unwrap(), Some(0));
+
+        let mut header = HttpHeader { client_id: 0 };
+        assert!(header.poll(&mut urcs).unwrap().is_none());
+        urcs.process_line(b"+CHTTPNMIH: 0,200,0,\r\n", b"");
+        assert_eq!(header.poll(&mut urcs).unwrap().unwrap().status_code, 200);
+        let mut body = HttpBodyChunk {
+            client_id: 0,
+            buf: &mut buf,
+        };
+        assert_eq!(body.poll(&mut urcs).unwrap(), None);
+        urcs.process_line(b"+CHTTPNMIC: 0,0,2,2,2,4142\r\n", b"");
+        assert_eq!(body.poll(&mut urcs).unwrap(), Some((2, true)));
+    }
+
+    #[cfg(feature = "nonblocking")]
+    #[test]
+    fn test_next_mqtt_message_ends_with_the_disconnection() {
+        let mut urcs = UrcDispatcher::new();
+        let mut waiter = NextMqttMessage(MqttMessage {
+            mqtt_id: 0,
+            data_format: MQTTDataFormat::Hex,
+        });
+        assert!(waiter.poll(&mut urcs).unwrap().is_none());
+        urcs.process_line(b"+CMQPUB: 0,\"a\",0,0,0,2,\"01\"\r\n", b"");
+        urcs.process_line(b"+CMQDISCON: 0\r\n", b"");
+        let message = waiter.poll(&mut urcs).unwrap().unwrap().unwrap();
+        assert_eq!(message.payload.as_slice(), &[1]);
+        assert!(matches!(waiter.poll(&mut urcs), Ok(Some(None))));
+        assert!(waiter.poll(&mut urcs).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_credential_chunks() {
+        let kind = CredentialType::CaCertificate;
+        let buffer_size = 36;
+        assert_eq!(max_credential_chunk_size(buffer_size), 2);
+        let chunks: std::vec::Vec<_> = credential_chunks(kind, b"ABCDE", buffer_size)
+            .unwrap()
+            .map(|chunk| (chunk.data, chunk.total_length, chunk.last))
+            .collect();
+        assert_eq!(
+            chunks,
+            [
+                (&b"AB"[..], 5, false),
+                (&b"CD"[..], 5, false),
+                (&b"E"[..], 5, true)
+            ]
+        );
+
+        assert!(matches!(
+            credential_chunks(kind, b"", buffer_size),
+            Err(AtError::InvalidParameter)
+        ));
+        assert!(matches!(
+            credential_chunks(kind, b"ABCDE", 0),
+            Err(AtError::InvalidParameter)
+        ));
+    }
+
+    #[test]
+    fn test_next_unlock_step() {
+        assert_eq!(
+            next_unlock_step(PinStatus::Ready, 0, 1).unwrap(),
+            UnlockStep::Unlocked
+        );
+        assert_eq!(
+            next_unlock_step(PinStatus::SimPin, 0, 1).unwrap(),
+            UnlockStep::EnterPin
+        );
+        assert!(matches!(
+            next_unlock_step(PinStatus::SimPin, 1, 1),
+            Err(AtError::IllegalPinStatus(PinStatus::SimPin))
+        ));
+        assert!(matches!(
+            next_unlock_step(PinStatus::SimPuk, 0, 1),
+            Err(AtError::IllegalPinStatus(PinStatus::SimPuk))
+        ));
+    }
+}
@@ -0,0 +1,378 @@
+//! Dispatch of the received MQTT messages by topic.
+//!
+//! The [MqttRouter] keeps a table of topic filters, which may contain the `+` and `#` wildcards,
+//! with the handler of the messages received in the matching topics. Registering a route with
+//! [MqttRouter::subscribe] also subscribes the [Mqtt] session to the filter.
+use crate::at_command::mqtt::{
+    MQTTError, MQTTReceivedMessage, Mqtt, MAX_SUBSCRIPTIONS, MAX_TOPIC_SIZE,
+};
+use crate::Modem;
+use embedded_hal::delay::DelayNs;
+use embedded_hal::digital::OutputPin;
+use embedded_io::{Read, ReadReady, Write};
+
+/// Handler of the messages received in the topics of a route
+pub type MqttRouteHandler = fn(&MQTTReceivedMessage);
+
+/// Route of a [MqttRouter]
+#[derive(Clone, Debug)]
+pub struct MqttRoute {
+    pub filter: heapless::String<MAX_TOPIC_SIZE>,
+    pub qos: u8,
+    pub handler: MqttRouteHandler,
+}
+
+/// Returns true if the topic matches the filter. `+` matches one level and `#`, which must be the
+/// last level, matches any number of levels, including the parent level. The wildcards at the
+/// first level do not match the topics starting with `$`
+pub fn topic_matches(filter: &str, topic: &str) -> bool {
+    if topic.starts_with('$') && (filter.starts_with('+') || filter.starts_with('#')) {
+        return false;
+    }
+
+    let mut filter_levels = filter.split('/');
+    let mut topic_levels = topic.split('/');
+    loop {
+        match (filter_levels.next(), topic_levels.next()) {
+            (Some("#"), _) => return true,
+            (Some(filter_level), Some(topic_level)) => {
+                if filter_level != "+" && filter_level != topic_level {
+                    return false;
+                }
+            }
+            (None, None) => return true,
+            _ => return false,
+        }
+    }
+}
+
+/// Checks that the wildcards of the filter take whole levels and that `#` is the last level
+fn is_valid_filter(filter: &str) -> bool {
+    if filter.is_empty() {
+        return false;
+    }
+
+    let mut levels = filter.split('/').peekable();
+    while let Some(level) = levels.next() {
+        let valid = match level {
+            "+" => true,
+            "#" => levels.peek().is_none(),
+            _ => !level.contains(['+', '#']),
+        };
+        if !valid {
+            return false;
+        }
+    }
+
+    true
+}
+
+/// Table of the routes of the received MQTT messages, `N` is the maximum number of routes
+pub struct MqttRouter<const N: usize = MAX_SUBSCRIPTIONS> {
+    routes: heapless::Vec<MqttRoute, N>,
+}
+
+impl<const N: usize> Default for MqttRouter<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> MqttRouter<N> {
+    pub const fn new() -> Self {
+        Self {
+            routes: heapless::Vec::new(),
+        }
+    }
+
+    /// Creates the route, without subscribing to the filter. Adding again a known filter replaces
+    /// its route. Fails with [MQTTError::Subscribe] if the filter is not valid or too long, or
+    /// there are already `N` routes
+    pub fn add_route(
+        &mut self,
+        filter: &str,
+        qos: u8,
+        handler: MqttRouteHandler,
+    ) -> Result<(), MQTTError> {
+        let known = self.check_route(filter)?;
+        let route = MqttRoute {
+            filter: filter.try_into().map_err(|_| MQTTError::Subscribe)?,
+            qos,
+            handler,
+        };
+        match known {
+            Some(index) => self.routes[index] = route,
+            // There is space, it has been checked before
+            None => {
+                let _ = self.routes.push(route);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the index of the route of the filter, if known, checking that it can be added
+    fn check_route(&self, filter: &str) -> Result<Option<usize>, MQTTError> {
+        if !is_valid_filter(filter) || filter.len() > MAX_TOPIC_SIZE {
+            return Err(MQTTError::Subscribe);
+        }
+        let known = self.routes.iter().position(|route| route.filter == filter);
+        if known.is_none() && self.routes.is_full() {
+            return Err(MQTTError::Subscribe);
+        }
+
+        Ok(known)
+    }
+
+    /// Removes the route of the filter, returns false if it is not known
+    pub fn remove_route(&mut self, filter: &str) -> bool {
+        let routes = self.routes.len();
+        self.routes.retain(|route| route.filter != filter);
+
+        self.routes.len() != routes
+    }
+
+    pub fn routes(&self) -> &[MqttRoute] {
+        &self.routes
+    }
+
+    /// Calls the handlers of all the routes matching the topic of the message. Returns the number
+    /// of called handlers
+    pub fn dispatch(&self, message: &MQTTReceivedMessage) -> usize {
+        let mut handled = 0;
+        for route in &self.routes {
+            if topic_matches(&route.filter, &message.topic) {
+                (route.handler)(message);
+                handled += 1;
+            }
+        }
+
+        handled
+    }
+
+    /// Subscribes the session to the filter with the QoS and creates its route
+    pub fn subscribe<T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs, const M: usize>(
+        &mut self,
+        filter: &str,
+        qos: u8,
+        handler: MqttRouteHandler,
+        mqtt: &mut Mqtt,
+        modem: &mut Modem<'_, T, U, P, D, M>,
+    ) -> Result<(), MQTTError> {
+        self.check_route(filter)?;
+        mqtt.subscribe(filter, qos, modem)?;
+
+        self.add_route(filter, qos, handler)
+    }
+
+    /// Unsubscribes the session from the filter and removes its route
+    pub fn unsubscribe<T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs, const M: usize>(
+        &mut self,
+        filter: &str,
+        mqtt: &mut Mqtt,
+        modem: &mut Modem<'_, T, U, P, D, M>,
+    ) -> Result<(), MQTTError> {
+        mqtt.unsubscribe(filter, modem)?;
+        self.remove_route(filter);
+
+        Ok(())
+    }
+
+    /// Dispatches the messages already received by the session. Returns the number of received
+    /// messages
+    pub fn poll<T: Write, U: Read + ReadReady, P: OutputPin, D: DelayNs, const M: usize>(
+        &self,
+        mqtt: &Mqtt,
+        modem: &mut Modem<'_, T, U, P, D, M>,
+    ) -> Result<usize, MQTTError> {
+        mqtt.poll(modem, |message| {
+            self.dispatch(&message);
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::at_command::mqtt::{MQTTMessage, MQTTSessionSettings};
+    use crate::emulator::Sim7020Emulator;
+    use crate::testing::{registered_modem, test_connection_settings};
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_topic_matches() {
+        for (filter, topic, matches) in [
+            ("sensors/temp", "sensors/temp", true),
+            ("sensors/temp", "sensors/hum", false),
+            ("sensors/+", "sensors/temp", true),
+            ("sensors/+", "sensors", false),
+            ("sensors/+", "sensors/temp/1", false),
+            ("+/+", "/temp", true),
+            ("sensors/+/value", "sensors/temp/value", true),
+            ("sensors/#", "sensors", true),
+            ("sensors/#", "sensors/temp/1", true),
+            ("sensors/#", "alarms/temp", false),
+            ("#", "sensors/temp", true),
+            ("#", "$SYS/uptime", false),
+            ("+/uptime", "$SYS/uptime", false),
+            ("$SYS/#", "$SYS/uptime", true),
+            ("sensors/temp", "sensors/temp/1", false),
+        ] {
+            assert_eq!(topic_matches(filter, topic), matches, "{filter} {topic}");
+        }
+
+        for (filter, valid) in [
+            ("sensors/+/value", true),
+            ("sensors/#", true),
+            ("#", true),
+            ("", false),
+            ("sensors/#/value", false),
+            ("sensors/te+", false),
+            ("sensors#", false),
+        ] {
+            assert_eq!(is_valid_filter(filter), valid, "{filter}");
+        }
+    }
+
+    static RECEIVED: Mutex<std::vec::Vec<(&str, std::string::String)>> =
+        Mutex::new(std::vec::Vec::new());
+
+    fn record(route: &'static str, message: &MQTTReceivedMessage) {
+        RECEIVED
+            .lock()
+            .unwrap()
+            .push((route, message.topic.as_str().into()));
+    }
+
+    #[test]
+    fn test_router_dispatches_by_topic() {
+        let mut router: MqttRouter<2> = MqttRouter::new();
+        router
+            .add_route("sensors/+", 0, |message| record("sensors", message))
+            .unwrap();
+        router
+            .add_route("#", 0, |message| record("all", message))
+            .unwrap();
+        assert_eq!(
+            router.add_route("alarms", 0, |_| {}),
+            Err(MQTTError::Subscribe)
+        );
+        assert_eq!(
+            router.add_route("sensors/#/value", 0, |_| {}),
+            Err(MQTTError::Subscribe)
+        );
+        // Replaces the known route
+        router
+            .add_route("#", 1, |message| record("any", message))
+            .unwrap();
+        assert_eq!(router.routes().len(), 2);
+
+        let message = MQTTReceivedMessage {
+            mqtt_id: 0,
+            topic: "sensors/temp".try_into().unwrap(),
+            qos: 0,
+            retained: false,
+            dup: false,
+            payload: heapless::Vec::new(),
+        };
+        assert_eq!(router.dispatch(&message), 2);
+        assert!(router.remove_route("sensors/+"));
+        assert!(!router.remove_route("sensors/+"));
+        assert_eq!(router.dispatch(&message), 1);
+
+        assert_eq!(
+            RECEIVED.lock().unwrap().as_slice(),
+            [
+                ("sensors", "sensors/temp".into()),
+                ("any", "sensors/temp".into()),
+                ("any", "sensors/temp".into()),
+            ]
+        );
+    }
+
+    static ROUTED: Mutex<std::vec::Vec<(&str, std::vec::Vec<u8>)>> =
+        Mutex::new(std::vec::Vec::new());
+
+    fn route(route: &'static str, message: &MQTTReceivedMessage) {
+        ROUTED
+            .lock()
+            .unwrap()
+            .push((route, message.payload.to_vec()));
+    }
+
+    #[test]
+    fn test_router_subscribes_the_session() {
+        let emulator = Sim7020Emulator::new();
+        let (mut writer, mut reader) = emulator.split();
+        let mut modem = registered_modem(&mut writer, &mut reader);
+        let settings = MQTTSessionSettings::new("broker.example.com", 1883);
+        let mut mqtt = Mqtt::new(&settings)
+            .create_session(&mut modem)
+            .unwrap()
+            .connect(test_connection_settings(), &mut modem)
+            .unwrap();
+
+        let mut router: MqttRouter = MqttRouter::new();
+        router
+            .subscribe(
+                "sensors/+",
+                1,
+                |message| route("sensors", message),
+                &mut mqtt,
+                &mut modem,
+            )
+            .unwrap();
+        router
+            .subscribe(
+                "alarms/#",
+                2,
+                |message| route("alarms", message),
+                &mut mqtt,
+                &mut modem,
+            )
+            .unwrap();
+        // Not subscribed if the filter is not valid
+        assert_eq!(
+            router.subscribe("alarms/#/x", 0, |_| {}, &mut mqtt, &mut modem),
+            Err(MQTTError::Subscribe)
+        );
+        assert_eq!(mqtt.subscriptions().len(), 2);
+        assert_eq!(mqtt.subscriptions()[1].qos, 2);
+        assert_eq!(
+            emulator.mqtt_session(0).unwrap().subscriptions.as_slice(),
+            ["sensors/+", "alarms/#"]
+        );
+
+        for (topic, message) in [
+            ("sensors/temp", b"21.5"),
+            ("commands", b"boot"),
+            ("alarms/fire/1", b"true"),
+        ] {
+            mqtt.publish(
+                &MQTTMessage {
+                    topic,
+                    qos: 0,
+                    retained: false,
+                    dup: false,
+                    message,
+                },
+                &mut modem,
+            )
+            .unwrap();
+        }
+        assert_eq!(router.poll(&mqtt, &mut modem), Ok(2));
+        assert_eq!(
+            ROUTED.lock().unwrap().as_slice(),
+            [("sensors", b"21.5".to_vec()), ("alarms", b"true".to_vec())]
+        );
+
+        router
+            .unsubscribe("alarms/#", &mut mqtt, &mut modem)
+            .unwrap();
+        assert_eq!(router.routes().len(), 1);
+        assert_eq!(
+            emulator.mqtt_session(0).unwrap().subscriptions.as_slice(),
+            ["sensors/+"]
+        );
+    }
+}
@@ -0,0 +1,311 @@
+//! Helpers to test the code that uses the [crate::Modem] without the hardware.
+//!
+//! [ScriptedSerial] plays a script of [Step]s, checking the commands written by the modem and
+//! providing the replies of the module. [NoopPin] and [NoopDelay] complete the peripherals
+//! needed to create the modem.
+//!
+//! ```
+//! use sim7020::testing::{NoopDelay, NoopPin, ScriptedSerial, Step};
+//! use sim7020::Modem;
+//!
+//! let script = [Step::Expect(b"ATE0\r\n"), Step::Reply(b"\r\nOK\r\n")];
+//! let serial = ScriptedSerial::new(&script);
+//! let (mut writer, mut reader) = serial.split();
+//!
+//! let _modem = Modem::new(&mut writer, &mut reader, NoopPin, NoopPin, NoopDelay).unwrap();
+//! serial.done();
+//! ```
+//!
+//! This module is available with the `testing` feature
+
+use core::cell::RefCell;
+use core::convert::Infallible;
+use embedded_io::{ErrorType, Read, ReadReady, Write};
+
+#[cfg(test)]
+use crate::at_command::mqtt::{MQTTConnectionSettings, MQTTVersion};
+#[cfg(test)]
+use crate::at_command::wireless::StartWirelessConnection;
+#[cfg(test)]
+use crate::emulator::{EmulatorReader, EmulatorWriter};
+
+/// One step of the conversation between the modem and the module
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Step<'a> {
+    /// Bytes that the modem must write
+    Expect(&'a [u8]),
+    /// Bytes that the module sends, they become readable once the previous steps are done
+    Reply(&'a [u8]),
+}
+
+#[derive(Debug, Default)]
+struct Position {
+    /// Index of the current step
+    step: usize,
+    /// Bytes of the current step already consumed
+    offset: usize,
+}
+
+/// Serial port that follows a script. Writing something that is not expected by the script
+/// panics, as well as calling [ScriptedSerial::done] before the script is finished
+pub struct ScriptedSerial<'a> {
+    script: &'a [Step<'a>],
+    position: RefCell<Position>,
+}
+
+impl<'a> ScriptedSerial<'a> {
+    pub fn new(script: &'a [Step<'a>]) -> Self {
+        Self {
+            script,
+            position: RefCell::new(Position::default()),
+        }
+    }
+
+    /// Returns the writer and the reader handles of the serial port
+    pub fn split(&self) -> (SerialWriter<'_, 'a>, SerialReader<'_, 'a>) {
+        (SerialWriter { serial: self }, SerialReader { serial: self })
+    }
+
+    /// Panics if the script has not been completely consumed
+    pub fn done(&self) {
+        let position = self.position.borrow();
+        assert!(
+            position.step == self.script.len(),
+            "script not finished, pending step {}: {:?}",
+            position.step,
+            self.script[position.step]
+        );
+    }
+
+    /// Indicates if the current step is a reply with bytes pending to be read
+    fn reply_pending(&self) -> bool {
+        let position = self.position.borrow();
+        matches!(self.script.get(position.step), Some(Step::Reply(_)))
+    }
+
+    fn write_bytes(&self, buf: &[u8]) -> usize {
+        let mut position = self.position.borrow_mut();
+        for (index, &byte) in buf.iter().enumerate() {
+            let expected = match self.script.get(position.step) {
+                Some(Step::Expect(expected)) => expected,
+                step => panic!(
+                    "unexpected write {:?} at step {}: {:?}",
+                    &buf[index..],
+                    position.step,
+                    step
+                ),
+            };
+            assert!(
+                expected[position.offset] == byte,
+                "unexpected write {:?} at step {}, expected {:?}",
+                &buf[index..],
+                position.step,
+                &expected[position.offset..]
+            );
+            position.offset += 1;
+            if position.offset == expected.len() {
+                position.step += 1;
+                position.offset = 0;
+            }
+        }
+
+        buf.len()
+    }
+
+    fn read_bytes(&self, buf: &mut [u8]) -> usize {
+        let mut position = self.position.borrow_mut();
+        let Some(Step::Reply(reply)) = self.script.get(position.step) else {
+            return 0;
+        };
+        let pending = &reply[position.offset..];
+        let size = pending.len().min(buf.len());
+        buf[..size].copy_from_slice(&pending[..size]);
+        position.offset += size;
+        if position.offset == reply.len() {
+            position.step += 1;
+            position.offset = 0;
+        }
+
+        size
+    }
+}
+
+/// Writer handle of a [ScriptedSerial]
+pub struct SerialWriter<'s, 'a> {
+    serial: &'s ScriptedSerial<'a>,
+}
+
+/// Reader handle of a [ScriptedSerial]
+pub struct SerialReader<'s, 'a> {
+    serial: &'s ScriptedSerial<'a>,
+}
+
+impl ErrorType for SerialWriter<'_, '_> {
+    type Error = Infallible;
+}
+
+impl ErrorType for SerialReader<'_, '_> {
+    type Error = Infallible;
+}
+
+impl Write for SerialWriter<'_, '_> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        Ok(self.serial.write_bytes(buf))
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl Read for SerialReader<'_, '_> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        Ok(self.serial.read_bytes(buf))
+    }
+}
+
+impl ReadReady for SerialReader<'_, '_> {
+    fn read_ready(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.serial.reply_pending())
+    }
+}
+
+#[cfg(feature = "nonblocking")]
+impl embedded_io_async::Write for SerialWriter<'_, '_> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        Ok(self.serial.write_bytes(buf))
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "nonblocking")]
+impl embedded_io_async::Read for SerialReader<'_, '_> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        Ok(self.serial.read_bytes(buf))
+    }
+}
+
+/// Output pin that does nothing
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoopPin;
+
+impl embedded_hal::digital::ErrorType for NoopPin {
+    type Error = Infallible;
+}
+
+impl embedded_hal::digital::OutputPin for NoopPin {
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Delay that returns immediately
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoopDelay;
+
+impl embedded_hal::delay::DelayNs for NoopDelay {
+    fn delay_ns(&mut self, _ns: u32) {}
+}
+
+#[cfg(feature = "nonblocking")]
+impl embedded_hal_async::delay::DelayNs for NoopDelay {
+    async fn delay_ns(&mut self, _ns: u32) {}
+}
+
+/// Creates a modem on the halves of a [crate::emulator::Sim7020Emulator] and brings up its
+/// wireless connection
+#[cfg(test)]
+pub(crate) fn registered_modem<'a, 'b>(
+    writer: &'a mut EmulatorWriter<'b>,
+    reader: &'a mut EmulatorReader<'b>,
+) -> crate::Modem<'a, EmulatorWriter<'b>, EmulatorReader<'b>, NoopPin, NoopDelay> {
+    let mut modem = crate::Modem::new(writer, reader, NoopPin, NoopPin, NoopDelay).unwrap();
+    modem
+        .send_and_wait_response(&StartWirelessConnection)
+        .unwrap();
+    modem
+}
+
+/// Async version of [registered_modem], with the given delay
+#[cfg(all(test, feature = "nonblocking"))]
+pub(crate) async fn registered_async_modem<D: embedded_hal_async::delay::DelayNs>(
+    emulator: &crate::emulator::Sim7020Emulator,
+    delay: D,
+) -> crate::nonblocking::AsyncModem<EmulatorWriter<'_>, EmulatorReader<'_>, NoopPin, D> {
+    let (writer, reader) = emulator.split();
+    let mut modem = crate::nonblocking::AsyncModem::new(writer, reader, NoopPin, NoopPin, delay)
+        .await
+        .unwrap();
+    modem
+        .send_and_wait_response(StartWirelessConnection)
+        .await
+        .unwrap();
+    modem
+}
+
+/// MQTT connection settings of a plain client, without will nor credentials
+#[cfg(test)]
+pub(crate) fn test_connection_settings() -> MQTTConnectionSettings<'static> {
+    MQTTConnectionSettings {
+        version: MQTTVersion::MQTT311,
+        client_id: "client",
+        keepalive_interval: 60,
+        clean_session: true,
+        will_flag: false,
+        will_options: None,
+        username: "",
+        password: "",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_scripted_serial() {
+        let script = [Step::Expect(b"AT\r\n"), Step::Reply(b"\r\nOK\r\n")];
+        let serial = ScriptedSerial::new(&script);
+        let (mut writer, mut reader) = serial.split();
+
+        assert!(!reader.read_ready().unwrap());
+        writer.write_all(b"AT").unwrap();
+        writer.write_all(b"\r\n").unwrap();
+        assert!(reader.read_ready().unwrap());
+
+        let mut buffer = [0; 4];
+        assert_eq!(reader.read(&mut buffer).unwrap(), 4);
+        assert_eq!(reader.read(&mut buffer).unwrap(), 2);
+        assert_eq!(&buffer[..2], b"\r\n");
+        assert!(!reader.read_ready().unwrap());
+        serial.done();
+    }
+
+    #[test]
+    #[should_panic(expected = "unexpected write")]
+    fn test_scripted_serial_unexpected_write() {
+        let script = [Step::Expect(b"AT\r\n")];
+        let serial = ScriptedSerial::new(&script);
+        let (mut writer, _) = serial.split();
+
+        writer.write_all(b"ATE0\r\n").unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "script not finished")]
+    fn test_scripted_serial_not_finished() {
+        let script = [Step::Expect(b"AT\r\n"), Step::Reply(b"\r\nOK\r\n")];
+        let serial = ScriptedSerial::new(&script);
+        let (mut writer, _) = serial.split();
+
+        writer.write_all(b"AT\r\n").unwrap();
+        serial.done();
+    }
+}
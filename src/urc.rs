@@ -0,0 +1,1341 @@
+//! Unsolicited result codes (URC).
+//!
+//! The SIM7020 sends some lines without a previous request, e.g. when data arrives on a socket
+//! or when the network registration changes. These lines can arrive at any time, also while a
+//! command is in flight, so the [Modem](crate::Modem) and the async modem strip them from the
+//! command responses and deliver them as [Urc] to a [UrcHandler] or to an internal queue.
+use crate::at_command::decode_hex;
+use crate::at_command::http::HttpHeaders;
+use crate::at_command::network_registration_status::NetworkRegistrationStatus;
+use crate::AtError;
+use at_commands::parser::CommandParser;
+#[cfg(feature = "defmt")]
+use defmt::warn;
+
+/// Maximum size of the payload carried by an [Urc]. The data of a larger URC is dropped and the
+/// next read of its socket, TLS connection, MQTT session or HTTP client fails with
+/// [AtError::CapacityError]
+pub const URC_PAYLOAD_SIZE: usize = 256;
+/// Maximum size of the MQTT topic carried by an [Urc]
+pub const URC_TOPIC_SIZE: usize = 128;
+/// Maximum number of [Urc] stored until they are consumed
+pub const URC_QUEUE_SIZE: usize = 4;
+/// Maximum number of MQTT publications stored until they are received
+pub const MQTT_QUEUE_SIZE: usize = 4;
+/// Number of sockets supported by the module, the socket ids go from 0 to `MAX_SOCKETS - 1`
+pub const MAX_SOCKETS: usize = 5;
+/// Maximum number of received bytes stored for each socket until they are read, including two
+/// bytes for the length of each `+CSONMI`
+pub const SOCKET_BUFFER_SIZE: usize = 512;
+/// Size of the length that precedes the data of each `+CSONMI` in the buffer of its socket
+const RECORD_HEADER_SIZE: usize = 2;
+/// Number of TLS connections whose received data is buffered, the TLS ids go from 1 to
+/// `MAX_TLS_CONNECTIONS`
+pub const MAX_TLS_CONNECTIONS: usize = 2;
+/// Number of HTTP clients supported by the module, the client ids go from 0 to
+/// `MAX_HTTP_CLIENTS - 1`
+pub const MAX_HTTP_CLIENTS: usize = 4;
+/// Maximum number of bytes of the body of an HTTP response stored until they are read
+pub const HTTP_BUFFER_SIZE: usize = 1024;
+/// Maximum size of the decoded headers of an HTTP response. The module sends them hex encoded in
+/// a single line, which must also fit the line buffer of the modem. Larger headers are dropped
+/// and the wait for the response fails with [AtError::CapacityError]
+pub const HTTP_HEADERS_SIZE: usize = 512;
+/// Maximum size of the host names carried by an [Urc]
+pub const URC_HOST_SIZE: usize = 64;
+/// Maximum size of an IP address in text form
+pub const IP_ADDRESS_SIZE: usize = 48;
+
+/// Function called for each received [Urc]. Must return true if the URC has been consumed,
+/// otherwise it will be stored in the queue of the modem
+pub type UrcHandler = fn(&Urc) -> bool;
+
+/// Data received in a socket (`+CSONMI`)
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(PartialEq, Clone, Debug)]
+pub struct SocketData {
+    pub socket_id: u8,
+    /// Data already decoded from hex
+    pub data: heapless::Vec<u8, URC_PAYLOAD_SIZE>,
+}
+
+/// Data received in a TLS connection (`+CTLSRECV`)
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(PartialEq, Clone, Debug)]
+pub struct TlsData {
+    pub tls_id: u8,
+    /// Data already decoded from hex
+    pub data: heapless::Vec<u8, URC_PAYLOAD_SIZE>,
+}
+
+/// Message received in a subscribed MQTT topic (`+CMQPUB`)
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(PartialEq, Clone, Debug)]
+pub struct MqttPublication {
+    pub mqtt_id: u8,
+    pub topic: heapless::String<URC_TOPIC_SIZE>,
+    pub qos: u8,
+    pub retained: bool,
+    pub dup: bool,
+    /// The payload as sent by the modem. Depending on the configured
+    /// [MQTTDataFormat](crate::at_command::mqtt::MQTTDataFormat) it may be hex encoded
+    pub payload: heapless::Vec<u8, URC_PAYLOAD_SIZE>,
+}
+
+/// Changes of the power saving mode (`+CPSMSTATUS`)
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(PartialEq, Clone, Debug)]
+pub enum PowerSavingModeStatus {
+    Entered,
+    Exited,
+}
+
+/// Status code and headers of an HTTP response (`+CHTTPNMIH`)
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(PartialEq, Clone, Debug)]
+pub struct HttpResponseHeader {
+    pub client_id: u8,
+    pub status_code: u16,
+    /// Header lines already decoded from hex, each one ended with `\r\n`
+    pub headers: heapless::Vec<u8, HTTP_HEADERS_SIZE>,
+}
+
+impl HttpResponseHeader {
+    /// Iterates over the name and the value of each header
+    pub fn headers(&self) -> HttpHeaders<'_> {
+        HttpHeaders::new(&self.headers)
+    }
+
+    /// Returns the value of the first header with the given name, ignoring the case
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers().find_header(name)
+    }
+}
+
+/// Fragment of the content of an HTTP response (`+CHTTPNMIC`)
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(PartialEq, Clone, Debug)]
+pub struct HttpContent {
+    pub client_id: u8,
+    /// Indicates if more fragments will follow
+    pub more_data: bool,
+    /// Total length of the content
+    pub content_length: u32,
+    /// Length of the content received so far, including this fragment
+    pub sum_length: u32,
+    /// Content of this fragment, already decoded from hex
+    pub content: heapless::Vec<u8, URC_PAYLOAD_SIZE>,
+}
+
+/// Result of the resolution of a host name (`+CDNSGIP`)
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(PartialEq, Clone, Debug)]
+pub enum DnsResolution {
+    /// The host has been resolved to the given address
+    Resolved {
+        host: heapless::String<URC_HOST_SIZE>,
+        address: heapless::String<IP_ADDRESS_SIZE>,
+    },
+    /// The resolution failed with the given DNS error code
+    Failed(i32),
+}
+
+impl DnsResolution {
+    /// Returns the resolved address
+    pub fn address(&self) -> Result<core::net::IpAddr, AtError> {
+        match self {
+            DnsResolution::Resolved { address, .. } => {
+                address.parse().map_err(|_| AtError::AtParseError)
+            }
+            DnsResolution::Failed(code) => Err(AtError::DnsResolutionFailed(*code)),
+        }
+    }
+}
+
+/// The unsolicited result codes handled by the driver
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(PartialEq, Clone, Debug)]
+pub enum Urc {
+    SocketData(SocketData),
+    MqttPublication(MqttPublication),
+    PowerSavingMode(PowerSavingModeStatus),
+    NetworkRegistration(NetworkRegistrationStatus),
+    HttpResponseHeader(HttpResponseHeader),
+    HttpContent(HttpContent),
+    DnsResolution(DnsResolution),
+    TlsData(TlsData),
+    /// The MQTT session with the given id has been disconnected from the broker (`+CMQDISCON`)
+    MqttDisconnected(u8),
+}
+
+const CSONMI: &[u8] = b"+CSONMI:";
+const CMQPUB: &[u8] = b"+CMQPUB:";
+const CMQDISCON: &[u8] = b"+CMQDISCON:";
+const CPSMSTATUS: &[u8] = b"+CPSMSTATUS:";
+const CEREG: &[u8] = b"+CEREG:";
+const CHTTPNMIH: &[u8] = b"+CHTTPNMIH:";
+const CHTTPNMIC: &[u8] = b"+CHTTPNMIC:";
+const CDNSGIP: &[u8] = b"+CDNSGIP:";
+const CTLSRECV: &[u8] = b"+CTLSRECV:";
+
+impl Urc {
+    /// Parses a single line, without the line terminator, into an [Urc]
+    pub fn parse(line: &[u8]) -> Result<Urc, AtError> {
+        let line = trim_line(line);
+
+        if line.starts_with(CSONMI) {
+            Self::parse_socket_data(line)
+        } else if line.starts_with(CMQPUB) {
+            Self::parse_mqtt_publication(line)
+        } else if line.starts_with(CMQDISCON) {
+            Self::parse_mqtt_disconnection(line)
+        } else if line.starts_with(CPSMSTATUS) {
+            Self::parse_power_saving_mode(line)
+        } else if line.starts_with(CEREG) {
+            Self::parse_network_registration(line)
+        } else if line.starts_with(CHTTPNMIH) {
+            Self::parse_http_response_header(line)
+        } else if line.starts_with(CHTTPNMIC) {
+            Self::parse_http_content(line)
+        } else if line.starts_with(CDNSGIP) {
+            Self::parse_dns_resolution(line)
+        } else if line.starts_with(CTLSRECV) {
+            Self::parse_tls_data(line)
+        } else {
+            Err(AtError::AtParseError)
+        }
+    }
+
+    fn parse_socket_data(line: &[u8]) -> Result<Urc, AtError> {
+        let (socket_id, _length, hex) = CommandParser::parse(line)
+            .expect_identifier(CSONMI)
+            .expect_int_parameter()
+            .expect_int_parameter()
+            .expect_raw_string()
+            .finish()?;
+
+        let mut data = heapless::Vec::new();
+        decode_hex(hex.as_bytes(), &mut data)?;
+
+        Ok(Urc::SocketData(SocketData {
+            socket_id: socket_id as u8,
+            data,
+        }))
+    }
+
+    fn parse_tls_data(line: &[u8]) -> Result<Urc, AtError> {
+        let (tls_id, _length, hex) = CommandParser::parse(line)
+            .expect_identifier(CTLSRECV)
+            .expect_int_parameter()
+            .expect_int_parameter()
+            .expect_raw_string()
+            .finish()?;
+
+        let mut data = heapless::Vec::new();
+        decode_hex(hex.as_bytes(), &mut data)?;
+
+        Ok(Urc::TlsData(TlsData {
+            tls_id: tls_id as u8,
+            data,
+        }))
+    }
+
+    fn parse_mqtt_publication(line: &[u8]) -> Result<Urc, AtError> {
+        let (mqtt_id, topic, qos, retained, dup, _length, payload) = CommandParser::parse(line)
+            .expect_identifier(CMQPUB)
+            .expect_int_parameter()
+            .expect_string_parameter()
+            .expect_int_parameter()
+            .expect_int_parameter()
+            .expect_int_parameter()
+            .expect_int_parameter()
+            .expect_string_parameter()
+            .finish()?;
+
+        Ok(Urc::MqttPublication(MqttPublication {
+            mqtt_id: mqtt_id as u8,
+            topic: topic.try_into()?,
+            qos: qos as u8,
+            retained: retained != 0,
+            dup: dup != 0,
+            payload: heapless::Vec::from_slice(payload.as_bytes())?,
+        }))
+    }
+
+    fn parse_mqtt_disconnection(line: &[u8]) -> Result<Urc, AtError> {
+        let (mqtt_id,) = CommandParser::parse(line)
+            .expect_identifier(CMQDISCON)
+            .expect_int_parameter()
+            .finish()?;
+
+        Ok(Urc::MqttDisconnected(mqtt_id as u8))
+    }
+
+    fn parse_power_saving_mode(line: &[u8]) -> Result<Urc, AtError> {
+        let (status,) = CommandParser::parse(line)
+            .expect_identifier(CPSMSTATUS)
+            .expect_string_parameter()
+            .finish()?;
+
+        match status {
+            "ENTER PSM" => Ok(Urc::PowerSavingMode(PowerSavingModeStatus::Entered)),
+            "EXIT PSM" => Ok(Urc::PowerSavingMode(PowerSavingModeStatus::Exited)),
+            _ => Err(AtError::AtParseError),
+        }
+    }
+
+    fn parse_network_registration(line: &[u8]) -> Result<Urc, AtError> {
+        let (status,) = CommandParser::parse(line)
+            .expect_identifier(CEREG)
+            .expect_int_parameter()
+            .finish()?;
+
+        if !(0..=7).contains(&status) {
+            return Err(AtError::AtParseError);
+        }
+
+        Ok(Urc::NetworkRegistration(status.into()))
+    }
+
+    fn parse_http_response_header(line: &[u8]) -> Result<Urc, AtError> {
+        let (client_id, status_code, _length, hex) = CommandParser::parse(line)
+            .expect_identifier(CHTTPNMIH)
+            .expect_int_parameter()
+            .expect_int_parameter()
+            .expect_int_parameter()
+            .expect_raw_string()
+            .finish()?;
+
+        let mut headers = heapless::Vec::new();
+        decode_hex(hex.as_bytes(), &mut headers)?;
+
+        Ok(Urc::HttpResponseHeader(HttpResponseHeader {
+            client_id: client_id as u8,
+            status_code: u16::try_from(status_code).map_err(|_| AtError::AtParseError)?,
+            headers,
+        }))
+    }
+
+    fn parse_http_content(line: &[u8]) -> Result<Urc, AtError> {
+        let (client_id, flag, content_length, sum_length, _current_length, hex) =
+            CommandParser::parse(line)
+                .expect_identifier(CHTTPNMIC)
+                .expect_int_parameter()
+                .expect_int_parameter()
+                .expect_int_parameter()
+                .expect_int_parameter()
+                .expect_int_parameter()
+                .expect_raw_string()
+                .finish()?;
+
+        let mut content = heapless::Vec::new();
+        decode_hex(hex.as_bytes(), &mut content)?;
+
+        Ok(Urc::HttpContent(HttpContent {
+            client_id: client_id as u8,
+            more_data: flag != 0,
+            content_length: content_length as u32,
+            sum_length: sum_length as u32,
+            content,
+        }))
+    }
+
+    fn parse_dns_resolution(line: &[u8]) -> Result<Urc, AtError> {
+        let (success, rest) = CommandParser::parse(line)
+            .expect_identifier(CDNSGIP)
+            .expect_int_parameter()
+            .expect_raw_string()
+            .finish()?;
+
+        if success == 0 {
+            let code = rest.trim().parse().map_err(|_| AtError::AtParseError)?;
+            return Ok(Urc::DnsResolution(DnsResolution::Failed(code)));
+        }
+
+        // The module may report a second address that is ignored
+        let (host, address, _) = CommandParser::parse(rest.as_bytes())
+            .expect_string_parameter()
+            .expect_string_parameter()
+            .expect_optional_string_parameter()
+            .finish()?;
+
+        Ok(Urc::DnsResolution(DnsResolution::Resolved {
+            host: host.try_into()?,
+            address: address.try_into()?,
+        }))
+    }
+}
+
+/// Removes the leading and trailing whitespaces, including the line terminators
+pub(crate) fn trim_line(line: &[u8]) -> &[u8] {
+    let start = line
+        .iter()
+        .position(|c| !c.is_ascii_whitespace())
+        .unwrap_or(line.len());
+    let end = line
+        .iter()
+        .rposition(|c| !c.is_ascii_whitespace())
+        .map_or(start, |end| end + 1);
+
+    &line[start..end]
+}
+
+/// Checks if the given line is an URC. Some URC share the prefix with the response of a
+/// command (e.g. `AT+CEREG?` answers with `+CEREG: <n>,<stat>`), so the command in flight is
+/// taken into account
+pub(crate) fn is_urc(line: &[u8], command: &[u8]) -> bool {
+    let line = trim_line(line);
+
+    if line.starts_with(CEREG) {
+        // The name of the command without the ':'
+        let name = &CEREG[..CEREG.len() - 1];
+        return !command
+            .strip_prefix(b"AT")
+            .is_some_and(|command| command.starts_with(name));
+    }
+
+    [
+        CSONMI, CMQPUB, CMQDISCON, CPSMSTATUS, CHTTPNMIH, CHTTPNMIC, CDNSGIP, CTLSRECV,
+    ]
+    .iter()
+    .any(|prefix| line.starts_with(prefix))
+}
+
+/// Response of an HTTP client that has not been read. The fragments of the body are appended as
+/// they arrive
+struct HttpResponseBuffer {
+    header: Option<HttpResponseHeader>,
+    body: heapless::Deque<u8, HTTP_BUFFER_SIZE>,
+    /// The last fragment of the body has been received
+    complete: bool,
+    /// Part of the body has been dropped because the buffer was full
+    overflow: bool,
+    /// The header has been dropped because it did not fit
+    header_overflow: bool,
+}
+
+impl HttpResponseBuffer {
+    const fn new() -> Self {
+        Self {
+            header: None,
+            body: heapless::Deque::new(),
+            complete: false,
+            overflow: false,
+            header_overflow: false,
+        }
+    }
+
+    fn start(&mut self, header: HttpResponseHeader) {
+        // The module does not send any content when the body is empty
+        self.complete = header.header("Content-Length") == Some("0");
+        self.overflow = false;
+        self.body.clear();
+        self.header = Some(header);
+    }
+
+    fn push(&mut self, content: &HttpContent) {
+        for &byte in &content.content {
+            if self.body.push_back(byte).is_err() {
+                #[cfg(feature = "defmt")]
+                warn!("HTTP buffer is full, dropping the received content");
+                self.overflow = true;
+                break;
+            }
+        }
+        if !content.more_data {
+            self.complete = true;
+        }
+    }
+}
+
+/// Stores the received [Urc] until they are consumed, calling the registered [UrcHandler].
+/// The data received in the sockets and TLS connections and the HTTP responses that are not
+/// consumed by the handler are stored in a buffer for each of them instead of the queue, so they
+/// are not lost when other URC arrive. The same applies to the MQTT publications, which have
+/// their own queue
+pub(crate) struct UrcDispatcher {
+    queue: heapless::Deque<Urc, URC_QUEUE_SIZE>,
+    mqtt: heapless::Deque<MqttPublication, MQTT_QUEUE_SIZE>,
+    /// Bitmask of the MQTT sessions disconnected from the broker that have not been checked
+    mqtt_disconnections: u8,
+    /// Bitmasks of the sockets, TLS connections and MQTT sessions whose received data has been
+    /// dropped because it did not fit, reported by the next read
+    socket_overflows: u8,
+    tls_overflows: u8,
+    mqtt_overflows: u8,
+    /// The data of each `+CSONMI` is stored as a record preceded by its length, so the datagrams
+    /// of the UDP sockets keep their boundaries
+    sockets: [heapless::Deque<u8, SOCKET_BUFFER_SIZE>; MAX_SOCKETS],
+    tls: [heapless::Deque<u8, SOCKET_BUFFER_SIZE>; MAX_TLS_CONNECTIONS],
+    http: [HttpResponseBuffer; MAX_HTTP_CLIENTS],
+    /// Last DNS resolution that has not been consumed
+    dns_resolution: Option<DnsResolution>,
+    handler: Option<UrcHandler>,
+}
+
+impl UrcDispatcher {
+    pub(crate) const fn new() -> Self {
+        Self {
+            queue: heapless::Deque::new(),
+            mqtt: heapless::Deque::new(),
+            mqtt_disconnections: 0,
+            socket_overflows: 0,
+            tls_overflows: 0,
+            mqtt_overflows: 0,
+            sockets: [const { heapless::Deque::new() }; MAX_SOCKETS],
+            tls: [const { heapless::Deque::new() }; MAX_TLS_CONNECTIONS],
+            http: [const { HttpResponseBuffer::new() }; MAX_HTTP_CLIENTS],
+            dns_resolution: None,
+            handler: None,
+        }
+    }
+
+    pub(crate) fn set_handler(&mut self, handler: Option<UrcHandler>) {
+        self.handler = handler;
+    }
+
+    /// Processes a received line. Returns true if the line is an URC, in which case it must not
+    /// be considered part of the response of the command in flight
+    pub(crate) fn process_line(&mut self, line: &[u8], command: &[u8]) -> bool {
+        if !is_urc(line, command) {
+            return false;
+        }
+
+        match Urc::parse(line) {
+            Ok(urc) => self.dispatch(urc),
+            Err(_e) => {
+                #[cfg(feature = "defmt")]
+                warn!("Discarding URC that could not be parsed: {}", _e);
+                self.record_overflow(line);
+            }
+        }
+
+        true
+    }
+
+    /// Processes the beginning of a line that does not fit the buffer of the modem. Returns true
+    /// if the line is an URC, whose data is reported as dropped, in which case the rest of the
+    /// line must be discarded
+    pub(crate) fn process_long_line(&mut self, line: &[u8], command: &[u8]) -> bool {
+        if !is_urc(line, command) {
+            return false;
+        }
+
+        #[cfg(feature = "defmt")]
+        warn!("Discarding URC that does not fit the buffer");
+        self.record_overflow(line);
+        true
+    }
+
+    /// Records that the data of the URC has been dropped, e.g. because its payload is larger than
+    /// [URC_PAYLOAD_SIZE], so the next read of the socket, TLS connection, MQTT session or HTTP
+    /// client fails instead of silently missing the data
+    fn record_overflow(&mut self, line: &[u8]) {
+        let line = trim_line(line);
+        let id = |prefix: &[u8]| -> Option<u8> {
+            let digits = line
+                .strip_prefix(prefix)?
+                .split(|&byte| byte == b',')
+                .next()?;
+            core::str::from_utf8(digits).ok()?.trim().parse().ok()
+        };
+
+        if let Some(socket_id) = id(CSONMI) {
+            self.socket_overflows |= 1 << (socket_id % 8);
+        } else if let Some(index) = id(CTLSRECV).and_then(Self::tls_index) {
+            self.tls_overflows |= 1 << index;
+        } else if let Some(mqtt_id) = id(CMQPUB) {
+            self.mqtt_overflows |= 1 << (mqtt_id % 8);
+        } else if let Some(response) = id(CHTTPNMIH).and_then(|id| self.http.get_mut(id as usize)) {
+            response.header_overflow = true;
+        } else if let Some(response) = id(CHTTPNMIC).and_then(|id| self.http.get_mut(id as usize)) {
+            response.overflow = true;
+        }
+    }
+
+    /// Clears the overflow flag of the id, failing with [AtError::CapacityError] if it was set
+    fn check_overflow(overflows: &mut u8, id: u8) -> Result<(), AtError> {
+        let mask = 1 << (id % 8);
+        if *overflows & mask != 0 {
+            *overflows &= !mask;
+            return Err(AtError::CapacityError);
+        }
+
+        Ok(())
+    }
+
+    fn dispatch(&mut self, urc: Urc) {
+        if let Some(handler) = self.handler {
+            if handler(&urc) {
+                return;
+            }
+        }
+
+        if let Urc::TlsData(data) = &urc {
+            if let Some(index) = Self::tls_index(data.tls_id) {
+                if !Self::store_socket_data(&mut self.tls[index], &data.data) {
+                    self.tls_overflows |= 1 << index;
+                }
+                return;
+            }
+        }
+
+        match urc {
+            Urc::SocketData(data) if (data.socket_id as usize) < MAX_SOCKETS => {
+                let buffer = &mut self.sockets[data.socket_id as usize];
+                if !Self::store_socket_record(buffer, &data.data) {
+                    self.socket_overflows |= 1 << data.socket_id;
+                }
+                return;
+            }
+            Urc::HttpResponseHeader(header) if (header.client_id as usize) < MAX_HTTP_CLIENTS => {
+                self.http[header.client_id as usize].start(header);
+                return;
+            }
+            Urc::HttpContent(content) if (content.client_id as usize) < MAX_HTTP_CLIENTS => {
+                self.http[content.client_id as usize].push(&content);
+                return;
+            }
+            Urc::DnsResolution(resolution) => {
+                self.dns_resolution = Some(resolution);
+                return;
+            }
+            Urc::MqttPublication(publication) => {
+                if self.mqtt.is_full() {
+                    #[cfg(feature = "defmt")]
+                    warn!("MQTT queue is full, dropping the oldest publication");
+                    if let Some(dropped) = self.mqtt.pop_front() {
+                        self.mqtt_overflows |= 1 << (dropped.mqtt_id % 8);
+                    }
+                }
+                let _ = self.mqtt.push_back(publication);
+                return;
+            }
+            Urc::MqttDisconnected(mqtt_id) => {
+                self.mqtt_disconnections |= 1 << (mqtt_id % 8);
+                return;
+            }
+            _ => {}
+        }
+
+        if self.queue.is_full() {
+            #[cfg(feature = "defmt")]
+            warn!("URC queue is full, dropping the oldest URC");
+            self.queue.pop_front();
+        }
+
+        // There is always space after removing the oldest element
+        let _ = self.queue.push_back(urc);
+    }
+
+    /// Returns false if part of the data has been dropped because the buffer is full
+    fn store_socket_data(
+        buffer: &mut heapless::Deque<u8, SOCKET_BUFFER_SIZE>,
+        data: &[u8],
+    ) -> bool {
+        for &byte in data {
+            if buffer.push_back(byte).is_err() {
+                #[cfg(feature = "defmt")]
+                warn!("Socket buffer is full, dropping the received data");
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// Stores the data as a record preceded by its length. Returns false if the record has been
+    /// dropped because the buffer is full
+    fn store_socket_record(
+        buffer: &mut heapless::Deque<u8, SOCKET_BUFFER_SIZE>,
+        data: &[u8],
+    ) -> bool {
+        if buffer.capacity() - buffer.len() < RECORD_HEADER_SIZE + data.len() {
+            #[cfg(feature = "defmt")]
+            warn!("Socket buffer is full, dropping the received data");
+            return false;
+        }
+
+        let length = (data.len() as u16).to_be_bytes();
+        for &byte in length.iter().chain(data) {
+            // The space has been checked
+            let _ = buffer.push_back(byte);
+        }
+
+        true
+    }
+
+    fn pop_record_length(buffer: &mut heapless::Deque<u8, SOCKET_BUFFER_SIZE>) -> Option<usize> {
+        let high = buffer.pop_front()?;
+        let low = buffer.pop_front()?;
+
+        Some(u16::from_be_bytes([high, low]) as usize)
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<Urc> {
+        self.queue.pop_front()
+    }
+
+    /// Moves the stored data of the socket into [buf]. Returns the number of bytes copied. Fails
+    /// once with [AtError::CapacityError] if received data has been dropped
+    pub(crate) fn read_socket_data(
+        &mut self,
+        socket_id: u8,
+        buf: &mut [u8],
+    ) -> Result<usize, AtError> {
+        let Some(buffer) = self.sockets.get_mut(socket_id as usize) else {
+            return Ok(0);
+        };
+        Self::check_overflow(&mut self.socket_overflows, socket_id)?;
+
+        let mut size = 0;
+        while size < buf.len() {
+            let Some(length) = Self::pop_record_length(buffer) else {
+                break;
+            };
+            let copied = length.min(buf.len() - size);
+            Self::read_buffer(buffer, &mut buf[size..size + copied]);
+            size += copied;
+            if copied < length {
+                // Keep the rest of the record, there is space for the length just popped
+                let [high, low] = ((length - copied) as u16).to_be_bytes();
+                let _ = buffer.push_front(low);
+                let _ = buffer.push_front(high);
+            }
+        }
+
+        Ok(size)
+    }
+
+    /// Moves the oldest datagram received in the socket into [buf], discarding the bytes that do
+    /// not fit. Returns the number of bytes copied, None if there is no datagram. Fails once with
+    /// [AtError::CapacityError] if received data has been dropped
+    pub(crate) fn read_socket_datagram(
+        &mut self,
+        socket_id: u8,
+        buf: &mut [u8],
+    ) -> Result<Option<usize>, AtError> {
+        let Some(buffer) = self.sockets.get_mut(socket_id as usize) else {
+            return Ok(None);
+        };
+        Self::check_overflow(&mut self.socket_overflows, socket_id)?;
+
+        let Some(length) = Self::pop_record_length(buffer) else {
+            return Ok(None);
+        };
+        let copied = length.min(buf.len());
+        Self::read_buffer(buffer, &mut buf[..copied]);
+        for _ in copied..length {
+            buffer.pop_front();
+        }
+
+        Ok(Some(copied))
+    }
+
+    /// Moves the stored data of the TLS connection into [buf]. Returns the number of bytes copied.
+    /// Fails once with [AtError::CapacityError] if received data has been dropped
+    pub(crate) fn read_tls_data(&mut self, tls_id: u8, buf: &mut [u8]) -> Result<usize, AtError> {
+        match Self::tls_index(tls_id) {
+            Some(index) => {
+                Self::check_overflow(&mut self.tls_overflows, index as u8)?;
+                Ok(Self::read_buffer(&mut self.tls[index], buf))
+            }
+            None => Ok(0),
+        }
+    }
+
+    /// Index of the buffer of the TLS connection, the TLS ids start at 1
+    fn tls_index(tls_id: u8) -> Option<usize> {
+        (1..=MAX_TLS_CONNECTIONS)
+            .contains(&(tls_id as usize))
+            .then(|| tls_id as usize - 1)
+    }
+
+    fn read_buffer<const S: usize>(buffer: &mut heapless::Deque<u8, S>, buf: &mut [u8]) -> usize {
+        let size = buf.len().min(buffer.len());
+        for (out, byte) in buf.iter_mut().zip(buffer.iter()) {
+            *out = *byte;
+        }
+        for _ in 0..size {
+            buffer.pop_front();
+        }
+
+        size
+    }
+
+    /// Returns the status code and the headers of the response of the HTTP client, once they
+    /// have been received. Fails once with [AtError::CapacityError] if the headers have been
+    /// dropped because they did not fit
+    pub(crate) fn take_http_header(
+        &mut self,
+        client_id: u8,
+    ) -> Result<Option<HttpResponseHeader>, AtError> {
+        let Some(response) = self.http.get_mut(client_id as usize) else {
+            return Ok(None);
+        };
+        if response.header_overflow {
+            response.header_overflow = false;
+            return Err(AtError::CapacityError);
+        }
+
+        Ok(response.header.take())
+    }
+
+    /// Moves the stored body of the HTTP response into [buf]. Returns the number of bytes copied
+    /// and whether the whole body has been read. Fails with [AtError::CapacityError] if part of
+    /// the body has been dropped
+    pub(crate) fn read_http_body(
+        &mut self,
+        client_id: u8,
+        buf: &mut [u8],
+    ) -> Result<(usize, bool), AtError> {
+        let Some(response) = self.http.get_mut(client_id as usize) else {
+            return Err(AtError::InvalidParameter);
+        };
+        if response.overflow {
+            response.overflow = false;
+            return Err(AtError::CapacityError);
+        }
+
+        let size = Self::read_buffer(&mut response.body, buf);
+        Ok((size, response.complete && response.body.is_empty()))
+    }
+
+    /// Discards the stored response of the HTTP client, e.g. before sending a new request
+    pub(crate) fn clear_http_response(&mut self, client_id: u8) {
+        if let Some(response) = self.http.get_mut(client_id as usize) {
+            *response = HttpResponseBuffer::new();
+        }
+    }
+
+    /// Returns the oldest stored publication received by the MQTT session. Fails once with
+    /// [AtError::CapacityError] if a publication has been dropped because it did not fit
+    pub(crate) fn take_mqtt_publication(
+        &mut self,
+        mqtt_id: u8,
+    ) -> Result<Option<MqttPublication>, AtError> {
+        Self::check_overflow(&mut self.mqtt_overflows, mqtt_id)?;
+        let mut publication = None;
+        // Rotate the whole queue to keep the order of the publications of the other sessions
+        for _ in 0..self.mqtt.len() {
+            let Some(stored) = self.mqtt.pop_front() else {
+                break;
+            };
+            if publication.is_none() && stored.mqtt_id == mqtt_id {
+                publication = Some(stored);
+            } else {
+                let _ = self.mqtt.push_back(stored);
+            }
+        }
+
+        Ok(publication)
+    }
+
+    /// Returns true if the MQTT session has been disconnected from the broker since the last call
+    pub(crate) fn take_mqtt_disconnection(&mut self, mqtt_id: u8) -> bool {
+        let mask = 1 << (mqtt_id % 8);
+        let disconnected = self.mqtt_disconnections & mask != 0;
+        self.mqtt_disconnections &= !mask;
+
+        disconnected
+    }
+
+    /// Discards the stored publications of the MQTT session, e.g. once the session is closed
+    pub(crate) fn clear_mqtt_publications(&mut self, mqtt_id: u8) {
+        self.mqtt_overflows &= !(1 << (mqtt_id % 8));
+        for _ in 0..self.mqtt.len() {
+            if let Some(stored) = self.mqtt.pop_front() {
+                if stored.mqtt_id != mqtt_id {
+                    let _ = self.mqtt.push_back(stored);
+                }
+            }
+        }
+    }
+
+    /// Returns the last DNS resolution that has not been consumed
+    pub(crate) fn take_dns_resolution(&mut self) -> Option<DnsResolution> {
+        self.dns_resolution.take()
+    }
+
+    /// Discards the stored data of the socket, e.g. once the socket is closed
+    pub(crate) fn clear_socket_data(&mut self, socket_id: u8) {
+        if let Some(buffer) = self.sockets.get_mut(socket_id as usize) {
+            buffer.clear();
+            self.socket_overflows &= !(1 << socket_id);
+        }
+    }
+
+    /// Discards the stored data of the TLS connection, e.g. once the connection is closed
+    pub(crate) fn clear_tls_data(&mut self, tls_id: u8) {
+        if let Some(index) = Self::tls_index(tls_id) {
+            self.tls[index].clear();
+            self.tls_overflows &= !(1 << index);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_socket_data() {
+        let urc = Urc::parse(b"+CSONMI: 1,8,deadbeef\r\n").unwrap();
+
+        match urc {
+            Urc::SocketData(data) => {
+                assert_eq!(data.socket_id, 1);
+                assert_eq!(data.data.as_slice(), &[0xDE, 0xAD, 0xBE, 0xEF]);
+            }
+            _ => panic!("Expected Urc::SocketData"),
+        }
+    }
+
+    #[test]
+    fn parse_tls_data() {
+        let urc = Urc::parse(b"+CTLSRECV: 2,4,4F4B\r\n").unwrap();
+
+        assert_eq!(
+            urc,
+            Urc::TlsData(TlsData {
+                tls_id: 2,
+                data: heapless::Vec::from_slice(b"OK").unwrap(),
+            })
+        );
+    }
+
+    #[test]
+    fn parse_mqtt_publication() {
+        let urc = Urc::parse(b"+CMQPUB: 0,\"sensors/temp\",1,0,1,4,\"21.5\"").unwrap();
+
+        match urc {
+            Urc::MqttPublication(message) => {
+                assert_eq!(message.mqtt_id, 0);
+                assert_eq!(message.topic.as_str(), "sensors/temp");
+                assert_eq!(message.qos, 1);
+                assert!(!message.retained);
+                assert!(message.dup);
+                assert_eq!(message.payload.as_slice(), b"21.5");
+            }
+            _ => panic!("Expected Urc::MqttPublication"),
+        }
+    }
+
+    #[test]
+    fn parse_mqtt_disconnection() {
+        assert_eq!(
+            Urc::parse(b"+CMQDISCON: 2\r\n").unwrap(),
+            Urc::MqttDisconnected(2)
+        );
+        assert!(is_urc(b"+CMQDISCON: 2", b"AT+CMQDISCON=2"));
+    }
+
+    #[test]
+    fn parse_power_saving_mode() {
+        assert_eq!(
+            Urc::parse(b"+CPSMSTATUS: \"ENTER PSM\"").unwrap(),
+            Urc::PowerSavingMode(PowerSavingModeStatus::Entered)
+        );
+        assert_eq!(
+            Urc::parse(b"+CPSMSTATUS: \"EXIT PSM\"").unwrap(),
+            Urc::PowerSavingMode(PowerSavingModeStatus::Exited)
+        );
+        assert!(Urc::parse(b"+CPSMSTATUS: \"SLEEPING\"").is_err());
+    }
+
+    #[test]
+    fn parse_network_registration() {
+        assert_eq!(
+            Urc::parse(b"+CEREG: 5,\"1A2B\",\"01A2B3C4\",9").unwrap(),
+            Urc::NetworkRegistration(NetworkRegistrationStatus::RegisteredRoaming)
+        );
+        assert!(Urc::parse(b"+CEREG: 42").is_err());
+    }
+
+    #[test]
+    fn parse_http_content() {
+        let urc = Urc::parse(b"+CHTTPNMIC: 0,1,10,5,5,48656c6c6f").unwrap();
+
+        match urc {
+            Urc::HttpContent(content) => {
+                assert_eq!(content.client_id, 0);
+                assert!(content.more_data);
+                assert_eq!(content.content_length, 10);
+                assert_eq!(content.sum_length, 5);
+                assert_eq!(content.content.as_slice(), b"Hello");
+            }
+            _ => panic!("Expected Urc::HttpContent"),
+        }
+    }
+
+    #[test]
+    fn parse_http_response_header() {
+        let urc = Urc::parse(b"+CHTTPNMIH: 1,404,12,583a20790d0a\r\n").unwrap();
+
+        match urc {
+            Urc::HttpResponseHeader(header) => {
+                assert_eq!(header.client_id, 1);
+                assert_eq!(header.status_code, 404);
+                assert_eq!(header.header("x"), Some("y"));
+            }
+            _ => panic!("Expected Urc::HttpResponseHeader"),
+        }
+        assert!(Urc::parse(b"+CHTTPNMIH: 1,-1,0,").is_err());
+    }
+
+    #[test]
+    fn parse_dns_resolution() {
+        assert_eq!(
+            Urc::parse(b"+CDNSGIP: 1,\"example.com\",\"93.184.216.34\",\"93.184.216.35\"").unwrap(),
+            Urc::DnsResolution(DnsResolution::Resolved {
+                host: "example.com".try_into().unwrap(),
+                address: "93.184.216.34".try_into().unwrap(),
+            })
+        );
+
+        let urc = Urc::parse(b"+CDNSGIP: 0,8\r\n").unwrap();
+        assert_eq!(urc, Urc::DnsResolution(DnsResolution::Failed(8)));
+        match urc {
+            Urc::DnsResolution(resolution) => assert!(matches!(
+                resolution.address(),
+                Err(AtError::DnsResolutionFailed(8))
+            )),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn parse_unknown_line_fails() {
+        assert!(Urc::parse(b"+CSQ: 20,99").is_err());
+    }
+
+    #[test]
+    fn cereg_is_a_response_of_the_cereg_command() {
+        assert!(is_urc(b"\r\n+CEREG: 1\r\n", b"AT+CSQ\r\n"));
+        assert!(!is_urc(b"+CEREG: 0,1\r\n", b"AT+CEREG?\r\n"));
+        assert!(is_urc(b"+CSONMI: 0,2,00\r\n", b"AT+CSONMI?\r\n"));
+        assert!(!is_urc(b"+CSQ: 20,99\r\n", b"AT+CSQ\r\n"));
+    }
+
+    #[test]
+    fn dispatcher_queues_urc() {
+        let mut dispatcher = UrcDispatcher::new();
+
+        assert!(dispatcher.process_line(b"+CPSMSTATUS: \"EXIT PSM\"\r\n", b""));
+        assert!(!dispatcher.process_line(b"OK\r\n", b""));
+
+        assert_eq!(
+            dispatcher.pop(),
+            Some(Urc::PowerSavingMode(PowerSavingModeStatus::Exited))
+        );
+        assert_eq!(dispatcher.pop(), None);
+    }
+
+    #[test]
+    fn dispatcher_drops_oldest_when_full() {
+        let mut dispatcher = UrcDispatcher::new();
+
+        for _ in 0..URC_QUEUE_SIZE {
+            dispatcher.process_line(b"+CPSMSTATUS: \"ENTER PSM\"", b"");
+        }
+        dispatcher.process_line(b"+CPSMSTATUS: \"EXIT PSM\"", b"");
+
+        for _ in 0..URC_QUEUE_SIZE - 1 {
+            assert_eq!(
+                dispatcher.pop(),
+                Some(Urc::PowerSavingMode(PowerSavingModeStatus::Entered))
+            );
+        }
+        assert_eq!(
+            dispatcher.pop(),
+            Some(Urc::PowerSavingMode(PowerSavingModeStatus::Exited))
+        );
+    }
+
+    #[test]
+    fn dispatcher_buffers_socket_data() {
+        let mut dispatcher = UrcDispatcher::new();
+
+        dispatcher.process_line(b"+CSONMI: 1,6,414243\r\n", b"");
+        dispatcher.process_line(b"+CPSMSTATUS: \"ENTER PSM\"", b"");
+        dispatcher.process_line(b"+CSONMI: 0,2,5A\r\n", b"");
+        dispatcher.process_line(b"+CSONMI: 1,4,4445\r\n", b"");
+
+        let mut buf = [0; 4];
+        assert_eq!(dispatcher.read_socket_data(1, &mut buf).unwrap(), 4);
+        assert_eq!(&buf, b"ABCD");
+        assert_eq!(dispatcher.read_socket_data(1, &mut buf).unwrap(), 1);
+        assert_eq!(&buf[..1], b"E");
+        assert_eq!(dispatcher.read_socket_data(1, &mut buf).unwrap(), 0);
+
+        dispatcher.clear_socket_data(0);
+        assert_eq!(dispatcher.read_socket_data(0, &mut buf).unwrap(), 0);
+
+        // Only the PSM status has been queued
+        assert_eq!(
+            dispatcher.pop(),
+            Some(Urc::PowerSavingMode(PowerSavingModeStatus::Entered))
+        );
+        assert_eq!(dispatcher.pop(), None);
+    }
+
+    #[test]
+    fn dispatcher_queues_mqtt_publications() {
+        let mut dispatcher = UrcDispatcher::new();
+
+        dispatcher.process_line(b"+CMQPUB: 0,\"a\",0,0,0,2,\"01\"\r\n", b"");
+        dispatcher.process_line(b"+CMQPUB: 1,\"b\",0,0,0,2,\"02\"\r\n", b"");
+        for _ in 0..MQTT_QUEUE_SIZE - 1 {
+            dispatcher.process_line(b"+CMQPUB: 0,\"c\",0,0,0,2,\"03\"\r\n", b"");
+        }
+        // The publications do not go to the generic queue
+        assert_eq!(dispatcher.pop(), None);
+
+        // The oldest publication has been dropped
+        let publication = dispatcher.take_mqtt_publication(1).unwrap().unwrap();
+        assert_eq!(publication.topic.as_str(), "b");
+        assert_eq!(dispatcher.take_mqtt_publication(1).unwrap(), None);
+
+        // The dropped publication is reported once
+        assert!(matches!(
+            dispatcher.take_mqtt_publication(0),
+            Err(AtError::CapacityError)
+        ));
+        let publication = dispatcher.take_mqtt_publication(0).unwrap().unwrap();
+        assert_eq!(publication.topic.as_str(), "c");
+        dispatcher.clear_mqtt_publications(0);
+        assert_eq!(dispatcher.take_mqtt_publication(0).unwrap(), None);
+
+        dispatcher.process_line(b"+CMQDISCON: 1\r\n", b"");
+        assert!(!dispatcher.take_mqtt_disconnection(0));
+        assert!(dispatcher.take_mqtt_disconnection(1));
+        assert!(!dispatcher.take_mqtt_disconnection(1));
+    }
+
+    #[test]
+    fn dispatcher_reassembles_http_response() {
+        let mut dispatcher = UrcDispatcher::new();
+        let mut buf = [0; 4];
+
+        assert_eq!(dispatcher.read_http_body(0, &mut buf).unwrap(), (0, false));
+        dispatcher.process_line(b"+CHTTPNMIH: 0,200,0,\r\n", b"");
+        dispatcher.process_line(b"+CHTTPNMIC: 0,1,5,3,3,48656c\r\n", b"");
+        dispatcher.process_line(b"+CPSMSTATUS: \"ENTER PSM\"", b"");
+        dispatcher.process_line(b"+CHTTPNMIC: 0,0,5,5,2,6c6f\r\n", b"");
+
+        assert_eq!(
+            dispatcher.take_http_header(0).unwrap().unwrap().status_code,
+            200
+        );
+        assert_eq!(dispatcher.take_http_header(0).unwrap(), None);
+        assert_eq!(dispatcher.read_http_body(0, &mut buf).unwrap(), (4, false));
+        assert_eq!(&buf, b"Hell");
+        assert_eq!(dispatcher.read_http_body(0, &mut buf).unwrap(), (1, true));
+        assert_eq!(&buf[..1], b"o");
+
+        // A response without body is complete once the header is received
+        dispatcher.process_line(
+            b"+CHTTPNMIH: 0,204,19,436f6e74656e742d4c656e6774683a20300d0a",
+            b"",
+        );
+        assert_eq!(dispatcher.read_http_body(0, &mut buf).unwrap(), (0, true));
+
+        dispatcher.process_line(b"+CHTTPNMIC: 1,1,4,2,2,4142\r\n", b"");
+        dispatcher.clear_http_response(1);
+        assert_eq!(dispatcher.read_http_body(1, &mut buf).unwrap(), (0, false));
+
+        assert_eq!(
+            dispatcher.pop(),
+            Some(Urc::PowerSavingMode(PowerSavingModeStatus::Entered))
+        );
+        assert_eq!(dispatcher.pop(), None);
+    }
+
+    #[test]
+    fn dispatcher_reports_http_overflow() {
+        let mut dispatcher = UrcDispatcher::new();
+        let mut line: heapless::String<600> = heapless::String::new();
+        line.push_str("+CHTTPNMIC: 2,1,2000,200,200,").unwrap();
+        for _ in 0..200 {
+            line.push_str("41").unwrap();
+        }
+
+        for _ in 0..HTTP_BUFFER_SIZE / 200 + 1 {
+            dispatcher.process_line(line.as_bytes(), b"");
+        }
+
+        let mut buf = [0; 16];
+        assert!(matches!(
+            dispatcher.read_http_body(2, &mut buf),
+            Err(AtError::CapacityError)
+        ));
+    }
+
+    #[test]
+    fn dispatcher_reports_http_header_overflow() {
+        let mut dispatcher = UrcDispatcher::new();
+        let header = format!("X-Long: {}\r\n", "a".repeat(290));
+        let hex: String = header.bytes().map(|byte| format!("{byte:02x}")).collect();
+
+        // Larger than the payload of the other URC, but it fits the headers
+        let line = format!("+CHTTPNMIH: 0,200,{},{}\r\n", header.len(), hex);
+        dispatcher.process_line(line.as_bytes(), b"");
+        let received = dispatcher.take_http_header(0).unwrap().unwrap();
+        assert_eq!(received.header("X-Long").unwrap().len(), 290);
+
+        // Larger than the headers
+        let hex = hex.repeat(2);
+        let line = format!("+CHTTPNMIH: 1,200,{},{}\r\n", header.len() * 2, hex);
+        dispatcher.process_line(line.as_bytes(), b"");
+        assert!(matches!(
+            dispatcher.take_http_header(1),
+            Err(AtError::CapacityError)
+        ));
+        assert_eq!(dispatcher.take_http_header(1).unwrap(), None);
+
+        // Longer than the line buffer of the modem
+        assert!(dispatcher.process_long_line(b"+CHTTPNMIH: 2,200,600,0d0a", b""));
+        assert!(matches!(
+            dispatcher.take_http_header(2),
+            Err(AtError::CapacityError)
+        ));
+    }
+
+    #[test]
+    fn dispatcher_reports_dropped_data() {
+        let mut dispatcher = UrcDispatcher::new();
+        let mut buf = [0; 16];
+        let payload = "41".repeat(URC_PAYLOAD_SIZE + 44);
+
+        // Larger than the payload of an URC
+        for line in [
+            format!("+CSONMI: 2,{},{}\r\n", payload.len(), payload),
+            format!("+CTLSRECV: 1,{},{}\r\n", payload.len(), payload),
+            format!(
+                "+CMQPUB: 1,\"a\",0,0,0,{},\"{}\"\r\n",
+                payload.len(),
+                payload
+            ),
+            format!("+CHTTPNMIC: 3,0,300,300,300,{}\r\n", payload),
+        ] {
+            assert!(dispatcher.process_line(line.as_bytes(), b""));
+        }
+        dispatcher.process_line(b"+CSONMI: 2,2,5A\r\n", b"");
+
+        // The loss is reported once, then the data that fits is read
+        assert!(matches!(
+            dispatcher.read_socket_data(2, &mut buf),
+            Err(AtError::CapacityError)
+        ));
+        assert_eq!(dispatcher.read_socket_data(2, &mut buf).unwrap(), 1);
+        assert_eq!(dispatcher.read_socket_data(0, &mut buf).unwrap(), 0);
+        assert!(matches!(
+            dispatcher.read_tls_data(1, &mut buf),
+            Err(AtError::CapacityError)
+        ));
+        assert_eq!(dispatcher.read_tls_data(1, &mut buf).unwrap(), 0);
+        assert!(matches!(
+            dispatcher.take_mqtt_publication(1),
+            Err(AtError::CapacityError)
+        ));
+        assert_eq!(dispatcher.take_mqtt_publication(1).unwrap(), None);
+        assert_eq!(dispatcher.take_mqtt_publication(0).unwrap(), None);
+        assert!(matches!(
+            dispatcher.read_http_body(3, &mut buf),
+            Err(AtError::CapacityError)
+        ));
+
+        // The socket buffer is full
+        let line = format!("+CSONMI: 3,{},{}\r\n", 400, "42".repeat(200));
+        for _ in 0..SOCKET_BUFFER_SIZE / 200 + 1 {
+            dispatcher.process_line(line.as_bytes(), b"");
+        }
+        assert!(matches!(
+            dispatcher.read_socket_data(3, &mut buf),
+            Err(AtError::CapacityError)
+        ));
+        dispatcher.clear_socket_data(3);
+        assert_eq!(dispatcher.read_socket_data(3, &mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn dispatcher_keeps_datagram_boundaries() {
+        let mut dispatcher = UrcDispatcher::new();
+        let mut buf = [0; 3];
+
+        assert_eq!(dispatcher.read_socket_datagram(1, &mut buf).unwrap(), None);
+        dispatcher.process_line(b"+CSONMI: 1,8,41424344\r\n", b"");
+        dispatcher.process_line(b"+CSONMI: 1,4,4546\r\n", b"");
+        dispatcher.process_line(b"+CSONMI: 1,8,47484950\r\n", b"");
+
+        // The rest of the datagram is discarded
+        assert_eq!(
+            dispatcher.read_socket_datagram(1, &mut buf).unwrap(),
+            Some(3)
+        );
+        assert_eq!(&buf, b"ABC");
+        assert_eq!(
+            dispatcher.read_socket_datagram(1, &mut buf).unwrap(),
+            Some(2)
+        );
+        assert_eq!(&buf[..2], b"EF");
+
+        // Read as a stream the records are joined and split
+        dispatcher.process_line(b"+CSONMI: 1,4,5152\r\n", b"");
+        assert_eq!(dispatcher.read_socket_data(1, &mut buf).unwrap(), 3);
+        assert_eq!(&buf, b"GHI");
+        assert_eq!(dispatcher.read_socket_data(1, &mut buf).unwrap(), 3);
+        assert_eq!(&buf, b"PQR");
+        assert_eq!(dispatcher.read_socket_data(1, &mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn dispatcher_buffers_tls_data() {
+        let mut dispatcher = UrcDispatcher::new();
+
+        dispatcher.process_line(b"+CTLSRECV: 1,4,4142\r\n", b"");
+        dispatcher.process_line(b"+CSONMI: 1,2,5A\r\n", b"");
+        dispatcher.process_line(b"+CTLSRECV: 1,2,43\r\n", b"");
+        // There is no buffer for this TLS id
+        dispatcher.process_line(b"+CTLSRECV: 6,2,44\r\n", b"");
+
+        let mut buf = [0; 4];
+        assert_eq!(dispatcher.read_tls_data(1, &mut buf).unwrap(), 3);
+        assert_eq!(&buf[..3], b"ABC");
+        assert_eq!(dispatcher.read_socket_data(1, &mut buf).unwrap(), 1);
+        assert_eq!(dispatcher.read_tls_data(6, &mut buf).unwrap(), 0);
+
+        dispatcher.process_line(b"+CTLSRECV: 2,2,45\r\n", b"");
+        dispatcher.clear_tls_data(2);
+        assert_eq!(dispatcher.read_tls_data(2, &mut buf).unwrap(), 0);
+
+        match dispatcher.pop() {
+            Some(Urc::TlsData(data)) => {
+                assert_eq!(data.tls_id, 6);
+                assert_eq!(data.data.as_slice(), b"D");
+            }
+            urc => panic!("Expected Urc::TlsData, got {:?}", urc),
+        }
+    }
+
+    #[test]
+    fn dispatcher_handler_consumes_urc() {
+        fn consume_psm(urc: &Urc) -> bool {
+            matches!(urc, Urc::PowerSavingMode(_))
+        }
+
+        let mut dispatcher = UrcDispatcher::new();
+        dispatcher.set_handler(Some(consume_psm));
+
+        dispatcher.process_line(b"+CPSMSTATUS: \"ENTER PSM\"", b"");
+        dispatcher.process_line(b"+CEREG: 1", b"");
+
+        assert_eq!(
+            dispatcher.pop(),
+            Some(Urc::NetworkRegistration(
+                NetworkRegistrationStatus::RegisteredHomeNetwork
+            ))
+        );
+        assert_eq!(dispatcher.pop(), None);
+    }
+}